@@ -0,0 +1,209 @@
+#ifndef NES_EMULATOR_H
+#define NES_EMULATOR_H
+
+/* Generated by cbindgen from src/capi.rs, run the capi_tests with NES_UPDATE_HEADER=1 to regenerate. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NES_OK 0
+
+/**
+ * A null handle or buffer.
+ */
+#define NES_ERROR_NULL -1
+
+/**
+ * The call needs a ROM, load one with `nes_load_rom` first.
+ */
+#define NES_ERROR_NO_ROM -2
+
+#define NES_ERROR_INVALID_ROM -3
+
+#define NES_ERROR_INVALID_ARGUMENT -4
+
+/**
+ * The game ran an opcode the CPU does not implement, the console stays where it stopped.
+ */
+#define NES_ERROR_UNSUPPORTED -5
+
+/**
+ * A save state that is damaged, from a newer build or from another game.
+ */
+#define NES_ERROR_INVALID_STATE -6
+
+/**
+ * The emulator panicked, a bug. The ROM is unloaded since the console may be half updated.
+ */
+#define NES_ERROR_PANIC -7
+
+#define NES_SCREEN_WIDTH 256
+
+#define NES_SCREEN_HEIGHT 240
+
+#define NES_PLAYERS 4
+
+#define NES_BUTTON_A 1
+
+#define NES_BUTTON_B 2
+
+#define NES_BUTTON_SELECT 4
+
+#define NES_BUTTON_START 8
+
+#define NES_BUTTON_UP 16
+
+#define NES_BUTTON_DOWN 32
+
+#define NES_BUTTON_LEFT 64
+
+#define NES_BUTTON_RIGHT 128
+
+/**
+ * Opaque emulator handle.
+ */
+typedef struct NesEmulator NesEmulator;
+
+/**
+ * CPU registers, as returned by `nes_registers`.
+ */
+typedef struct NesRegisters {
+  uint16_t pc;
+  uint8_t a;
+  uint8_t x;
+  uint8_t y;
+  uint8_t sp;
+  /**
+   * NV-BDIZC
+   */
+  uint8_t status;
+  /**
+   * CPU cycles since power on.
+   */
+  uint64_t cycles;
+} NesRegisters;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an emulator with no ROM loaded. Free it with `nes_destroy`.
+ */
+struct NesEmulator *nes_create(void);
+
+/**
+ * # Safety
+ * `emulator` must come from `nes_create` and not be used afterwards. Null is ignored.
+ */
+void nes_destroy(struct NesEmulator *emulator);
+
+/**
+ * The message of the last failed call, empty if none failed. Valid until the next call.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+const char *nes_last_error(const struct NesEmulator *emulator);
+
+/**
+ * Loads an iNES, NES 2.0 or UNIF image and powers the console on. The bytes are copied.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle, `data` must be valid for `size` bytes.
+ */
+int32_t nes_load_rom(struct NesEmulator *emulator, const uint8_t *data, uintptr_t size);
+
+/**
+ * Executes one CPU instruction (or interrupt), returns 1 if it completed a frame, 0 if not,
+ * or an error code.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+int32_t nes_step(struct NesEmulator *emulator);
+
+/**
+ * Runs until the PPU finishes a frame.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+int32_t nes_run_frame(struct NesEmulator *emulator);
+
+/**
+ * The last frame completed by `nes_step` or `nes_run_frame` (blank before the first one) as
+ * `NES_SCREEN_WIDTH` x `NES_SCREEN_HEIGHT` RGBA8888 pixels, row by row. Valid until the next
+ * call on this handle; null without a ROM.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+const uint8_t *nes_framebuffer(struct NesEmulator *emulator);
+
+/**
+ * Sets the `NES_BUTTON_*` bits held by `player` (0-3) until changed.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+int32_t nes_set_buttons(struct NesEmulator *emulator, uint32_t player, uint8_t buttons);
+
+/**
+ * Reads the CPU address space without side effects: I/O registers read as open bus. Returns 0
+ * without a ROM.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+uint8_t nes_peek(const struct NesEmulator *emulator, uint16_t addr);
+
+/**
+ * Writes to the CPU address space, exactly like a CPU write (so poking a register has its
+ * usual effect).
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+int32_t nes_poke(struct NesEmulator *emulator, uint16_t addr, uint8_t data);
+
+/**
+ * # Safety
+ * `emulator` must be null or a live handle, `registers` null or writable.
+ */
+int32_t nes_registers(struct NesEmulator *emulator, struct NesRegisters *registers);
+
+/**
+ * Size of the buffer `nes_save_state` needs, the same for every state of a game; 0 without a
+ * ROM.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle.
+ */
+uintptr_t nes_state_size(struct NesEmulator *emulator);
+
+/**
+ * Writes a snapshot of the console to `buffer`, which needs `nes_state_size` bytes (any more
+ * are zero-filled).
+ *
+ * # Safety
+ * `emulator` must be null or a live handle, `buffer` valid for `size` bytes.
+ */
+int32_t nes_save_state(struct NesEmulator *emulator, uint8_t *buffer, uintptr_t size);
+
+/**
+ * Restores a snapshot written by `nes_save_state` for the same game. On error the console is
+ * left as it was.
+ *
+ * # Safety
+ * `emulator` must be null or a live handle, `buffer` valid for `size` bytes.
+ */
+int32_t nes_load_state(struct NesEmulator *emulator, const uint8_t *buffer, uintptr_t size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NES_EMULATOR_H */
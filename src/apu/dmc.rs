@@ -0,0 +1,385 @@
+use crate::cartridge::Region;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Output unit periods in CPU cycles, indexed by the low 4 bits of $4010.
+const NTSC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// The delta modulation channel, $4010-$4013. It plays 1-bit deltas from samples it fetches
+/// itself from $C000-$FFFF; the fetches are DMA reads serviced by the system, see
+/// `dma_request` and `fill_sample_buffer`.
+///  $4010: IL-- RRRR  IRQ enabled, loop, rate index
+///  $4011: -DDD DDDD  direct load of the output level
+///  $4012: AAAA AAAA  sample address, $C000 + A * 64
+///  $4013: LLLL LLLL  sample length, L * 16 + 1 bytes
+pub struct Dmc {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    irq: bool,
+    level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    pub fn new(region: Region) -> Self {
+        let rates = rates(region);
+        Dmc {
+            rates,
+            irq_enabled: false,
+            looping: false,
+            timer_period: rates[0],
+            timer: rates[0] - 1,
+            irq: false,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.rates = rates(region);
+    }
+
+    /// `register` is the address offset within the channel, 0-3.
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.looping = data & 0b0100_0000 != 0;
+                self.timer_period = self.rates[(data & 0b1111) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = data & 0b0111_1111,
+            2 => self.sample_address = 0xC000 + data as u16 * 64,
+            3 => self.sample_length = data as u16 * 16 + 1,
+            _ => {}
+        }
+    }
+
+    /// Bit 4 of $4015: stops the sample, or starts it if it had finished.
+    /// Also acknowledges the IRQ.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Whether sample bytes are left to fetch, as reported by $4015.
+    pub fn is_active(&self) -> bool {
+        return self.bytes_remaining > 0;
+    }
+
+    pub fn irq(&self) -> bool {
+        return self.irq;
+    }
+
+    /// The address the memory reader wants to fetch, when its buffer is empty.
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            return Some(self.current_address);
+        }
+        return None;
+    }
+
+    /// Completes the fetch asked for by `dma_request`.
+    pub fn fill_sample_buffer(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // the address wraps to $8000, not $0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            // the level moves by 2 and stays within 0-127
+            if self.shift_register & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Current level, 0-127.
+    pub fn output(&self) -> u8 {
+        return self.level;
+    }
+}
+
+fn rates(region: Region) -> &'static [u16; 16] {
+    match region {
+        Region::Pal => &PAL_RATES,
+        Region::Ntsc | Region::Multi | Region::Dendy => &NTSC_RATES,
+    }
+}
+
+impl SaveState for Dmc {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.irq_enabled);
+        w.bool(self.looping);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.bool(self.irq);
+        w.u8(self.level);
+        w.u16(self.sample_address);
+        w.u16(self.sample_length);
+        w.u16(self.current_address);
+        w.u16(self.bytes_remaining);
+        w.bool(self.sample_buffer.is_some());
+        w.u8(self.sample_buffer.unwrap_or(0));
+        w.u8(self.shift_register);
+        w.u8(self.bits_remaining);
+        w.bool(self.silence);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = r.bool()?;
+        self.looping = r.bool()?;
+        self.timer_period = r.u16()?;
+        if self.timer_period == 0 {
+            return Err(r.error("DMC timer period 0"));
+        }
+        self.timer = r.u16()?;
+        self.irq = r.bool()?;
+        self.level = r.u8()?;
+        if self.level > 127 {
+            return Err(r.error(&format!("DMC level {}", self.level)));
+        }
+        self.sample_address = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.current_address = r.u16()?;
+        self.bytes_remaining = r.u16()?;
+        let buffered = r.bool()?;
+        let sample = r.u8()?;
+        self.sample_buffer = buffered.then_some(sample);
+        self.shift_register = r.u8()?;
+        self.bits_remaining = r.u8()?;
+        if !(1..=8).contains(&self.bits_remaining) {
+            return Err(r.error(&format!("DMC with {} bits remaining", self.bits_remaining)));
+        }
+        self.silence = r.bool()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays `sample` at rate index 15, serving the DMA requests immediately.
+    fn play(dmc: &mut Dmc, sample: &[u8], cycles: usize) -> Vec<u8> {
+        let mut levels = vec![];
+        for _ in 0..cycles {
+            if let Some(addr) = dmc.dma_request() {
+                let offset = (addr - 0xC000) as usize % sample.len();
+                dmc.fill_sample_buffer(sample[offset]);
+            }
+            dmc.clock_timer();
+            levels.push(dmc.output());
+        }
+        return levels;
+    }
+
+    fn sample_dmc(control: u8, length: u8) -> Dmc {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write_register(0, control);
+        dmc.write_register(1, 64);
+        dmc.write_register(2, 0);
+        dmc.write_register(3, length);
+        dmc.set_enabled(true);
+        return dmc;
+    }
+
+    /// Loads a saved DMC with the bytes at `offset` overwritten.
+    fn load_with(offset: usize, bytes: &[u8]) -> Result<(), String> {
+        let mut w = StateWriter::new();
+        Dmc::new(Region::Ntsc).save(&mut w);
+        let mut state = w.into_bytes();
+        state[offset..offset + bytes.len()].copy_from_slice(bytes);
+        return Dmc::new(Region::Ntsc).load(&mut StateReader::new(*b"APU ", &state));
+    }
+
+    #[test]
+    fn test_load_rejects_impossible_values() {
+        assert_eq!(load_with(7, &[127]), Ok(()));
+        assert_eq!(
+            load_with(2, &[0, 0]),
+            Err("APU chunk: DMC timer period 0".to_string())
+        );
+        assert_eq!(
+            load_with(7, &[128]),
+            Err("APU chunk: DMC level 128".to_string())
+        );
+        assert_eq!(
+            load_with(19, &[0]),
+            Err("APU chunk: DMC with 0 bits remaining".to_string())
+        );
+        assert_eq!(
+            load_with(19, &[9]),
+            Err("APU chunk: DMC with 9 bits remaining".to_string())
+        );
+    }
+
+    #[test]
+    fn test_register_decoding() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write_register(2, 0x10);
+        dmc.write_register(3, 0x02);
+        dmc.set_enabled(true);
+
+        assert_eq!(dmc.dma_request(), Some(0xC400));
+        assert_eq!(dmc.bytes_remaining, 33);
+    }
+
+    #[test]
+    fn test_direct_load() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write_register(1, 0xFF);
+
+        assert_eq!(dmc.output(), 0x7F);
+    }
+
+    #[test]
+    fn test_rate_tables_per_region() {
+        let mut ntsc = Dmc::new(Region::Ntsc);
+        let mut pal = Dmc::new(Region::Pal);
+        ntsc.write_register(0, 0x0F);
+        pal.write_register(0, 0x0F);
+
+        assert_eq!(ntsc.timer_period, 54);
+        assert_eq!(pal.timer_period, 50);
+    }
+
+    #[test]
+    fn test_deltas_follow_sample_bits() {
+        // one byte: 4 ups then 4 downs
+        let mut dmc = sample_dmc(0x0F, 0);
+
+        let mut levels = play(&mut dmc, &[0x0F], 54 * 30);
+
+        levels.dedup();
+        assert_eq!(levels, vec![64, 66, 68, 70, 72, 70, 68, 66, 64]);
+    }
+
+    #[test]
+    fn test_level_is_clamped() {
+        let mut dmc = sample_dmc(0x4F, 0);
+        dmc.write_register(1, 126);
+
+        let levels = play(&mut dmc, &[0xFF], 54 * 24);
+
+        assert_eq!(levels.iter().max(), Some(&126));
+    }
+
+    #[test]
+    fn test_irq_at_end_of_sample() {
+        let mut dmc = sample_dmc(0x8F, 1);
+
+        play(&mut dmc, &[0], 54 * 8 * 16);
+        assert!(!dmc.irq());
+        assert!(dmc.is_active());
+
+        play(&mut dmc, &[0], 54 * 8 * 2);
+        assert!(dmc.irq());
+        assert!(!dmc.is_active());
+
+        dmc.set_enabled(false);
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn test_looping_sample_restarts_without_irq() {
+        let mut dmc = sample_dmc(0xCF, 0);
+
+        play(&mut dmc, &[0], 54 * 8 * 4);
+
+        assert!(!dmc.irq());
+        assert!(dmc.is_active());
+    }
+
+    #[test]
+    fn test_disabling_stops_fetches() {
+        let mut dmc = sample_dmc(0x0F, 4);
+
+        dmc.set_enabled(false);
+
+        assert_eq!(dmc.dma_request(), None);
+        assert!(!dmc.is_active());
+    }
+
+    #[test]
+    fn test_address_wraps_to_8000() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write_register(2, 0xFF);
+        dmc.write_register(3, 0xFF);
+        dmc.set_enabled(true);
+        for _ in 0..0x40 {
+            dmc.fill_sample_buffer(0);
+            dmc.sample_buffer = None;
+        }
+
+        assert_eq!(dmc.dma_request(), Some(0x8000));
+    }
+}
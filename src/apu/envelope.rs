@@ -0,0 +1,129 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Volume envelope shared by the pulse and noise channels: either a constant volume or a
+/// sawtooth decaying from 15, clocked by the frame counter's quarter frames.
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    /// Constant volume, or the divider period of the decay.
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Takes the low 6 bits of $4000/$4004/$400C: --LC VVVV.
+    pub fn write_control(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant_volume = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+
+    /// Writing the length counter load register restarts the envelope.
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = self.volume;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looping {
+            self.decay = 15;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            return self.volume;
+        }
+        return self.decay;
+    }
+}
+
+impl SaveState for Envelope {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.start);
+        w.bool(self.looping);
+        w.bool(self.constant_volume);
+        w.u8(self.volume);
+        w.u8(self.divider);
+        w.u8(self.decay);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.start = r.bool()?;
+        self.looping = r.bool()?;
+        self.constant_volume = r.bool()?;
+        self.volume = r.u8()?;
+        self.divider = r.u8()?;
+        self.decay = r.u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_volume() {
+        let mut envelope = Envelope::default();
+        envelope.write_control(0b0001_0111);
+        envelope.restart();
+        for _ in 0..20 {
+            envelope.clock();
+        }
+
+        assert_eq!(envelope.output(), 7);
+    }
+
+    #[test]
+    fn test_decay_steps_every_period_plus_one_clocks() {
+        let mut envelope = Envelope::default();
+        envelope.write_control(0b0000_0010);
+        envelope.restart();
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        for _ in 0..3 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 14);
+        for _ in 0..3 * 14 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        for _ in 0..30 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+    }
+
+    #[test]
+    fn test_looping_decay_restarts_at_15() {
+        let mut envelope = Envelope::default();
+        envelope.write_control(0b0010_0000);
+        envelope.restart();
+
+        for _ in 0..16 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+}
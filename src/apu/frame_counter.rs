@@ -0,0 +1,274 @@
+use crate::cartridge::Region;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// CPU cycles after a reset at which the sequencer steps, and the length of a sequence.
+struct Timings {
+    steps: [u32; 4],
+    four_step_period: u32,
+    five_step_last: u32,
+    five_step_period: u32,
+}
+
+const NTSC_TIMINGS: Timings = Timings {
+    steps: [7457, 14913, 22371, 29829],
+    four_step_period: 29830,
+    five_step_last: 37281,
+    five_step_period: 37282,
+};
+
+const PAL_TIMINGS: Timings = Timings {
+    steps: [8313, 16627, 24939, 33253],
+    four_step_period: 33254,
+    five_step_last: 41565,
+    five_step_period: 41566,
+};
+
+/// What the sequencer clocks on a given cycle. A half frame clocks the quarter frame units too.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameStep {
+    None,
+    Quarter,
+    Half,
+}
+
+/// The frame sequencer at $4017, clocking envelopes, sweeps and length counters.
+///  $4017: MI-- ----  mode (0: 4-step, 1: 5-step), IRQ inhibit
+/// In 4-step mode it raises the frame IRQ at the end of every sequence unless inhibited.
+pub struct FrameCounter {
+    timings: &'static Timings,
+    five_step: bool,
+    irq_inhibit: bool,
+    irq: bool,
+    cycle: u32,
+    /// A $4017 write takes effect 3 or 4 cycles later: (cycles left, value).
+    pending_write: Option<(u8, u8)>,
+}
+
+impl FrameCounter {
+    pub fn new(region: Region) -> Self {
+        FrameCounter {
+            timings: timings(region),
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            pending_write: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.timings = timings(region);
+    }
+
+    /// `odd_cycle` is the parity of the CPU cycle of the write: written between APU cycles the
+    /// reset is delayed by 4 CPU cycles, during one by 3.
+    pub fn write(&mut self, data: u8, odd_cycle: bool) {
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        let delay = if odd_cycle { 4 } else { 3 };
+        self.pending_write = Some((delay, data));
+    }
+
+    pub fn irq(&self) -> bool {
+        return self.irq;
+    }
+
+    /// Reading $4015 acknowledges the frame IRQ.
+    pub fn clear_irq(&mut self) {
+        self.irq = false;
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock(&mut self) -> FrameStep {
+        if let Some((delay, data)) = self.pending_write {
+            if delay > 1 {
+                self.pending_write = Some((delay - 1, data));
+            } else {
+                self.pending_write = None;
+                self.five_step = data & 0b1000_0000 != 0;
+                self.cycle = 0;
+                // entering 5-step mode clocks everything straight away
+                if self.five_step {
+                    return FrameStep::Half;
+                }
+                return FrameStep::None;
+            }
+        }
+
+        self.cycle += 1;
+        let steps = self.timings.steps;
+
+        if self.five_step {
+            if self.cycle == self.timings.five_step_period {
+                self.cycle = 0;
+            }
+            return match self.cycle {
+                c if c == steps[0] || c == steps[2] => FrameStep::Quarter,
+                c if c == steps[1] || c == self.timings.five_step_last => FrameStep::Half,
+                _ => FrameStep::None,
+            };
+        }
+
+        // the flag is set on the 3 cycles around the last step
+        let last = steps[3];
+        let period = self.timings.four_step_period;
+        if (self.cycle == last - 1 || self.cycle == last || self.cycle == period)
+            && !self.irq_inhibit
+        {
+            self.irq = true;
+        }
+        if self.cycle == period {
+            self.cycle = 0;
+        }
+        return match self.cycle {
+            c if c == steps[0] || c == steps[2] => FrameStep::Quarter,
+            c if c == steps[1] || c == last => FrameStep::Half,
+            _ => FrameStep::None,
+        };
+    }
+}
+
+fn timings(region: Region) -> &'static Timings {
+    match region {
+        Region::Pal => &PAL_TIMINGS,
+        Region::Ntsc | Region::Multi | Region::Dendy => &NTSC_TIMINGS,
+    }
+}
+
+impl SaveState for FrameCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.five_step);
+        w.bool(self.irq_inhibit);
+        w.bool(self.irq);
+        w.u32(self.cycle);
+        w.bool(self.pending_write.is_some());
+        let (delay, data) = self.pending_write.unwrap_or((0, 0));
+        w.u8(delay);
+        w.u8(data);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.five_step = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.irq = r.bool()?;
+        self.cycle = r.u32()?;
+        let pending = r.bool()?;
+        let write = (r.u8()?, r.u8()?);
+        self.pending_write = pending.then_some(write);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cycles (counted from 1) at which something is clocked.
+    fn run(counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, FrameStep)> {
+        let mut clocks = vec![];
+        for cycle in 1..=cycles {
+            let step = counter.clock();
+            if step != FrameStep::None {
+                clocks.push((cycle, step));
+            }
+        }
+        return clocks;
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut counter = FrameCounter::new(Region::Ntsc);
+
+        let clocks = run(&mut counter, 29830 + 7457);
+
+        assert_eq!(
+            clocks,
+            vec![
+                (7457, FrameStep::Quarter),
+                (14913, FrameStep::Half),
+                (22371, FrameStep::Quarter),
+                (29829, FrameStep::Half),
+                (29830 + 7457, FrameStep::Quarter),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut counter = FrameCounter::new(Region::Ntsc);
+        counter.write(0x80, false);
+
+        let clocks = run(&mut counter, 3 + 37282);
+
+        assert_eq!(
+            clocks,
+            vec![
+                (3, FrameStep::Half),
+                (3 + 7457, FrameStep::Quarter),
+                (3 + 14913, FrameStep::Half),
+                (3 + 22371, FrameStep::Quarter),
+                (3 + 37281, FrameStep::Half),
+            ]
+        );
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_pal_timings() {
+        let mut counter = FrameCounter::new(Region::Pal);
+
+        let clocks = run(&mut counter, 33254);
+
+        let cycles: Vec<u32> = clocks.iter().map(|(cycle, _)| *cycle).collect();
+        assert_eq!(cycles, vec![8313, 16627, 24939, 33253]);
+        assert!(counter.irq());
+    }
+
+    #[test]
+    fn test_irq_at_end_of_four_step_sequence() {
+        let mut counter = FrameCounter::new(Region::Ntsc);
+
+        run(&mut counter, 29827);
+        assert!(!counter.irq());
+        run(&mut counter, 1);
+        assert!(counter.irq());
+
+        // set again on the following two cycles even if acknowledged
+        counter.clear_irq();
+        run(&mut counter, 1);
+        assert!(counter.irq());
+        counter.clear_irq();
+        run(&mut counter, 1);
+        assert!(counter.irq());
+        counter.clear_irq();
+        run(&mut counter, 1);
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut counter = FrameCounter::new(Region::Ntsc);
+        run(&mut counter, 29830);
+        assert!(counter.irq());
+
+        counter.write(0x40, false);
+        assert!(!counter.irq());
+        run(&mut counter, 3 + 29830);
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_write_delay_depends_on_cycle_parity() {
+        for (odd_cycle, delay) in [(false, 3), (true, 4)] {
+            let mut counter = FrameCounter::new(Region::Ntsc);
+            run(&mut counter, 100);
+            counter.write(0x00, odd_cycle);
+
+            let clocks = run(&mut counter, 7457 + 4);
+
+            assert_eq!(clocks, vec![(delay + 7457, FrameStep::Quarter)]);
+        }
+    }
+}
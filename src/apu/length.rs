@@ -0,0 +1,110 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Length counter loads, indexed by bits 7-3 of $4003/$4007/$400B/$400F.
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, //
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel once it reaches zero, clocked by the frame counter's half frames.
+#[derive(Default)]
+pub struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    /// Set through $4015, disabling the channel clears the counter immediately.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Loads the counter from the top 5 bits of a channel's last register, if enabled.
+    pub fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.counter > 0;
+    }
+
+    pub fn counter(&self) -> u8 {
+        return self.counter;
+    }
+}
+
+impl SaveState for LengthCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.halted);
+        w.u8(self.counter);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.enabled = r.bool()?;
+        self.halted = r.bool()?;
+        self.counter = r.u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_only_when_enabled() {
+        let mut length = LengthCounter::default();
+        length.load(0b0000_1000);
+        assert_eq!(length.counter(), 0);
+
+        length.set_enabled(true);
+        length.load(0b0000_1000);
+        assert_eq!(length.counter(), 254);
+    }
+
+    #[test]
+    fn test_clock_counts_down_unless_halted() {
+        let mut length = LengthCounter::default();
+        length.set_enabled(true);
+        length.load(0b0001_1000);
+        assert_eq!(length.counter(), 2);
+
+        length.set_halted(true);
+        length.clock();
+        assert_eq!(length.counter(), 2);
+
+        length.set_halted(false);
+        length.clock();
+        length.clock();
+        length.clock();
+        assert!(!length.is_active());
+    }
+
+    #[test]
+    fn test_disabling_clears_counter() {
+        let mut length = LengthCounter::default();
+        length.set_enabled(true);
+        length.load(0);
+
+        length.set_enabled(false);
+
+        assert!(!length.is_active());
+    }
+}
@@ -0,0 +1,66 @@
+/// The 2A03's nonlinear DAC. Pulses share one resistor network and triangle, noise and DMC
+/// another, so each group's level is a lookup on the sum of its channels.
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, level) in pulse_table.iter_mut().enumerate().skip(1) {
+            *level = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, level) in tnd_table.iter_mut().enumerate().skip(1) {
+            *level = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Mixer {
+            pulse_table,
+            tnd_table,
+        }
+    }
+
+    /// Output level 0.0-1.0 from the channel levels: pulses and noise 0-15, DMC 0-127.
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse = self.pulse_table[(pulse1 + pulse2) as usize];
+        let tnd_index = 3 * triangle as usize + 2 * noise as usize + dmc as usize;
+        return pulse + self.tnd_table[tnd_index];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silence_is_zero() {
+        assert_eq!(Mixer::new().mix(0, 0, 0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_full_scale() {
+        let mixer = Mixer::new();
+
+        assert!((mixer.mix(15, 15, 0, 0, 0) - 0.2575).abs() < 0.0005);
+        assert!((mixer.mix(0, 0, 15, 15, 127) - 0.7424).abs() < 0.0005);
+        assert!(mixer.mix(15, 15, 15, 15, 127) <= 1.0);
+    }
+
+    #[test]
+    fn test_pulses_are_not_additive() {
+        let mixer = Mixer::new();
+
+        let one = mixer.mix(15, 0, 0, 0, 0);
+        let two = mixer.mix(15, 15, 0, 0, 0);
+
+        assert!(two < 2.0 * one);
+        assert_eq!(mixer.mix(15, 0, 0, 0, 0), mixer.mix(0, 15, 0, 0, 0));
+    }
+}
@@ -0,0 +1,371 @@
+pub mod dmc;
+pub mod envelope;
+pub mod frame_counter;
+pub mod length;
+pub mod mixer;
+pub mod noise;
+pub mod pulse;
+pub mod triangle;
+
+use crate::cartridge::Region;
+use crate::state::{SaveState, StateReader, StateWriter};
+use dmc::Dmc;
+use frame_counter::{FrameCounter, FrameStep};
+use mixer::Mixer;
+use noise::Noise;
+use pulse::{Pulse, PulseChannel};
+use triangle::Triangle;
+
+/// The 2A03's audio unit, clocked once per CPU cycle.
+///  $4000-$4003: pulse 1
+///  $4004-$4007: pulse 2
+///  $4008-$400B: triangle
+///  $400C-$400F: noise
+///  $4010-$4013: DMC
+///  $4015: channel enables (write) / length counter and IRQ status (read)
+///  $4017: frame counter
+pub struct Apu {
+    pub cycles: u64,
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    mixer: Mixer,
+    samples: Vec<f32>,
+}
+
+/// CPU cycles per second, which is also the APU's sample rate.
+pub fn cpu_clock_rate(region: Region) -> f64 {
+    match region {
+        Region::Ntsc | Region::Multi => 21_477_272.0 / 12.0,
+        Region::Pal => 26_601_712.0 / 16.0,
+        Region::Dendy => 26_601_712.0 / 15.0,
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            cycles: 0,
+            pulse1: Pulse::new(PulseChannel::One),
+            pulse2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(Region::Ntsc),
+            dmc: Dmc::new(Region::Ntsc),
+            frame_counter: FrameCounter::new(Region::Ntsc),
+            mixer: Mixer::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.frame_counter.set_region(region);
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write_register(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write_register(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write_register(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write_register(addr - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write_register(addr - 0x4010, data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0b0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0100 != 0);
+                self.noise.length.set_enabled(data & 0b1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            0x4017 => {
+                let odd_cycle = !self.cycles.is_multiple_of(2);
+                self.frame_counter.write(data, odd_cycle);
+            }
+            _ => {}
+        }
+    }
+
+    /// $4015: a bit per channel whose length counter is non-zero (for the DMC: with sample
+    /// bytes left), the frame IRQ in bit 6 and the DMC IRQ in bit 7.
+    /// Reading acknowledges the frame IRQ.
+    pub fn read_status(&mut self) -> u8 {
+        let lengths = [
+            self.pulse1.length.is_active(),
+            self.pulse2.length.is_active(),
+            self.triangle.length.is_active(),
+            self.noise.length.is_active(),
+            self.dmc.is_active(),
+        ];
+        let mut status = 0;
+        for (bit, active) in lengths.iter().enumerate() {
+            if *active {
+                status |= 1 << bit;
+            }
+        }
+        if self.frame_counter.irq() {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq() {
+            status |= 0b1000_0000;
+        }
+        self.frame_counter.clear_irq();
+        return status;
+    }
+
+    /// Advances one CPU cycle and records the output level for that cycle.
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        match self.frame_counter.clock() {
+            FrameStep::None => {}
+            FrameStep::Quarter => self.clock_quarter_frame(),
+            FrameStep::Half => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        // pulse timers run at half the CPU clock
+        if self.cycles.is_multiple_of(2) {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        let output = self.output();
+        self.samples.push(output);
+    }
+
+    /// Envelopes and the triangle's linear counter, clocked four times per frame by the frame
+    /// counter.
+    pub fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    /// Length counters and sweeps, clocked twice per frame by the frame counter.
+    pub fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    /// State of the APU's IRQ output.
+    pub fn irq(&self) -> bool {
+        return self.frame_counter.irq() || self.dmc.irq();
+    }
+
+    /// Address of the sample byte the DMC needs, the system reads it and stalls the CPU.
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        return self.dmc.dma_request();
+    }
+
+    pub fn dmc_dma_complete(&mut self, data: u8) {
+        self.dmc.fill_sample_buffer(data);
+    }
+
+    /// Returns the samples produced since the last call, one per CPU cycle at
+    /// `cpu_clock_rate`; `audio::AudioOutput` turns them into a host stream.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        return std::mem::take(&mut self.samples);
+    }
+
+    /// Mixed output level, 0.0-1.0.
+    fn output(&self) -> f32 {
+        return self.mixer.mix(
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        );
+    }
+}
+
+/// The channels and the clock; samples not yet taken are dropped on load.
+impl SaveState for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        w.u64(self.cycles);
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        self.frame_counter.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.cycles = r.u64()?;
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.frame_counter.load(r)?;
+        self.samples.clear();
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_sample_per_cycle() {
+        let mut apu = Apu::new();
+        for _ in 0..10 {
+            apu.tick();
+        }
+
+        assert_eq!(apu.take_samples().len(), 10);
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn test_status_reflects_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b11);
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b01);
+
+        apu.write_register(0x4007, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b11);
+
+        apu.write_register(0x4015, 0b10);
+        assert_eq!(apu.read_status(), 0b10);
+    }
+
+    #[test]
+    fn test_length_counter_needs_channel_enabled() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4003, 0b0000_1000);
+
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_status_covers_triangle_and_noise() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b1100);
+        apu.write_register(0x400B, 0b0000_1000);
+        apu.write_register(0x400F, 0b0000_1000);
+
+        assert_eq!(apu.read_status(), 0b1100);
+    }
+
+    #[test]
+    fn test_dmc_status_and_irq() {
+        let mut apu = Apu::new();
+        // IRQ enabled, 1 byte sample
+        apu.write_register(0x4010, 0x8F);
+        apu.write_register(0x4013, 0);
+        apu.write_register(0x4015, 0b1_0000);
+        assert_eq!(apu.read_status(), 0b1_0000);
+        assert_eq!(apu.dmc_dma_request(), Some(0xC000));
+
+        apu.dmc_dma_complete(0x55);
+
+        assert_eq!(apu.dmc_dma_request(), None);
+        assert_eq!(apu.read_status(), 0b1000_0000);
+        assert!(apu.irq());
+        apu.write_register(0x4015, 0);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_frame_irq_is_read_and_cleared_through_status() {
+        let mut apu = Apu::new();
+        for _ in 0..29830 {
+            apu.tick();
+        }
+        assert!(apu.irq());
+
+        assert_eq!(apu.read_status(), 0b0100_0000);
+        assert!(!apu.irq());
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_frame_counter_clocks_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        // length index 3: 2 half frames
+        apu.write_register(0x4003, 0b0001_1000);
+
+        for _ in 0..14913 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_status() & 0b01, 0b01);
+        for _ in 0..29829 - 14913 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_status() & 0b01, 0);
+    }
+
+    #[test]
+    fn test_triangle_output_per_cpu_cycle() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0100);
+        // linear counter 127, period 3: 4 CPU cycles per step
+        apu.write_register(0x4008, 0x7F);
+        apu.write_register(0x400A, 3);
+        apu.write_register(0x400B, 0);
+        apu.clock_quarter_frame();
+
+        let mut levels = vec![];
+        for _ in 0..4 * 32 {
+            apu.tick();
+            levels.push(apu.triangle.output());
+        }
+
+        assert_eq!(levels[0..4], [14; 4]);
+        assert_eq!(levels[4..8], [13; 4]);
+        assert_eq!(levels.iter().min(), Some(&0));
+        assert_eq!(levels[levels.len() - 1], 15);
+    }
+
+    #[test]
+    fn test_pulse_output_per_cpu_cycle() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        // 50% duty, constant volume 10, period 8: 18 CPU cycles per step
+        apu.write_register(0x4000, 0b1011_1010);
+        apu.write_register(0x4002, 8);
+        apu.write_register(0x4003, 0);
+
+        let mut levels = vec![];
+        for _ in 0..18 * 16 {
+            apu.tick();
+            levels.push(apu.pulse1.output());
+        }
+
+        let high = levels.iter().filter(|&&level| level > 0).count();
+        assert_eq!(high, levels.len() / 2);
+        assert!(levels.iter().all(|&level| level == 0 || level == 10));
+    }
+
+    #[test]
+    fn test_half_frame_clocks_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        // length index 3: 2 half frames
+        apu.write_register(0x4003, 0b0001_1000);
+
+        apu.clock_half_frame();
+        assert_eq!(apu.read_status(), 0b01);
+        apu.clock_half_frame();
+        assert_eq!(apu.read_status(), 0);
+    }
+}
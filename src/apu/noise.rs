@@ -0,0 +1,219 @@
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+use crate::cartridge::Region;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Timer periods in CPU cycles, indexed by the low 4 bits of $400E.
+const NTSC_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// The noise channel, $400C-$400F, a pseudo-random bit stream from a 15-bit LFSR.
+///  $400C: --LC VVVV  length halt / envelope loop, constant volume, volume / envelope period
+///  $400E: M--- PPPP  mode (short sequence), period index
+///  $400F: LLLL L---  length counter load
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    periods: &'static [u16; 16],
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Self {
+        Noise {
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            periods: periods(region),
+            short_mode: false,
+            timer_period: NTSC_PERIODS[0],
+            timer: 0,
+            shift_register: 1,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = periods(region);
+    }
+
+    /// `register` is the address offset within the channel, 0-3 ($400D is unused).
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length.set_halted(data & 0b0010_0000 != 0);
+                self.envelope.write_control(data);
+            }
+            2 => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.timer_period = self.periods[(data & 0b1111) as usize];
+            }
+            3 => {
+                self.length.load(data);
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        // short mode taps bit 6 instead of bit 1, giving a 93-step sequence
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current level, 0-15.
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 != 0 || !self.length.is_active() {
+            return 0;
+        }
+        return self.envelope.output();
+    }
+}
+
+fn periods(region: Region) -> &'static [u16; 16] {
+    match region {
+        Region::Pal => &PAL_PERIODS,
+        Region::Ntsc | Region::Multi | Region::Dendy => &NTSC_PERIODS,
+    }
+}
+
+impl SaveState for Noise {
+    fn save(&self, w: &mut StateWriter) {
+        self.envelope.save(w);
+        self.length.save(w);
+        w.bool(self.short_mode);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.u16(self.shift_register);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.envelope.load(r)?;
+        SaveState::load(&mut self.length, r)?;
+        self.short_mode = r.bool()?;
+        self.timer_period = r.u16()?;
+        if self.timer_period == 0 {
+            return Err(r.error("noise timer period 0"));
+        }
+        self.timer = r.u16()?;
+        self.shift_register = r.u16()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing_noise(region: Region, mode_and_period: u8) -> Noise {
+        let mut noise = Noise::new(region);
+        noise.length.set_enabled(true);
+        noise.write_register(0, 0b0001_1111);
+        noise.write_register(2, mode_and_period);
+        noise.write_register(3, 0);
+        return noise;
+    }
+
+    /// Number of LFSR steps until the register returns to its starting value.
+    fn sequence_length(noise: &mut Noise) -> usize {
+        let start = noise.shift_register;
+        let period = noise.timer_period as usize;
+        for steps in 1..=0x8000 {
+            for _ in 0..period {
+                noise.clock_timer();
+            }
+            if noise.shift_register == start {
+                return steps;
+            }
+        }
+        panic!("the sequence does not repeat");
+    }
+
+    #[test]
+    fn test_long_mode_repeats_every_32767_steps() {
+        let mut noise = playing_noise(Region::Ntsc, 0);
+        // step once to get off the initial state
+        for _ in 0..4 {
+            noise.clock_timer();
+        }
+
+        assert_eq!(sequence_length(&mut noise), 32767);
+    }
+
+    #[test]
+    fn test_short_mode_repeats_every_93_steps() {
+        let mut noise = playing_noise(Region::Ntsc, 0b1000_0000);
+        for _ in 0..4 * 10 {
+            noise.clock_timer();
+        }
+
+        assert_eq!(sequence_length(&mut noise), 93);
+    }
+
+    #[test]
+    fn test_lfsr_feedback() {
+        let mut noise = playing_noise(Region::Ntsc, 0);
+
+        noise.clock_timer();
+
+        // 1 xor 0 shifted into bit 14
+        assert_eq!(noise.shift_register, 0x4000);
+        assert_eq!(noise.output(), 15);
+    }
+
+    #[test]
+    fn test_period_tables_per_region() {
+        let ntsc = playing_noise(Region::Ntsc, 0x0F);
+        let pal = playing_noise(Region::Pal, 0x0F);
+
+        assert_eq!(ntsc.timer_period, 4068);
+        assert_eq!(pal.timer_period, 3778);
+    }
+
+    #[test]
+    fn test_load_rejects_a_zero_period() {
+        let mut w = StateWriter::new();
+        Noise::new(Region::Ntsc).save(&mut w);
+        let mut state = w.into_bytes();
+        let period = state.len() - 6;
+        state[period..period + 2].fill(0);
+
+        let mut noise = Noise::new(Region::Ntsc);
+        assert_eq!(
+            noise.load(&mut StateReader::new(*b"APU ", &state)),
+            Err("APU chunk: noise timer period 0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_silent_without_length() {
+        let mut noise = playing_noise(Region::Ntsc, 0);
+        noise.clock_timer();
+
+        noise.length.set_enabled(false);
+
+        assert_eq!(noise.output(), 0);
+    }
+}
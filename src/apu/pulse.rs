@@ -0,0 +1,334 @@
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+/// The two pulse channels differ only in how the sweep unit negates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PulseChannel {
+    /// Negates with ones' complement: the change is subtracted plus one more.
+    One,
+    /// Negates with two's complement.
+    Two,
+}
+
+/// A square wave channel, $4000-$4003 or $4004-$4007.
+///  $4000: DDLC VVVV  duty, length halt / envelope loop, constant volume, volume / envelope period
+///  $4001: EPPP NSSS  sweep enabled, period, negate, shift
+///  $4002: TTTT TTTT  timer low bits
+///  $4003: LLLL LTTT  length counter load, timer high bits
+pub struct Pulse {
+    channel: PulseChannel,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    duty: usize,
+    sequence_step: usize,
+    timer_period: u16,
+    timer: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Pulse {
+            channel,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    /// `register` is the address offset within the channel, 0-3.
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = (data >> 6) as usize;
+                self.length.set_halted(data & 0b0010_0000 != 0);
+                self.envelope.write_control(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0b1000_0000 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0b0000_1000 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data);
+                self.envelope.restart();
+                self.sequence_step = 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Clocked every APU cycle (every other CPU cycle).
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+        self.clock_sweep();
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0
+            && self.sweep_enabled
+            && self.sweep_shift > 0
+            && !self.is_sweep_muting()
+        {
+            self.timer_period = self.sweep_target();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// The period the sweep unit is heading to, computed continuously.
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            return self.timer_period + change;
+        }
+        return match self.channel {
+            PulseChannel::One => self.timer_period.saturating_sub(change + 1),
+            PulseChannel::Two => self.timer_period.saturating_sub(change),
+        };
+    }
+
+    /// Periods under 8 or targets over $7FF silence the channel, even with the sweep disabled.
+    fn is_sweep_muting(&self) -> bool {
+        return self.timer_period < 8 || self.sweep_target() > 0x7FF;
+    }
+
+    pub fn timer_period(&self) -> u16 {
+        return self.timer_period;
+    }
+
+    /// Current level, 0-15.
+    pub fn output(&self) -> u8 {
+        if DUTY_SEQUENCES[self.duty][self.sequence_step] == 0
+            || !self.length.is_active()
+            || self.is_sweep_muting()
+        {
+            return 0;
+        }
+        return self.envelope.output();
+    }
+}
+
+impl SaveState for Pulse {
+    fn save(&self, w: &mut StateWriter) {
+        self.envelope.save(w);
+        self.length.save(w);
+        w.u8(self.duty as u8);
+        w.u8(self.sequence_step as u8);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_period);
+        w.bool(self.sweep_negate);
+        w.u8(self.sweep_shift);
+        w.u8(self.sweep_divider);
+        w.bool(self.sweep_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.envelope.load(r)?;
+        SaveState::load(&mut self.length, r)?;
+        self.duty = (r.u8()? & 0x03) as usize;
+        self.sequence_step = (r.u8()? & 0x07) as usize;
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_period = r.u8()?;
+        self.sweep_negate = r.bool()?;
+        self.sweep_shift = r.u8()?;
+        self.sweep_divider = r.u8()?;
+        self.sweep_reload = r.bool()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_pulse(channel: PulseChannel) -> Pulse {
+        let mut pulse = Pulse::new(channel);
+        pulse.length.set_enabled(true);
+        return pulse;
+    }
+
+    /// Output over `steps` sequencer steps with the given timer period.
+    fn waveform(pulse: &mut Pulse, period: u16, steps: usize) -> Vec<u8> {
+        let mut levels = vec![];
+        for _ in 0..steps {
+            for _ in 0..=period {
+                pulse.clock_timer();
+            }
+            levels.push(pulse.output());
+        }
+        return levels;
+    }
+
+    #[test]
+    fn test_duty_cycles() {
+        let expected = [
+            [1, 0, 0, 0, 0, 0, 0, 0],
+            [1, 1, 0, 0, 0, 0, 0, 0],
+            [1, 1, 1, 1, 0, 0, 0, 0],
+            [0, 0, 1, 1, 1, 1, 1, 1],
+        ];
+        for (duty, expected) in expected.iter().enumerate() {
+            let mut pulse = enabled_pulse(PulseChannel::One);
+            pulse.write_register(0, (duty as u8) << 6 | 0b0001_0001);
+            pulse.write_register(2, 8);
+            pulse.write_register(3, 0);
+
+            let levels = waveform(&mut pulse, 8, 8);
+
+            assert_eq!(levels, expected.to_vec(), "duty {}", duty);
+        }
+    }
+
+    #[test]
+    fn test_silenced_when_length_counter_expires() {
+        let mut pulse = enabled_pulse(PulseChannel::One);
+        pulse.write_register(0, 0b1011_1111);
+        pulse.write_register(2, 100);
+        // length index 3: 2 half frames
+        pulse.write_register(3, 0b0001_1000);
+        pulse.write_register(0, 0b1001_1111);
+        pulse.clock_timer();
+        assert_eq!(pulse.output(), 15);
+
+        pulse.clock_half_frame();
+        assert_eq!(pulse.output(), 15);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn test_low_periods_are_muted() {
+        let mut pulse = enabled_pulse(PulseChannel::One);
+        pulse.write_register(0, 0b1011_1111);
+        pulse.write_register(2, 7);
+        pulse.write_register(3, 0);
+        pulse.clock_timer();
+
+        assert_eq!(pulse.output(), 0);
+        pulse.write_register(2, 8);
+        assert_eq!(pulse.output(), 15);
+    }
+
+    #[test]
+    fn test_sweep_target_overflow_mutes_even_when_disabled() {
+        let mut pulse = enabled_pulse(PulseChannel::Two);
+        pulse.write_register(0, 0b1011_1111);
+        pulse.write_register(1, 0b0000_0001);
+        pulse.write_register(2, 0xFF);
+        pulse.write_register(3, 0b0000_0101);
+
+        // $5FF + $2FF > $7FF
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn test_sweep_adds_to_period() {
+        let mut pulse = enabled_pulse(PulseChannel::One);
+        // enabled, period 0, shift 1
+        pulse.write_register(1, 0b1000_0001);
+        pulse.write_register(2, 0x00);
+        pulse.write_register(3, 0x01);
+
+        pulse.clock_half_frame();
+
+        assert_eq!(pulse.timer_period(), 0x180);
+    }
+
+    #[test]
+    fn test_sweep_negate_differs_between_channels() {
+        let mut periods = vec![];
+        for channel in [PulseChannel::One, PulseChannel::Two] {
+            let mut pulse = enabled_pulse(channel);
+            // enabled, period 0, negate, shift 2
+            pulse.write_register(1, 0b1000_1010);
+            pulse.write_register(2, 0x00);
+            pulse.write_register(3, 0x01);
+            pulse.clock_half_frame();
+            periods.push(pulse.timer_period());
+        }
+
+        assert_eq!(periods, vec![0x100 - 0x40 - 1, 0x100 - 0x40]);
+    }
+
+    #[test]
+    fn test_sweep_divider_period() {
+        let mut pulse = enabled_pulse(PulseChannel::Two);
+        // enabled, period 2, shift 3
+        pulse.write_register(1, 0b1010_0011);
+        pulse.write_register(2, 0x00);
+        pulse.write_register(3, 0x01);
+
+        let mut periods = vec![];
+        for _ in 0..4 {
+            pulse.clock_half_frame();
+            periods.push(pulse.timer_period());
+        }
+
+        // the divider starts expired, then reloads and expires every third clock
+        assert_eq!(periods, vec![0x120, 0x120, 0x120, 0x144]);
+    }
+
+    #[test]
+    fn test_envelope_decays_with_quarter_frames() {
+        let mut pulse = enabled_pulse(PulseChannel::One);
+        // 50% duty, envelope period 0
+        pulse.write_register(0, 0b1000_0000);
+        pulse.write_register(2, 100);
+        pulse.write_register(3, 0);
+        pulse.clock_timer();
+
+        pulse.clock_quarter_frame();
+        assert_eq!(pulse.output(), 15);
+        pulse.clock_quarter_frame();
+        assert_eq!(pulse.output(), 14);
+    }
+}
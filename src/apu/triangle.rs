@@ -0,0 +1,199 @@
+use super::length::LengthCounter;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, //
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// The triangle channel, $4008-$400B.
+///  $4008: CRRR RRRR  length halt / linear counter control, linear counter reload value
+///  $400A: TTTT TTTT  timer low bits
+///  $400B: LLLL LTTT  length counter load, timer high bits
+pub struct Triangle {
+    pub length: LengthCounter,
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    sequence_step: usize,
+    timer_period: u16,
+    timer: u16,
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            length: LengthCounter::default(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+        }
+    }
+
+    /// `register` is the address offset within the channel, 0-3 ($4009 is unused).
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0b1000_0000 != 0;
+                self.length.set_halted(self.control);
+                self.linear_reload_value = data & 0b0111_1111;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Clocked every CPU cycle, the sequencer only moves while both counters are non-zero.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period;
+        // periods of 0 and 1 would play at ultrasonic frequencies, which the hardware averages
+        // to a constant level: holding the sequencer still gives the same result without pops
+        if self.linear_counter > 0 && self.length.is_active() && self.timer_period >= 2 {
+            self.sequence_step = (self.sequence_step + 1) % SEQUENCE.len();
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current level, 0-15. A stopped triangle keeps outputting its last step.
+    pub fn output(&self) -> u8 {
+        return SEQUENCE[self.sequence_step];
+    }
+}
+
+impl SaveState for Triangle {
+    fn save(&self, w: &mut StateWriter) {
+        self.length.save(w);
+        w.bool(self.control);
+        w.u8(self.linear_reload_value);
+        w.u8(self.linear_counter);
+        w.bool(self.linear_reload);
+        w.u8(self.sequence_step as u8);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        SaveState::load(&mut self.length, r)?;
+        self.control = r.bool()?;
+        self.linear_reload_value = r.u8()?;
+        self.linear_counter = r.u8()?;
+        self.linear_reload = r.bool()?;
+        self.sequence_step = (r.u8()? & 0x1F) as usize;
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing_triangle(period: u16, linear: u8) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write_register(0, linear);
+        triangle.write_register(2, period as u8);
+        triangle.write_register(3, (period >> 8) as u8);
+        triangle.clock_quarter_frame();
+        return triangle;
+    }
+
+    fn levels(triangle: &mut Triangle, period: u16, steps: usize) -> Vec<u8> {
+        let mut levels = vec![];
+        for _ in 0..steps {
+            for _ in 0..=period {
+                triangle.clock_timer();
+            }
+            levels.push(triangle.output());
+        }
+        return levels;
+    }
+
+    #[test]
+    fn test_32_step_sequence() {
+        let mut triangle = playing_triangle(10, 0x7F);
+
+        let levels = levels(&mut triangle, 10, 32);
+
+        let mut expected: Vec<u8> = SEQUENCE[1..].to_vec();
+        expected.push(SEQUENCE[0]);
+        assert_eq!(levels, expected);
+    }
+
+    #[test]
+    fn test_linear_counter_stops_sequencer() {
+        let mut triangle = playing_triangle(10, 2);
+
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        let before = triangle.output();
+        let levels = levels(&mut triangle, 10, 4);
+
+        assert!(levels.iter().all(|&level| level == before));
+    }
+
+    #[test]
+    fn test_control_flag_keeps_reloading_linear_counter() {
+        let mut triangle = playing_triangle(10, 0b1000_0010);
+
+        for _ in 0..5 {
+            triangle.clock_quarter_frame();
+        }
+
+        assert_ne!(levels(&mut triangle, 10, 2), vec![15, 15]);
+    }
+
+    #[test]
+    fn test_length_counter_stops_sequencer() {
+        let mut triangle = playing_triangle(10, 0x7F);
+        triangle.length.set_enabled(false);
+
+        let levels = levels(&mut triangle, 10, 4);
+
+        assert_eq!(levels, vec![15; 4]);
+    }
+
+    #[test]
+    fn test_ultrasonic_periods_hold_the_output() {
+        let mut triangle = playing_triangle(1, 0x7F);
+
+        let levels = levels(&mut triangle, 1, 8);
+
+        assert_eq!(levels, vec![15; 8]);
+    }
+}
@@ -0,0 +1,352 @@
+//! Turns the APU's one-sample-per-CPU-cycle output into a stream a host can play: the
+//! console's analog filters and a band-limited resampler.
+
+use std::f64::consts::PI;
+
+use crate::apu::cpu_clock_rate;
+use crate::cartridge::Region;
+
+/// Kernel phases per output sample, the resolution of step positions.
+const PHASES: usize = 64;
+/// Kernel taps on each side of a step.
+const HALF_WIDTH: usize = 8;
+const TAPS: usize = HALF_WIDTH * 2;
+
+/// Band-limited resampler for piecewise-constant input. The APU output only changes on a small
+/// fraction of cycles, so instead of filtering every input sample each change adds a
+/// band-limited step (a windowed sinc impulse, integrated on output) at its exact position.
+pub struct Resampler {
+    /// Output samples per input sample.
+    ratio: f64,
+    kernel: Vec<[f32; TAPS]>,
+    /// Input samples consumed so far.
+    input_count: u64,
+    last_input: f32,
+    /// Output samples emitted so far.
+    output_count: u64,
+    /// Impulses not emitted yet, `deltas[0]` is output sample `output_count`.
+    deltas: Vec<f32>,
+    level: f32,
+}
+
+impl Resampler {
+    /// Only downsamples: fails unless `output_rate` is above 0 and below `input_rate`.
+    pub fn new(input_rate: f64, output_rate: f64) -> Result<Self, String> {
+        if !(output_rate > 0.0 && output_rate < input_rate) {
+            return Err(format!(
+                "unsupported sample rate {} Hz, it must be above 0 and below {:.0} Hz",
+                output_rate, input_rate
+            ));
+        }
+        return Ok(Resampler {
+            ratio: output_rate / input_rate,
+            kernel: impulse_kernel(),
+            input_count: 0,
+            last_input: 0.0,
+            output_count: 0,
+            deltas: vec![],
+            level: 0.0,
+        });
+    }
+
+    /// Feeds input samples, returns the output samples that are complete.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        for &sample in input {
+            let delta = sample - self.last_input;
+            if delta != 0.0 {
+                self.add_step(delta);
+                self.last_input = sample;
+            }
+            self.input_count += 1;
+        }
+
+        // impulses of later steps reach back HALF_WIDTH samples before their position
+        let time = self.input_count as f64 * self.ratio;
+        let ready = (time.floor() as u64)
+            .saturating_sub(HALF_WIDTH as u64)
+            .saturating_sub(self.output_count) as usize;
+        if self.deltas.len() < ready {
+            self.deltas.resize(ready, 0.0);
+        }
+
+        let mut output = Vec::with_capacity(ready);
+        for delta in self.deltas.drain(..ready) {
+            self.level += delta;
+            output.push(self.level);
+        }
+        self.output_count += ready as u64;
+        return output;
+    }
+
+    fn add_step(&mut self, delta: f32) {
+        let time = self.input_count as f64 * self.ratio;
+        let position = time.floor() as u64;
+        let phase = ((time - position as f64) * PHASES as f64) as usize;
+
+        // the impulse covers output samples position - HALF_WIDTH + 1 ..= position + HALF_WIDTH
+        let first = (position + 1).saturating_sub(HALF_WIDTH as u64);
+        let skipped = (first + HALF_WIDTH as u64 - 1 - position) as usize;
+        let start = first.saturating_sub(self.output_count) as usize;
+        let end = start + TAPS - skipped;
+        if self.deltas.len() < end {
+            self.deltas.resize(end, 0.0);
+        }
+        let taps = &self.kernel[phase];
+        // the part of the impulse before the first output sample still moves the level
+        let before_start: f32 = taps[..skipped].iter().sum();
+        self.deltas[start] += delta * before_start;
+        for (i, tap) in taps[skipped..].iter().enumerate() {
+            self.deltas[start + i] += delta * tap;
+        }
+    }
+}
+
+/// For every phase, a windowed sinc impulse cut off a little under the output Nyquist
+/// frequency, normalised so each impulse adds exactly its step.
+fn impulse_kernel() -> Vec<[f32; TAPS]> {
+    let cutoff = 0.9;
+    let mut kernel = vec![[0.0; TAPS]; PHASES];
+    for (phase, taps) in kernel.iter_mut().enumerate() {
+        let offset = phase as f64 / PHASES as f64;
+        let mut values = [0.0f64; TAPS];
+        for (i, value) in values.iter_mut().enumerate() {
+            // distance from the step to output sample position - HALF_WIDTH + 1 + i
+            let x = i as f64 + 1.0 - HALF_WIDTH as f64 - offset;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * cutoff * x).sin() / (PI * cutoff * x)
+            };
+            // Blackman window over the kernel's span
+            let w = (x + HALF_WIDTH as f64) / TAPS as f64;
+            let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+            *value = sinc * window.max(0.0);
+        }
+        let sum: f64 = values.iter().sum();
+        for (tap, value) in taps.iter_mut().zip(values.iter()) {
+            *tap = (value / sum) as f32;
+        }
+    }
+    return kernel;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+/// First-order RC filter.
+pub struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, cutoff: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        Filter {
+            kind,
+            alpha: alpha as f32,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => {
+                self.alpha * (self.previous_output + input - self.previous_input)
+            }
+            FilterKind::LowPass => {
+                self.previous_output + self.alpha * (input - self.previous_output)
+            }
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        return output;
+    }
+}
+
+/// The NES's output stage: high-pass at 90 Hz and 440 Hz, low-pass at 14 kHz.
+pub fn nes_filters(sample_rate: f64) -> Vec<Filter> {
+    return vec![
+        Filter::new(FilterKind::HighPass, 90.0, sample_rate),
+        Filter::new(FilterKind::HighPass, 440.0, sample_rate),
+        Filter::new(FilterKind::LowPass, 14_000.0, sample_rate),
+    ];
+}
+
+/// APU samples in, filtered host-rate samples out.
+pub struct AudioOutput {
+    sample_rate: u32,
+    resampler: Resampler,
+    filters: Vec<Filter>,
+}
+
+impl AudioOutput {
+    pub fn new(region: Region, sample_rate: u32) -> Result<Self, String> {
+        return Ok(AudioOutput {
+            sample_rate,
+            resampler: Resampler::new(cpu_clock_rate(region), sample_rate as f64)?,
+            filters: nes_filters(sample_rate as f64),
+        });
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    /// Takes samples as returned by `Apu::take_samples`.
+    pub fn process(&mut self, apu_samples: &[f32]) -> Vec<f32> {
+        let mut output = self.resampler.process(apu_samples);
+        for sample in output.iter_mut() {
+            for filter in self.filters.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+        return output;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT_RATE: f64 = 1_789_773.0;
+
+    fn square_wave(frequency: f64, amplitude: f32, samples: usize) -> Vec<f32> {
+        let period = INPUT_RATE / frequency;
+        return (0..samples)
+            .map(|i| {
+                if (i as f64 % period) < period / 2.0 {
+                    amplitude
+                } else {
+                    -amplitude
+                }
+            })
+            .collect();
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        let sum: f32 = samples.iter().map(|s| s * s).sum();
+        return (sum / samples.len() as f32).sqrt();
+    }
+
+    #[test]
+    fn test_kernel_phases_sum_to_one() {
+        for taps in impulse_kernel() {
+            let sum: f32 = taps.iter().sum();
+            assert!((sum - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_output_sample_count_follows_ratio() {
+        let mut resampler = Resampler::new(INPUT_RATE, 48_000.0).unwrap();
+
+        let mut count = 0;
+        for _ in 0..10 {
+            count += resampler.process(&vec![0.5; 29_830]).len();
+        }
+
+        let expected = 298_300.0 * 48_000.0 / INPUT_RATE;
+        assert!((count as f64 - expected).abs() <= HALF_WIDTH as f64 + 1.0);
+    }
+
+    #[test]
+    fn test_constant_input_settles_to_its_level() {
+        let mut resampler = Resampler::new(INPUT_RATE, 44_100.0).unwrap();
+
+        let output = resampler.process(&vec![0.25; 10_000]);
+
+        assert!(output[20..].iter().all(|s| (s - 0.25).abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_audible_tone_passes() {
+        let mut resampler = Resampler::new(INPUT_RATE, 48_000.0).unwrap();
+
+        let output = resampler.process(&square_wave(440.0, 0.5, 200_000));
+
+        let rms = rms(&output[100..]);
+        assert!((rms - 0.5).abs() < 0.05, "{}", rms);
+    }
+
+    #[test]
+    fn test_ultrasonic_tone_is_removed() {
+        let mut resampler = Resampler::new(INPUT_RATE, 48_000.0).unwrap();
+
+        // 55 kHz, like a triangle at a tiny period
+        let output = resampler.process(&square_wave(55_000.0, 0.5, 200_000));
+
+        let rms = rms(&output[100..]);
+        assert!(rms < 0.05, "{}", rms);
+    }
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut filter = Filter::new(FilterKind::HighPass, 90.0, 48_000.0);
+
+        let mut output = 1.0;
+        for _ in 0..48_000 {
+            output = filter.process(1.0);
+        }
+
+        assert!(output.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_low_pass_keeps_dc_and_cuts_high_frequencies() {
+        let mut filter = Filter::new(FilterKind::LowPass, 14_000.0, 48_000.0);
+        let mut output = 0.0;
+        for _ in 0..1000 {
+            output = filter.process(1.0);
+        }
+        assert!((output - 1.0).abs() < 1e-3);
+
+        let mut filter = Filter::new(FilterKind::LowPass, 1_000.0, 48_000.0);
+        let alternating: Vec<f32> = (0..1000)
+            .map(|i| filter.process(if i % 2 == 0 { 1.0 } else { -1.0 }))
+            .collect();
+        assert!(rms(&alternating[100..]) < 0.1);
+    }
+
+    #[test]
+    fn test_rejects_sample_rates_it_cannot_produce() {
+        assert!(Resampler::new(INPUT_RATE, 0.0).is_err());
+        assert!(Resampler::new(INPUT_RATE, INPUT_RATE).is_err());
+        assert!(Resampler::new(INPUT_RATE, f64::NAN).is_err());
+        assert_eq!(
+            AudioOutput::new(Region::Ntsc, 2_000_000).err(),
+            Some(
+                "unsupported sample rate 2000000 Hz, it must be above 0 and below 1789773 Hz"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_audio_output_centres_the_signal() {
+        let mut audio = AudioOutput::new(Region::Ntsc, 48_000).unwrap();
+
+        // one second of a 440 Hz tone riding on a DC offset
+        let input: Vec<f32> = square_wave(440.0, 0.1, INPUT_RATE as usize)
+            .iter()
+            .map(|s| s + 0.3)
+            .collect();
+        let output = audio.process(&input);
+
+        let tail = &output[output.len() - 4800..];
+        let mean: f32 = tail.iter().sum::<f32>() / tail.len() as f32;
+        assert!(mean.abs() < 0.01, "{}", mean);
+        assert!(rms(tail) > 0.05);
+    }
+}
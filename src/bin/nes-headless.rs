@@ -0,0 +1,36 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::process::ExitCode;
+
+use nes_emulator::headless::{execute, Options, USAGE};
+
+fn main() -> ExitCode {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    return match execute(&options) {
+        Ok(summary) => {
+            for correction in summary.corrections.iter().flatten() {
+                eprintln!(
+                    "nes-headless: header corrected from the ROM database, {}",
+                    correction
+                );
+            }
+            println!(
+                "{} frames, last frame {:08x}",
+                options.frames, summary.last_frame_hash
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("nes-headless: {}", e);
+            ExitCode::FAILURE
+        }
+    };
+}
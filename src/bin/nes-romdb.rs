@@ -0,0 +1,43 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+use nes_emulator::rom_db::reimport;
+
+const USAGE: &str = "usage: nes-romdb <nes20db.xml> [<rom_db.txt>]";
+const BUNDLED_DATABASE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/rom_db.txt");
+
+/// Regenerates the entries of the bundled ROM database (or another one in its format) from the
+/// NES 2.0 XML database.
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (xml, database) = match args.as_slice() {
+        [xml] => (xml.as_str(), BUNDLED_DATABASE),
+        [xml, database] => (xml.as_str(), database.as_str()),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = fs::read_to_string(xml)
+        .map_err(|e| format!("unable to read {}: {}", xml, e))
+        .and_then(|xml| {
+            let text = fs::read_to_string(database)
+                .map_err(|e| format!("unable to read {}: {}", database, e))?;
+            return reimport(&text, &xml);
+        })
+        .and_then(|text| {
+            return fs::write(database, text)
+                .map_err(|e| format!("unable to write {}: {}", database, e));
+        });
+    return match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("nes-romdb: {}", e);
+            ExitCode::FAILURE
+        }
+    };
+}
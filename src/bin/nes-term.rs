@@ -0,0 +1,159 @@
+//! Plays a ROM in the terminal: truecolor half-block graphics, keyboard as controller 1.
+//!
+//!   arrows: D-pad   x: A   z: B   space: Select   enter: Start   esc, q: quit
+
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, terminal};
+
+use nes_emulator::cartridge::Region;
+use nes_emulator::controller::Buttons;
+use nes_emulator::loader::load_rom;
+use nes_emulator::nes::Nes;
+use nes_emulator::palette::Palette;
+use nes_emulator::png::Image;
+use nes_emulator::terminal::{downscale, fit, render, FrameLimiter, HeldButtons};
+
+/// Without release events a button stays held this many frames after its last press or
+/// auto-repeat, long enough to bridge the usual delay before a key starts repeating.
+const HOLD_FRAMES: u32 = 20;
+
+/// Restores the terminal however the frontend exits.
+struct RawTerminal {
+    release_events: bool,
+}
+
+impl RawTerminal {
+    fn enter() -> io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if release_events {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        return Ok(RawTerminal { release_events });
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.release_events {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn button(code: KeyCode) -> Option<Buttons> {
+    return match code {
+        KeyCode::Up => Some(Buttons::Up),
+        KeyCode::Down => Some(Buttons::Down),
+        KeyCode::Left => Some(Buttons::Left),
+        KeyCode::Right => Some(Buttons::Right),
+        KeyCode::Char('x') => Some(Buttons::A),
+        KeyCode::Char('z') => Some(Buttons::B),
+        KeyCode::Char(' ') => Some(Buttons::Select),
+        KeyCode::Enter => Some(Buttons::Start),
+        _ => None,
+    };
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    return matches!(key.code, KeyCode::Esc | KeyCode::Char('q'))
+        || key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+}
+
+fn play(nes: &mut Nes, palette: &Palette) -> io::Result<()> {
+    let raw = RawTerminal::enter()?;
+    let mut held = HeldButtons::new(if raw.release_events {
+        None
+    } else {
+        Some(HOLD_FRAMES)
+    });
+    let mut limiter = FrameLimiter::new(match nes.region() {
+        Region::Pal => 50.0,
+        _ => 60.0,
+    });
+    let mut stdout = io::stdout();
+
+    loop {
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) if is_quit(&key) => return Ok(()),
+                Event::Key(key) => match (button(key.code), key.kind) {
+                    (Some(buttons), KeyEventKind::Release) => held.release(buttons),
+                    (Some(buttons), _) => held.press(buttons),
+                    (None, _) => {}
+                },
+                Event::Resize(_, _) => {
+                    execute!(stdout, terminal::Clear(terminal::ClearType::All))?;
+                }
+                _ => {}
+            }
+        }
+
+        nes.set_buttons(0, held.next_frame());
+        let (frame, _) = nes.run_frame().map_err(io::Error::other)?;
+        let image = Image::from_frame(frame, palette);
+        let (columns, rows) = terminal::size()?;
+        let (width, height) = fit(image.width, image.height, columns as usize, rows as usize);
+        stdout.write_all(render(&downscale(&image, width, height)).as_bytes())?;
+        stdout.flush()?;
+
+        limiter.wait();
+    }
+}
+
+fn main() -> ExitCode {
+    let (flags, paths): (Vec<String>, Vec<String>) =
+        env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let soft_patch = !flags.is_empty();
+    let ([path], true) = (
+        paths.as_slice(),
+        flags.iter().all(|flag| flag == "--soft-patch"),
+    ) else {
+        eprintln!("usage: nes-term <rom> [--soft-patch]");
+        return ExitCode::from(2);
+    };
+    let nes = load_rom(Path::new(path), soft_patch).and_then(|loaded| {
+        for correction in loaded.corrections.iter().flatten() {
+            eprintln!(
+                "nes-term: header corrected from the ROM database, {}",
+                correction
+            );
+        }
+        let rom = loaded.rom;
+        let palette = Palette::for_region(rom.region);
+        Ok((Nes::new(rom)?, palette))
+    });
+    let (mut nes, palette) = match nes {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("nes-term: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    return match play(&mut nes, &palette) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("nes-term: {}", e);
+            ExitCode::FAILURE
+        }
+    };
+}
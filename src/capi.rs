@@ -0,0 +1,410 @@
+//! C ABI for embedding the emulator in C, Python (ctypes) and other non-Rust tools. Every
+//! function takes the opaque handle returned by `nes_create`; functions that can fail return
+//! `NES_OK` or a negative `NES_ERROR_*` code and leave a message for `nes_last_error`. Panics
+//! never unwind into the caller: the functions that run the emulation return
+//! `NES_ERROR_PANIC` instead.
+//!
+//! `include/nes_emulator.h` is generated from this file by cbindgen, the `capi_tests`
+//! integration test checks it is up to date (run it with `NES_UPDATE_HEADER=1` to regenerate).
+
+use std::any::Any;
+use std::ffi::{c_char, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::controller::Buttons;
+use crate::loader::parse_rom;
+use crate::nes::Nes;
+use crate::palette::{Palette, PixelFormat};
+use crate::ppu::Frame;
+
+pub const NES_OK: i32 = 0;
+/// A null handle or buffer.
+pub const NES_ERROR_NULL: i32 = -1;
+/// The call needs a ROM, load one with `nes_load_rom` first.
+pub const NES_ERROR_NO_ROM: i32 = -2;
+pub const NES_ERROR_INVALID_ROM: i32 = -3;
+pub const NES_ERROR_INVALID_ARGUMENT: i32 = -4;
+/// The game ran an opcode the CPU does not implement, the console stays where it stopped.
+pub const NES_ERROR_UNSUPPORTED: i32 = -5;
+/// A save state that is damaged, from a newer build or from another game.
+pub const NES_ERROR_INVALID_STATE: i32 = -6;
+/// The emulator panicked, a bug. The ROM is unloaded since the console may be half updated.
+pub const NES_ERROR_PANIC: i32 = -7;
+
+pub const NES_SCREEN_WIDTH: u32 = 256;
+pub const NES_SCREEN_HEIGHT: u32 = 240;
+pub const NES_PLAYERS: u32 = 4;
+
+pub const NES_BUTTON_A: u8 = 0x01;
+pub const NES_BUTTON_B: u8 = 0x02;
+pub const NES_BUTTON_SELECT: u8 = 0x04;
+pub const NES_BUTTON_START: u8 = 0x08;
+pub const NES_BUTTON_UP: u8 = 0x10;
+pub const NES_BUTTON_DOWN: u8 = 0x20;
+pub const NES_BUTTON_LEFT: u8 = 0x40;
+pub const NES_BUTTON_RIGHT: u8 = 0x80;
+
+/// Opaque emulator handle.
+pub struct NesEmulator {
+    nes: Option<Nes>,
+    palette: Palette,
+    /// The last frame the PPU finished, the PPU's own frame is drawn over from the next one.
+    last_frame: Frame,
+    /// RGBA8888, filled by `nes_framebuffer`.
+    framebuffer: Vec<u8>,
+    last_error: CString,
+}
+
+/// CPU registers, as returned by `nes_registers`.
+#[repr(C)]
+pub struct NesRegisters {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    /// NV-BDIZC
+    pub status: u8,
+    /// CPU cycles since power on.
+    pub cycles: u64,
+}
+
+impl NesEmulator {
+    fn fail(&mut self, code: i32, message: &str) -> i32 {
+        self.last_error = CString::new(message.replace('\0', " ")).unwrap();
+        return code;
+    }
+
+    fn nes_or_fail(&mut self) -> Result<&mut Nes, i32> {
+        if self.nes.is_none() {
+            return Err(self.fail(NES_ERROR_NO_ROM, "no rom loaded"));
+        }
+        return Ok(self.nes.as_mut().unwrap());
+    }
+
+    fn frame_completed(&mut self) {
+        if let Some(nes) = &self.nes {
+            self.last_frame.clone_from(nes.frame());
+        }
+    }
+
+    /// Runs `f`, turning a panic into `NES_ERROR_PANIC`.
+    fn catch_panic(&mut self, f: impl FnOnce(&mut NesEmulator) -> i32) -> i32 {
+        return match panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(code) => code,
+            Err(payload) => {
+                self.nes = None;
+                let message = format!("emulator panicked: {}", panic_message(&*payload));
+                self.fail(NES_ERROR_PANIC, &message)
+            }
+        };
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message;
+    }
+    return match payload.downcast_ref::<String>() {
+        Some(message) => message,
+        None => "unknown panic",
+    };
+}
+
+/// Creates an emulator with no ROM loaded. Free it with `nes_destroy`.
+#[no_mangle]
+pub extern "C" fn nes_create() -> *mut NesEmulator {
+    let emulator = NesEmulator {
+        nes: None,
+        palette: Palette::ntsc(),
+        last_frame: Frame::new(),
+        framebuffer: vec![],
+        last_error: CString::default(),
+    };
+    return Box::into_raw(Box::new(emulator));
+}
+
+/// # Safety
+/// `emulator` must come from `nes_create` and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn nes_destroy(emulator: *mut NesEmulator) {
+    if !emulator.is_null() {
+        drop(Box::from_raw(emulator));
+    }
+}
+
+/// The message of the last failed call, empty if none failed. Valid until the next call.
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_last_error(emulator: *const NesEmulator) -> *const c_char {
+    return match emulator.as_ref() {
+        Some(emulator) => emulator.last_error.as_ptr(),
+        None => c"null emulator handle".as_ptr(),
+    };
+}
+
+/// Loads an iNES, NES 2.0 or UNIF image and powers the console on. The bytes are copied.
+///
+/// # Safety
+/// `emulator` must be null or a live handle, `data` must be valid for `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_rom(
+    emulator: *mut NesEmulator,
+    data: *const u8,
+    size: usize,
+) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    if data.is_null() {
+        return emulator.fail(NES_ERROR_NULL, "null rom data");
+    }
+    let raw = slice::from_raw_parts(data, size);
+    return emulator.catch_panic(|emulator| {
+        let loaded = parse_rom(raw).and_then(|loaded| {
+            let rom = loaded.rom;
+            let palette = Palette::for_region(rom.region);
+            Ok((Nes::new(rom)?, palette))
+        });
+        return match loaded {
+            Ok((nes, palette)) => {
+                emulator.nes = Some(nes);
+                emulator.palette = palette;
+                emulator.last_frame = Frame::new();
+                NES_OK
+            }
+            Err(e) => emulator.fail(NES_ERROR_INVALID_ROM, &e),
+        };
+    });
+}
+
+/// Executes one CPU instruction (or interrupt), returns 1 if it completed a frame, 0 if not,
+/// or an error code.
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_step(emulator: *mut NesEmulator) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    return emulator.catch_panic(|emulator| {
+        let stepped = match emulator.nes_or_fail() {
+            Ok(nes) => nes.step(),
+            Err(code) => return code,
+        };
+        return match stepped {
+            Ok(true) => {
+                emulator.frame_completed();
+                1
+            }
+            Ok(false) => 0,
+            Err(e) => emulator.fail(NES_ERROR_UNSUPPORTED, &e),
+        };
+    });
+}
+
+/// Runs until the PPU finishes a frame.
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_run_frame(emulator: *mut NesEmulator) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    return emulator.catch_panic(|emulator| {
+        let ran = match emulator.nes_or_fail() {
+            Ok(nes) => nes.run_frame().map(|_| ()),
+            Err(code) => return code,
+        };
+        return match ran {
+            Ok(()) => {
+                emulator.frame_completed();
+                NES_OK
+            }
+            Err(e) => emulator.fail(NES_ERROR_UNSUPPORTED, &e),
+        };
+    });
+}
+
+/// The last frame completed by `nes_step` or `nes_run_frame` (blank before the first one) as
+/// `NES_SCREEN_WIDTH` x `NES_SCREEN_HEIGHT` RGBA8888 pixels, row by row. Valid until the next
+/// call on this handle; null without a ROM.
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_framebuffer(emulator: *mut NesEmulator) -> *const u8 {
+    let Some(emulator) = emulator.as_mut() else {
+        return ptr::null();
+    };
+    if emulator.nes.is_none() {
+        return ptr::null();
+    }
+    emulator.framebuffer = emulator
+        .palette
+        .convert_frame(&emulator.last_frame, PixelFormat::Rgba8888);
+    return emulator.framebuffer.as_ptr();
+}
+
+/// Sets the `NES_BUTTON_*` bits held by `player` (0-3) until changed.
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_buttons(
+    emulator: *mut NesEmulator,
+    player: u32,
+    buttons: u8,
+) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    if player >= NES_PLAYERS {
+        return emulator.fail(NES_ERROR_INVALID_ARGUMENT, "player must be 0-3");
+    }
+    return match emulator.nes_or_fail() {
+        Ok(nes) => {
+            nes.set_buttons(player as usize, Buttons::from_bits_retain(buttons));
+            NES_OK
+        }
+        Err(code) => code,
+    };
+}
+
+/// Reads the CPU address space without side effects: I/O registers read as open bus. Returns 0
+/// without a ROM.
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_peek(emulator: *const NesEmulator, addr: u16) -> u8 {
+    return match emulator.as_ref().and_then(|emulator| emulator.nes.as_ref()) {
+        Some(nes) => nes.cpu.memory.peek(addr),
+        None => 0,
+    };
+}
+
+/// Writes to the CPU address space, exactly like a CPU write (so poking a register has its
+/// usual effect).
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_poke(emulator: *mut NesEmulator, addr: u16, data: u8) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    return match emulator.nes_or_fail() {
+        Ok(nes) => {
+            nes.cpu.memory.write(addr, data);
+            NES_OK
+        }
+        Err(code) => code,
+    };
+}
+
+/// # Safety
+/// `emulator` must be null or a live handle, `registers` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn nes_registers(
+    emulator: *mut NesEmulator,
+    registers: *mut NesRegisters,
+) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    if registers.is_null() {
+        return emulator.fail(NES_ERROR_NULL, "null registers");
+    }
+    let cpu = match emulator.nes_or_fail() {
+        Ok(nes) => &nes.cpu,
+        Err(code) => return code,
+    };
+    *registers = NesRegisters {
+        pc: cpu.program_counter,
+        a: cpu.register_a,
+        x: cpu.register_x,
+        y: cpu.register_y,
+        sp: cpu.stack_pointer,
+        status: cpu.status.bits(),
+        cycles: cpu.cycles,
+    };
+    return NES_OK;
+}
+
+/// Size of the buffer `nes_save_state` needs, the same for every state of a game; 0 without a
+/// ROM.
+///
+/// # Safety
+/// `emulator` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn nes_state_size(emulator: *mut NesEmulator) -> usize {
+    return match emulator.as_ref().and_then(|emulator| emulator.nes.as_ref()) {
+        Some(nes) => nes.save_state().len(),
+        None => 0,
+    };
+}
+
+/// Writes a snapshot of the console to `buffer`, which needs `nes_state_size` bytes (any more
+/// are zero-filled).
+///
+/// # Safety
+/// `emulator` must be null or a live handle, `buffer` valid for `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_save_state(
+    emulator: *mut NesEmulator,
+    buffer: *mut u8,
+    size: usize,
+) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    if buffer.is_null() {
+        return emulator.fail(NES_ERROR_NULL, "null state buffer");
+    }
+    let state = match emulator.nes_or_fail() {
+        Ok(nes) => nes.save_state(),
+        Err(code) => return code,
+    };
+    if state.len() > size {
+        let message = format!("state buffer too small, {} bytes needed", state.len());
+        return emulator.fail(NES_ERROR_INVALID_ARGUMENT, &message);
+    }
+    let buffer = slice::from_raw_parts_mut(buffer, size);
+    buffer[..state.len()].copy_from_slice(&state);
+    buffer[state.len()..].fill(0);
+    return NES_OK;
+}
+
+/// Restores a snapshot written by `nes_save_state` for the same game. On error the console is
+/// left as it was.
+///
+/// # Safety
+/// `emulator` must be null or a live handle, `buffer` valid for `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_state(
+    emulator: *mut NesEmulator,
+    buffer: *const u8,
+    size: usize,
+) -> i32 {
+    let Some(emulator) = emulator.as_mut() else {
+        return NES_ERROR_NULL;
+    };
+    if buffer.is_null() {
+        return emulator.fail(NES_ERROR_NULL, "null state buffer");
+    }
+    let result = match emulator.nes_or_fail() {
+        Ok(nes) => nes.load_state(slice::from_raw_parts(buffer, size)),
+        Err(code) => return code,
+    };
+    return match result {
+        Ok(()) => NES_OK,
+        Err(e) => emulator.fail(NES_ERROR_INVALID_STATE, &e),
+    };
+}
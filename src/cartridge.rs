@@ -0,0 +1,365 @@
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+pub const PRG_ROM_PAGE_SIZE: usize = 16 * 1024;
+pub const CHR_ROM_PAGE_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Multi,
+    Dendy,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RomFormat {
+    INes,
+    Nes2,
+    Unif,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rom {
+    pub format: RomFormat,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub trainer: Option<Vec<u8>>,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub region: Region,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+}
+
+impl Rom {
+    /// Parses an iNES 1.0 or NES 2.0 image.
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err("file is not in iNES file format".to_string());
+        }
+
+        let flags_6 = raw[6];
+        let flags_7 = raw[7];
+        let is_nes2 = flags_7 & 0b0000_1100 == 0b0000_1000;
+
+        let mirroring = match (flags_6 & 0b1000 != 0, flags_6 & 0b1 != 0) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+        let battery = flags_6 & 0b0010 != 0;
+        let has_trainer = flags_6 & 0b0100 != 0;
+
+        let rom = if is_nes2 {
+            Rom::parse_nes2_header(raw, mirroring, battery)?
+        } else {
+            Rom::parse_ines_header(raw, mirroring, battery)
+        };
+        let (prg_rom_size, chr_rom_size) = Rom::rom_sizes(raw, is_nes2)?;
+
+        let trainer_start = HEADER_SIZE;
+        let prg_rom_start = trainer_start + if has_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let end = chr_rom_start + chr_rom_size;
+
+        if raw.len() < end {
+            return Err(format!(
+                "rom is truncated: header declares {} bytes but file has {}",
+                end,
+                raw.len()
+            ));
+        }
+
+        return Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..end].to_vec(),
+            trainer: if has_trainer {
+                Some(raw[trainer_start..prg_rom_start].to_vec())
+            } else {
+                None
+            },
+            ..rom
+        });
+    }
+
+    fn parse_ines_header(raw: &[u8], mirroring: Mirroring, battery: bool) -> Rom {
+        // a lot of old dumps have garbage (ie.: "DiskDude!") in bytes 7-15,
+        // in which case the upper mapper nibble cannot be trusted.
+        let dirty_header = raw[12..16].iter().any(|b| *b != 0);
+        let mapper_hi = if dirty_header { 0 } else { raw[7] & 0xF0 };
+        let mapper = (mapper_hi | (raw[6] >> 4)) as u16;
+
+        let prg_ram_size = if raw[8] == 0 {
+            8 * 1024
+        } else {
+            raw[8] as usize * 8 * 1024
+        };
+        let region = if !dirty_header && raw[9] & 0b1 != 0 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        };
+        let chr_ram_size = if raw[5] == 0 { 8 * 1024 } else { 0 };
+
+        return Rom {
+            format: RomFormat::INes,
+            prg_rom: vec![],
+            chr_rom: vec![],
+            trainer: None,
+            mapper,
+            submapper: 0,
+            mirroring,
+            battery,
+            region,
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            chr_ram_size,
+            chr_nvram_size: 0,
+        };
+    }
+
+    fn parse_nes2_header(raw: &[u8], mirroring: Mirroring, battery: bool) -> Result<Rom, String> {
+        let mapper = ((raw[8] as u16 & 0x0F) << 8) | (raw[7] & 0xF0) as u16 | (raw[6] >> 4) as u16;
+        let region = match raw[12] & 0b11 {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Multi,
+            _ => Region::Dendy,
+        };
+
+        return Ok(Rom {
+            format: RomFormat::Nes2,
+            prg_rom: vec![],
+            chr_rom: vec![],
+            trainer: None,
+            mapper,
+            submapper: raw[8] >> 4,
+            mirroring,
+            battery,
+            region,
+            prg_ram_size: nes2_ram_size(raw[10] & 0x0F),
+            prg_nvram_size: nes2_ram_size(raw[10] >> 4),
+            chr_ram_size: nes2_ram_size(raw[11] & 0x0F),
+            chr_nvram_size: nes2_ram_size(raw[11] >> 4),
+        });
+    }
+
+    fn rom_sizes(raw: &[u8], is_nes2: bool) -> Result<(usize, usize), String> {
+        if !is_nes2 {
+            return Ok((
+                raw[4] as usize * PRG_ROM_PAGE_SIZE,
+                raw[5] as usize * CHR_ROM_PAGE_SIZE,
+            ));
+        }
+
+        let prg_msb = raw[9] & 0x0F;
+        let chr_msb = raw[9] >> 4;
+        return Ok((
+            nes2_rom_size(prg_msb, raw[4], PRG_ROM_PAGE_SIZE)?,
+            nes2_rom_size(chr_msb, raw[5], CHR_ROM_PAGE_SIZE)?,
+        ));
+    }
+}
+
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        return 0;
+    }
+    return 64 << shift;
+}
+
+fn nes2_rom_size(msb: u8, lsb: u8, page_size: usize) -> Result<usize, String> {
+    if msb != 0x0F {
+        return Ok((((msb as usize) << 8) | lsb as usize) * page_size);
+    }
+
+    // exponent-multiplier notation: 2^E * (MM * 2 + 1)
+    let exponent = (lsb >> 2) as u32;
+    let multiplier = (lsb & 0b11) as usize * 2 + 1;
+    return 1usize
+        .checked_shl(exponent)
+        .filter(|_| exponent < usize::BITS)
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid NES 2.0 rom size exponent: {}", exponent));
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub struct TestRom {
+        pub header: Vec<u8>,
+        pub trainer: Option<Vec<u8>>,
+        pub prg_rom: Vec<u8>,
+        pub chr_rom: Vec<u8>,
+    }
+
+    pub fn create_rom(rom: TestRom) -> Vec<u8> {
+        let mut result = Vec::with_capacity(
+            rom.header.len()
+                + rom.trainer.as_ref().map_or(0, |t| t.len())
+                + rom.prg_rom.len()
+                + rom.chr_rom.len(),
+        );
+
+        result.extend(&rom.header);
+        if let Some(t) = rom.trainer {
+            result.extend(t);
+        }
+        result.extend(&rom.prg_rom);
+        result.extend(&rom.chr_rom);
+
+        return result;
+    }
+
+    /// An NTSC iNES `Rom` with 8 KiB of PRG RAM, and 8 KiB of CHR RAM when `chr_rom` is empty.
+    /// The other test ROMs start from this one.
+    pub fn ines_rom(mapper: u16, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Rom {
+        return Rom {
+            format: RomFormat::INes,
+            chr_ram_size: if chr_rom.is_empty() { 0x2000 } else { 0 },
+            prg_rom,
+            chr_rom,
+            trainer: None,
+            mapper,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            region: Region::Ntsc,
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            chr_nvram_size: 0,
+        };
+    }
+
+    #[test]
+    fn test_ines_rom() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, RomFormat::INes);
+        assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert_eq!(rom.chr_ram_size, 0);
+    }
+
+    #[test]
+    fn test_ines_rom_with_trainer() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E,
+                0x45,
+                0x53,
+                0x1A,
+                0x02,
+                0x01,
+                0x31 | 0b100,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+            ],
+            trainer: Some(vec![0; TRAINER_SIZE]),
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.trainer, Some(vec![0; TRAINER_SIZE]));
+        assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_ines_rom_ignores_upper_mapper_nibble_of_dirty_header() {
+        let mut header = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x10, 0x40];
+        header.extend(b"DiskDude");
+
+        let raw = create_rom(TestRom {
+            header,
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.mapper, 1);
+    }
+
+    #[test]
+    fn test_nes2_rom() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x43, 0x08, 0x21, 00, 0x70, 0x07, 0x01, 00, 00,
+                00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, RomFormat::Nes2);
+        assert_eq!(rom.mapper, 0x104);
+        assert_eq!(rom.submapper, 2);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.region, Region::Pal);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 8 * 1024);
+        assert_eq!(rom.chr_ram_size, 8 * 1024);
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::new(&raw);
+        assert!(rom.is_err());
+    }
+
+    #[test]
+    fn test_not_ines_rom() {
+        let rom = Rom::new(&[0x4E, 0x45, 0x53, 0x1B]);
+        assert_eq!(rom.unwrap_err(), "file is not in iNES file format");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+use bitflags::bitflags;
+
+bitflags! {
+    /// Standard controller buttons, in the order the shift register reports them.
+    #[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+    pub struct Buttons: u8 {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const Select = 0b0000_0100;
+        const Start = 0b0000_1000;
+        const Up = 0b0001_0000;
+        const Down = 0b0010_0000;
+        const Left = 0b0100_0000;
+        const Right = 0b1000_0000;
+    }
+}
+
+impl Buttons {
+    /// Parses button names joined with `+` (ie.: `Right+A`), `.` meaning none.
+    pub fn parse(text: &str) -> Result<Buttons, String> {
+        if text == "." {
+            return Ok(Buttons::empty());
+        }
+        let mut buttons = Buttons::empty();
+        for name in text.split('+') {
+            buttons |=
+                Buttons::from_name(name).ok_or_else(|| format!("unknown button {}", name))?;
+        }
+        return Ok(buttons);
+    }
+}
+
+/// The standard joypad: a 4021 shift register loaded from the buttons while the strobe
+/// ($4016 bit 0) is high, then shifted out one bit per read of $4016/$4017.
+#[derive(Default)]
+pub struct Controller {
+    buttons: Buttons,
+    strobe: bool,
+    shift_register: u8,
+    /// Bits shifted out since the strobe went low, the register fills with 1s behind them.
+    reads: u8,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Host side: the buttons currently held.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.reload();
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        return self.buttons;
+    }
+
+    /// A write to $4016, only bit 0 (the strobe) is wired to the controller.
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.reload();
+        }
+    }
+
+    /// The serial data bit: A, B, Select, Start, Up, Down, Left, Right, then 1s.
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            // the register keeps reloading, so A is read over and over
+            return self.buttons.bits() & 1;
+        }
+        if self.reads >= 8 {
+            return 1;
+        }
+        let bit = self.shift_register & 1;
+        self.shift_register >>= 1;
+        self.reads += 1;
+        return bit;
+    }
+
+    fn reload(&mut self) {
+        self.shift_register = self.buttons.bits();
+        self.reads = 0;
+    }
+}
+
+impl SaveState for Controller {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.buttons.bits());
+        w.bool(self.strobe);
+        w.u8(self.shift_register);
+        w.u8(self.reads);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.buttons = Buttons::from_bits_retain(r.u8()?);
+        self.strobe = r.bool()?;
+        self.shift_register = r.u8()?;
+        self.reads = r.u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(controller: &mut Controller, count: usize) -> Vec<u8> {
+        return (0..count).map(|_| controller.read()).collect();
+    }
+
+    #[test]
+    fn test_serial_order_then_ones() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Buttons::A | Buttons::Start | Buttons::Left);
+        controller.write(1);
+        controller.write(0);
+
+        let bits = read_all(&mut controller, 12);
+
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 1, 0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_returns_a() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Buttons::A | Buttons::B);
+        controller.write(1);
+
+        assert_eq!(read_all(&mut controller, 3), vec![1, 1, 1]);
+        controller.set_buttons(Buttons::B);
+        assert_eq!(controller.read(), 0);
+    }
+
+    #[test]
+    fn test_buttons_are_latched_when_strobe_falls() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Buttons::Right);
+        controller.write(1);
+        controller.write(0);
+
+        controller.set_buttons(Buttons::A);
+
+        assert_eq!(read_all(&mut controller, 8), vec![0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_strobe_restarts_the_sequence() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Buttons::B);
+        controller.write(1);
+        controller.write(0);
+        read_all(&mut controller, 5);
+
+        controller.write(1);
+        controller.write(0);
+
+        assert_eq!(read_all(&mut controller, 2), vec![0, 1]);
+    }
+}
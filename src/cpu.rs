@@ -3,6 +3,7 @@ use bitflags::bitflags;
 use crate::{
     memory::Memory,
     operation::{AddressingMode, OpName, OPERATIONS_MAP},
+    state::{SaveState, StateReader, StateWriter},
 };
 
 pub struct CPU {
@@ -13,8 +14,16 @@ pub struct CPU {
     pub program_counter: u16,
     pub stack_pointer: u8,
     pub memory: Memory,
+    pub cycles: u64,
+    nmi_pending: bool,
+    irq_line: bool,
 }
 
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+const INTERRUPT_CYCLES: u64 = 7;
+
 bitflags! {
     ///  7 6 5 4 3 2 1 0
     ///  N V _ B D I Z C
@@ -45,6 +54,12 @@ impl Flags {
     }
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CPU {
     pub fn new() -> Self {
         CPU {
@@ -55,6 +70,9 @@ impl CPU {
             program_counter: 0,
             stack_pointer: 0xFF,
             memory: Memory::new(),
+            cycles: 0,
+            nmi_pending: false,
+            irq_line: false,
         }
     }
 
@@ -73,7 +91,54 @@ impl CPU {
 
     fn load(&mut self, program: Vec<u8>) {
         self.memory.load_program(program);
-        self.memory.write_u16(0xFFFC, 0x8000);
+        self.memory.write_u16(RESET_VECTOR, 0x8000);
+    }
+
+    /// Power-up state, then jumps through the reset vector of whatever is mapped at $FFFC.
+    pub fn power_on(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.status = Flags::InteruptDisable | Flags::Unused;
+        self.stack_pointer = 0xFD;
+        self.reset(false);
+    }
+
+    /// The reset button: registers are kept, the stack pointer moves down 3 as if an interrupt
+    /// pushed without writing, and execution restarts at the reset vector.
+    pub fn soft_reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status.insert(Flags::InteruptDisable);
+        self.reset(false);
+    }
+
+    /// Edge-triggered, serviced before the next instruction.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Level-triggered, serviced before each instruction while held and not masked.
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    /// Services a pending interrupt or executes one instruction, returns the cycles it took.
+    /// Fails, leaving the CPU where it was, on an opcode it does not implement.
+    pub fn step(&mut self) -> Result<u64, String> {
+        let start = self.cycles;
+
+        if std::mem::take(&mut self.nmi_pending) {
+            self.interrupt(NMI_VECTOR, false);
+            self.cycles += INTERRUPT_CYCLES;
+        } else if self.irq_line && !self.status.contains(Flags::InteruptDisable) {
+            self.interrupt(IRQ_VECTOR, false);
+            self.cycles += INTERRUPT_CYCLES;
+        } else {
+            let op_code = self.memory.read(self.program_counter);
+            self.execute(op_code)?;
+        }
+
+        return Ok(self.cycles - start);
     }
 
     fn reset(&mut self, reset_registers_and_status: bool) {
@@ -84,7 +149,9 @@ impl CPU {
             self.status = Flags::Unused;
         }
 
-        self.program_counter = self.memory.read_u16(0xFFFC);
+        self.program_counter = self.memory.read_u16(RESET_VECTOR);
+        // the reset sequence takes as long as an interrupt
+        self.cycles += INTERRUPT_CYCLES;
     }
 
     fn get_op_target_addr(&mut self, mode: &AddressingMode) -> u16 {
@@ -121,11 +188,11 @@ impl CPU {
             IndirectX => {
                 let zero_page_addr = self.memory.read(self.program_counter);
                 let addr = zero_page_addr.wrapping_add(self.register_x);
-                return self.memory.read_u16(addr as u16);
+                return self.read_zero_page_u16(addr);
             }
             IndirectY => {
                 let zero_page_addr = self.memory.read(self.program_counter);
-                let addr = self.memory.read_u16(zero_page_addr as u16);
+                let addr = self.read_zero_page_u16(zero_page_addr);
                 return addr.wrapping_add(self.register_y as u16);
             }
             Implied => {
@@ -134,87 +201,179 @@ impl CPU {
         }
     }
 
-    fn run(&mut self) {
-        use OpName::*;
+    /// A pointer in the zero page, its high byte at $00 when the low byte is at $FF.
+    fn read_zero_page_u16(&mut self, addr: u8) -> u16 {
+        let lo = self.memory.read(addr as u16) as u16;
+        let hi = self.memory.read(addr.wrapping_add(1) as u16) as u16;
+        return (hi << 8) | lo;
+    }
+
+    /// Whether indexing moves the operand's address to another page.
+    fn crosses_page(&mut self, mode: &AddressingMode) -> bool {
+        let base = match mode {
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                self.memory.read_u16(self.program_counter)
+            }
+            AddressingMode::IndirectY => {
+                let zero_page_addr = self.memory.read(self.program_counter);
+                self.read_zero_page_u16(zero_page_addr)
+            }
+            _ => return false,
+        };
+        let addr = self.get_op_target_addr(mode);
+        return base & 0xFF00 != addr & 0xFF00;
+    }
 
+    fn run(&mut self) {
         loop {
             let op_code = self.memory.read(self.program_counter);
-            let op = OPERATIONS_MAP
-                .get(&op_code)
-                .unwrap_or_else(|| panic!("unrecognized operation: 0x{:02X?}", op_code));
-
-            self.program_counter += 1;
-
-            match op.mnemonic_name {
-                ADC => self.adc(&op.addressing_mode),
-                AND => self.and(&op.addressing_mode),
-                ASL => self.asl(&op.addressing_mode),
-                BCC => self.bcc(),
-                BCS => self.bcs(),
-                BEQ => self.beq(),
-                BMI => self.bmi(),
-                BNE => self.bne(),
-                BPL => self.bpl(),
-                BVC => self.bvc(),
-                BVS => self.bvs(),
-                BIT => self.bit(&op.addressing_mode),
-                BRK => return, // todo: actually implement this
-                CLC => self.set_carry_flag(false),
-                CLD => self.set_decimal_flag(false),
-                CLI => self.set_interupt_flag(false),
-                CLV => self.set_overflow_flag(false),
-                CMP => self.cmp(&op.addressing_mode),
-                CPX => self.cpx(&op.addressing_mode),
-                CPY => self.cpy(&op.addressing_mode),
-                DEC => self.dec(&op.addressing_mode),
-                DEX => self.dex(),
-                DEY => self.dey(),
-                EOR => self.eor(&op.addressing_mode),
-                INC => self.inc(&op.addressing_mode),
-                INX => self.inx(),
-                INY => self.iny(),
-                JMP => self.jmp(&op.addressing_mode),
-                JSR => self.jsr(),
-                LDA => self.lda(&op.addressing_mode),
-                LDX => self.ldx(&op.addressing_mode),
-                LDY => self.ldy(&op.addressing_mode),
-                LSR => self.lsr(&op.addressing_mode),
-                NOP => {}
-                ORA => self.ora(&op.addressing_mode),
-                PHA => self.pha(),
-                PHP => self.php(),
-                PLA => self.pla(),
-                PLP => self.plp(),
-                ROL => self.rol(&op.addressing_mode),
-                ROR => self.ror(&op.addressing_mode),
-                RTI => self.rti(),
-                TAX => self.tax(),
-                TAY => self.tay(),
-
-                _ => todo!("op not implemented"),
+            // test programs end with BRK, stop there instead of jumping through the IRQ vector
+            if op_code == 0x00 {
+                self.program_counter = self.program_counter.wrapping_add(1);
+                self.cycles += INTERRUPT_CYCLES;
+                return;
             }
+            self.execute(op_code).unwrap_or_else(|e| panic!("{}", e));
+        }
+    }
 
-            match op.mnemonic_name {
-                JMP | JSR => {
-                    // no-op
-                }
-                _ => {
-                    self.program_counter += (op.bytes - 1) as u16;
-                }
+    fn execute(&mut self, op_code: u8) -> Result<(), String> {
+        use OpName::*;
+
+        #[cfg(feature = "test-hooks")]
+        if op_code == 0xF2 {
+            panic!("test hook at ${:04X}", self.program_counter);
+        }
+
+        let op = OPERATIONS_MAP.get(&op_code).ok_or_else(|| {
+            format!(
+                "unsupported opcode ${:02X} at ${:04X}",
+                op_code, self.program_counter
+            )
+        })?;
+
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.cycles += op.cycles as u64;
+        // reads fix up the high byte of an indexed address with an extra cycle, writes and
+        // read-modify-writes always take it and have it in their base count
+        if matches!(
+            op.mnemonic_name,
+            ADC | AND | CMP | EOR | LDA | LDX | LDY | ORA | SBC
+        ) && self.crosses_page(&op.addressing_mode)
+        {
+            self.cycles += 1;
+        }
+
+        match op.mnemonic_name {
+            ADC => self.adc(&op.addressing_mode),
+            AND => self.and(&op.addressing_mode),
+            ASL => self.asl(&op.addressing_mode),
+            BCC => self.bcc(),
+            BCS => self.bcs(),
+            BEQ => self.beq(),
+            BMI => self.bmi(),
+            BNE => self.bne(),
+            BPL => self.bpl(),
+            BVC => self.bvc(),
+            BVS => self.bvs(),
+            BIT => self.bit(&op.addressing_mode),
+            BRK => self.brk(),
+            CLC => self.set_carry_flag(false),
+            CLD => self.set_decimal_flag(false),
+            CLI => self.set_interupt_flag(false),
+            CLV => self.set_overflow_flag(false),
+            CMP => self.cmp(&op.addressing_mode),
+            CPX => self.cpx(&op.addressing_mode),
+            CPY => self.cpy(&op.addressing_mode),
+            DEC => self.dec(&op.addressing_mode),
+            DEX => self.dex(),
+            DEY => self.dey(),
+            EOR => self.eor(&op.addressing_mode),
+            INC => self.inc(&op.addressing_mode),
+            INX => self.inx(),
+            INY => self.iny(),
+            JMP => self.jmp(&op.addressing_mode),
+            JSR => self.jsr(),
+            LDA => self.lda(&op.addressing_mode),
+            LDX => self.ldx(&op.addressing_mode),
+            LDY => self.ldy(&op.addressing_mode),
+            LSR => self.lsr(&op.addressing_mode),
+            NOP => {}
+            ORA => self.ora(&op.addressing_mode),
+            PHA => self.pha(),
+            PHP => self.php(),
+            PLA => self.pla(),
+            PLP => self.plp(),
+            ROL => self.rol(&op.addressing_mode),
+            ROR => self.ror(&op.addressing_mode),
+            RTI => self.rti(),
+            RTS => self.rts(),
+            SBC => self.sbc(&op.addressing_mode),
+            SEC => self.set_carry_flag(true),
+            SED => self.set_decimal_flag(true),
+            SEI => self.set_interupt_flag(true),
+            STA => self.store(&op.addressing_mode, self.register_a),
+            STX => self.store(&op.addressing_mode, self.register_x),
+            STY => self.store(&op.addressing_mode, self.register_y),
+            TAX => self.tax(),
+            TAY => self.tay(),
+            TSX => self.set_register_x(self.stack_pointer),
+            TXA => self.set_register_a(self.register_x),
+            TXS => self.stack_pointer = self.register_x,
+            TYA => self.set_register_a(self.register_y),
+        }
+
+        match op.mnemonic_name {
+            JMP | JSR | BRK => {
+                // no-op
+            }
+            _ => {
+                self.program_counter = self.program_counter.wrapping_add((op.bytes - 1) as u16);
             }
         }
+
+        self.oam_dma();
+        return Ok(());
+    }
+
+    /// Copies the page written to $4014 into OAM through $2004, halting the CPU meanwhile.
+    fn oam_dma(&mut self) {
+        let Some(page) = self.memory.take_oam_dma() else {
+            return;
+        };
+
+        let base = (page as u16) << 8;
+        for offset in 0..=0xFF {
+            let value = self.memory.read(base + offset);
+            self.memory.write(0x2004, value);
+        }
+
+        // 1 halt cycle, 1 more to align to a read cycle when starting on an odd one,
+        // then 256 read/write pairs
+        self.cycles += 513 + (self.cycles % 2);
     }
 
     fn adc(&mut self, mode: &AddressingMode) {
         let addr = self.get_op_target_addr(mode);
+        let mem_value = self.memory.read(addr);
+        self.add_to_register_a(mem_value);
+    }
 
+    /// A - M - (1 - C), which is A + !M + C.
+    fn sbc(&mut self, mode: &AddressingMode) {
+        let addr = self.get_op_target_addr(mode);
         let mem_value = self.memory.read(addr);
-        let carry_in: u8 = if self.carry_flag() { 1 } else { 0 };
+        self.add_to_register_a(!mem_value);
+    }
 
-        let wrapped_sum = self.register_a.wrapping_add(mem_value + carry_in);
+    fn add_to_register_a(&mut self, value: u8) {
+        let carry_in: u16 = if self.carry_flag() { 1 } else { 0 };
 
-        let carry_out = wrapped_sum < self.register_a;
-        let overflow = ((self.register_a ^ wrapped_sum) & (mem_value ^ wrapped_sum) & 0x80) != 0;
+        let sum = self.register_a as u16 + value as u16 + carry_in;
+        let wrapped_sum = sum as u8;
+
+        let carry_out = sum > 0xFF;
+        let overflow = ((self.register_a ^ wrapped_sum) & (value ^ wrapped_sum) & 0x80) != 0;
 
         self.set_carry_flag(carry_out);
         self.set_overflow_flag(overflow);
@@ -244,6 +403,21 @@ impl CPU {
         }
     }
 
+    fn brk(&mut self) {
+        // BRK is followed by a padding byte, which the return address skips
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.interrupt(IRQ_VECTOR, true);
+    }
+
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        self.push_u16_to_stack(self.program_counter);
+        let mut status = self.status | Flags::Unused;
+        status.set(Flags::Break, brk);
+        self.push_to_stack(status.bits());
+        self.set_interupt_flag(true);
+        self.program_counter = self.memory.read_u16(vector);
+    }
+
     fn bcc(&mut self) {
         let condition = !self.carry_flag();
         self.branch(condition);
@@ -291,15 +465,20 @@ impl CPU {
 
         let addr = self.get_op_target_addr(&AddressingMode::Immediate);
         let offset = self.memory.read(addr);
-        if offset == 0 {
-            return;
-        }
+        // a taken branch costs a cycle, and another one when it lands on another page than
+        // the next instruction
+        let next_instruction = self.program_counter.wrapping_add(1);
+        self.cycles += 1;
 
         let usigned_offset = (offset & 0b0111_1111) as u16;
         if offset & 0b1000_0000 != 0 {
-            self.program_counter -= usigned_offset;
+            self.program_counter = self.program_counter.wrapping_sub(usigned_offset);
         } else {
-            self.program_counter += usigned_offset;
+            self.program_counter = self.program_counter.wrapping_add(usigned_offset);
+        }
+
+        if self.program_counter.wrapping_add(1) & 0xFF00 != next_instruction & 0xFF00 {
+            self.cycles += 1;
         }
     }
 
@@ -377,6 +556,11 @@ impl CPU {
         self.set_register_y(mem_value);
     }
 
+    fn store(&mut self, mode: &AddressingMode, value: u8) {
+        let addr = self.get_op_target_addr(mode);
+        self.memory.write(addr, value);
+    }
+
     fn tax(&mut self) {
         self.set_register_x(self.register_a);
     }
@@ -407,7 +591,12 @@ impl CPU {
 
         let mut v = addr;
         if *mode == AddressingMode::Indirect {
-            v = self.memory.read_u16(addr);
+            // the high byte comes from the same page: JMP ($10FF) reads $10FF and $1000
+            let lo = self.memory.read(addr) as u16;
+            let hi =
+                self.memory
+                    .read((addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF)) as u16;
+            v = (hi << 8) | lo;
         }
 
         self.program_counter = v;
@@ -418,7 +607,7 @@ impl CPU {
 
         // the subroutine return address on the stack
         // points to the second byte of data for JSR (ie.: 0x20, 0x00, ->0xFF<-)
-        self.push_u16_to_stack(self.program_counter + 1);
+        self.push_u16_to_stack(self.program_counter.wrapping_add(1));
         self.program_counter = subroutine_addr;
     }
 
@@ -472,7 +661,7 @@ impl CPU {
         let mut value = self.register_a;
         let carry_out = value & 0b1000_0000 != 0;
 
-        value = value << 1;
+        value <<= 1;
         if carry_in {
             value += 1;
         }
@@ -487,7 +676,7 @@ impl CPU {
         let mut value = self.memory.read(addr);
         let carry_out = value & 0b1000_0000 != 0;
 
-        value = value << 1;
+        value <<= 1;
         if carry_in {
             value += 1;
         }
@@ -509,9 +698,9 @@ impl CPU {
         let mut value = self.register_a;
         let carry_out = value & 0b0000_0001 != 0;
 
-        value = value >> 1;
+        value >>= 1;
         if carry_in {
-            value = value | 0b1000_0000;
+            value |= 0b1000_0000;
         }
 
         self.set_register_a(value);
@@ -524,9 +713,9 @@ impl CPU {
         let mut value = self.memory.read(addr);
         let carry_out = value & 0b0000_0001 != 0;
 
-        value = value >> 1;
+        value >>= 1;
         if carry_in {
-            value = value | 0b1000_0000;
+            value |= 0b1000_0000;
         }
 
         self.set_memory(addr, value);
@@ -538,6 +727,11 @@ impl CPU {
         self.program_counter = self.pop_u16_from_stack();
     }
 
+    fn rts(&mut self) {
+        // JSR pushed the address of its last byte
+        self.program_counter = self.pop_u16_from_stack().wrapping_add(1);
+    }
+
     fn set_register_a(&mut self, value: u8) {
         self.register_a = value;
         self.set_zero_flag(self.register_a);
@@ -636,7 +830,7 @@ impl CPU {
 
     fn push_to_stack(&mut self, value: u8) {
         self.memory.write(self.stack_pointer_u16(), value);
-        self.stack_pointer -= 1;
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
     fn pop_u16_from_stack(&mut self) -> u16 {
@@ -647,10 +841,9 @@ impl CPU {
     }
 
     fn pop_stack(&mut self) -> u8 {
-        let stack_pointer_u16 = self.stack_pointer_u16() + 1;
-        let value = self.memory.read(stack_pointer_u16);
-        self.memory.write(stack_pointer_u16, 0x00);
-        self.stack_pointer += 1;
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        let value = self.memory.read(self.stack_pointer_u16());
+        self.memory.write(self.stack_pointer_u16(), 0x00);
         return value;
     }
 
@@ -659,6 +852,34 @@ impl CPU {
     }
 }
 
+/// Registers and interrupt lines; the memory map is saved separately.
+impl SaveState for CPU {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.register_a);
+        w.u8(self.register_x);
+        w.u8(self.register_y);
+        w.u8(self.status.bits());
+        w.u16(self.program_counter);
+        w.u8(self.stack_pointer);
+        w.u64(self.cycles);
+        w.bool(self.nmi_pending);
+        w.bool(self.irq_line);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.register_a = r.u8()?;
+        self.register_x = r.u8()?;
+        self.register_y = r.u8()?;
+        self.status = Flags::from_bits_retain(r.u8()?);
+        self.program_counter = r.u16()?;
+        self.stack_pointer = r.u8()?;
+        self.cycles = r.u64()?;
+        self.nmi_pending = r.bool()?;
+        self.irq_line = r.bool()?;
+        return Ok(());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Flags;
@@ -729,18 +950,99 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn test_stack_panic_when_pointer_overflows() {
+    fn test_program_counter_wraps_around() {
         let mut cpu = CPU::new();
-        cpu.pop_stack();
+        // NOP
+        cpu.memory.write(0xFFFF, 0xEA);
+        cpu.program_counter = 0xFFFF;
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x0000);
+    }
+
+    fn interrupt_test_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        // NOP; NOP
+        cpu.load(vec![0xEA, 0xEA]);
+        cpu.memory.write_u16(0xFFFA, 0x9000);
+        cpu.memory.write_u16(0xFFFE, 0xA000);
+        cpu.power_on();
+        cpu.set_interupt_flag(false);
+        return cpu;
     }
 
     #[test]
-    #[should_panic]
-    fn test_stack_panic_when_pointer_underflow() {
+    fn test_irq_is_serviced_when_not_masked() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.set_irq_line(true);
+
+        assert_eq!(cpu.step().unwrap(), 7);
+
+        assert_eq!(cpu.program_counter, 0xA000);
+        assert!(cpu.status.contains(Flags::InteruptDisable));
+        // the pushed status has B clear
+        assert_eq!(cpu.memory.read(0x01FB), 0b0010_0000);
+        assert_eq!(cpu.memory.read(0x01FC), 0x00);
+        assert_eq!(cpu.memory.read(0x01FD), 0x80);
+    }
+
+    #[test]
+    fn test_irq_is_masked_by_interrupt_disable() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.set_interupt_flag(true);
+        cpu.set_irq_line(true);
+
+        assert_eq!(cpu.step().unwrap(), 2);
+
+        assert_eq!(cpu.program_counter, 0x8001);
+    }
+
+    #[test]
+    fn test_nmi_is_serviced_once_even_when_masked() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.set_interupt_flag(true);
+        cpu.trigger_nmi();
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x9000);
+        cpu.memory.write(0x9000, 0xEA);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x9001);
+    }
+
+    #[test]
+    fn test_brk_jumps_through_irq_vector_when_stepping() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.memory.write(0x8000, 0x00);
+
+        assert_eq!(cpu.step().unwrap(), 7);
+
+        assert_eq!(cpu.program_counter, 0xA000);
+        // the pushed status has B set and the return address skips the padding byte
+        assert_eq!(cpu.memory.read(0x01FB), 0b0011_0000);
+        assert_eq!(cpu.memory.read(0x01FC), 0x02);
+    }
+
+    #[test]
+    fn test_stack_pointer_wraps_when_popping_past_the_top() {
         let mut cpu = CPU::new();
-        for n in 1..257 {
-            cpu.push_to_stack(n as u8);
+        cpu.memory.write(0x0100, 0x55);
+
+        assert_eq!(cpu.pop_stack(), 0x55);
+        assert_eq!(cpu.stack_pointer, 0x00);
+    }
+
+    #[test]
+    fn test_stack_pointer_wraps_when_pushing_past_the_bottom() {
+        let mut cpu = CPU::new();
+        for _ in 0..256 {
+            cpu.push_to_stack(0xAA);
         }
+        assert_eq!(cpu.stack_pointer, 0xFF);
+
+        cpu.push_to_stack(0x55);
+        assert_eq!(cpu.memory.read(0x01FF), 0x55);
+        assert_eq!(cpu.stack_pointer, 0xFE);
     }
 }
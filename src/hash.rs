@@ -0,0 +1,212 @@
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    return table;
+}
+
+/// CRC-32 (IEEE 802.3), as used by IPS/BPS/UPS, zip and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    return Crc32::new().update(data).finish();
+}
+
+/// Incremental CRC-32, for data that is not contiguous in memory.
+#[derive(Clone, Copy)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { crc: 0xFFFF_FFFF }
+    }
+
+    pub fn update(mut self, data: &[u8]) -> Self {
+        for byte in data {
+            self.crc = CRC32_TABLE[((self.crc ^ *byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+        return self;
+    }
+
+    pub fn finish(self) -> u32 {
+        return !self.crc;
+    }
+}
+
+/// SHA-1, as used by rom databases to identify dumps.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    return hasher.finish();
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+/// Incremental SHA-1.
+#[derive(Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    block: [u8; 64],
+    block_len: usize,
+    len: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Self {
+        Sha1 {
+            state: [
+                0x6745_2301,
+                0xEFCD_AB89,
+                0x98BA_DCFE,
+                0x1032_5476,
+                0xC3D2_E1F0,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+
+        for byte in data {
+            self.block[self.block_len] = *byte;
+            self.block_len += 1;
+            if self.block_len == 64 {
+                self.process_block();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let bit_len = self.len.wrapping_mul(8);
+
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0x00]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0; 20];
+        for (i, word) in self.state.iter().enumerate() {
+            digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        return digest;
+    }
+
+    fn process_block(&mut self) {
+        let mut w = [0u32; 80];
+        for (i, chunk) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn test_crc32_incremental() {
+        let crc = Crc32::new().update(b"1234").update(b"56789").finish();
+        assert_eq!(crc, 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(
+            to_hex(&sha1(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            to_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            to_hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_sha1_incremental_over_multiple_blocks() {
+        let data = vec![0x61; 1000];
+        let mut hasher = Sha1::new();
+        hasher.update(&data[..3]);
+        hasher.update(&data[3..700]);
+        hasher.update(&data[700..]);
+
+        assert_eq!(hasher.finish(), sha1(&data));
+    }
+}
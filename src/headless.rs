@@ -0,0 +1,332 @@
+//! Runs a ROM with no window or audio device: scripted input in, screenshot, per-frame hashes
+//! and audio out. Meant for regression testing games in CI.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::audio::AudioOutput;
+use crate::controller::Buttons;
+use crate::hash::Crc32;
+use crate::input::four_score::FourScore;
+use crate::input::{Device, Port};
+use crate::loader::{load_rom, LoadedRom};
+use crate::nes::Nes;
+use crate::palette::Palette;
+use crate::png::Image;
+use crate::ppu::Frame;
+use crate::rom_db::HeaderCorrection;
+use crate::wav;
+
+pub const USAGE: &str = "usage: nes-headless <rom> [--frames N] [--screenshot out.png] \
+[--wav out.wav] [--hashes out.txt] [--input script.txt] [--sample-rate HZ] [--soft-patch]";
+
+const DEFAULT_FRAMES: u32 = 600;
+const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+/// Rates sound cards and WAV players commonly handle.
+const SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8_000..=192_000;
+const PLAYERS: usize = 4;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub frames: u32,
+    pub screenshot: Option<PathBuf>,
+    pub wav: Option<PathBuf>,
+    /// One line per frame: the frame number and the CRC-32 of its pixels.
+    pub hashes: Option<PathBuf>,
+    pub input: Option<PathBuf>,
+    pub sample_rate: u32,
+    /// Applies a patch sitting next to the rom, see `patch::load_with_soft_patch`.
+    pub soft_patch: bool,
+}
+
+impl Options {
+    /// Parses the command line arguments, without the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut rom = None;
+        let mut frames = DEFAULT_FRAMES;
+        let mut screenshot = None;
+        let mut wav = None;
+        let mut hashes = None;
+        let mut input = None;
+        let mut sample_rate = DEFAULT_SAMPLE_RATE;
+        let mut soft_patch = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--frames" => frames = parse_number(&value()?)?,
+                "--screenshot" => screenshot = Some(PathBuf::from(value()?)),
+                "--wav" => wav = Some(PathBuf::from(value()?)),
+                "--hashes" => hashes = Some(PathBuf::from(value()?)),
+                "--input" => input = Some(PathBuf::from(value()?)),
+                "--sample-rate" => sample_rate = parse_number(&value()?)?,
+                "--soft-patch" => soft_patch = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+
+        if !SAMPLE_RATES.contains(&sample_rate) {
+            return Err(format!(
+                "sample rate must be {} to {} Hz",
+                SAMPLE_RATES.start(),
+                SAMPLE_RATES.end()
+            ));
+        }
+
+        return Ok(Options {
+            rom: rom.ok_or("no rom given".to_string())?,
+            frames,
+            screenshot,
+            wav,
+            hashes,
+            input,
+            sample_rate,
+            soft_patch,
+        });
+    }
+}
+
+fn parse_number(value: &str) -> Result<u32, String> {
+    return value
+        .parse()
+        .map_err(|_| format!("invalid number: {}", value));
+}
+
+/// Per-frame controller input. Each line is a frame number followed by the buttons of players
+/// 1 to 4, held from that frame until a later line changes them:
+///
+/// ```text
+/// # press Start for two frames, then hold Right while tapping A
+/// 60 Start
+/// 62 .
+/// 90 Right+A
+/// 91 Right
+/// ```
+///
+/// Buttons are `A`, `B`, `Select`, `Start`, `Up`, `Down`, `Left` and `Right` joined with `+`,
+/// `.` meaning none. Missing players hold nothing.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct InputScript {
+    /// (first frame, buttons per player), sorted by frame.
+    changes: Vec<(u32, [Buttons; PLAYERS])>,
+    players: usize,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut script = InputScript::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+
+            let mut columns = line.split_whitespace();
+            let frame = parse_number(columns.next().unwrap()).map_err(error)?;
+            if let Some((last, _)) = script.changes.last() {
+                if frame <= *last {
+                    return Err(error(format!(
+                        "frame {} is not after frame {}",
+                        frame, last
+                    )));
+                }
+            }
+
+            let mut buttons = [Buttons::empty(); PLAYERS];
+            for (player, column) in columns.enumerate() {
+                if player == PLAYERS {
+                    return Err(error(format!("more than {} players", PLAYERS)));
+                }
+                buttons[player] = Buttons::parse(column).map_err(error)?;
+                script.players = script.players.max(player + 1);
+            }
+            script.changes.push((frame, buttons));
+        }
+        return Ok(script);
+    }
+
+    /// Number of players the script has columns for.
+    pub fn players(&self) -> usize {
+        return self.players;
+    }
+
+    /// The buttons held by each player during `frame`.
+    pub fn buttons(&self, frame: u32) -> [Buttons; PLAYERS] {
+        return self
+            .changes
+            .iter()
+            .take_while(|(first, _)| *first <= frame)
+            .last()
+            .map_or([Buttons::empty(); PLAYERS], |(_, buttons)| *buttons);
+    }
+}
+
+/// CRC-32 of a frame's palette indices and emphasis bits.
+pub fn frame_hash(frame: &Frame) -> u32 {
+    return Crc32::new()
+        .update(&frame.pixels)
+        .update(&frame.emphasis)
+        .finish();
+}
+
+pub struct Run {
+    pub last_frame: Frame,
+    pub frame_hashes: Vec<u32>,
+    pub audio: Vec<f32>,
+}
+
+/// Runs `frames` frames, applying `script` before each one. A script with more than two players
+/// plugs in a Four Score.
+pub fn run(
+    nes: &mut Nes,
+    frames: u32,
+    script: &InputScript,
+    sample_rate: u32,
+) -> Result<Run, String> {
+    if script.players() > 2 {
+        nes.input_mut()
+            .connect(Port::One, Device::FourScore(FourScore::new()))
+            .expect("a Four Score fits port 1");
+    }
+
+    let mut audio_output = AudioOutput::new(nes.region(), sample_rate)?;
+    let mut frame_hashes = Vec::with_capacity(frames as usize);
+    let mut audio = vec![];
+    for frame in 0..frames {
+        for (player, buttons) in script.buttons(frame).iter().enumerate() {
+            nes.set_buttons(player, *buttons);
+        }
+        let (picture, samples) = nes
+            .run_frame()
+            .map_err(|e| format!("frame {}: {}", frame, e))?;
+        frame_hashes.push(frame_hash(picture));
+        audio.extend(audio_output.process(&samples));
+    }
+
+    return Ok(Run {
+        last_frame: nes.frame().clone(),
+        frame_hashes,
+        audio,
+    });
+}
+
+pub struct Summary {
+    pub last_frame_hash: u32,
+    /// Header fields the ROM database corrected, None when the dump is not in it.
+    pub corrections: Option<Vec<HeaderCorrection>>,
+}
+
+/// Does everything the options ask for.
+pub fn execute(options: &Options) -> Result<Summary, String> {
+    let LoadedRom { rom, corrections } = load_rom(&options.rom, options.soft_patch)?;
+    let palette = Palette::for_region(rom.region);
+    let script = match &options.input {
+        Some(path) => InputScript::parse(&read_text(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => InputScript::default(),
+    };
+
+    let mut nes = Nes::new(rom)?;
+    let run = run(&mut nes, options.frames, &script, options.sample_rate)?;
+
+    if let Some(path) = &options.screenshot {
+        Image::from_frame(&run.last_frame, &palette).save_png(path)?;
+    }
+    if let Some(path) = &options.wav {
+        wav::save(path, options.sample_rate, &run.audio)?;
+    }
+    if let Some(path) = &options.hashes {
+        let mut text = String::new();
+        for (frame, hash) in run.frame_hashes.iter().enumerate() {
+            writeln!(text, "{} {:08x}", frame, hash).unwrap();
+        }
+        fs::write(path, text).map_err(|e| format!("unable to write {}: {}", path.display(), e))?;
+    }
+    return Ok(Summary {
+        last_frame_hash: frame_hash(&run.last_frame),
+        corrections,
+    });
+}
+
+fn read_text(path: &Path) -> Result<String, String> {
+    return fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        return line.split_whitespace().map(String::from).collect();
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = Options::parse(args(
+            "game.nes --frames 10 --screenshot out.png --wav out.wav --input script.txt",
+        ))
+        .unwrap();
+
+        assert_eq!(options.rom, PathBuf::from("game.nes"));
+        assert_eq!(options.frames, 10);
+        assert_eq!(options.screenshot, Some(PathBuf::from("out.png")));
+        assert_eq!(options.wav, Some(PathBuf::from("out.wav")));
+        assert_eq!(options.hashes, None);
+        assert_eq!(options.input, Some(PathBuf::from("script.txt")));
+        assert_eq!(options.sample_rate, DEFAULT_SAMPLE_RATE);
+        assert!(!options.soft_patch);
+        assert!(
+            Options::parse(args("game.nes --soft-patch"))
+                .unwrap()
+                .soft_patch
+        );
+    }
+
+    #[test]
+    fn test_parse_options_errors() {
+        assert!(Options::parse(args("--frames 10")).is_err());
+        assert!(Options::parse(args("game.nes --frames")).is_err());
+        assert!(Options::parse(args("game.nes --frames ten")).is_err());
+        assert!(Options::parse(args("game.nes --fast")).is_err());
+        assert!(Options::parse(args("game.nes other.nes")).is_err());
+        assert_eq!(
+            Options::parse(args("game.nes --sample-rate 2000000")).unwrap_err(),
+            "sample rate must be 8000 to 192000 Hz"
+        );
+        assert!(Options::parse(args("game.nes --sample-rate 0")).is_err());
+        assert!(Options::parse(args("game.nes --sample-rate 48000")).is_ok());
+    }
+
+    #[test]
+    fn test_script_holds_buttons_until_changed() {
+        let script = InputScript::parse("# comment\n10 Start\n12 . Right+A\n\n20 .").unwrap();
+
+        assert_eq!(script.players(), 2);
+        assert_eq!(script.buttons(0)[0], Buttons::empty());
+        assert_eq!(script.buttons(10)[0], Buttons::Start);
+        assert_eq!(script.buttons(11)[0], Buttons::Start);
+        assert_eq!(script.buttons(12)[0], Buttons::empty());
+        assert_eq!(script.buttons(15)[1], Buttons::Right | Buttons::A);
+        assert_eq!(script.buttons(25)[1], Buttons::empty());
+    }
+
+    #[test]
+    fn test_script_errors_name_the_line() {
+        assert_eq!(
+            InputScript::parse("1 A\n2 Jump").unwrap_err(),
+            "line 2: unknown button Jump"
+        );
+        assert!(InputScript::parse("5 A\n3 B").is_err());
+        assert!(InputScript::parse("1 A B A B A").is_err());
+    }
+}
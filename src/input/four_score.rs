@@ -0,0 +1,110 @@
+use crate::controller::Controller;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Reads per port: two controllers, then the signature.
+const REPORT_BITS: u8 = 24;
+
+/// The NES Four Score multitap, plugged into both controller ports. Each port reports two
+/// controllers back to back (port 1: players 1 and 3, port 2: players 2 and 4), followed by a
+/// signature identifying the adapter: $10 on $4016, $20 on $4017, most significant bit first.
+#[derive(Default)]
+pub struct FourScore {
+    pub controllers: [Controller; 4],
+    reads: [u8; 2],
+    strobe: bool,
+}
+
+impl FourScore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        for controller in self.controllers.iter_mut() {
+            controller.write(data);
+        }
+        if self.strobe {
+            self.reads = [0; 2];
+        }
+    }
+
+    /// Serial bit for `port` 0 ($4016) or 1 ($4017).
+    pub fn read(&mut self, port: usize) -> u8 {
+        if self.strobe {
+            return self.controllers[port].read();
+        }
+
+        let read = self.reads[port];
+        if read >= REPORT_BITS {
+            return 1;
+        }
+        self.reads[port] += 1;
+        return match read {
+            0..=7 => self.controllers[port].read(),
+            8..=15 => self.controllers[port + 2].read(),
+            _ => {
+                let signature: u8 = if port == 0 { 0x10 } else { 0x20 };
+                (signature >> (7 - (read - 16))) & 1
+            }
+        };
+    }
+}
+
+impl SaveState for FourScore {
+    fn save(&self, w: &mut StateWriter) {
+        for controller in &self.controllers {
+            controller.save(w);
+        }
+        w.bytes(&self.reads);
+        w.bool(self.strobe);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for controller in self.controllers.iter_mut() {
+            controller.load(r)?;
+        }
+        r.bytes(&mut self.reads)?;
+        self.strobe = r.bool()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Buttons;
+
+    fn report(four_score: &mut FourScore, port: usize) -> Vec<u8> {
+        four_score.write(1);
+        four_score.write(0);
+        return (0..26).map(|_| four_score.read(port)).collect();
+    }
+
+    #[test]
+    fn test_port_1_reports_players_1_and_3_then_signature() {
+        let mut four_score = FourScore::new();
+        four_score.controllers[0].set_buttons(Buttons::A);
+        four_score.controllers[2].set_buttons(Buttons::Right);
+
+        let bits = report(&mut four_score, 0);
+
+        assert_eq!(bits[0..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[8..16], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bits[16..24], [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(bits[24..], [1, 1]);
+    }
+
+    #[test]
+    fn test_port_2_reports_players_2_and_4_then_signature() {
+        let mut four_score = FourScore::new();
+        four_score.controllers[1].set_buttons(Buttons::B);
+        four_score.controllers[3].set_buttons(Buttons::Start);
+
+        let bits = report(&mut four_score, 1);
+
+        assert_eq!(bits[0..8], [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[8..16], [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(bits[16..24], [0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+}
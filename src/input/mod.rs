@@ -0,0 +1,370 @@
+pub mod four_score;
+pub mod vaus;
+pub mod zapper;
+
+use crate::controller::Controller;
+use crate::ppu::Ppu;
+use crate::state::{SaveState, StateReader, StateWriter};
+use four_score::FourScore;
+use vaus::Vaus;
+use zapper::Zapper;
+
+const JOYPAD1: u16 = 0x4016;
+
+/// Where a device is plugged in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Port {
+    /// Controller port 1, read through $4016.
+    One,
+    /// Controller port 2, read through $4017.
+    Two,
+    /// The Famicom's 15-pin expansion port, which can drive bits of both $4016 and $4017.
+    Expansion,
+}
+
+pub enum Device {
+    Empty,
+    Controller(Controller),
+    /// Occupies both controller ports, so it can only be connected to port 1.
+    FourScore(FourScore),
+    Zapper(Zapper),
+    Vaus(Vaus),
+}
+
+impl Device {
+    /// Bits 0-4 of a read of $4016 (`port` 0) or $4017 (`port` 1) for a device in a
+    /// controller port.
+    fn read_port(&mut self, port: usize, ppu: Option<&Ppu>) -> u8 {
+        match self {
+            Device::Empty => 0,
+            Device::Controller(controller) => controller.read(),
+            Device::FourScore(four_score) => four_score.read(port),
+            Device::Zapper(zapper) => zapper.read(ppu),
+            Device::Vaus(vaus) => {
+                let fire = if vaus.fire() { 0b1_0000 } else { 0 };
+                fire | (vaus.read_serial() << 3)
+            }
+        }
+    }
+
+    /// The same for a device in the expansion port, which has its own wiring.
+    fn read_expansion(&mut self, port: usize, ppu: Option<&Ppu>) -> u8 {
+        match (self, port) {
+            // a third controller shows up on D1 of $4016
+            (Device::Controller(controller), 0) => controller.read() << 1,
+            // the Famicom Zapper uses the NES Zapper's bits on $4017
+            (Device::Zapper(zapper), 1) => zapper.read(ppu),
+            (Device::Vaus(vaus), 0) => (vaus.fire() as u8) << 1,
+            (Device::Vaus(vaus), 1) => vaus.read_serial() << 1,
+            _ => 0,
+        }
+    }
+
+    /// Identifies the kind of device in save states.
+    fn kind(&self) -> u8 {
+        match self {
+            Device::Empty => 0,
+            Device::Controller(_) => 1,
+            Device::FourScore(_) => 2,
+            Device::Zapper(_) => 3,
+            Device::Vaus(_) => 4,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        match self {
+            Device::Empty | Device::Zapper(_) => {}
+            Device::Controller(controller) => controller.write(data),
+            Device::FourScore(four_score) => four_score.write(data),
+            Device::Vaus(vaus) => vaus.write(data),
+        }
+    }
+}
+
+impl SaveState for Device {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.kind());
+        match self {
+            Device::Empty => {}
+            Device::Controller(controller) => controller.save(w),
+            Device::FourScore(four_score) => four_score.save(w),
+            Device::Zapper(zapper) => zapper.save(w),
+            Device::Vaus(vaus) => vaus.save(w),
+        }
+    }
+
+    /// Plugs in a new device first if the state has a different kind in this port.
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let kind = r.u8()?;
+        if kind != self.kind() {
+            *self = match kind {
+                0 => Device::Empty,
+                1 => Device::Controller(Controller::new()),
+                2 => Device::FourScore(FourScore::new()),
+                3 => Device::Zapper(Zapper::new()),
+                4 => Device::Vaus(Vaus::new()),
+                _ => return Err(r.error(&format!("invalid device kind {}", kind))),
+            };
+        }
+        return match self {
+            Device::Empty => Ok(()),
+            Device::Controller(controller) => controller.load(r),
+            Device::FourScore(four_score) => four_score.load(r),
+            Device::Zapper(zapper) => zapper.load(r),
+            Device::Vaus(vaus) => vaus.load(r),
+        };
+    }
+}
+
+/// Everything plugged into the console, behind $4016 (writes strobe all devices) and
+/// $4016/$4017 reads. Starts with a standard controller in each port.
+pub struct Input {
+    port1: Device,
+    port2: Device,
+    expansion: Device,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            port1: Device::Controller(Controller::new()),
+            port2: Device::Controller(Controller::new()),
+            expansion: Device::Empty,
+        }
+    }
+
+    /// Plugs `device` into `port`, replacing what was there.
+    pub fn connect(&mut self, port: Port, device: Device) -> Result<(), String> {
+        let is_four_score = matches!(device, Device::FourScore(_));
+        match port {
+            Port::One => {
+                if is_four_score {
+                    self.port2 = Device::Empty;
+                }
+                self.port1 = device;
+            }
+            Port::Two | Port::Expansion if is_four_score => {
+                return Err("the Four Score connects to port 1 and covers both ports".to_string())
+            }
+            Port::Two => {
+                if matches!(self.port1, Device::FourScore(_)) {
+                    return Err("port 2 is taken by the Four Score".to_string());
+                }
+                self.port2 = device;
+            }
+            Port::Expansion => self.expansion = device,
+        }
+        return Ok(());
+    }
+
+    pub fn device(&self, port: Port) -> &Device {
+        match port {
+            Port::One => &self.port1,
+            Port::Two => &self.port2,
+            Port::Expansion => &self.expansion,
+        }
+    }
+
+    pub fn device_mut(&mut self, port: Port) -> &mut Device {
+        match port {
+            Port::One => &mut self.port1,
+            Port::Two => &mut self.port2,
+            Port::Expansion => &mut self.expansion,
+        }
+    }
+
+    /// The controller of `player` (0-3): players 1 and 2 sit in the controller ports, 3 and 4
+    /// behind a Four Score (or player 3 in the Famicom expansion port).
+    pub fn controller(&self, player: usize) -> Option<&Controller> {
+        if let Device::FourScore(four_score) = &self.port1 {
+            return four_score.controllers.get(player);
+        }
+        let device = match player {
+            0 => &self.port1,
+            1 => &self.port2,
+            2 => &self.expansion,
+            _ => return None,
+        };
+        return match device {
+            Device::Controller(controller) => Some(controller),
+            _ => None,
+        };
+    }
+
+    pub fn controller_mut(&mut self, player: usize) -> Option<&mut Controller> {
+        if matches!(self.port1, Device::FourScore(_)) {
+            return match &mut self.port1 {
+                Device::FourScore(four_score) => four_score.controllers.get_mut(player),
+                _ => None,
+            };
+        }
+        let device = match player {
+            0 => &mut self.port1,
+            1 => &mut self.port2,
+            2 => &mut self.expansion,
+            _ => return None,
+        };
+        return match device {
+            Device::Controller(controller) => Some(controller),
+            _ => None,
+        };
+    }
+
+    /// The first Zapper found, port 2 being the usual place.
+    pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+        for device in [&mut self.port2, &mut self.port1, &mut self.expansion] {
+            if let Device::Zapper(zapper) = device {
+                return Some(zapper);
+            }
+        }
+        return None;
+    }
+
+    pub fn vaus_mut(&mut self) -> Option<&mut Vaus> {
+        for device in [&mut self.port2, &mut self.port1, &mut self.expansion] {
+            if let Device::Vaus(vaus) = device {
+                return Some(vaus);
+            }
+        }
+        return None;
+    }
+
+    /// A write to $4016.
+    pub fn write(&mut self, data: u8) {
+        self.port1.write(data);
+        self.port2.write(data);
+        self.expansion.write(data);
+    }
+
+    /// Bits 0-4 of a read of $4016 or $4017; the PPU is what the Zapper looks at.
+    pub fn read(&mut self, addr: u16, ppu: Option<&Ppu>) -> u8 {
+        let port = (addr - JOYPAD1) as usize;
+        let device = if port == 0 || matches!(self.port1, Device::FourScore(_)) {
+            &mut self.port1
+        } else {
+            &mut self.port2
+        };
+        let bits = device.read_port(port, ppu);
+        return bits | self.expansion.read_expansion(port, ppu);
+    }
+}
+
+impl SaveState for Input {
+    fn save(&self, w: &mut StateWriter) {
+        self.port1.save(w);
+        self.port2.save(w);
+        self.expansion.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.port1.load(r)?;
+        self.port2.load(r)?;
+        self.expansion.load(r)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Buttons;
+
+    fn strobe(input: &mut Input) {
+        input.write(1);
+        input.write(0);
+    }
+
+    #[test]
+    fn test_standard_controllers_by_default() {
+        let mut input = Input::new();
+        input.controller_mut(1).unwrap().set_buttons(Buttons::A);
+        strobe(&mut input);
+
+        assert_eq!(input.read(0x4016, None), 0);
+        assert_eq!(input.read(0x4017, None), 1);
+    }
+
+    #[test]
+    fn test_four_score_serves_both_ports() {
+        let mut input = Input::new();
+        input
+            .connect(Port::One, Device::FourScore(FourScore::new()))
+            .unwrap();
+        input.controller_mut(3).unwrap().set_buttons(Buttons::A);
+        strobe(&mut input);
+
+        let bits: Vec<u8> = (0..9).map(|_| input.read(0x4017, None)).collect();
+
+        assert_eq!(bits[8], 1);
+        assert!(matches!(input.device(Port::Two), Device::Empty));
+    }
+
+    #[test]
+    fn test_four_score_only_fits_port_1() {
+        let mut input = Input::new();
+
+        assert!(input
+            .connect(Port::Two, Device::FourScore(FourScore::new()))
+            .is_err());
+
+        input
+            .connect(Port::One, Device::FourScore(FourScore::new()))
+            .unwrap();
+        assert!(input
+            .connect(Port::Two, Device::Controller(Controller::new()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_zapper_in_port_2() {
+        let mut input = Input::new();
+        input
+            .connect(Port::Two, Device::Zapper(Zapper::new()))
+            .unwrap();
+        input.zapper_mut().unwrap().set_trigger(true);
+
+        assert_eq!(input.read(0x4017, None), 0b1_1000);
+        assert_eq!(input.read(0x4016, None), 0);
+    }
+
+    #[test]
+    fn test_vaus_in_port_2() {
+        let mut input = Input::new();
+        input.connect(Port::Two, Device::Vaus(Vaus::new())).unwrap();
+        let vaus = input.vaus_mut().unwrap();
+        vaus.set_position(0x80);
+        vaus.set_fire(true);
+        strobe(&mut input);
+
+        // !$80 = $7F
+        assert_eq!(input.read(0x4017, None), 0b1_0000);
+        assert_eq!(input.read(0x4017, None), 0b1_1000);
+    }
+
+    #[test]
+    fn test_famicom_expansion_devices() {
+        let mut input = Input::new();
+        input
+            .connect(Port::Expansion, Device::Controller(Controller::new()))
+            .unwrap();
+        input.controller_mut(2).unwrap().set_buttons(Buttons::A);
+        strobe(&mut input);
+        assert_eq!(input.read(0x4016, None), 0b10);
+
+        input
+            .connect(Port::Expansion, Device::Vaus(Vaus::new()))
+            .unwrap();
+        input.vaus_mut().unwrap().set_fire(true);
+        input.vaus_mut().unwrap().set_position(0x7F);
+        strobe(&mut input);
+        // fire on $4016 D1, the knob's first (inverted) bit on $4017 D1
+        assert_eq!(input.read(0x4016, None), 0b10);
+        assert_eq!(input.read(0x4017, None), 0b10);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Knob range of the Arkanoid controller, as reported by the game's calibration.
+pub const VAUS_MIN: u8 = 0x62;
+pub const VAUS_MAX: u8 = 0xF2;
+
+/// The Arkanoid "Vaus" paddle. Strobing latches the potentiometer into a shift register
+/// that is read back serially, most significant bit first and inverted.
+/// On the NES port: D3 serial data, D4 fire button.
+/// On the Famicom expansion port: $4017 D1 serial data, $4016 D1 fire button.
+pub struct Vaus {
+    position: u8,
+    fire: bool,
+    shift_register: u8,
+}
+
+impl Default for Vaus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vaus {
+    pub fn new() -> Self {
+        Vaus {
+            position: VAUS_MIN,
+            fire: false,
+            shift_register: 0,
+        }
+    }
+
+    /// Knob position, clamped to `VAUS_MIN..=VAUS_MAX`.
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position.clamp(VAUS_MIN, VAUS_MAX);
+    }
+
+    pub fn position(&self) -> u8 {
+        return self.position;
+    }
+
+    pub fn set_fire(&mut self, pressed: bool) {
+        self.fire = pressed;
+    }
+
+    pub fn fire(&self) -> bool {
+        return self.fire;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        if data & 1 != 0 {
+            self.shift_register = !self.position;
+        }
+    }
+
+    /// Next potentiometer bit, 0 or 1.
+    pub fn read_serial(&mut self) -> u8 {
+        let bit = self.shift_register >> 7;
+        self.shift_register <<= 1;
+        return bit;
+    }
+}
+
+impl SaveState for Vaus {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.position);
+        w.bool(self.fire);
+        w.u8(self.shift_register);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.set_position(r.u8()?);
+        self.fire = r.bool()?;
+        self.shift_register = r.u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_is_read_msb_first_inverted() {
+        let mut vaus = Vaus::new();
+        vaus.set_position(0xA5);
+        vaus.write(1);
+        vaus.write(0);
+
+        let bits: Vec<u8> = (0..8).map(|_| vaus.read_serial()).collect();
+
+        // !$A5 = $5A
+        assert_eq!(bits, vec![0, 1, 0, 1, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_position_is_clamped() {
+        let mut vaus = Vaus::new();
+
+        vaus.set_position(0x00);
+        assert_eq!(vaus.position(), VAUS_MIN);
+        vaus.set_position(0xFF);
+        assert_eq!(vaus.position(), VAUS_MAX);
+    }
+}
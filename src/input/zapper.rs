@@ -0,0 +1,191 @@
+use crate::palette::Palette;
+use crate::ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Pixels around the aim point the photodiode sees.
+const SENSOR_RADIUS: i32 = 2;
+/// Scanlines the photodiode keeps reporting light after the beam lit the aim point.
+const LIGHT_SCANLINES: u16 = 20;
+/// Luma, 0-255, a pixel needs to register.
+const BRIGHTNESS_THRESHOLD: u32 = 0x80;
+
+/// The Zapper light gun. The host aims it at a framebuffer position and pulls the trigger;
+/// the photodiode senses light when the beam recently drew bright pixels around the aim point.
+///  D3: light sensed (0) / not sensed (1)
+///  D4: trigger pulled (1)
+pub struct Zapper {
+    aim: Option<(usize, usize)>,
+    trigger: bool,
+    palette: Palette,
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            aim: None,
+            trigger: false,
+            palette: Palette::ntsc(),
+        }
+    }
+
+    /// Screen position the gun points at, `None` when aimed away from the screen.
+    pub fn aim(&mut self, position: Option<(usize, usize)>) {
+        self.aim = position.filter(|&(x, y)| x < SCREEN_WIDTH && y < SCREEN_HEIGHT);
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// D3 and D4 as seen on the port.
+    pub fn read(&self, ppu: Option<&Ppu>) -> u8 {
+        let light = ppu.is_some_and(|ppu| self.senses_light(ppu));
+        let mut bits = 0;
+        if !light {
+            bits |= 0b0000_1000;
+        }
+        if self.trigger {
+            bits |= 0b0001_0000;
+        }
+        return bits;
+    }
+
+    fn senses_light(&self, ppu: &Ppu) -> bool {
+        let (aim_x, aim_y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+
+        for dy in -SENSOR_RADIUS..=SENSOR_RADIUS {
+            for dx in -SENSOR_RADIUS..=SENSOR_RADIUS {
+                let x = aim_x as i32 + dx;
+                let y = aim_y as i32 + dy;
+                if x < 0 || y < 0 || x >= SCREEN_WIDTH as i32 || y >= SCREEN_HEIGHT as i32 {
+                    continue;
+                }
+                let (x, y) = (x as usize, y as usize);
+                if recently_drawn(ppu, x, y) && self.is_bright(ppu, x, y) {
+                    return true;
+                }
+            }
+        }
+        return false;
+    }
+
+    fn is_bright(&self, ppu: &Ppu, x: usize, y: usize) -> bool {
+        let frame = ppu.frame();
+        let color = self
+            .palette
+            .color(frame.get_pixel(x, y), frame.get_emphasis(x, y));
+        let luma = (color.r as u32 * 299 + color.g as u32 * 587 + color.b as u32 * 114) / 1000;
+        return luma >= BRIGHTNESS_THRESHOLD;
+    }
+}
+
+/// Whether the beam drew pixel (x, y) of the current frame in the last `LIGHT_SCANLINES` lines.
+fn recently_drawn(ppu: &Ppu, x: usize, y: usize) -> bool {
+    let (scanline, dot) = (ppu.scanline as usize, ppu.dot as usize);
+    let drawn = y < scanline || (y == scanline && x + 1 < dot);
+    return drawn && scanline - y < LIGHT_SCANLINES as usize;
+}
+
+/// The aim and trigger; the palette it judges brightness with is configuration.
+impl SaveState for Zapper {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.aim.is_some());
+        let (x, y) = self.aim.unwrap_or((0, 0));
+        w.u16(x as u16);
+        w.u16(y as u16);
+        w.bool(self.trigger);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let aimed = r.bool()?;
+        let position = (r.u16()? as usize, r.u16()? as usize);
+        self.aim(aimed.then_some(position));
+        self.trigger = r.bool()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::ppu::test::{test_ppu, write_bytes};
+
+    /// A PPU showing a backdrop of `color`, with the beam stopped at (`scanline`, `dot`).
+    fn screen_at(color: u8, scanline: u16, dot: u16) -> Ppu {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x3F00, &[color]);
+        ppu.write_register(0x2001, 0x0A);
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.tick(1);
+        }
+        return ppu;
+    }
+
+    #[test]
+    fn test_trigger() {
+        let mut zapper = Zapper::new();
+        zapper.set_trigger(true);
+
+        assert_eq!(zapper.read(None), 0b0001_1000);
+    }
+
+    #[test]
+    fn test_senses_light_just_after_the_beam_passes() {
+        let ppu = screen_at(0x30, 105, 0);
+        let mut zapper = Zapper::new();
+
+        zapper.aim(Some((128, 100)));
+
+        assert_eq!(zapper.read(Some(&ppu)), 0b0000_0000);
+    }
+
+    #[test]
+    fn test_no_light_before_the_beam_gets_there() {
+        let ppu = screen_at(0x30, 50, 0);
+        let mut zapper = Zapper::new();
+
+        zapper.aim(Some((128, 100)));
+
+        assert_eq!(zapper.read(Some(&ppu)), 0b0000_1000);
+    }
+
+    #[test]
+    fn test_light_fades_after_a_few_scanlines() {
+        let ppu = screen_at(0x30, 150, 0);
+        let mut zapper = Zapper::new();
+
+        zapper.aim(Some((128, 100)));
+
+        assert_eq!(zapper.read(Some(&ppu)), 0b0000_1000);
+    }
+
+    #[test]
+    fn test_dark_pixels_are_not_sensed() {
+        let ppu = screen_at(0x0D, 105, 0);
+        let mut zapper = Zapper::new();
+
+        zapper.aim(Some((128, 100)));
+
+        assert_eq!(zapper.read(Some(&ppu)), 0b0000_1000);
+    }
+
+    #[test]
+    fn test_aiming_off_screen() {
+        let ppu = screen_at(0x30, 105, 0);
+        let mut zapper = Zapper::new();
+
+        zapper.aim(Some((300, 100)));
+
+        assert_eq!(zapper.read(Some(&ppu)), 0b0000_1000);
+    }
+}
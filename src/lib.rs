@@ -1,3 +1,28 @@
+#![allow(clippy::needless_return)]
+
+pub mod apu;
+pub mod audio;
+pub mod capi;
+pub mod cartridge;
+pub mod controller;
 pub mod cpu;
+pub mod hash;
+pub mod headless;
+pub mod input;
+pub mod libretro;
+pub mod loader;
+pub mod mapper;
 pub mod memory;
+pub mod movie;
+pub mod nes;
 pub mod operation;
+pub mod palette;
+pub mod patch;
+pub mod png;
+pub mod ppu;
+pub mod rewind;
+pub mod rom_db;
+pub mod state;
+pub mod terminal;
+pub mod unif;
+pub mod wav;
@@ -0,0 +1,584 @@
+//! The libretro core API, exported from the `cdylib` build so RetroArch and other libretro
+//! frontends can load the emulator. Frontends call in from a single thread, which is what makes
+//! the global state below sound.
+//!
+//! Panics must not unwind into the frontend: entry points that run the emulation or touch its
+//! state catch them, and the game then stops running until another one is loaded.
+
+use std::cell::UnsafeCell;
+use std::ffi::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::apu::cpu_clock_rate;
+use crate::audio::AudioOutput;
+use crate::cartridge::Region;
+use crate::controller::Buttons;
+use crate::loader::{parse_rom, LoadedRom};
+use crate::nes::Nes;
+use crate::palette::{Palette, PixelFormat};
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const RETRO_API_VERSION: u32 = 1;
+
+pub const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: u32 = 10;
+pub const RETRO_ENVIRONMENT_EXPERIMENTAL: u32 = 0x10000;
+pub const RETRO_ENVIRONMENT_SET_MEMORY_MAPS: u32 = 36 | RETRO_ENVIRONMENT_EXPERIMENTAL;
+pub const RETRO_PIXEL_FORMAT_XRGB8888: u32 = 1;
+
+pub const RETRO_DEVICE_JOYPAD: u32 = 1;
+pub const RETRO_DEVICE_ID_JOYPAD_B: u32 = 0;
+pub const RETRO_DEVICE_ID_JOYPAD_SELECT: u32 = 2;
+pub const RETRO_DEVICE_ID_JOYPAD_START: u32 = 3;
+pub const RETRO_DEVICE_ID_JOYPAD_UP: u32 = 4;
+pub const RETRO_DEVICE_ID_JOYPAD_DOWN: u32 = 5;
+pub const RETRO_DEVICE_ID_JOYPAD_LEFT: u32 = 6;
+pub const RETRO_DEVICE_ID_JOYPAD_RIGHT: u32 = 7;
+pub const RETRO_DEVICE_ID_JOYPAD_A: u32 = 8;
+
+pub const RETRO_REGION_NTSC: u32 = 0;
+pub const RETRO_REGION_PAL: u32 = 1;
+
+pub const RETRO_MEMORY_SAVE_RAM: u32 = 0;
+pub const RETRO_MEMORY_SYSTEM_RAM: u32 = 2;
+
+pub const RETRO_MEMDESC_SAVE_RAM: u64 = 1 << 1;
+pub const RETRO_MEMDESC_SYSTEM_RAM: u64 = 1 << 2;
+
+const SAMPLE_RATE: u32 = 48_000;
+/// CPU cycles per frame: 341 x 262 dots (one skipped every other frame) / 3 on NTSC,
+/// 341 x 312 / 3.2 on PAL.
+const NTSC_CPU_CYCLES_PER_FRAME: f64 = 29_780.5;
+const PAL_CPU_CYCLES_PER_FRAME: f64 = 33_247.5;
+
+/// Joypad ids in the order of the controller's shift register.
+const JOYPAD_BUTTONS: [(u32, Buttons); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_A, Buttons::A),
+    (RETRO_DEVICE_ID_JOYPAD_B, Buttons::B),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, Buttons::Select),
+    (RETRO_DEVICE_ID_JOYPAD_START, Buttons::Start),
+    (RETRO_DEVICE_ID_JOYPAD_UP, Buttons::Up),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, Buttons::Down),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, Buttons::Left),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, Buttons::Right),
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: u32,
+    pub base_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct RetroMemoryDescriptor {
+    pub flags: u64,
+    pub ptr: *mut c_void,
+    pub offset: usize,
+    pub start: usize,
+    pub select: usize,
+    pub disconnect: usize,
+    pub len: usize,
+    pub addrspace: *const c_char,
+}
+
+#[repr(C)]
+pub struct RetroMemoryMap {
+    pub descriptors: *const RetroMemoryDescriptor,
+    pub num_descriptors: u32,
+}
+
+pub type RetroEnvironment = unsafe extern "C" fn(cmd: u32, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh =
+    unsafe extern "C" fn(data: *const c_void, width: u32, height: u32, pitch: usize);
+pub type RetroAudioSample = unsafe extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = unsafe extern "C" fn();
+pub type RetroInputState = unsafe extern "C" fn(port: u32, device: u32, index: u32, id: u32) -> i16;
+
+/// Global state only ever touched from the frontend's thread.
+struct Global<T>(UnsafeCell<T>);
+
+unsafe impl<T> Sync for Global<T> {}
+
+impl<T> Global<T> {
+    /// # Safety
+    /// The caller must not hold another reference obtained from this cell.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get(&self) -> &mut T {
+        return &mut *self.0.get();
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample: Option<RetroAudioSample>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+struct Core {
+    nes: Nes,
+    palette: Palette,
+    audio: AudioOutput,
+    /// XRGB8888, as handed to the video callback.
+    video: Vec<u8>,
+    /// Interleaved stereo, as handed to the audio callback.
+    audio_buffer: Vec<i16>,
+    battery: bool,
+    /// Kept alive for as long as the frontend may read the map.
+    memory_descriptors: Vec<RetroMemoryDescriptor>,
+    /// Set when the emulation panicked or ran an opcode the CPU does not implement. The core
+    /// stays loaded, so the frontend can still read its memory, but no longer runs or saves.
+    failed: bool,
+}
+
+static CALLBACKS: Global<Callbacks> = Global(UnsafeCell::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+}));
+
+static CORE: Global<Option<Core>> = Global(UnsafeCell::new(None));
+
+/// Set while an entry point borrows the core. A frontend calling back in from one of its
+/// callbacks then finds no core instead of a second mutable reference to it.
+static CORE_BUSY: AtomicBool = AtomicBool::new(false);
+
+/// A copy of the callbacks, so calling one never holds a reference into `CALLBACKS`.
+fn callbacks() -> Callbacks {
+    return unsafe { *CALLBACKS.get() };
+}
+
+fn set_callbacks(set: impl FnOnce(&mut Callbacks)) {
+    // nothing else borrows CALLBACKS: callbacks() copies them out
+    set(unsafe { CALLBACKS.get() });
+}
+
+/// Runs `f` on the loaded core, `None` when there is none or it is already borrowed. A panic
+/// in `f` is caught and marks the core failed.
+fn with_core<R>(f: impl FnOnce(&mut Core) -> R) -> Option<R> {
+    if CORE_BUSY.swap(true, Ordering::Acquire) {
+        return None;
+    }
+    let result = match unsafe { CORE.get().as_mut() } {
+        Some(core) => match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *core))) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                core.failed = true;
+                None
+            }
+        },
+        None => None,
+    };
+    CORE_BUSY.store(false, Ordering::Release);
+    return result;
+}
+
+/// `with_core` for a core that has not failed.
+fn with_running_core<R>(f: impl FnOnce(&mut Core) -> R) -> Option<R> {
+    return with_core(|core| if core.failed { None } else { Some(f(core)) }).flatten();
+}
+
+fn environment(cmd: u32, data: *mut c_void) -> bool {
+    return match callbacks().environment {
+        Some(environment) => unsafe { environment(cmd, data) },
+        None => false,
+    };
+}
+
+fn frame_rate(region: Region) -> f64 {
+    let cycles_per_frame = match region {
+        Region::Pal => PAL_CPU_CYCLES_PER_FRAME,
+        Region::Ntsc | Region::Multi | Region::Dendy => NTSC_CPU_CYCLES_PER_FRAME,
+    };
+    return cpu_clock_rate(region) / cycles_per_frame;
+}
+
+impl Core {
+    fn read_joypad(port: u32) -> Buttons {
+        let Some(input_state) = callbacks().input_state else {
+            return Buttons::empty();
+        };
+        let mut buttons = Buttons::empty();
+        for (id, button) in JOYPAD_BUTTONS {
+            if unsafe { input_state(port, RETRO_DEVICE_JOYPAD, 0, id) } != 0 {
+                buttons |= button;
+            }
+        }
+        return buttons;
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        if let Some(input_poll) = callbacks().input_poll {
+            unsafe { input_poll() };
+        }
+        for port in 0..2 {
+            self.nes.set_buttons(port, Core::read_joypad(port as u32));
+        }
+
+        let (frame, samples) = self.nes.run_frame()?;
+        // BGRA in memory is XRGB8888 read as a little-endian u32
+        self.video = self.palette.convert_frame(frame, PixelFormat::Bgra8888);
+        let samples = self.audio.process(&samples);
+
+        if let Some(video_refresh) = callbacks().video_refresh {
+            let pitch = SCREEN_WIDTH * PixelFormat::Bgra8888.bytes_per_pixel();
+            unsafe {
+                video_refresh(
+                    self.video.as_ptr() as *const c_void,
+                    SCREEN_WIDTH as u32,
+                    SCREEN_HEIGHT as u32,
+                    pitch,
+                )
+            };
+        }
+
+        self.audio_buffer.clear();
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.audio_buffer.extend([value, value]);
+        }
+        if let Some(audio_sample_batch) = callbacks().audio_sample_batch {
+            let mut written = 0;
+            while written < self.audio_buffer.len() / 2 {
+                let frames = self.audio_buffer.len() / 2 - written;
+                let accepted = unsafe {
+                    audio_sample_batch(self.audio_buffer[written * 2..].as_ptr(), frames)
+                };
+                if accepted == 0 {
+                    break;
+                }
+                written += accepted;
+            }
+        } else if let Some(audio_sample) = callbacks().audio_sample {
+            for pair in self.audio_buffer.chunks_exact(2) {
+                unsafe { audio_sample(pair[0], pair[1]) };
+            }
+        }
+        return Ok(());
+    }
+
+    /// (pointer, length) of a `RETRO_MEMORY_*` area, null and 0 when there is none.
+    fn memory(&mut self, id: u32) -> (*mut u8, usize) {
+        match id {
+            RETRO_MEMORY_SAVE_RAM if self.battery => match self.nes.prg_ram_mut() {
+                Some(mut prg_ram) => (prg_ram.as_mut_ptr(), prg_ram.len()),
+                None => (ptr::null_mut(), 0),
+            },
+            RETRO_MEMORY_SYSTEM_RAM => {
+                let ram = self.nes.ram_mut();
+                (ram.as_mut_ptr(), ram.len())
+            }
+            _ => (ptr::null_mut(), 0),
+        }
+    }
+
+    /// Describes the CPU address space for cheats and achievements: work RAM mirrored up to
+    /// $1FFF and the cartridge RAM at $6000.
+    fn set_memory_maps(&mut self) {
+        let (ram, ram_len) = self.memory(RETRO_MEMORY_SYSTEM_RAM);
+        let mut descriptors = vec![RetroMemoryDescriptor {
+            flags: RETRO_MEMDESC_SYSTEM_RAM,
+            ptr: ram as *mut c_void,
+            offset: 0,
+            start: 0x0000,
+            select: 0xE000,
+            disconnect: 0,
+            len: ram_len,
+            addrspace: ptr::null(),
+        }];
+        if let Some(mut prg_ram) = self.nes.prg_ram_mut() {
+            descriptors.push(RetroMemoryDescriptor {
+                flags: if self.battery {
+                    RETRO_MEMDESC_SAVE_RAM
+                } else {
+                    0
+                },
+                ptr: prg_ram.as_mut_ptr() as *mut c_void,
+                offset: 0,
+                start: 0x6000,
+                select: 0xE000,
+                disconnect: 0,
+                len: prg_ram.len(),
+                addrspace: ptr::null(),
+            });
+        }
+        self.memory_descriptors = descriptors;
+
+        let mut map = RetroMemoryMap {
+            descriptors: self.memory_descriptors.as_ptr(),
+            num_descriptors: self.memory_descriptors.len() as u32,
+        };
+        environment(
+            RETRO_ENVIRONMENT_SET_MEMORY_MAPS,
+            &mut map as *mut RetroMemoryMap as *mut c_void,
+        );
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> u32 {
+    return RETRO_API_VERSION;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: RetroEnvironment) {
+    set_callbacks(|callbacks| callbacks.environment = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefresh) {
+    set_callbacks(|callbacks| callbacks.video_refresh = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(callback: RetroAudioSample) {
+    set_callbacks(|callbacks| callbacks.audio_sample = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatch) {
+    set_callbacks(|callbacks| callbacks.audio_sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPoll) {
+    set_callbacks(|callbacks| callbacks.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: RetroInputState) {
+    set_callbacks(|callbacks| callbacks.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    retro_unload_game();
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c"nes-emulator".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"nes|unf|unif".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let region = with_core(|core| core.nes.region()).unwrap_or(Region::Ntsc);
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: SCREEN_WIDTH as u32,
+            base_height: SCREEN_HEIGHT as u32,
+            max_width: SCREEN_WIDTH as u32,
+            max_height: SCREEN_HEIGHT as u32,
+            aspect_ratio: 4.0 / 3.0,
+        },
+        timing: RetroSystemTiming {
+            fps: frame_rate(region),
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+/// Only standard joypads are supported.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: u32, _device: u32) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_running_core(|core| core.nes.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_running_core(|core| {
+        if core.run().is_err() {
+            core.failed = true;
+        }
+    });
+}
+
+/// States of a game all have the same size, the frontend allocates this much once.
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    return with_running_core(|core| core.nes.save_state().len()).unwrap_or(0);
+}
+
+/// # Safety
+/// `data` must be null or valid for `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let Some(state) = with_running_core(|core| core.nes.save_state()) else {
+        return false;
+    };
+    if state.len() > size {
+        return false;
+    }
+    let buffer = slice::from_raw_parts_mut(data as *mut u8, size);
+    buffer[..state.len()].copy_from_slice(&state);
+    // the rest reads back as padding
+    buffer[state.len()..].fill(0);
+    return true;
+}
+
+/// # Safety
+/// `data` must be null or valid for `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(data as *const u8, size);
+    return with_running_core(|core| core.nes.load_state(state).is_ok()).unwrap_or(false);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: u32, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a `retro_game_info` whose data is valid for its size.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    retro_unload_game();
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    if game.data.is_null() {
+        return false;
+    }
+    let raw = slice::from_raw_parts(game.data as *const u8, game.size);
+    let Ok(Some(core)) = panic::catch_unwind(|| new_core(raw)) else {
+        return false;
+    };
+    if !replace_core(Some(core)) {
+        return false;
+    }
+    return with_core(|core| core.set_memory_maps()).is_some();
+}
+
+fn new_core(raw: &[u8]) -> Option<Core> {
+    let LoadedRom { rom, .. } = parse_rom(raw).ok()?;
+
+    let mut pixel_format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut pixel_format as *mut u32 as *mut c_void,
+    ) {
+        return None;
+    }
+
+    let region = rom.region;
+    let battery = rom.battery;
+    let nes = Nes::new(rom).ok()?;
+    let audio = AudioOutput::new(region, SAMPLE_RATE).ok()?;
+    return Some(Core {
+        nes,
+        palette: Palette::for_region(region),
+        audio,
+        video: vec![],
+        audio_buffer: vec![],
+        battery,
+        memory_descriptors: vec![],
+        failed: false,
+    });
+}
+
+/// Swaps the loaded core, refused (returning false) while an entry point borrows it.
+fn replace_core(core: Option<Core>) -> bool {
+    if CORE_BUSY.swap(true, Ordering::Acquire) {
+        return false;
+    }
+    unsafe { *CORE.get() = core };
+    CORE_BUSY.store(false, Ordering::Release);
+    return true;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: u32,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    return false;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    replace_core(None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> u32 {
+    return match with_core(|core| core.nes.region()) {
+        Some(Region::Pal) => RETRO_REGION_PAL,
+        _ => RETRO_REGION_NTSC,
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: u32) -> *mut c_void {
+    return with_core(|core| core.memory(id).0 as *mut c_void).unwrap_or(ptr::null_mut());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: u32) -> usize {
+    return with_core(|core| core.memory(id).1).unwrap_or(0);
+}
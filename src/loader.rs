@@ -0,0 +1,111 @@
+//! Turns ROM files into a `Rom`, whatever their format, for every frontend.
+
+use std::fs;
+use std::path::Path;
+
+use crate::cartridge::Rom;
+use crate::patch::load_with_soft_patch;
+use crate::rom_db::{HeaderCorrection, RomDatabase};
+use crate::unif;
+
+pub struct LoadedRom {
+    pub rom: Rom,
+    /// Header fields the ROM database changed, for frontends to report: empty when the header
+    /// was right, None when the dump is not in the database.
+    pub corrections: Option<Vec<HeaderCorrection>>,
+}
+
+/// Parses an iNES, NES 2.0 or UNIF image, correcting its header from the bundled database.
+pub fn parse_rom(raw: &[u8]) -> Result<LoadedRom, String> {
+    return parse_rom_with(raw, &RomDatabase::bundled());
+}
+
+/// `parse_rom` with another database.
+pub fn parse_rom_with(raw: &[u8], database: &RomDatabase) -> Result<LoadedRom, String> {
+    let mut rom = if unif::is_unif(raw) {
+        unif::parse(raw)?.rom
+    } else {
+        Rom::new(raw)?
+    };
+    let corrections = database.correct_header(&mut rom);
+    return Ok(LoadedRom { rom, corrections });
+}
+
+/// Reads a rom file, with `soft_patch` applying a patch that sits next to it.
+pub fn load_rom(path: &Path, soft_patch: bool) -> Result<LoadedRom, String> {
+    let data = if soft_patch {
+        load_with_soft_patch(path)?.data
+    } else {
+        fs::read(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?
+    };
+    return parse_rom(&data).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::{create_rom, TestRom};
+    use crate::cartridge::{CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+    use crate::rom_db::{HeaderField, RomHashes};
+
+    #[test]
+    fn test_parse_rom_reports_header_corrections() {
+        // mapper 0 in the header, with "DiskDude!" garbage in bytes 7-15
+        let mut header = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x44];
+        header.extend(b"iskDude!");
+        let raw = create_rom(TestRom {
+            header,
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let hashes = RomHashes::new(&Rom::new(&raw).unwrap());
+        let database = RomDatabase::parse(&format!(
+            "{:08X};{};2;0;V;NTSC;0;0;0;0;0;Test Game",
+            hashes.crc32, hashes.sha1
+        ))
+        .unwrap();
+
+        let loaded = parse_rom_with(&raw, &database).unwrap();
+
+        assert_eq!(loaded.rom.mapper, 2);
+        assert_eq!(loaded.rom.mirroring, crate::cartridge::Mirroring::Vertical);
+        let fields: Vec<HeaderField> = loaded
+            .corrections
+            .unwrap()
+            .iter()
+            .map(|c| c.field)
+            .collect();
+        assert!(fields.contains(&HeaderField::Mapper), "{:?}", fields);
+        assert!(parse_rom(&raw).unwrap().corrections.is_none());
+    }
+
+    #[test]
+    fn test_parse_rom_tells_a_right_header_from_an_unknown_dump() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let rom = Rom::new(&raw).unwrap();
+        let hashes = RomHashes::new(&rom);
+        let database = RomDatabase::parse(&format!(
+            "{:08X};{};0;0;H;NTSC;{};0;0;0;0;Test Game",
+            hashes.crc32, hashes.sha1, rom.prg_ram_size
+        ))
+        .unwrap();
+
+        let loaded = parse_rom_with(&raw, &database).unwrap();
+
+        assert_eq!(loaded.corrections, Some(vec![]));
+        assert_eq!(
+            parse_rom_with(&raw, &RomDatabase::default())
+                .unwrap()
+                .corrections,
+            None
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::{Mirroring, Rom, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+
+/// A cartridge board, seen from both the CPU ($4020-$FFFF) and the PPU ($0000-$1FFF) buses. Its
+/// save state holds the RAM and bank registers, never the ROM.
+pub trait Mapper: SaveState {
+    fn cpu_read(&mut self, addr: u16) -> u8;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    /// State of the board's IRQ output, none of the simple boards have one.
+    fn irq(&self) -> bool {
+        return false;
+    }
+
+    /// The work RAM at $6000-$7FFF, battery backed on some boards.
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return None;
+    }
+}
+
+/// The mapper is shared between the CPU bus and the PPU.
+pub type Cartridge = Rc<RefCell<Box<dyn Mapper>>>;
+
+pub fn new_cartridge(rom: Rom) -> Result<Cartridge, String> {
+    return Ok(Rc::new(RefCell::new(new_mapper(rom)?)));
+}
+
+pub fn new_mapper(rom: Rom) -> Result<Box<dyn Mapper>, String> {
+    match rom.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        2 => Ok(Box::new(Uxrom::new(rom))),
+        3 => Ok(Box::new(Cnrom::new(rom))),
+        7 => Ok(Box::new(Axrom::new(rom))),
+        _ => Err(format!("mapper {} is not supported", rom.mapper)),
+    }
+}
+
+/// PRG/CHR storage shared by the discrete logic boards.
+struct Board {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Board {
+    fn new(rom: Rom) -> Self {
+        let chr_is_ram = rom.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; rom.chr_ram_size.max(CHR_ROM_PAGE_SIZE)]
+        } else {
+            rom.chr_rom
+        };
+
+        Board {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr,
+            chr_is_ram,
+            mirroring: rom.mirroring,
+        }
+    }
+
+    fn read_prg(&self, bank: usize, bank_size: usize, addr: u16) -> u8 {
+        if self.prg_rom.is_empty() {
+            return 0;
+        }
+        let offset = bank * bank_size + (addr as usize % bank_size);
+        return self.prg_rom[offset % self.prg_rom.len()];
+    }
+
+    fn read_chr(&self, bank: usize, addr: u16) -> u8 {
+        let offset = bank * CHR_ROM_PAGE_SIZE + (addr as usize & 0x1FFF);
+        return self.chr[offset % self.chr.len()];
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data;
+        }
+    }
+
+    fn prg_bank_count(&self, bank_size: usize) -> usize {
+        return (self.prg_rom.len() / bank_size).max(1);
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        return self.prg_ram[(addr - 0x6000) as usize];
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.prg_ram[(addr - 0x6000) as usize] = data;
+    }
+}
+
+impl SaveState for Board {
+    fn save(&self, w: &mut StateWriter) {
+        w.vec(&self.prg_ram);
+        if self.chr_is_ram {
+            w.vec(&self.chr);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.vec_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            r.vec_into(&mut self.chr)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Mapper 0: no banking, 16 KiB PRG is mirrored at $C000.
+pub struct Nrom {
+    board: Board,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            board: Board::new(rom),
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.board.read_prg_ram(addr),
+            0x8000..=0xFFFF => self.board.read_prg(0, 2 * PRG_ROM_PAGE_SIZE, addr - 0x8000),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.board.write_prg_ram(addr, data);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        return self.board.read_chr(0, addr);
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.board.write_chr(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.board.mirroring;
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.board.prg_ram);
+    }
+}
+
+impl SaveState for Nrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.board.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.board.load(r)?;
+        return Ok(());
+    }
+}
+
+/// Mapper 2: switchable 16 KiB bank at $8000, last bank fixed at $C000.
+pub struct Uxrom {
+    board: Board,
+    prg_bank: usize,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            board: Board::new(rom),
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.board.read_prg_ram(addr),
+            0x8000..=0xBFFF => self.board.read_prg(self.prg_bank, PRG_ROM_PAGE_SIZE, addr),
+            0xC000..=0xFFFF => {
+                let last_bank = self.board.prg_bank_count(PRG_ROM_PAGE_SIZE) - 1;
+                self.board.read_prg(last_bank, PRG_ROM_PAGE_SIZE, addr)
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.board.write_prg_ram(addr, data),
+            0x8000..=0xFFFF => self.prg_bank = data as usize & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        return self.board.read_chr(0, addr);
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.board.write_chr(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.board.mirroring;
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.board.prg_ram);
+    }
+}
+
+impl SaveState for Uxrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.board.save(w);
+        w.u8(self.prg_bank as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.board.load(r)?;
+        self.prg_bank = r.u8()? as usize & 0x0F;
+        return Ok(());
+    }
+}
+
+/// Mapper 3: fixed PRG, switchable 8 KiB CHR bank.
+pub struct Cnrom {
+    board: Board,
+    chr_bank: usize,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom) -> Self {
+        Cnrom {
+            board: Board::new(rom),
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.board.read_prg_ram(addr),
+            0x8000..=0xFFFF => self.board.read_prg(0, 2 * PRG_ROM_PAGE_SIZE, addr - 0x8000),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.board.write_prg_ram(addr, data),
+            0x8000..=0xFFFF => self.chr_bank = data as usize & 0x03,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        return self.board.read_chr(self.chr_bank, addr);
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.board.write_chr(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.board.mirroring;
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.board.prg_ram);
+    }
+}
+
+impl SaveState for Cnrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.board.save(w);
+        w.u8(self.chr_bank as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.board.load(r)?;
+        self.chr_bank = r.u8()? as usize & 0x03;
+        return Ok(());
+    }
+}
+
+/// Mapper 7: switchable 32 KiB PRG bank and single-screen mirroring select.
+pub struct Axrom {
+    board: Board,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom) -> Self {
+        Axrom {
+            board: Board::new(rom),
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.board.read_prg_ram(addr),
+            0x8000..=0xFFFF => {
+                self.board
+                    .read_prg(self.prg_bank, 2 * PRG_ROM_PAGE_SIZE, addr - 0x8000)
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.board.write_prg_ram(addr, data),
+            0x8000..=0xFFFF => {
+                self.prg_bank = data as usize & 0x07;
+                self.mirroring = if data & 0x10 != 0 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        return self.board.read_chr(0, addr);
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.board.write_chr(addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.board.prg_ram);
+    }
+}
+
+impl SaveState for Axrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.board.save(w);
+        w.u8(self.prg_bank as u8);
+        w.mirroring(self.mirroring);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.board.load(r)?;
+        self.prg_bank = r.u8()? as usize & 0x07;
+        self.mirroring = r.mirroring()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::ines_rom;
+
+    fn test_rom(mapper: u16, prg_banks: usize, chr_banks: usize) -> Rom {
+        let mut prg_rom = vec![0; prg_banks * PRG_ROM_PAGE_SIZE];
+        for (i, bank) in prg_rom.chunks_mut(PRG_ROM_PAGE_SIZE).enumerate() {
+            bank.fill(i as u8);
+        }
+        let mut chr_rom = vec![0; chr_banks * CHR_ROM_PAGE_SIZE];
+        for (i, bank) in chr_rom.chunks_mut(CHR_ROM_PAGE_SIZE).enumerate() {
+            bank.fill(0x10 + i as u8);
+        }
+
+        Rom {
+            mirroring: Mirroring::Vertical,
+            ..ines_rom(mapper, prg_rom, chr_rom)
+        }
+    }
+
+    #[test]
+    fn test_nrom_128_mirrors_prg() {
+        let mut mapper = new_mapper(test_rom(0, 1, 1)).unwrap();
+
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 0);
+        assert_eq!(mapper.ppu_read(0x0000), 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_nrom_chr_ram_is_writable() {
+        let mut mapper = new_mapper(test_rom(0, 2, 0)).unwrap();
+        mapper.ppu_write(0x1234, 0x55);
+
+        assert_eq!(mapper.ppu_read(0x1234), 0x55);
+        assert_eq!(mapper.cpu_read(0xC000), 1);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = new_mapper(test_rom(0, 1, 1)).unwrap();
+        mapper.cpu_write(0x6001, 0x42);
+
+        assert_eq!(mapper.cpu_read(0x6001), 0x42);
+    }
+
+    #[test]
+    fn test_uxrom_switches_lower_bank_and_fixes_last() {
+        let mut mapper = new_mapper(test_rom(2, 4, 0)).unwrap();
+        mapper.cpu_write(0x8000, 2);
+
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_cnrom_switches_chr_bank() {
+        let mut mapper = new_mapper(test_rom(3, 2, 4)).unwrap();
+        mapper.cpu_write(0x8000, 3);
+
+        assert_eq!(mapper.ppu_read(0x0000), 0x13);
+    }
+
+    #[test]
+    fn test_axrom_switches_prg_and_mirroring() {
+        let mut mapper = new_mapper(test_rom(7, 4, 0)).unwrap();
+        mapper.cpu_write(0x8000, 0x11);
+
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        assert!(new_mapper(test_rom(255, 1, 1)).is_err());
+    }
+}
@@ -1,18 +1,126 @@
+use crate::apu::Apu;
+use crate::controller::Controller;
+use crate::input::Input;
+use crate::mapper::Cartridge;
+use crate::ppu::Ppu;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD1: u16 = 0x4016;
+const JOYPAD2: u16 = 0x4017;
+/// Bits of $4016/$4017 reads not driven by the controller ports, left with whatever was last
+/// on the data bus.
+const JOYPAD_OPEN_BUS: u8 = 0b1110_0000;
+
+/// A CPU bus cycle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BusAccess {
+    Read(u16),
+    Write(u16),
+}
+
+/// The CPU bus. Without attached devices every address is plain RAM, which the CPU tests rely on;
+/// RAM is only mirrored up to $1FFF once a cartridge is attached.
 pub struct Memory {
-    memory: [u8; 0xFFFF],
+    memory: [u8; 0x10000],
     debug: bool,
     hex_dump: Vec<u8>,
+    ppu: Option<Ppu>,
+    apu: Option<Apu>,
+    cartridge: Option<Cartridge>,
+    input: Option<Input>,
+    oam_dma_page: Option<u8>,
+    last_access: BusAccess,
+    /// Last value read or written, what undriven bits read back as.
+    data_bus: u8,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
         Memory {
-            memory: [0; 0xFFFF],
+            memory: [0; 0x10000],
             debug: false,
             hex_dump: vec![],
+            ppu: None,
+            apu: None,
+            cartridge: None,
+            input: None,
+            oam_dma_page: None,
+            last_access: BusAccess::Read(0),
+            data_bus: 0,
         }
     }
 
+    /// Maps the PPU registers at $2000-$3FFF, which are plain RAM until a PPU is attached.
+    pub fn attach_ppu(&mut self, ppu: Ppu) {
+        self.ppu = Some(ppu);
+    }
+
+    pub fn ppu(&self) -> Option<&Ppu> {
+        return self.ppu.as_ref();
+    }
+
+    pub fn ppu_mut(&mut self) -> Option<&mut Ppu> {
+        return self.ppu.as_mut();
+    }
+
+    pub fn attach_apu(&mut self, apu: Apu) {
+        self.apu = Some(apu);
+    }
+
+    pub fn apu(&self) -> Option<&Apu> {
+        return self.apu.as_ref();
+    }
+
+    pub fn apu_mut(&mut self) -> Option<&mut Apu> {
+        return self.apu.as_mut();
+    }
+
+    /// Maps the cartridge at $4020-$FFFF.
+    pub fn attach_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    /// Plugs standard controllers into both ports, read through $4016/$4017.
+    pub fn attach_controllers(&mut self) {
+        self.attach_input(Input::new());
+    }
+
+    /// Maps $4016/$4017 to whatever `input` has plugged in.
+    pub fn attach_input(&mut self, input: Input) {
+        self.input = Some(input);
+    }
+
+    pub fn input(&self) -> Option<&Input> {
+        return self.input.as_ref();
+    }
+
+    pub fn input_mut(&mut self) -> Option<&mut Input> {
+        return self.input.as_mut();
+    }
+
+    /// Port 0 is read through $4016, port 1 through $4017; see `Input::controller_mut`.
+    pub fn controller_mut(&mut self, port: usize) -> Option<&mut Controller> {
+        return self.input.as_mut()?.controller_mut(port);
+    }
+
+    /// Returns the page written to $4014 since the last call, the CPU performs the transfer.
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        return self.oam_dma_page.take();
+    }
+
+    /// The most recent read or write, which a DMA halting the CPU lands on.
+    pub fn last_access(&self) -> BusAccess {
+        return self.last_access;
+    }
+
     pub fn set_debug(&mut self) {
         self.debug = true;
     }
@@ -25,7 +133,7 @@ impl Memory {
         let lo = self.read(addr) as u16;
         let hi = self.read(addr.wrapping_add(1)) as u16;
 
-        return (hi << 8) | (lo as u16);
+        return (hi << 8) | lo;
     }
 
     pub fn write_u16(&mut self, addr: u16, data: u16) {
@@ -37,22 +145,125 @@ impl Memory {
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        self.last_access = BusAccess::Write(addr);
+        self.data_bus = data;
+        match addr {
+            0x0000..=0x1FFF if self.cartridge.is_some() => {
+                self.memory[mirror_ram_addr(addr)] = data
+            }
+            0x2000..=0x3FFF if self.ppu.is_some() => {
+                self.ppu.as_mut().unwrap().write_register(addr, data)
+            }
+            OAM_DMA => self.oam_dma_page = Some(data),
+            JOYPAD1 if self.input.is_some() => self.input.as_mut().unwrap().write(data),
+            0x4000..=0x4013 | APU_STATUS | 0x4017 if self.apu.is_some() => {
+                self.apu.as_mut().unwrap().write_register(addr, data)
+            }
+            0x4020..=0xFFFF if self.cartridge.is_some() => self
+                .cartridge
+                .as_ref()
+                .unwrap()
+                .borrow_mut()
+                .cpu_write(addr, data),
+            _ => self.memory[addr as usize] = data,
+        }
     }
 
     pub fn read(&mut self, addr: u16) -> u8 {
-        let value = self.memory[addr as usize];
+        self.last_access = BusAccess::Read(addr);
+        let value = match addr {
+            0x0000..=0x1FFF if self.cartridge.is_some() => self.memory[mirror_ram_addr(addr)],
+            0x2000..=0x3FFF if self.ppu.is_some() => self.ppu.as_mut().unwrap().read_register(addr),
+            APU_STATUS if self.apu.is_some() => self.apu.as_mut().unwrap().read_status(),
+            JOYPAD1 | JOYPAD2 if self.input.is_some() => {
+                let bits = self.input.as_mut().unwrap().read(addr, self.ppu.as_ref());
+                (self.data_bus & JOYPAD_OPEN_BUS) | bits
+            }
+            0x4020..=0xFFFF if self.cartridge.is_some() => {
+                self.cartridge.as_ref().unwrap().borrow_mut().cpu_read(addr)
+            }
+            _ => self.memory[addr as usize],
+        };
+        self.data_bus = value;
         if self.debug {
             self.hex_dump.push(value)
         }
         return value;
     }
 
+    /// Reads without side effects, for debuggers and tools. I/O registers would change state if
+    /// read, so they return what is left on the data bus instead.
+    pub fn peek(&self, addr: u16) -> u8 {
+        return match addr {
+            0x0000..=0x1FFF if self.cartridge.is_some() => self.memory[mirror_ram_addr(addr)],
+            0x2000..=0x3FFF if self.ppu.is_some() => self.data_bus,
+            0x4000..=0x401F if self.apu.is_some() || self.input.is_some() => self.data_bus,
+            0x4020..=0xFFFF if self.cartridge.is_some() => {
+                self.cartridge.as_ref().unwrap().borrow_mut().cpu_read(addr)
+            }
+            _ => self.memory[addr as usize],
+        };
+    }
+
+    /// The console's 2 KiB of work RAM, mirrored up to $1FFF once a cartridge is attached.
+    pub fn ram(&self) -> &[u8] {
+        return &self.memory[..0x0800];
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        return &mut self.memory[..0x0800];
+    }
+
     pub fn dump(&self) -> &Vec<u8> {
         return &self.hex_dump;
     }
 }
 
+/// The 2 KiB of internal RAM repeat up to $1FFF.
+fn mirror_ram_addr(addr: u16) -> usize {
+    return (addr & 0x07FF) as usize;
+}
+
+/// The whole address space and the bus latches; attached devices are saved on their own.
+impl SaveState for Memory {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.memory);
+        match self.oam_dma_page {
+            Some(page) => {
+                w.bool(true);
+                w.u8(page);
+            }
+            None => w.bool(false),
+        }
+        match self.last_access {
+            BusAccess::Read(addr) => {
+                w.u8(0);
+                w.u16(addr);
+            }
+            BusAccess::Write(addr) => {
+                w.u8(1);
+                w.u16(addr);
+            }
+        }
+        w.u8(self.data_bus);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.bytes(&mut self.memory)?;
+        self.oam_dma_page = match r.bool()? {
+            true => Some(r.u8()?),
+            false => None,
+        };
+        self.last_access = match r.u8()? {
+            0 => BusAccess::Read(r.u16()?),
+            1 => BusAccess::Write(r.u16()?),
+            kind => return Err(r.error(&format!("invalid bus access {}", kind))),
+        };
+        self.data_bus = r.u8()?;
+        return Ok(());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,6 +301,111 @@ mod tests {
         assert_eq!(mem.memory[0x8000..0x8005], program);
     }
 
+    #[test]
+    fn test_ram_mirrors_with_cartridge() {
+        use crate::cartridge::Region;
+        use crate::mapper::new_cartridge;
+        use crate::nes::test::test_rom;
+
+        let mut mem = Memory::new();
+        mem.attach_cartridge(new_cartridge(test_rom(Region::Ntsc, &[0xEA], &[])).unwrap());
+        mem.write(0x0805, 0x66);
+
+        assert_eq!(mem.read(0x0005), 0x66);
+        assert_eq!(mem.read(0x1805), 0x66);
+    }
+
+    #[test]
+    fn test_cartridge_is_mapped_when_attached() {
+        use crate::cartridge::Region;
+        use crate::mapper::new_cartridge;
+        use crate::nes::test::test_rom;
+
+        let mut mem = Memory::new();
+        mem.attach_cartridge(new_cartridge(test_rom(Region::Ntsc, &[0xEA], &[])).unwrap());
+        mem.write(0x8000, 0x66);
+
+        assert_eq!(mem.read(0x8000), 0xEA);
+        assert_eq!(mem.read_u16(0xFFFC), 0x8000);
+    }
+
+    #[test]
+    fn test_oam_dma_write_is_latched() {
+        let mut mem = Memory::new();
+        mem.write(0x4014, 0x02);
+
+        assert_eq!(mem.take_oam_dma(), Some(0x02));
+        assert_eq!(mem.take_oam_dma(), None);
+    }
+
+    #[test]
+    fn test_ppu_registers_are_mapped_when_attached() {
+        use crate::cartridge::Mirroring;
+        use crate::ppu::test::test_ppu;
+
+        let mut mem = Memory::new();
+        mem.attach_ppu(test_ppu(Mirroring::Horizontal));
+        mem.write(0x2006, 0x23);
+        mem.write(0x2006, 0x05);
+        mem.write(0x2007, 0x66);
+        mem.write(0x3FFE, 0x23);
+        mem.write(0x3FFE, 0x05);
+        mem.read(0x2007);
+
+        assert_eq!(mem.read(0x2007), 0x66);
+        assert_eq!(mem.memory[0x2007], 0);
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        use crate::cartridge::Mirroring;
+        use crate::ppu::test::test_ppu;
+
+        let mut mem = Memory::new();
+        mem.attach_ppu(test_ppu(Mirroring::Horizontal));
+        mem.write(0x0010, 0x42);
+        mem.write(0x2006, 0x23);
+        mem.write(0x2006, 0x00);
+        mem.write(0x2007, 0x11);
+        mem.write(0x2007, 0x22);
+        mem.write(0x2006, 0x23);
+        mem.write(0x2006, 0x00);
+        mem.read(0x2007);
+
+        assert_eq!(mem.peek(0x0010), 0x42);
+        mem.peek(0x2007);
+        // the PPU address only moved for the real read
+        assert_eq!(mem.read(0x2007), 0x11);
+    }
+
+    #[test]
+    fn test_apu_registers_are_mapped_when_attached() {
+        let mut mem = Memory::new();
+        mem.attach_apu(Apu::new());
+        mem.write(0x4015, 0b01);
+        mem.write(0x4003, 0b0000_1000);
+
+        assert_eq!(mem.read(0x4015), 0b01);
+        assert_eq!(mem.memory[0x4003], 0);
+    }
+
+    #[test]
+    fn test_controller_reads_keep_open_bus_bits() {
+        use crate::controller::Buttons;
+
+        let mut mem = Memory::new();
+        mem.attach_controllers();
+        mem.controller_mut(1).unwrap().set_buttons(Buttons::A);
+        mem.write(0x4016, 1);
+        mem.write(0x4016, 0);
+
+        // LDA $4017 leaves the address high byte on the bus
+        mem.write(0x0000, 0x40);
+        mem.read(0x0000);
+        assert_eq!(mem.read(0x4017), 0x41);
+        assert_eq!(mem.read(0x4016) & 0x1F, 0x00);
+    }
+
     #[test]
     fn test_hex_dump_debug_on() {
         let mut mem = Memory::new();
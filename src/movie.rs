@@ -0,0 +1,609 @@
+//! Input movies: the buttons of every frame from power on, recorded from the controller API and
+//! played back to reproduce a run exactly. A movie can carry the CRC-32 of work RAM at its end,
+//! which playback checks to tell a faithful replay from one that desynced.
+//!
+//! The native format is text, one line per frame after a small header:
+//!
+//! ```text
+//! nes-movie 1
+//! rom-crc32 1A2B3C4D
+//! ram-crc32 5E6F7A8B
+//! players 2
+//! frames
+//! . Start .
+//! . Right+A .
+//! reset . .
+//! ```
+//!
+//! Each frame line is `.` or `reset` (the reset button is pressed before the frame), then one
+//! column of buttons per player as parsed by `Buttons::parse`. Header keys this build
+//! does not know are skipped.
+//!
+//! FCEUX `.fm2` movies and the input log of BizHawk `.bk2` movies (`Input Log.txt`, the zip
+//! unpacked) can be imported; they carry no RAM hash.
+
+use std::fmt::Write;
+
+use crate::controller::Buttons;
+use crate::hash::crc32;
+use crate::input::four_score::FourScore;
+use crate::input::{Device, Port};
+use crate::nes::Nes;
+use crate::ppu::Frame;
+use crate::rewind::FrameInput;
+
+const MAGIC: &str = "nes-movie";
+const VERSION: u32 = 1;
+const PLAYERS: usize = 4;
+
+/// FM2 gamepad columns, left to right, which is also bit 7 to bit 0 of `Buttons`.
+const FM2_BUTTONS: &str = "RLDUTSBA";
+const FM2_SOFT_RESET: u32 = 1;
+const FM2_HARD_RESET: u32 = 2;
+/// BizHawk's NES log key, for logs that do not start with one.
+const BK2_DEFAULT_LOG_KEY: &str = "#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|\
+    P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct MovieFrame {
+    /// The reset button is pressed before this frame runs.
+    pub reset: bool,
+    pub input: FrameInput,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Movie {
+    /// 1 to 4, more than 2 needs a Four Score.
+    pub players: usize,
+    pub frames: Vec<MovieFrame>,
+    /// `Nes::rom_crc32` of the game it was recorded on.
+    pub rom_crc32: Option<u32>,
+    /// `ram_hash` after the last frame.
+    pub ram_crc32: Option<u32>,
+}
+
+impl Default for Movie {
+    fn default() -> Self {
+        Movie {
+            players: 2,
+            frames: vec![],
+            rom_crc32: None,
+            ram_crc32: None,
+        }
+    }
+}
+
+/// CRC-32 of the console's 2 KiB of work RAM.
+pub fn ram_hash(nes: &Nes) -> u32 {
+    return crc32(nes.ram());
+}
+
+impl Movie {
+    /// Reads a native, FM2 or BK2 input log movie, telling them apart by their first line.
+    pub fn parse_any(text: &str) -> Result<Movie, String> {
+        let first = text.lines().map(str::trim).find(|line| !line.is_empty());
+        return match first {
+            Some(line) if line.starts_with(MAGIC) => Movie::parse(text),
+            Some(line) if line.starts_with("[Input]") || line.starts_with("LogKey:") => {
+                Movie::from_bk2(text)
+            }
+            _ => Movie::from_fm2(text),
+        };
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::default();
+        let mut lines = text.lines().enumerate();
+        let error = |number: usize, message: String| format!("line {}: {}", number + 1, message);
+
+        let version = match lines.next() {
+            Some((_, line)) if line.starts_with(MAGIC) => line[MAGIC.len()..].trim(),
+            _ => return Err("not a movie".to_string()),
+        };
+        match version.parse::<u32>() {
+            Ok(version) if version <= VERSION => {}
+            _ => return Err(format!("unsupported movie version {}", version)),
+        }
+
+        for (number, line) in lines.by_ref() {
+            let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            let hex = || u32::from_str_radix(value, 16).map_err(|_| error(number, line.into()));
+            match key {
+                "frames" => break,
+                "rom-crc32" => movie.rom_crc32 = Some(hex()?),
+                "ram-crc32" => movie.ram_crc32 = Some(hex()?),
+                "players" => match value.parse() {
+                    Ok(players) if (1..=PLAYERS).contains(&players) => movie.players = players,
+                    _ => return Err(error(number, "players must be 1-4".to_string())),
+                },
+                _ => {}
+            }
+        }
+
+        for (number, line) in lines {
+            let mut columns = line.split_whitespace();
+            let mut frame = MovieFrame::default();
+            match columns.next() {
+                Some(".") => {}
+                Some("reset") => frame.reset = true,
+                Some(command) => return Err(error(number, format!("unknown command {}", command))),
+                None => continue,
+            }
+            for (player, column) in columns.enumerate() {
+                if player == movie.players {
+                    return Err(error(
+                        number,
+                        format!("more than {} players", movie.players),
+                    ));
+                }
+                frame.input[player] = Buttons::parse(column).map_err(|e| error(number, e))?;
+            }
+            movie.frames.push(frame);
+        }
+        return Ok(movie);
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{} {}\n", MAGIC, VERSION);
+        if let Some(crc) = self.rom_crc32 {
+            writeln!(text, "rom-crc32 {:08X}", crc).unwrap();
+        }
+        if let Some(crc) = self.ram_crc32 {
+            writeln!(text, "ram-crc32 {:08X}", crc).unwrap();
+        }
+        writeln!(text, "players {}", self.players).unwrap();
+        text.push_str("frames\n");
+        for frame in &self.frames {
+            text.push_str(if frame.reset { "reset" } else { "." });
+            for buttons in &frame.input[..self.players] {
+                text.push(' ');
+                text.push_str(&format_buttons(*buttons));
+            }
+            text.push('\n');
+        }
+        return text;
+    }
+
+    /// Imports an FCEUX text movie. Only gamepads (optionally on a Four Score) and soft resets
+    /// are supported; the movie has to start from power on.
+    pub fn from_fm2(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::default();
+        let mut four_score = false;
+        let mut ports = [1, 1];
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let line = line.trim_end();
+            if !line.starts_with('|') {
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                match (key, value) {
+                    ("binary", "1") => return Err("binary fm2 movies are not supported".into()),
+                    ("savestate", value) if !value.is_empty() => {
+                        return Err("movies starting from a save state are not supported".into())
+                    }
+                    ("fourscore", value) => four_score = value == "1",
+                    ("port0", value) => ports[0] = value.parse().map_err(|_| error(line.into()))?,
+                    ("port1", value) => ports[1] = value.parse().map_err(|_| error(line.into()))?,
+                    _ => {}
+                }
+                continue;
+            }
+
+            if ports.iter().any(|&port| port > 1) && !four_score {
+                return Err("only gamepads are supported, not the zapper".into());
+            }
+            movie.players = if four_score {
+                4
+            } else if ports[1] == 1 {
+                2
+            } else {
+                1
+            };
+
+            let fields: Vec<&str> = line.split('|').collect();
+            let command: u32 = fields
+                .get(1)
+                .and_then(|field| field.trim().parse().ok())
+                .ok_or_else(|| error("missing command".into()))?;
+            let mut frame = MovieFrame {
+                reset: command & FM2_SOFT_RESET != 0,
+                ..MovieFrame::default()
+            };
+            if command & !(FM2_SOFT_RESET | FM2_HARD_RESET) != 0 {
+                return Err(error(format!("unsupported command {}", command)));
+            }
+            if command & FM2_HARD_RESET != 0 && !movie.frames.is_empty() {
+                return Err(error("power cycling is not supported".into()));
+            }
+            let columns = if four_score { 4 } else { 2 };
+            let gamepads = frame.input.iter_mut().zip(fields.iter().skip(2));
+            for (player, (buttons, field)) in gamepads.take(columns).enumerate() {
+                if !four_score && ports[player] == 0 {
+                    continue;
+                }
+                *buttons = fm2_buttons(field).map_err(error)?;
+            }
+            movie.frames.push(frame);
+        }
+        return Ok(movie);
+    }
+
+    /// Imports the input log of a BizHawk movie: gamepads for up to 4 players and the reset
+    /// button. Power is only allowed on the first frame, the movie starts from power on anyway.
+    pub fn from_bk2(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::default();
+        let mut key = parse_log_key(BK2_DEFAULT_LOG_KEY)?;
+        movie.players = key.players;
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let line = line.trim();
+            if let Some(log_key) = line.strip_prefix("LogKey:") {
+                key = parse_log_key(log_key).map_err(error)?;
+                movie.players = key.players;
+                continue;
+            }
+            if !line.starts_with('|') {
+                continue;
+            }
+
+            let states: Vec<char> = line.chars().filter(|&c| c != '|').collect();
+            if states.len() != key.inputs.len() {
+                return Err(error(format!(
+                    "{} inputs, the log key has {}",
+                    states.len(),
+                    key.inputs.len()
+                )));
+            }
+            let mut frame = MovieFrame::default();
+            for (input, state) in key.inputs.iter().zip(states) {
+                if state == '.' {
+                    continue;
+                }
+                match *input {
+                    Bk2Input::Reset => frame.reset = true,
+                    Bk2Input::Power if movie.frames.is_empty() => {}
+                    Bk2Input::Power => return Err(error("power cycling is not supported".into())),
+                    Bk2Input::Button(player, buttons) => frame.input[player] |= buttons,
+                }
+            }
+            movie.frames.push(frame);
+        }
+        return Ok(movie);
+    }
+
+    /// Plays the movie on a console just powered on, plugging in a Four Score for more than two
+    /// players, and returns the final `ram_hash`.
+    pub fn play(&self, nes: &mut Nes) -> Result<u32, String> {
+        let mut player = Player::new(self, nes)?;
+        while player.run_frame(nes)?.is_some() {}
+        return Ok(ram_hash(nes));
+    }
+
+    /// Plays the movie and checks it ends with the RAM it was recorded with.
+    pub fn verify(&self, nes: &mut Nes) -> Result<(), String> {
+        let expected = self
+            .ram_crc32
+            .ok_or("the movie has no RAM hash to check against")?;
+        let actual = self.play(nes)?;
+        if actual != expected {
+            return Err(format!(
+                "playback desynced: RAM CRC-32 {:08X}, recorded {:08X}",
+                actual, expected
+            ));
+        }
+        return Ok(());
+    }
+}
+
+fn format_buttons(buttons: Buttons) -> String {
+    if buttons.is_empty() {
+        return ".".to_string();
+    }
+    return buttons
+        .iter_names()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>()
+        .join("+");
+}
+
+fn fm2_buttons(field: &str) -> Result<Buttons, String> {
+    if field.is_empty() {
+        return Ok(Buttons::empty());
+    }
+    if field.chars().count() != FM2_BUTTONS.len() {
+        return Err(format!("gamepad column {:?} is not 8 buttons", field));
+    }
+    let mut buttons = 0;
+    for state in field.chars() {
+        buttons = (buttons << 1) | (state != '.' && state != ' ') as u8;
+    }
+    return Ok(Buttons::from_bits_retain(buttons));
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Bk2Input {
+    Reset,
+    Power,
+    /// Player 0-3 and the button.
+    Button(usize, Buttons),
+}
+
+struct LogKey {
+    inputs: Vec<Bk2Input>,
+    players: usize,
+}
+
+/// `#Reset|Power|#P1 Up|P1 Down|...`: groups start with `#`, inputs are separated by `|`.
+fn parse_log_key(text: &str) -> Result<LogKey, String> {
+    let mut key = LogKey {
+        inputs: vec![],
+        players: 1,
+    };
+    for name in text.split(['#', '|']).filter(|name| !name.is_empty()) {
+        let input = match name.split_once(' ') {
+            _ if name == "Reset" => Bk2Input::Reset,
+            _ if name == "Power" => Bk2Input::Power,
+            Some((player, button)) if player.starts_with('P') => {
+                let player = match player[1..].parse::<usize>() {
+                    Ok(player) if (1..=PLAYERS).contains(&player) => player - 1,
+                    _ => return Err(format!("unsupported input {}", name)),
+                };
+                let buttons = Buttons::from_name(button)
+                    .ok_or_else(|| format!("unsupported input {}", name))?;
+                key.players = key.players.max(player + 1);
+                Bk2Input::Button(player, buttons)
+            }
+            _ => return Err(format!("unsupported input {}", name)),
+        };
+        key.inputs.push(input);
+    }
+    return Ok(key);
+}
+
+/// Records the buttons held on the controllers each frame, as set through `Nes::set_buttons`.
+pub struct Recorder {
+    movie: Movie,
+    reset_pending: bool,
+}
+
+impl Recorder {
+    /// Starts recording on a console just powered on.
+    pub fn new(nes: &Nes) -> Self {
+        let four_score = matches!(nes.input().device(Port::One), Device::FourScore(_));
+        return Recorder {
+            movie: Movie {
+                players: if four_score { 4 } else { 2 },
+                rom_crc32: Some(nes.rom_crc32()),
+                ..Movie::default()
+            },
+            reset_pending: false,
+        };
+    }
+
+    pub fn frames(&self) -> usize {
+        return self.movie.frames.len();
+    }
+
+    /// Presses the reset button, which goes into the movie with the next frame.
+    pub fn reset(&mut self, nes: &mut Nes) {
+        nes.reset();
+        self.reset_pending = true;
+    }
+
+    /// Records what the controllers hold and runs a frame.
+    pub fn run_frame<'a>(&mut self, nes: &'a mut Nes) -> Result<(&'a Frame, Vec<f32>), String> {
+        let mut frame = MovieFrame {
+            reset: std::mem::take(&mut self.reset_pending),
+            ..MovieFrame::default()
+        };
+        for (player, buttons) in frame.input.iter_mut().enumerate() {
+            *buttons = nes.buttons(player);
+        }
+        self.movie.frames.push(frame);
+        return nes.run_frame();
+    }
+
+    /// The movie so far, stamped with the current `ram_hash`.
+    pub fn finish(self, nes: &Nes) -> Movie {
+        return Movie {
+            ram_crc32: Some(ram_hash(nes)),
+            ..self.movie
+        };
+    }
+}
+
+/// Drives a console from a movie, frame by frame.
+pub struct Player<'a> {
+    movie: &'a Movie,
+    frame: usize,
+}
+
+impl<'a> Player<'a> {
+    /// Checks the movie is for the loaded game and plugs in what it needs.
+    pub fn new(movie: &'a Movie, nes: &mut Nes) -> Result<Self, String> {
+        if let Some(crc) = movie.rom_crc32 {
+            if crc != nes.rom_crc32() {
+                return Err(format!(
+                    "the movie is for another game (ROM CRC-32 {:08X}, loaded {:08X})",
+                    crc,
+                    nes.rom_crc32()
+                ));
+            }
+        }
+        if movie.players > 2 {
+            nes.input_mut()
+                .connect(Port::One, Device::FourScore(FourScore::new()))?;
+        }
+        return Ok(Player { movie, frame: 0 });
+    }
+
+    /// Frames played so far.
+    pub fn frame(&self) -> usize {
+        return self.frame;
+    }
+
+    /// Runs the next frame of the movie, `None` once it is over.
+    pub fn run_frame<'n>(
+        &mut self,
+        nes: &'n mut Nes,
+    ) -> Result<Option<(&'n Frame, Vec<f32>)>, String> {
+        let Some(frame) = self.movie.frames.get(self.frame) else {
+            return Ok(None);
+        };
+        self.frame += 1;
+        if frame.reset {
+            nes.reset();
+        }
+        for (player, buttons) in frame.input.iter().enumerate() {
+            nes.set_buttons(player, *buttons);
+        }
+        return nes.run_frame().map(Some);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(reset: bool, p1: Buttons, p2: Buttons) -> MovieFrame {
+        MovieFrame {
+            reset,
+            input: [p1, p2, Buttons::empty(), Buttons::empty()],
+        }
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let movie = Movie {
+            players: 2,
+            frames: vec![
+                frame(false, Buttons::Start, Buttons::empty()),
+                frame(true, Buttons::Right | Buttons::A, Buttons::B),
+            ],
+            rom_crc32: Some(0x1A2B3C4D),
+            ram_crc32: Some(0x0000BEEF),
+        };
+
+        let text = movie.to_text();
+
+        assert_eq!(
+            text,
+            "nes-movie 1\nrom-crc32 1A2B3C4D\nram-crc32 0000BEEF\nplayers 2\nframes\n\
+             . Start .\nreset A+Right B\n"
+        );
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+        assert_eq!(Movie::parse_any(&text).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Movie::parse("hello").unwrap_err(), "not a movie");
+        assert_eq!(
+            Movie::parse("nes-movie 2\n").unwrap_err(),
+            "unsupported movie version 2"
+        );
+        assert_eq!(
+            Movie::parse("nes-movie 1\nplayers 1\nframes\n. A B\n").unwrap_err(),
+            "line 4: more than 1 players"
+        );
+        assert_eq!(
+            Movie::parse("nes-movie 1\nframes\n. Jump\n").unwrap_err(),
+            "line 3: unknown button Jump"
+        );
+        // keys from newer builds are skipped
+        let movie = Movie::parse("nes-movie 1\nauthor someone\nframes\n. A\n").unwrap();
+        assert_eq!(
+            movie.frames,
+            vec![frame(false, Buttons::A, Buttons::empty())]
+        );
+    }
+
+    #[test]
+    fn test_fm2_import() {
+        let text = "version 3\nemuVersion 22020\nromFilename game\npalFlag 0\n\
+                    romChecksum base64:AAAA\nfourscore 0\nport0 1\nport1 1\nport2 0\n\
+                    |0|........|........||\n\
+                    |0|....T...|.......A||\n\
+                    |1|R......A|........||\n";
+
+        let movie = Movie::parse_any(text).unwrap();
+
+        assert_eq!(movie.players, 2);
+        assert_eq!(
+            movie.frames,
+            vec![
+                frame(false, Buttons::empty(), Buttons::empty()),
+                frame(false, Buttons::Start, Buttons::A),
+                frame(true, Buttons::Right | Buttons::A, Buttons::empty()),
+            ]
+        );
+        assert_eq!(movie.ram_crc32, None);
+    }
+
+    #[test]
+    fn test_fm2_four_score_and_errors() {
+        let movie =
+            Movie::from_fm2("fourscore 1\n|0|.......A|......B.|.....S..|....T...||\n").unwrap();
+        assert_eq!(movie.players, 4);
+        assert_eq!(
+            movie.frames[0].input,
+            [Buttons::A, Buttons::B, Buttons::Select, Buttons::Start]
+        );
+
+        assert_eq!(
+            Movie::from_fm2("port1 2\n|0|........|0 0 0||\n").unwrap_err(),
+            "only gamepads are supported, not the zapper"
+        );
+        assert_eq!(
+            Movie::from_fm2("binary 1\n").unwrap_err(),
+            "binary fm2 movies are not supported"
+        );
+        assert_eq!(
+            Movie::from_fm2("|2|........|........||\n|2|........|........||\n").unwrap_err(),
+            "line 2: power cycling is not supported"
+        );
+        assert_eq!(
+            Movie::from_fm2("|0|...|........||\n").unwrap_err(),
+            "line 1: gamepad column \"...\" is not 8 buttons"
+        );
+    }
+
+    #[test]
+    fn test_bk2_import() {
+        let text = "[Input]\n\
+                    LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|\n\
+                    |.P|........|........|\n\
+                    |..|U...S...|.......A|\n\
+                    |r.|........|...R....|\n\
+                    [/Input]\n";
+
+        let movie = Movie::parse_any(text).unwrap();
+
+        assert_eq!(movie.players, 2);
+        assert_eq!(
+            movie.frames,
+            vec![
+                frame(false, Buttons::empty(), Buttons::empty()),
+                frame(false, Buttons::Up | Buttons::Start, Buttons::A),
+                frame(true, Buttons::empty(), Buttons::Right),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bk2_errors() {
+        assert_eq!(
+            Movie::from_bk2("LogKey:#P1 Zapper X|\n").unwrap_err(),
+            "line 1: unsupported input P1 Zapper X"
+        );
+        assert_eq!(
+            Movie::from_bk2("|..|....|\n").unwrap_err(),
+            "line 1: 6 inputs, the log key has 18"
+        );
+        assert_eq!(
+            Movie::from_bk2("|..|........|........|\n|.P|........|........|\n").unwrap_err(),
+            "line 2: power cycling is not supported"
+        );
+    }
+}
@@ -0,0 +1,652 @@
+use std::cell::RefMut;
+
+use crate::apu::Apu;
+use crate::cartridge::{Region, Rom};
+use crate::controller::Buttons;
+use crate::cpu::CPU;
+use crate::hash::Crc32;
+use crate::input::Input;
+use crate::mapper::{new_cartridge, Cartridge};
+use crate::memory::BusAccess;
+use crate::ppu::{Frame, Ppu};
+use crate::state::{self, find_chunk, save_chunk, SaveState, StateWriter, Tag};
+
+/// Master clock cycles per CPU cycle and per PPU dot.
+/// NTSC runs 3 dots per CPU cycle, PAL 3.2 and Dendy 3.
+fn clock_dividers(region: Region) -> (u64, u64) {
+    match region {
+        Region::Ntsc | Region::Multi => (12, 4),
+        Region::Pal => (16, 5),
+        Region::Dendy => (15, 5),
+    }
+}
+
+/// CPU cycles a DMC sample fetch halts the CPU for, one less when it lands on a write.
+const DMC_DMA_CYCLES: u64 = 4;
+
+/// Save state chunks.
+const ROM_CHUNK: Tag = *b"ROM ";
+const NES_CHUNK: Tag = *b"NES ";
+const CPU_CHUNK: Tag = *b"CPU ";
+const MEMORY_CHUNK: Tag = *b"MEM ";
+const PPU_CHUNK: Tag = *b"PPU ";
+const APU_CHUNK: Tag = *b"APU ";
+const CARTRIDGE_CHUNK: Tag = *b"CART";
+const INPUT_CHUNK: Tag = *b"INPT";
+
+/// The whole console: the CPU drives the master clock and the PPU and APU catch up after
+/// every instruction, so interrupts they raise are seen before the next one.
+pub struct Nes {
+    pub cpu: CPU,
+    cartridge: Cartridge,
+    region: Region,
+    cpu_divider: u64,
+    ppu_divider: u64,
+    /// Master clock cycles the PPU has been run for.
+    ppu_clock: u64,
+    /// CRC-32 of PRG and CHR ROM, so states are only loaded into the game they came from.
+    rom_crc32: u32,
+}
+
+impl Nes {
+    pub fn new(rom: Rom) -> Result<Nes, String> {
+        let region = rom.region;
+        let rom_crc32 = Crc32::new()
+            .update(&rom.prg_rom)
+            .update(&rom.chr_rom)
+            .finish();
+        let cartridge = new_cartridge(rom)?;
+
+        let mut ppu = Ppu::new(cartridge.clone());
+        ppu.set_region(region);
+
+        let mut apu = Apu::new();
+        apu.set_region(region);
+
+        let mut cpu = CPU::new();
+        cpu.memory.attach_ppu(ppu);
+        cpu.memory.attach_apu(apu);
+        cpu.memory.attach_cartridge(cartridge.clone());
+        cpu.memory.attach_controllers();
+        cpu.power_on();
+
+        let (cpu_divider, ppu_divider) = clock_dividers(region);
+        return Ok(Nes {
+            cpu,
+            cartridge,
+            region,
+            cpu_divider,
+            ppu_divider,
+            ppu_clock: 0,
+            rom_crc32,
+        });
+    }
+
+    pub fn region(&self) -> Region {
+        return self.region;
+    }
+
+    /// CRC-32 of PRG ROM followed by CHR ROM, identifying the game whatever its header says.
+    pub fn rom_crc32(&self) -> u32 {
+        return self.rom_crc32;
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        return self
+            .cpu
+            .memory
+            .ppu()
+            .expect("the PPU is attached in Nes::new");
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        return self
+            .cpu
+            .memory
+            .ppu_mut()
+            .expect("the PPU is attached in Nes::new");
+    }
+
+    pub fn apu(&self) -> &Apu {
+        return self
+            .cpu
+            .memory
+            .apu()
+            .expect("the APU is attached in Nes::new");
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        return self
+            .cpu
+            .memory
+            .apu_mut()
+            .expect("the APU is attached in Nes::new");
+    }
+
+    /// Sets the buttons held on the controller of `player`, 0 and 1 being the controllers in
+    /// ports 1 ($4016) and 2 ($4017), typically once per frame before `run_frame`. Does nothing
+    /// if that player has no controller plugged in.
+    pub fn set_buttons(&mut self, player: usize, buttons: Buttons) {
+        if let Some(controller) = self.cpu.memory.controller_mut(player) {
+            controller.set_buttons(buttons);
+        }
+    }
+
+    /// The buttons held on the controller of `player`, none if that player has no controller.
+    pub fn buttons(&self, player: usize) -> Buttons {
+        return self
+            .input()
+            .controller(player)
+            .map_or(Buttons::empty(), |controller| controller.buttons());
+    }
+
+    /// What is plugged into the controller and expansion ports.
+    pub fn input(&self) -> &Input {
+        return self
+            .cpu
+            .memory
+            .input()
+            .expect("the controllers are attached in Nes::new");
+    }
+
+    pub fn input_mut(&mut self) -> &mut Input {
+        return self
+            .cpu
+            .memory
+            .input_mut()
+            .expect("the controllers are attached in Nes::new");
+    }
+
+    /// The console's 2 KiB of work RAM.
+    pub fn ram(&self) -> &[u8] {
+        return self.cpu.memory.ram();
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        return self.cpu.memory.ram_mut();
+    }
+
+    /// The cartridge's work RAM at $6000-$7FFF, if the board has any.
+    pub fn prg_ram_mut(&self) -> Option<RefMut<'_, [u8]>> {
+        return RefMut::filter_map(self.cartridge.borrow_mut(), |mapper| mapper.prg_ram_mut()).ok();
+    }
+
+    /// Presses the reset button: the CPU restarts at the reset vector, sound is silenced and
+    /// rendering and NMIs are turned off until the game sets them up again. RAM is kept.
+    pub fn reset(&mut self) {
+        self.apu_mut().write_register(0x4015, 0x00);
+        self.ppu_mut().write_register(0x2000, 0x00);
+        self.ppu_mut().write_register(0x2001, 0x00);
+        self.cpu.soft_reset();
+    }
+
+    /// A snapshot of the whole console, see `state` for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut rom = StateWriter::new();
+        rom.u32(self.rom_crc32);
+        let mut nes = StateWriter::new();
+        nes.u64(self.ppu_clock);
+        return state::encode(&[
+            (ROM_CHUNK, rom.into_bytes()),
+            (NES_CHUNK, nes.into_bytes()),
+            save_chunk(CPU_CHUNK, &self.cpu),
+            save_chunk(MEMORY_CHUNK, &self.cpu.memory),
+            save_chunk(PPU_CHUNK, self.ppu()),
+            save_chunk(APU_CHUNK, self.apu()),
+            save_chunk(CARTRIDGE_CHUNK, &**self.cartridge.borrow()),
+            save_chunk(INPUT_CHUNK, self.input()),
+        ]);
+    }
+
+    /// Restores a snapshot from `save_state`, taken on this game. On error the console is left
+    /// as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let chunks = state::decode(data)?;
+        let rom_crc32 = find_chunk(&chunks, ROM_CHUNK)?.u32()?;
+        if rom_crc32 != self.rom_crc32 {
+            return Err(format!(
+                "save state is for another game (ROM CRC-32 {:08X}, loaded {:08X})",
+                rom_crc32, self.rom_crc32
+            ));
+        }
+
+        let backup = self.save_state();
+        if let Err(e) = self.load_chunks(&chunks) {
+            let backup = state::decode(&backup).expect("a state just saved decodes");
+            self.load_chunks(&backup).expect("a state just saved loads");
+            return Err(e);
+        }
+        return Ok(());
+    }
+
+    fn load_chunks(&mut self, chunks: &[(Tag, &[u8])]) -> Result<(), String> {
+        self.ppu_clock = find_chunk(chunks, NES_CHUNK)?.u64()?;
+        SaveState::load(&mut self.cpu, &mut find_chunk(chunks, CPU_CHUNK)?)?;
+        self.cpu
+            .memory
+            .load(&mut find_chunk(chunks, MEMORY_CHUNK)?)?;
+        self.ppu_mut().load(&mut find_chunk(chunks, PPU_CHUNK)?)?;
+        self.apu_mut().load(&mut find_chunk(chunks, APU_CHUNK)?)?;
+        self.cartridge
+            .borrow_mut()
+            .load(&mut find_chunk(chunks, CARTRIDGE_CHUNK)?)?;
+        self.input_mut()
+            .load(&mut find_chunk(chunks, INPUT_CHUNK)?)?;
+        return Ok(());
+    }
+
+    pub fn frame(&self) -> &Frame {
+        return self.ppu().frame();
+    }
+
+    /// Runs one CPU instruction (or interrupt) and the PPU and APU cycles it covers.
+    /// Returns true when the PPU entered vblank during it, fails on an opcode the CPU does not
+    /// implement.
+    ///
+    /// The PPU and APU catch up once the whole instruction has run, not on each bus access: an
+    /// instruction's register reads and writes see them as they were when it started, up to 7
+    /// CPU cycles early, and an NMI raised during it is taken once it is done. Games that race
+    /// the vblank flag on an exact cycle may behave differently than on hardware.
+    pub fn step(&mut self) -> Result<bool, String> {
+        self.cpu.step()?;
+        let instruction_end = self.cpu.cycles;
+
+        // the APU goes first: DMC fetches stall the CPU, and the PPU has to cover those cycles
+        while self.apu().cycles < self.cpu.cycles {
+            let apu = self.apu_mut();
+            apu.tick();
+            if let Some(addr) = apu.dmc_dma_request() {
+                let on_last_cycle = apu.cycles == instruction_end;
+                self.dmc_dma(addr, on_last_cycle);
+            }
+        }
+        let irq = self.apu().irq() || self.cartridge.borrow().irq();
+
+        let master_clock = self.cpu.cycles * self.cpu_divider;
+        let mut frame_completed = false;
+        let ppu = self
+            .cpu
+            .memory
+            .ppu_mut()
+            .expect("the PPU is attached in Nes::new");
+        while self.ppu_clock + self.ppu_divider <= master_clock {
+            frame_completed |= ppu.tick(1);
+            self.ppu_clock += self.ppu_divider;
+        }
+        let nmi = ppu.poll_nmi();
+
+        if nmi {
+            self.cpu.trigger_nmi();
+        }
+        self.cpu.set_irq_line(irq);
+
+        return Ok(frame_completed);
+    }
+
+    /// Fetches a DMC sample byte, halting the CPU. When the halt lands on the instruction's
+    /// last cycle and that is a read, the read is repeated: reading $2007 then advances the
+    /// PPU address twice and reading $4016/$4017 drops a controller bit.
+    fn dmc_dma(&mut self, addr: u16, on_last_cycle: bool) {
+        let mut stall = DMC_DMA_CYCLES;
+        if on_last_cycle {
+            match self.cpu.memory.last_access() {
+                BusAccess::Write(_) => stall -= 1,
+                BusAccess::Read(last) if has_read_side_effects(last) => {
+                    self.cpu.memory.read(last);
+                }
+                BusAccess::Read(_) => {}
+            }
+        }
+
+        let data = self.cpu.memory.read(addr);
+        self.apu_mut().dmc_dma_complete(data);
+        self.cpu.cycles += stall;
+    }
+
+    /// Runs until the PPU finishes drawing a frame, returns it with the audio produced meanwhile.
+    pub fn run_frame(&mut self) -> Result<(&Frame, Vec<f32>), String> {
+        while !self.step()? {}
+        let samples = self.apu_mut().take_samples();
+        return Ok((self.frame(), samples));
+    }
+}
+
+/// $2007 (and its mirrors) and the controller ports change state when read.
+fn has_read_side_effects(addr: u16) -> bool {
+    return matches!(addr, 0x4016 | 0x4017) || (0x2000..=0x3FFF).contains(&addr) && addr & 7 == 7;
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::test::ines_rom;
+
+    const NMI_HANDLER: u16 = 0x8100;
+    const IRQ_HANDLER: u16 = 0x8200;
+
+    /// NROM image with `program` at $8000 (the reset vector) and `nmi` at $8100.
+    pub fn test_rom(region: Region, program: &[u8], nmi: &[u8]) -> Rom {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[..program.len()].copy_from_slice(program);
+        let nmi_offset = (NMI_HANDLER - 0x8000) as usize;
+        prg_rom[nmi_offset..nmi_offset + nmi.len()].copy_from_slice(nmi);
+        // RTI
+        prg_rom[(IRQ_HANDLER - 0x8000) as usize] = 0x40;
+
+        let vectors = [NMI_HANDLER, 0x8000, IRQ_HANDLER];
+        for (i, vector) in vectors.iter().enumerate() {
+            prg_rom[0x7FFA + i * 2] = (vector & 0xFF) as u8;
+            prg_rom[0x7FFA + i * 2 + 1] = (vector >> 8) as u8;
+        }
+
+        return Rom {
+            region,
+            ..ines_rom(0, prg_rom, vec![])
+        };
+    }
+
+    // JMP $8000
+    const IDLE_LOOP: [u8; 3] = [0x4C, 0x00, 0x80];
+
+    #[test]
+    fn test_reset_vector_is_read_from_cartridge() {
+        let nes = Nes::new(test_rom(Region::Ntsc, &IDLE_LOOP, &[])).unwrap();
+
+        assert_eq!(nes.cpu.program_counter, 0x8000);
+    }
+
+    #[test]
+    fn test_typical_reset_code_runs() {
+        // SEI; CLD; LDX #$FF; TXS; JSR $800B; JMP $8008; INX; RTS
+        let program = [
+            0x78, 0xD8, 0xA2, 0xFF, 0x9A, 0x20, 0x0B, 0x80, 0x4C, 0x08, 0x80, 0xE8, 0x60,
+        ];
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &program, &[])).unwrap();
+
+        nes.run_frame().unwrap();
+
+        assert_eq!(nes.cpu.register_x, 0x00);
+        assert_eq!(nes.cpu.stack_pointer, 0xFF);
+        assert_eq!(nes.cpu.program_counter, 0x8008);
+    }
+
+    #[test]
+    fn test_unsupported_opcode_is_an_error() {
+        // LDA #$01; KIL
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &[0xA9, 0x01, 0x02], &[])).unwrap();
+
+        assert_eq!(
+            nes.run_frame().err(),
+            Some("unsupported opcode $02 at $8002".to_string())
+        );
+        assert_eq!(nes.cpu.program_counter, 0x8002);
+        assert_eq!(
+            nes.step(),
+            Err("unsupported opcode $02 at $8002".to_string())
+        );
+    }
+
+    #[test]
+    fn test_failed_load_keeps_the_console_as_it_was() {
+        // LDA #$42; STA $00; JMP $8004
+        let program = [0xA9, 0x42, 0x85, 0x00, 0x4C, 0x04, 0x80];
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &program, &[])).unwrap();
+        nes.run_frame().unwrap();
+        let before = nes.save_state();
+        // the CPU and memory chunks load, then the PPU chunk is cut short
+        let chunks = state::decode(&before).unwrap();
+        let mut damaged: Vec<(Tag, Vec<u8>)> = chunks
+            .iter()
+            .map(|(tag, payload)| (*tag, payload.to_vec()))
+            .collect();
+        damaged
+            .iter_mut()
+            .find(|(tag, _)| *tag == PPU_CHUNK)
+            .unwrap()
+            .1
+            .truncate(100);
+        let mut other = Nes::new(test_rom(Region::Ntsc, &program, &[])).unwrap();
+        other.ram_mut()[0] = 0x99;
+        let other_before = other.save_state();
+
+        let error = other.load_state(&state::encode(&damaged)).unwrap_err();
+
+        assert_eq!(error, "PPU chunk: truncated");
+        assert_eq!(other.save_state(), other_before);
+        assert_eq!(other.ram_mut()[0], 0x99);
+    }
+
+    #[test]
+    fn test_region_mismatch_is_rejected() {
+        let mut pal = Nes::new(test_rom(Region::Pal, &IDLE_LOOP, &[])).unwrap();
+        // stop on one of the extra PAL scanlines
+        while pal.ppu().scanline < 280 {
+            pal.step().unwrap();
+        }
+        let mut ntsc = Nes::new(test_rom(Region::Ntsc, &IDLE_LOOP, &[])).unwrap();
+
+        let error = ntsc.load_state(&pal.save_state()).unwrap_err();
+
+        assert!(error.contains("another region"), "{}", error);
+    }
+
+    #[test]
+    fn test_reset_keeps_ram() {
+        // LDA #$42; STA $00; JMP $8004
+        let program = [0xA9, 0x42, 0x85, 0x00, 0x4C, 0x04, 0x80];
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &program, &[])).unwrap();
+        nes.run_frame().unwrap();
+        let stack_pointer = nes.cpu.stack_pointer;
+
+        nes.reset();
+
+        assert_eq!(nes.cpu.program_counter, 0x8000);
+        assert_eq!(nes.cpu.stack_pointer, stack_pointer.wrapping_sub(3));
+        assert_eq!(nes.ram_mut()[0], 0x42);
+    }
+
+    #[test]
+    fn test_vblank_nmi_is_delivered() {
+        // LDA #$80; STA $2000; JMP $8005
+        let program = [0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80];
+        // INC $00; RTI
+        let nmi = [0xE6, 0x00, 0x40];
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &program, &nmi)).unwrap();
+
+        for _ in 0..3 {
+            nes.run_frame().unwrap();
+        }
+        // the NMI raised at the end of the last frame is serviced on the next step
+        assert_eq!(nes.cpu.memory.read(0x00), 2);
+        nes.step().unwrap();
+        assert_eq!(nes.cpu.program_counter, NMI_HANDLER);
+    }
+
+    fn cycles_per_two_frames(region: Region) -> u64 {
+        let mut nes = Nes::new(test_rom(region, &IDLE_LOOP, &[])).unwrap();
+        nes.run_frame().unwrap();
+        let start = nes.cpu.cycles;
+        nes.run_frame().unwrap();
+        nes.run_frame().unwrap();
+        return nes.cpu.cycles - start;
+    }
+
+    #[test]
+    fn test_ntsc_frame_length() {
+        // 2 * 341 * 262 dots at 3 dots per cycle, give or take an instruction
+        let cycles = cycles_per_two_frames(Region::Ntsc);
+        assert!((59558..=59564).contains(&cycles), "{}", cycles);
+    }
+
+    #[test]
+    fn test_pal_frame_length() {
+        // 2 * 341 * 312 dots at 3.2 dots per cycle
+        let cycles = cycles_per_two_frames(Region::Pal);
+        assert!((66492..=66498).contains(&cycles), "{}", cycles);
+    }
+
+    #[test]
+    fn test_ppu_keeps_pace_with_cpu() {
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &IDLE_LOOP, &[])).unwrap();
+        for _ in 0..1000 {
+            nes.step().unwrap();
+        }
+
+        let ppu = nes.ppu();
+        let dots = (ppu.frame_count * 262 + ppu.scanline as u64) * 341 + ppu.dot as u64;
+        // the PPU powers up on the pre-render line of frame 0
+        let dots = dots + 341 - 262 * 341;
+        assert_eq!(dots, nes.cpu.cycles * 3);
+    }
+
+    // LDA #$0F; STA $4010; LDA #$00; STA $4012; STA $4013; LDA #$10 (or #$00); STA $4015
+    fn dmc_program(enable: u8) -> Vec<u8> {
+        let mut program = vec![
+            0xA9, 0x0F, 0x8D, 0x10, 0x40, 0xA9, 0x00, 0x8D, 0x12, 0x40, 0x8D, 0x13, 0x40, 0xA9,
+            enable, 0x8D, 0x15, 0x40,
+        ];
+        // JMP to itself
+        program.extend([0x4C, program.len() as u8, 0x80]);
+        return program;
+    }
+
+    #[test]
+    fn test_dmc_fetch_stalls_cpu() {
+        let mut cycles = vec![];
+        for enable in [0x00, 0x10] {
+            let mut nes = Nes::new(test_rom(Region::Ntsc, &dmc_program(enable), &[])).unwrap();
+            for _ in 0..10 {
+                nes.step().unwrap();
+            }
+            cycles.push(nes.cpu.cycles);
+        }
+
+        // a 1 byte sample is fetched once
+        assert_eq!(cycles[1] - cycles[0], DMC_DMA_CYCLES);
+    }
+
+    #[test]
+    fn test_dmc_fetch_ends_with_irq() {
+        let mut program = dmc_program(0x10);
+        // IRQ enabled
+        program[1] = 0x8F;
+        // CLI before the loop
+        program.insert(program.len() - 3, 0x58);
+        let loop_start = program.len() as u8 - 3;
+        let len = program.len();
+        program[len - 2] = loop_start;
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &program, &[])).unwrap();
+
+        let mut serviced = false;
+        for _ in 0..20 {
+            nes.step().unwrap();
+            serviced |= nes.cpu.program_counter == IRQ_HANDLER;
+        }
+
+        assert!(serviced);
+        assert_eq!(nes.cpu.memory.read(0x4015) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn test_dmc_fetch_on_write_cycle_is_shorter() {
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &IDLE_LOOP, &[])).unwrap();
+        nes.cpu.memory.write(0x4015, 0x10);
+        nes.cpu.memory.write(0x0000, 0);
+        let start = nes.cpu.cycles;
+
+        nes.dmc_dma(0xC000, true);
+
+        assert_eq!(nes.cpu.cycles - start, DMC_DMA_CYCLES - 1);
+    }
+
+    #[test]
+    fn test_dmc_fetch_repeats_ppudata_read() {
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &IDLE_LOOP, &[])).unwrap();
+        nes.cpu.memory.write(0x4015, 0x10);
+        nes.cpu.memory.write(0x2006, 0x21);
+        nes.cpu.memory.write(0x2006, 0x00);
+        nes.cpu.memory.read(0x2007);
+
+        nes.dmc_dma(0xC000, true);
+        nes.cpu.memory.write(0x2007, 0x66);
+
+        // the write landed on $2102 instead of $2101
+        nes.cpu.memory.write(0x2006, 0x21);
+        nes.cpu.memory.write(0x2006, 0x01);
+        nes.cpu.memory.read(0x2007);
+        assert_eq!(nes.cpu.memory.read(0x2007), 0x00);
+        assert_eq!(nes.cpu.memory.read(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_frame_irq_reaches_cpu() {
+        // CLI; JMP $8001
+        let program = [0x58, 0x4C, 0x01, 0x80];
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &program, &[])).unwrap();
+
+        while nes.cpu.program_counter != IRQ_HANDLER {
+            nes.step().unwrap();
+            assert!(nes.cpu.cycles < 40000, "no frame IRQ");
+        }
+
+        // the flag goes up 29828 cycles into the sequence, past the 7 reset cycles
+        assert!(
+            (29828..29850).contains(&nes.cpu.cycles),
+            "{}",
+            nes.cpu.cycles
+        );
+    }
+
+    #[test]
+    fn test_pulse_tone_reaches_audio_output() {
+        use crate::audio::AudioOutput;
+
+        // LDA #$01; STA $4015; LDA #$BF; STA $4000; LDA #$FD; STA $4002; LDA #$00; STA $4003
+        let mut program = vec![
+            0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40, 0xA9, 0xFD, 0x8D, 0x02,
+            0x40, 0xA9, 0x00, 0x8D, 0x03, 0x40,
+        ];
+        program.extend([0x4C, program.len() as u8, 0x80]);
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &program, &[])).unwrap();
+        let mut audio = AudioOutput::new(Region::Ntsc, 48_000).unwrap();
+
+        let mut output = vec![];
+        for _ in 0..10 {
+            let (_, samples) = nes.run_frame().unwrap();
+            output.extend(audio.process(&samples));
+        }
+
+        // 10 frames of about 1/60 s, less the resampler's latency
+        assert!((7900..=8000).contains(&output.len()), "{}", output.len());
+        let peak = output[4000..]
+            .iter()
+            .fold(0.0f32, |max, s| max.max(s.abs()));
+        assert!(peak > 0.05, "{}", peak);
+    }
+
+    #[test]
+    fn test_dmc_fetch_drops_controller_bit() {
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &IDLE_LOOP, &[])).unwrap();
+        nes.set_buttons(0, Buttons::A | Buttons::Select);
+        nes.cpu.memory.write(0x4015, 0x10);
+        nes.cpu.memory.write(0x4016, 1);
+        nes.cpu.memory.write(0x4016, 0);
+        assert_eq!(nes.cpu.memory.read(0x4016) & 1, 1);
+
+        nes.dmc_dma(0xC000, true);
+
+        // B was clocked out by the repeated read
+        assert_eq!(nes.cpu.memory.read(0x4016) & 1, 1);
+    }
+
+    #[test]
+    fn test_run_frame_returns_audio_for_every_cpu_cycle() {
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &IDLE_LOOP, &[])).unwrap();
+        nes.run_frame().unwrap();
+        let start = nes.cpu.cycles;
+
+        let (_, samples) = nes.run_frame().unwrap();
+
+        assert_eq!(samples.len() as u64, nes.cpu.cycles - start);
+    }
+}
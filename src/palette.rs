@@ -0,0 +1,358 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use crate::cartridge::Region;
+use crate::ppu::Frame;
+
+pub const COLORS: usize = 64;
+/// One table per combination of the 3 PPUMASK emphasis bits.
+pub const EMPHASIS_VARIANTS: usize = 8;
+const PAL_FILE_SIZE: usize = COLORS * 3;
+const PAL_FILE_WITH_EMPHASIS_SIZE: usize = COLORS * 3 * EMPHASIS_VARIANTS;
+
+/// How much an emphasis bit dims the two colour channels it does not emphasise.
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PixelFormat {
+    /// 4 bytes per pixel: R, G, B, A.
+    Rgba8888,
+    /// 2 bytes per pixel, little-endian RRRRRGGG GGGBBBBB.
+    Rgb565,
+    /// 4 bytes per pixel: B, G, R, A.
+    Bgra8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 | PixelFormat::Bgra8888 => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+}
+
+#[rustfmt::skip]
+const NTSC_COLORS: [Rgb; COLORS] = [
+    Rgb::new(0x80, 0x80, 0x80), Rgb::new(0x00, 0x3D, 0xA6), Rgb::new(0x00, 0x12, 0xB0), Rgb::new(0x44, 0x00, 0x96),
+    Rgb::new(0xA1, 0x00, 0x5E), Rgb::new(0xC7, 0x00, 0x28), Rgb::new(0xBA, 0x06, 0x00), Rgb::new(0x8C, 0x17, 0x00),
+    Rgb::new(0x5C, 0x2F, 0x00), Rgb::new(0x10, 0x45, 0x00), Rgb::new(0x05, 0x4A, 0x00), Rgb::new(0x00, 0x47, 0x2E),
+    Rgb::new(0x00, 0x41, 0x66), Rgb::new(0x00, 0x00, 0x00), Rgb::new(0x05, 0x05, 0x05), Rgb::new(0x05, 0x05, 0x05),
+    Rgb::new(0xC7, 0xC7, 0xC7), Rgb::new(0x00, 0x77, 0xFF), Rgb::new(0x21, 0x55, 0xFF), Rgb::new(0x82, 0x37, 0xFA),
+    Rgb::new(0xEB, 0x2F, 0xB5), Rgb::new(0xFF, 0x29, 0x50), Rgb::new(0xFF, 0x22, 0x00), Rgb::new(0xD6, 0x32, 0x00),
+    Rgb::new(0xC4, 0x62, 0x00), Rgb::new(0x35, 0x80, 0x00), Rgb::new(0x05, 0x8F, 0x00), Rgb::new(0x00, 0x8A, 0x55),
+    Rgb::new(0x00, 0x99, 0xCC), Rgb::new(0x21, 0x21, 0x21), Rgb::new(0x09, 0x09, 0x09), Rgb::new(0x09, 0x09, 0x09),
+    Rgb::new(0xFF, 0xFF, 0xFF), Rgb::new(0x0F, 0xD7, 0xFF), Rgb::new(0x69, 0xA2, 0xFF), Rgb::new(0xD4, 0x80, 0xFF),
+    Rgb::new(0xFF, 0x45, 0xF3), Rgb::new(0xFF, 0x61, 0x8B), Rgb::new(0xFF, 0x88, 0x33), Rgb::new(0xFF, 0x9C, 0x12),
+    Rgb::new(0xFA, 0xBC, 0x20), Rgb::new(0x9F, 0xE3, 0x0E), Rgb::new(0x2B, 0xF0, 0x35), Rgb::new(0x0C, 0xF0, 0xA4),
+    Rgb::new(0x05, 0xFB, 0xFF), Rgb::new(0x5E, 0x5E, 0x5E), Rgb::new(0x0D, 0x0D, 0x0D), Rgb::new(0x0D, 0x0D, 0x0D),
+    Rgb::new(0xFF, 0xFF, 0xFF), Rgb::new(0xA6, 0xFC, 0xFF), Rgb::new(0xB3, 0xEC, 0xFF), Rgb::new(0xDA, 0xAB, 0xEB),
+    Rgb::new(0xFF, 0xA8, 0xF9), Rgb::new(0xFF, 0xAB, 0xB3), Rgb::new(0xFF, 0xD2, 0xB0), Rgb::new(0xFF, 0xEF, 0xA6),
+    Rgb::new(0xFF, 0xF7, 0x9C), Rgb::new(0xD7, 0xE8, 0x95), Rgb::new(0xA6, 0xED, 0xAF), Rgb::new(0xA2, 0xF2, 0xDA),
+    Rgb::new(0x99, 0xFF, 0xFC), Rgb::new(0xDD, 0xDD, 0xDD), Rgb::new(0x11, 0x11, 0x11), Rgb::new(0x11, 0x11, 0x11),
+];
+
+/// Greyscale mode (PPUMASK bit 0) keeps only the brightness column of a palette index.
+pub fn greyscale_index(index: u8) -> u8 {
+    return index & 0x30;
+}
+
+/// Maps a 6-bit palette index and the 3 emphasis bits to RGB.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Palette {
+    colors: Vec<Rgb>,
+}
+
+impl Palette {
+    pub fn ntsc() -> Self {
+        return Palette::with_generated_emphasis(&NTSC_COLORS, Region::Ntsc);
+    }
+
+    /// The 2C07's palette, decoded from its composite signal the way a PAL set would.
+    pub fn pal() -> Self {
+        let mut colors = [Rgb::default(); COLORS];
+        for (index, color) in colors.iter_mut().enumerate() {
+            *color = decode_pal_signal(index as u8);
+        }
+        return Palette::with_generated_emphasis(&colors, Region::Pal);
+    }
+
+    pub fn for_region(region: Region) -> Self {
+        match region {
+            Region::Pal | Region::Dendy => Palette::pal(),
+            Region::Ntsc | Region::Multi => Palette::ntsc(),
+        }
+    }
+
+    /// Parses a .pal file: 64 RGB triplets, optionally followed by the 7 emphasised variants.
+    /// Emphasis for 192-byte files is generated using `region`'s emphasis bit order.
+    pub fn from_bytes(data: &[u8], region: Region) -> Result<Palette, String> {
+        let colors: Vec<Rgb> = data
+            .chunks_exact(3)
+            .map(|rgb| Rgb::new(rgb[0], rgb[1], rgb[2]))
+            .collect();
+
+        match data.len() {
+            PAL_FILE_SIZE => {
+                let base: [Rgb; COLORS] = colors.try_into().unwrap();
+                return Ok(Palette::with_generated_emphasis(&base, region));
+            }
+            PAL_FILE_WITH_EMPHASIS_SIZE => return Ok(Palette { colors }),
+            size => {
+                return Err(format!(
+                    "palette files are {} or {} bytes long, got {}",
+                    PAL_FILE_SIZE, PAL_FILE_WITH_EMPHASIS_SIZE, size
+                ))
+            }
+        }
+    }
+
+    pub fn load(path: &Path, region: Region) -> Result<Palette, String> {
+        let data =
+            fs::read(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        return Palette::from_bytes(&data, region);
+    }
+
+    /// Builds the 7 emphasised tables by dimming the channels each set bit does not emphasise.
+    /// PPUMASK bit 5 emphasises red on NTSC but green on PAL (and bit 6 the other way around).
+    pub fn with_generated_emphasis(base: &[Rgb; COLORS], region: Region) -> Self {
+        let swap_red_green = matches!(region, Region::Pal | Region::Dendy);
+        let mut colors = Vec::with_capacity(COLORS * EMPHASIS_VARIANTS);
+
+        for emphasis in 0..EMPHASIS_VARIANTS {
+            let (mut red, mut green) = (emphasis & 0b001 != 0, emphasis & 0b010 != 0);
+            let blue = emphasis & 0b100 != 0;
+            if swap_red_green {
+                (red, green) = (green, red);
+            }
+
+            let dim = |channel: u8, emphasised: bool| {
+                let others = [red, green, blue].iter().filter(|e| **e).count()
+                    - if emphasised { 1 } else { 0 };
+                let level = channel as f64 * EMPHASIS_ATTENUATION.powi(others as i32);
+                return level.round() as u8;
+            };
+            for color in base {
+                colors.push(Rgb::new(
+                    dim(color.r, red),
+                    dim(color.g, green),
+                    dim(color.b, blue),
+                ));
+            }
+        }
+
+        return Palette { colors };
+    }
+
+    pub fn color(&self, index: u8, emphasis: u8) -> Rgb {
+        let table = (emphasis as usize & 0b111) * COLORS;
+        return self.colors[table + (index as usize & 0x3F)];
+    }
+
+    /// The 64 colours of one emphasis table, as stored in a 192-byte .pal file.
+    pub fn table(&self, emphasis: u8) -> &[Rgb] {
+        let table = (emphasis as usize & 0b111) * COLORS;
+        return &self.colors[table..table + COLORS];
+    }
+
+    /// Serialises all 8 tables in the 1536-byte .pal layout.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        return self.colors.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    }
+
+    pub fn convert_frame(&self, frame: &Frame, format: PixelFormat) -> Vec<u8> {
+        let mut output = Vec::with_capacity(frame.pixels.len() * format.bytes_per_pixel());
+        for (index, emphasis) in frame.pixels.iter().zip(frame.emphasis.iter()) {
+            let color = self.color(*index, *emphasis);
+            match format {
+                PixelFormat::Rgba8888 => output.extend([color.r, color.g, color.b, 0xFF]),
+                PixelFormat::Bgra8888 => output.extend([color.b, color.g, color.r, 0xFF]),
+                PixelFormat::Rgb565 => {
+                    let value = ((color.r as u16 >> 3) << 11)
+                        | ((color.g as u16 >> 2) << 5)
+                        | (color.b as u16 >> 3);
+                    output.extend(value.to_le_bytes());
+                }
+            }
+        }
+        return output;
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::ntsc()
+    }
+}
+
+/// Decodes one palette entry from the 2C07's square-wave video signal.
+///
+///  index: 0bLLHHHH
+///         | +------- hue: 0 = grey above the colour levels, 1-12 = phase, 13 = grey below,
+///         |          14-15 = black
+///         +--------- luma level
+fn decode_pal_signal(index: u8) -> Rgb {
+    // signal levels relative to black (0.0) and white (1.0)
+    const LOW: [f64; 4] = [-0.117, 0.0, 0.308, 0.715];
+    const HIGH: [f64; 4] = [0.397, 0.681, 1.0, 1.0];
+    // the 2C07 shifts its colour phases by 15 degrees compared to the 2C02
+    const HUE_OFFSET: f64 = -PI / 12.0;
+    const SATURATION: f64 = 0.8;
+
+    let hue = (index & 0x0F) as usize;
+    let level = (index >> 4) as usize;
+    if hue >= 14 {
+        return Rgb::default();
+    }
+
+    let (mut y, mut u, mut v) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let signal = match hue {
+            0 => HIGH[level],
+            13 => LOW[level],
+            _ if (hue + phase) % 12 < 6 => HIGH[level],
+            _ => LOW[level],
+        };
+        let angle = 2.0 * PI * phase as f64 / 12.0 + HUE_OFFSET;
+        y += signal / 12.0;
+        u += signal * angle.cos() / 12.0;
+        v += signal * angle.sin() / 12.0;
+    }
+    let (u, v) = (u * SATURATION, v * SATURATION);
+
+    let to_byte = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    return Rgb::new(
+        to_byte(y + 1.140 * v),
+        to_byte(y - 0.395 * u - 0.581 * v),
+        to_byte(y + 2.032 * u),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ntsc_palette_colors() {
+        let palette = Palette::ntsc();
+
+        assert_eq!(palette.color(0x0D, 0), Rgb::new(0, 0, 0));
+        assert_eq!(palette.color(0x30, 0), Rgb::new(0xFF, 0xFF, 0xFF));
+        assert_eq!(palette.color(0x16, 0), Rgb::new(0xFF, 0x22, 0x00));
+        // indices are 6 bits wide
+        assert_eq!(palette.color(0x56, 0), palette.color(0x16, 0));
+    }
+
+    #[test]
+    fn test_pal_palette_greys_and_blacks() {
+        let palette = Palette::pal();
+
+        for index in [0x00, 0x10, 0x20, 0x30, 0x1D, 0x2D] {
+            let color = palette.color(index, 0);
+            assert!(color.r == color.g && color.g == color.b, "{:02X}", index);
+        }
+        assert_eq!(palette.color(0x0E, 0), Rgb::new(0, 0, 0));
+        assert_eq!(palette.color(0x30, 0), Rgb::new(0xFF, 0xFF, 0xFF));
+
+        // hues are distinct and reddish/bluish where expected
+        let red = palette.color(0x16, 0);
+        let blue = palette.color(0x12, 0);
+        assert!(red.r > red.b && blue.b > blue.r);
+    }
+
+    #[test]
+    fn test_emphasis_dims_other_channels() {
+        let palette = Palette::ntsc();
+        let white = palette.color(0x30, 0);
+
+        let red = palette.color(0x30, 0b001);
+        assert_eq!(red.r, white.r);
+        assert!(red.g < white.g && red.b < white.b);
+
+        let all = palette.color(0x30, 0b111);
+        assert!(all.r < white.r && all.r == all.g && all.g == all.b);
+    }
+
+    #[test]
+    fn test_pal_emphasis_swaps_red_and_green() {
+        let palette = Palette::with_generated_emphasis(&NTSC_COLORS, Region::Pal);
+        let white = palette.color(0x30, 0);
+
+        let green = palette.color(0x30, 0b001);
+        assert_eq!(green.g, white.g);
+        assert!(green.r < white.r);
+    }
+
+    #[test]
+    fn test_load_192_byte_file() {
+        let data: Vec<u8> = (0..PAL_FILE_SIZE).map(|i| i as u8).collect();
+
+        let palette = Palette::from_bytes(&data, Region::Ntsc).unwrap();
+
+        assert_eq!(palette.color(0x01, 0), Rgb::new(3, 4, 5));
+        assert_eq!(palette.table(0).len(), COLORS);
+        assert_ne!(palette.color(0x01, 0b010), palette.color(0x01, 0));
+    }
+
+    #[test]
+    fn test_load_1536_byte_file() {
+        let data: Vec<u8> = (0..PAL_FILE_WITH_EMPHASIS_SIZE)
+            .map(|i| (i / 3 / COLORS) as u8)
+            .collect();
+
+        let palette = Palette::from_bytes(&data, Region::Ntsc).unwrap();
+
+        assert_eq!(palette.color(0x20, 5), Rgb::new(5, 5, 5));
+        assert_eq!(palette.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_reject_bad_file_size() {
+        let err = Palette::from_bytes(&[0; 100], Region::Ntsc).unwrap_err();
+
+        assert!(err.contains("100"));
+    }
+
+    #[test]
+    fn test_greyscale_index() {
+        assert_eq!(greyscale_index(0x16), 0x10);
+        assert_eq!(greyscale_index(0x3F), 0x30);
+    }
+
+    #[test]
+    fn test_convert_frame_formats() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, 0x16);
+        frame.set_pixel(1, 0, 0x30);
+        frame.set_emphasis(1, 0, 0b100);
+        let palette = Palette::ntsc();
+
+        let rgba = palette.convert_frame(&frame, PixelFormat::Rgba8888);
+        assert_eq!(rgba.len(), frame.pixels.len() * 4);
+        assert_eq!(rgba[0..4], [0xFF, 0x22, 0x00, 0xFF]);
+        let tinted = palette.color(0x30, 0b100);
+        assert_eq!(rgba[4..8], [tinted.r, tinted.g, tinted.b, 0xFF]);
+
+        let bgra = palette.convert_frame(&frame, PixelFormat::Bgra8888);
+        assert_eq!(bgra[0..4], [0x00, 0x22, 0xFF, 0xFF]);
+
+        let rgb565 = palette.convert_frame(&frame, PixelFormat::Rgb565);
+        assert_eq!(rgb565.len(), frame.pixels.len() * 2);
+        // red 11111, green 001000, blue 00000
+        assert_eq!(u16::from_le_bytes([rgb565[0], rgb565[1]]), 0xF900);
+    }
+}
@@ -0,0 +1,630 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hash::crc32;
+
+const IPS_TAG: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_TAG: &[u8] = b"BPS1";
+const UPS_TAG: &[u8] = b"UPS1";
+const FOOTER_SIZE: usize = 12;
+/// Largest target a BPS or UPS patch may ask for, far above any NES game. Sizes are read from
+/// the patch, so a corrupt one could otherwise claim gigabytes.
+const MAX_TARGET_SIZE: usize = 16 * 1024 * 1024;
+
+/// Extensions looked up next to a rom file when soft-patching, in order of preference.
+const SOFT_PATCH_EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+    Ups,
+}
+
+pub struct PatchedRom {
+    pub data: Vec<u8>,
+    pub patch: Option<PathBuf>,
+}
+
+pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+    if patch.starts_with(IPS_TAG) {
+        return Some(PatchFormat::Ips);
+    }
+    if patch.starts_with(BPS_TAG) {
+        return Some(PatchFormat::Bps);
+    }
+    if patch.starts_with(UPS_TAG) {
+        return Some(PatchFormat::Ups);
+    }
+    return None;
+}
+
+/// Applies a patch of any supported format to the rom bytes.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    match detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        Some(PatchFormat::Ups) => apply_ups(rom, patch),
+        None => Err("unrecognized patch format".to_string()),
+    }
+}
+
+/// Reads `path` and, if a patch with the same stem sits next to it (ie.: `game.nes` + `game.ips`),
+/// applies it in memory. The rom file on disk is never modified.
+pub fn load_with_soft_patch(path: &Path) -> Result<PatchedRom, String> {
+    let rom = fs::read(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+
+    for extension in SOFT_PATCH_EXTENSIONS {
+        let patch_path = path.with_extension(extension);
+        if !patch_path.is_file() {
+            continue;
+        }
+
+        let patch = fs::read(&patch_path)
+            .map_err(|e| format!("unable to read {}: {}", patch_path.display(), e))?;
+        let data = apply(&rom, &patch)
+            .map_err(|e| format!("unable to apply {}: {}", patch_path.display(), e))?;
+
+        return Ok(PatchedRom {
+            data,
+            patch: Some(patch_path),
+        });
+    }
+
+    return Ok(PatchedRom {
+        data: rom,
+        patch: None,
+    });
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(IPS_TAG) {
+        return Err("not an IPS patch".to_string());
+    }
+
+    let mut output = rom.to_vec();
+    let mut reader = PatchReader::new(patch, IPS_TAG.len());
+
+    loop {
+        let record = reader.bytes(3)?;
+        if record == IPS_EOF {
+            break;
+        }
+        let offset = be(record);
+        let size = be(reader.bytes(2)?);
+
+        let (len, data) = if size == 0 {
+            // RLE record: 2 bytes run length, 1 byte value
+            let len = be(reader.bytes(2)?);
+            (len, vec![reader.byte()?; len])
+        } else {
+            (size, reader.bytes(size)?.to_vec())
+        };
+
+        if output.len() < offset + len {
+            output.resize(offset + len, 0);
+        }
+        output[offset..offset + len].copy_from_slice(&data);
+    }
+
+    // optional truncation extension, 3 bytes after the EOF marker
+    if reader.remaining() == 3 {
+        let truncated_size = be(reader.bytes(3)?);
+        output.truncate(truncated_size);
+    }
+
+    return Ok(output);
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(BPS_TAG) || patch.len() < BPS_TAG.len() + FOOTER_SIZE {
+        return Err("not a BPS patch".to_string());
+    }
+    let footer = Footer::read(patch)?;
+    if crc32(rom) != footer.source_crc {
+        return Err(format!(
+            "source crc32 mismatch: expected {:08X}, got {:08X}",
+            footer.source_crc,
+            crc32(rom)
+        ));
+    }
+
+    let actions_end = patch.len() - FOOTER_SIZE;
+    let mut reader = PatchReader::new(&patch[..actions_end], BPS_TAG.len());
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err(format!(
+            "source size mismatch: expected {}, got {}",
+            source_size,
+            rom.len()
+        ));
+    }
+    check_target_size(target_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    while reader.remaining() > 0 {
+        let data = reader.varint()?;
+        let len = (data >> 2) + 1;
+        if target.len() + len > target_size {
+            return Err(format!(
+                "patch writes past the target size of {} bytes",
+                target_size
+            ));
+        }
+
+        match data & 0b11 {
+            // SourceRead
+            0 => {
+                let start = target.len();
+                let bytes = rom
+                    .get(start..start + len)
+                    .ok_or("source read out of bounds")?;
+                target.extend_from_slice(bytes);
+            }
+            // TargetRead
+            1 => target.extend_from_slice(reader.bytes(len)?),
+            // SourceCopy
+            2 => {
+                source_offset = move_offset(source_offset, reader.varint()?)
+                    .ok_or("source copy out of bounds")?;
+                let bytes = rom
+                    .get(source_offset..source_offset + len)
+                    .ok_or("source copy out of bounds")?;
+                target.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // TargetCopy, may overlap the bytes being written
+            _ => {
+                target_offset = move_offset(target_offset, reader.varint()?)
+                    .filter(|offset| *offset < target.len())
+                    .ok_or("target copy out of bounds")?;
+                for _ in 0..len {
+                    target.push(target[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(format!(
+            "target size mismatch: expected {}, got {}",
+            target_size,
+            target.len()
+        ));
+    }
+    if crc32(&target) != footer.target_crc {
+        return Err(format!(
+            "target crc32 mismatch: expected {:08X}, got {:08X}",
+            footer.target_crc,
+            crc32(&target)
+        ));
+    }
+
+    return Ok(target);
+}
+
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(UPS_TAG) || patch.len() < UPS_TAG.len() + FOOTER_SIZE {
+        return Err("not a UPS patch".to_string());
+    }
+    let footer = Footer::read(patch)?;
+
+    let actions_end = patch.len() - FOOTER_SIZE;
+    let mut reader = PatchReader::new(&patch[..actions_end], UPS_TAG.len());
+    let input_size = reader.varint()?;
+    let output_size = reader.varint()?;
+
+    // UPS patches are reversible: applying one to its output gives back the input
+    let rom_crc = crc32(rom);
+    let (expected_size, target_size, target_crc) = if rom_crc == footer.source_crc {
+        (input_size, output_size, footer.target_crc)
+    } else if rom_crc == footer.target_crc {
+        (output_size, input_size, footer.source_crc)
+    } else {
+        return Err(format!(
+            "source crc32 mismatch: expected {:08X}, got {:08X}",
+            footer.source_crc, rom_crc
+        ));
+    };
+    if rom.len() != expected_size {
+        return Err(format!(
+            "source size mismatch: expected {}, got {}",
+            expected_size,
+            rom.len()
+        ));
+    }
+    check_target_size(target_size)?;
+
+    // hunks cover the longer of the two files, anything past that is not part of either
+    let mut output = rom.to_vec();
+    output.resize(target_size.max(rom.len()), 0);
+    let mut offset: usize = 0;
+
+    while reader.remaining() > 0 {
+        offset = offset
+            .checked_add(reader.varint()?)
+            .filter(|offset| *offset <= output.len())
+            .ok_or("patch skips past the end of the file")?;
+        loop {
+            let xor = reader.byte()?;
+            if xor == 0 {
+                offset += 1;
+                break;
+            }
+            let byte = output
+                .get_mut(offset)
+                .ok_or_else(|| format!("patch writes past the end of the file at {:#X}", offset))?;
+            *byte ^= xor;
+            offset += 1;
+        }
+    }
+
+    output.truncate(target_size);
+    if crc32(&output) != target_crc {
+        return Err(format!(
+            "target crc32 mismatch: expected {:08X}, got {:08X}",
+            target_crc,
+            crc32(&output)
+        ));
+    }
+
+    return Ok(output);
+}
+
+struct Footer {
+    source_crc: u32,
+    target_crc: u32,
+}
+
+impl Footer {
+    /// Reads the source/target checksums and verifies the patch checksum.
+    fn read(patch: &[u8]) -> Result<Self, String> {
+        let footer = &patch[patch.len() - FOOTER_SIZE..];
+        let patch_crc = le_u32(&footer[8..12]);
+        if crc32(&patch[..patch.len() - 4]) != patch_crc {
+            return Err("patch crc32 mismatch, the patch file is corrupted".to_string());
+        }
+
+        return Ok(Footer {
+            source_crc: le_u32(&footer[0..4]),
+            target_crc: le_u32(&footer[4..8]),
+        });
+    }
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        PatchReader { data, position }
+    }
+
+    fn remaining(&self) -> usize {
+        return self.data.len().saturating_sub(self.position);
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        return Ok(self.bytes(1)?[0]);
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| format!("patch is truncated at offset {:#X}", self.position))?;
+        self.position += len;
+        return Ok(bytes);
+    }
+
+    /// Variable-length number shared by BPS and UPS.
+    fn varint(&mut self) -> Result<usize, String> {
+        let mut data: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.byte()?;
+            data = (x as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|v| data.checked_add(v))
+                .ok_or("patch number overflow")?;
+            if x & 0x80 != 0 {
+                return Ok(data);
+            }
+            shift = shift.checked_shl(7).ok_or("patch number overflow")?;
+            data = data.checked_add(shift).ok_or("patch number overflow")?;
+        }
+    }
+}
+
+fn check_target_size(target_size: usize) -> Result<(), String> {
+    if target_size > MAX_TARGET_SIZE {
+        return Err(format!(
+            "target size of {} bytes is over the {} byte limit",
+            target_size, MAX_TARGET_SIZE
+        ));
+    }
+    return Ok(());
+}
+
+/// Moves a BPS copy offset by a relative amount: the low bit is the sign, the rest the distance.
+/// None when it would go below 0 or overflow.
+fn move_offset(offset: usize, data: usize) -> Option<usize> {
+    let distance = data >> 1;
+    return if data & 1 != 0 {
+        offset.checked_sub(distance)
+    } else {
+        offset.checked_add(distance)
+    };
+}
+
+fn be(bytes: &[u8]) -> usize {
+    return bytes.iter().fold(0, |acc, b| (acc << 8) | *b as usize);
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    return u32::from_le_bytes(bytes.try_into().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_varint(mut value: usize) -> Vec<u8> {
+        let mut result = vec![];
+        loop {
+            let x = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                result.push(0x80 | x);
+                return result;
+            }
+            result.push(x);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        return patch;
+    }
+
+    #[test]
+    fn test_ips_records() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        patch.extend(b"EOF");
+
+        let result = apply_ips(&[0, 1, 2, 3], &patch).unwrap();
+
+        assert_eq!(result, vec![0, 0xAA, 0xBB, 3]);
+    }
+
+    #[test]
+    fn test_ips_rle_record_extends_rom() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x7F]);
+        patch.extend(b"EOF");
+
+        let result = apply_ips(&[0, 1, 2], &patch).unwrap();
+
+        assert_eq!(result, vec![0, 1, 0x7F, 0x7F, 0x7F, 0x7F]);
+    }
+
+    #[test]
+    fn test_ips_truncation() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x00, 0x00, 0x01, 0xFF]);
+        patch.extend(b"EOF");
+        patch.extend([0x00, 0x00, 0x02]);
+
+        let result = apply_ips(&[0, 1, 2, 3], &patch).unwrap();
+
+        assert_eq!(result, vec![0xFF, 1]);
+    }
+
+    #[test]
+    fn test_ips_truncated_patch() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x00, 0x00, 0x04, 0xFF]);
+
+        assert!(apply_ips(&[0, 1, 2, 3], &patch).is_err());
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"hello world".to_vec();
+        let target = b"hello hello world!".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_varint(source.len()));
+        patch.extend(encode_varint(target.len()));
+        patch.extend(encode_varint(0));
+        // SourceRead "hello "
+        patch.extend(encode_varint((6 - 1) << 2));
+        // TargetCopy "hello " from target offset 0
+        patch.extend(encode_varint(((6 - 1) << 2) | 3));
+        patch.extend(encode_varint(0));
+        // SourceCopy "world" from source offset 6
+        patch.extend(encode_varint(((5 - 1) << 2) | 2));
+        patch.extend(encode_varint(6 << 1));
+        // TargetRead "!"
+        patch.extend(encode_varint(1));
+        patch.push(b'!');
+        let patch = with_footer(patch, &source, &target);
+
+        let result = apply(&source, &patch).unwrap();
+
+        assert_eq!(result, target);
+    }
+
+    #[test]
+    fn test_bps_rejects_wrong_source() {
+        let source = b"abc".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(0));
+        patch.extend(encode_varint(2 << 2));
+        let patch = with_footer(patch, &source, &source);
+
+        let result = apply_bps(b"abd", &patch);
+
+        assert!(result.unwrap_err().starts_with("source crc32 mismatch"));
+    }
+
+    #[test]
+    fn test_bps_rejects_corrupted_patch() {
+        let source = b"abc".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(0));
+        patch.extend(encode_varint(2 << 2));
+        let mut patch = with_footer(patch, &source, &source);
+        patch[4] ^= 1;
+
+        let result = apply_bps(&source, &patch);
+
+        assert!(result.unwrap_err().starts_with("patch crc32 mismatch"));
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = vec![0, 1, 2, 3];
+        let target = vec![0, 5, 2, 3, 9];
+
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(encode_varint(source.len()));
+        patch.extend(encode_varint(target.len()));
+        patch.extend(encode_varint(1));
+        patch.extend([1 ^ 5, 0x00]);
+        // offsets are relative to the byte after the previous hunk's terminator
+        patch.extend(encode_varint(1));
+        patch.extend([9, 0x00]);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+        assert_eq!(apply(&target, &patch).unwrap(), source);
+    }
+
+    #[test]
+    fn test_bps_rejects_huge_target_before_allocating() {
+        let source = b"abc".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(usize::MAX >> 8));
+        patch.extend(encode_varint(0));
+        let patch = with_footer(patch, &source, &source);
+
+        let result = apply_bps(&source, &patch);
+
+        assert!(result.unwrap_err().ends_with("byte limit"));
+    }
+
+    #[test]
+    fn test_bps_actions_stay_within_target_size() {
+        let source = b"abc".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(2));
+        patch.extend(encode_varint(0));
+        // TargetRead "x", then TargetCopy of a huge length from offset 0
+        patch.extend(encode_varint(1));
+        patch.push(b'x');
+        patch.extend(encode_varint(((1 << 40) << 2) | 3));
+        patch.extend(encode_varint(0));
+        let patch = with_footer(patch, &source, &source);
+
+        let result = apply_bps(&source, &patch);
+
+        assert_eq!(
+            result.unwrap_err(),
+            "patch writes past the target size of 2 bytes"
+        );
+    }
+
+    #[test]
+    fn test_bps_offsets_cannot_overflow() {
+        let source = b"abc".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(3));
+        patch.extend(encode_varint(0));
+        // SourceCopy 1 byte, moving the offset back past 0
+        patch.extend(encode_varint(2));
+        patch.extend(encode_varint((5 << 1) | 1));
+        let patch = with_footer(patch, &source, &source);
+
+        let result = apply_bps(&source, &patch);
+
+        assert_eq!(result.unwrap_err(), "source copy out of bounds");
+    }
+
+    #[test]
+    fn test_ups_stays_within_the_files() {
+        let source = vec![0, 1, 2, 3];
+
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(encode_varint(source.len()));
+        patch.extend(encode_varint(source.len()));
+        patch.extend(encode_varint(1 << 40));
+        patch.extend([1, 0x00]);
+        let patch = with_footer(patch, &source, &source);
+
+        assert_eq!(
+            apply_ups(&source, &patch).unwrap_err(),
+            "patch skips past the end of the file"
+        );
+
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(encode_varint(source.len()));
+        patch.extend(encode_varint(MAX_TARGET_SIZE + 1));
+        let patch = with_footer(patch, &source, &source);
+
+        assert!(apply_ups(&source, &patch)
+            .unwrap_err()
+            .ends_with("byte limit"));
+    }
+
+    #[test]
+    fn test_unrecognized_patch() {
+        assert!(apply(&[0], b"NOPE").is_err());
+    }
+
+    #[test]
+    fn test_soft_patch() {
+        let dir = std::env::temp_dir().join(format!("nes-soft-patch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.nes");
+        fs::write(&rom_path, [0, 1, 2]).unwrap();
+
+        let unpatched = load_with_soft_patch(&rom_path).unwrap();
+        assert_eq!(unpatched.data, vec![0, 1, 2]);
+        assert_eq!(unpatched.patch, None);
+
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x01, 0xAA]);
+        patch.extend(b"EOF");
+        fs::write(dir.join("game.ips"), patch).unwrap();
+
+        let patched = load_with_soft_patch(&rom_path).unwrap();
+        assert_eq!(patched.data, vec![0, 0xAA, 2]);
+        assert_eq!(patched.patch, Some(dir.join("game.ips")));
+        assert_eq!(fs::read(&rom_path).unwrap(), vec![0, 1, 2]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
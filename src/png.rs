@@ -0,0 +1,233 @@
+use std::fs;
+use std::path::Path;
+
+use crate::hash::{crc32, Crc32};
+use crate::palette::{Palette, PixelFormat, Rgb};
+use crate::ppu::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const COLOR_TYPE_RGBA: u8 = 6;
+/// Largest payload of an uncompressed deflate block.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// An RGBA8888 pixel buffer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            rgba: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn from_frame(frame: &Frame, palette: &Palette) -> Self {
+        Image {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            rgba: palette.convert_frame(frame, PixelFormat::Rgba8888),
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        let i = (y * self.width + x) * 4;
+        self.rgba[i..i + 4].copy_from_slice(&[color.r, color.g, color.b, 0xFF]);
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> Rgb {
+        let i = (y * self.width + x) * 4;
+        return Rgb::new(self.rgba[i], self.rgba[i + 1], self.rgba[i + 2]);
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        return encode(self.width, self.height, &self.rgba);
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), String> {
+        return fs::write(path, self.to_png())
+            .map_err(|e| format!("unable to write {}: {}", path.display(), e));
+    }
+}
+
+/// Encodes RGBA8888 pixels as a PNG, uncompressed: deflate's stored blocks keep the encoder
+/// tiny and debug images are small anyway.
+pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width * height * 4, "pixel buffer size");
+
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = vec![];
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // bit depth, colour type, compression, filter, interlace
+    header.extend([8, COLOR_TYPE_RGBA, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    // every scanline starts with its filter type, 0 = none
+    let mut scanlines = Vec::with_capacity(height * (width * 4 + 1));
+    for row in rgba.chunks_exact(width * 4) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+
+    return png;
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(
+        Crc32::new()
+            .update(kind)
+            .update(data)
+            .finish()
+            .to_be_bytes(),
+    );
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate, 32K window, no preset dictionary, fastest
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+
+    out.extend(adler32(data).to_be_bytes());
+    return out;
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    return (b << 16) | a;
+}
+
+/// Checks the chunk CRCs of a PNG, used by tests of code that writes images.
+pub fn verify_chunks(png: &[u8]) -> Result<Vec<[u8; 4]>, String> {
+    if !png.starts_with(&SIGNATURE) {
+        return Err("missing PNG signature".to_string());
+    }
+
+    let mut kinds = vec![];
+    let mut offset = SIGNATURE.len();
+    while offset < png.len() {
+        let header = png
+            .get(offset..offset + 8)
+            .ok_or("truncated chunk header")?;
+        let len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let body = png
+            .get(offset + 4..offset + 8 + len)
+            .ok_or("truncated chunk")?;
+        let crc = png
+            .get(offset + 8 + len..offset + 12 + len)
+            .ok_or("truncated chunk CRC")?;
+        if crc32(body).to_be_bytes() != crc {
+            return Err(format!("bad CRC in chunk at {}", offset));
+        }
+        kinds.push(header[4..8].try_into().unwrap());
+        offset += 12 + len;
+    }
+    return Ok(kinds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Undoes `zlib_stored`, only understands stored blocks.
+    fn inflate_stored(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut offset = 2;
+        loop {
+            let last = data[offset] & 1 != 0;
+            let len = u16::from_le_bytes([data[offset + 1], data[offset + 2]]) as usize;
+            let nlen = u16::from_le_bytes([data[offset + 3], data[offset + 4]]);
+            assert_eq!(nlen, !(len as u16));
+            out.extend_from_slice(&data[offset + 5..offset + 5 + len]);
+            offset += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(
+            data[offset..offset + 4],
+            adler32(&out).to_be_bytes(),
+            "adler32"
+        );
+        return out;
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_encode_small_image() {
+        let mut image = Image::new(2, 2);
+        image.set_pixel(1, 0, Rgb::new(0xFF, 0x00, 0x00));
+        image.set_pixel(0, 1, Rgb::new(0x00, 0xFF, 0x00));
+
+        let png = image.to_png();
+
+        assert_eq!(
+            verify_chunks(&png).unwrap(),
+            vec![*b"IHDR", *b"IDAT", *b"IEND"]
+        );
+        // IHDR: 2x2, 8 bits RGBA
+        assert_eq!(png[16..29], [0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        let raw = inflate_stored(&png[41..41 + idat_len]);
+        assert_eq!(
+            raw,
+            vec![
+                0, 0, 0, 0, 0, 0xFF, 0, 0, 0xFF, //
+                0, 0, 0xFF, 0, 0xFF, 0, 0, 0, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_images_span_several_stored_blocks() {
+        let image = Image::new(256, 240);
+
+        let png = image.to_png();
+
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        let raw = inflate_stored(&png[41..41 + idat_len]);
+        assert_eq!(raw.len(), 240 * (256 * 4 + 1));
+        assert!(verify_chunks(&png).is_ok());
+    }
+
+    #[test]
+    fn test_verify_chunks_detects_corruption() {
+        let mut png = Image::new(1, 1).to_png();
+        png[20] ^= 1;
+
+        assert!(verify_chunks(&png).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! Views of PPU memory for debuggers and tests. Nothing here changes emulation state:
+//! memory is read directly, without going through $2007 or the read buffer.
+
+use super::scroll::{COARSE_X, COARSE_Y, FINE_Y, NAMETABLE_X, NAMETABLE_Y};
+use super::sprites::Sprite;
+use super::{Ppu, PALETTE_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::{Palette, Rgb};
+use crate::png::Image;
+
+pub const PATTERN_TABLE_SIZE: usize = 128;
+pub const PALETTE_SWATCH_SIZE: usize = 16;
+/// Colour of the scroll rectangle drawn over the nametables.
+pub const SCROLL_OVERLAY: Rgb = Rgb::new(0xFF, 0x00, 0xFF);
+
+impl Ppu {
+    /// Copy of palette RAM, $3F00-$3F1F.
+    pub fn palette_ram(&self) -> [u8; PALETTE_SIZE] {
+        return self.palette_table;
+    }
+
+    /// Position of the top-left corner of the screen in the 512x480 nametable space, taken from t.
+    pub fn scroll_position(&self) -> (usize, usize) {
+        let coarse_x = (self.t & COARSE_X) as usize;
+        let coarse_y = ((self.t & COARSE_Y) >> 5) as usize;
+        let fine_y = ((self.t & FINE_Y) >> 12) as usize;
+        let x = coarse_x * 8 + self.fine_x as usize;
+        let y = coarse_y * 8 + fine_y;
+
+        let x = if self.t & NAMETABLE_X != 0 {
+            x + SCREEN_WIDTH
+        } else {
+            x
+        };
+        let y = if self.t & NAMETABLE_Y != 0 {
+            y + SCREEN_HEIGHT
+        } else {
+            y
+        };
+        return (x, y);
+    }
+
+    /// Pattern table 0 ($0000) or 1 ($1000) as a 16x16 grid of tiles, coloured with palette
+    /// 0-7 (4-7 are the sprite palettes).
+    pub fn pattern_table_image(
+        &mut self,
+        table: usize,
+        palette_index: u8,
+        palette: &Palette,
+    ) -> Image {
+        let base = (table as u16 & 1) * 0x1000;
+        let mut image = Image::new(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE);
+        for tile in 0..256 {
+            let (tile_x, tile_y) = ((tile % 16) * 8, (tile / 16) * 8);
+            for row in 0..8 {
+                let addr = base + tile as u16 * 16 + row as u16;
+                let lo = self.read_vram(addr);
+                let hi = self.read_vram(addr + 8);
+                for column in 0..8 {
+                    let value = pixel_value(lo, hi, column);
+                    let color = self.debug_color(palette_index, value, palette);
+                    image.set_pixel(tile_x + column, tile_y + row, color);
+                }
+            }
+        }
+        return image;
+    }
+
+    /// The four logical nametables as a 512x480 image laid out as
+    ///  $2000 $2400
+    ///  $2800 $2C00
+    /// with the visible screen outlined (wrapping around the edges like the scroll does).
+    pub fn nametable_image(&mut self, palette: &Palette) -> Image {
+        let width = SCREEN_WIDTH * 2;
+        let height = SCREEN_HEIGHT * 2;
+        let pattern_table = self.ctrl.background_pattern_addr();
+
+        let mut image = Image::new(width, height);
+        for table in 0..4u16 {
+            let base = 0x2000 + table * 0x400;
+            let origin_x = (table as usize & 1) * SCREEN_WIDTH;
+            let origin_y = (table as usize >> 1) * SCREEN_HEIGHT;
+            for tile_row in 0..30u16 {
+                for tile_column in 0..32u16 {
+                    let index = self.read_vram(base + tile_row * 32 + tile_column);
+                    let attribute_addr = base + 0x3C0 + (tile_row / 4) * 8 + tile_column / 4;
+                    let shift = ((tile_row & 2) << 1) | (tile_column & 2);
+                    let palette_index = (self.read_vram(attribute_addr) >> shift) & 0b11;
+
+                    for row in 0..8 {
+                        let addr = pattern_table + index as u16 * 16 + row;
+                        let lo = self.read_vram(addr);
+                        let hi = self.read_vram(addr + 8);
+                        for column in 0..8 {
+                            let value = pixel_value(lo, hi, column);
+                            let color = self.debug_color(palette_index, value, palette);
+                            let x = origin_x + tile_column as usize * 8 + column;
+                            let y = origin_y + tile_row as usize * 8 + row as usize;
+                            image.set_pixel(x, y, color);
+                        }
+                    }
+                }
+            }
+        }
+
+        let (scroll_x, scroll_y) = self.scroll_position();
+        for i in 0..SCREEN_WIDTH {
+            let x = (scroll_x + i) % width;
+            image.set_pixel(x, scroll_y % height, SCROLL_OVERLAY);
+            image.set_pixel(x, (scroll_y + SCREEN_HEIGHT - 1) % height, SCROLL_OVERLAY);
+        }
+        for i in 0..SCREEN_HEIGHT {
+            let y = (scroll_y + i) % height;
+            image.set_pixel(scroll_x % width, y, SCROLL_OVERLAY);
+            image.set_pixel((scroll_x + SCREEN_WIDTH - 1) % width, y, SCROLL_OVERLAY);
+        }
+        return image;
+    }
+
+    /// All 64 sprites as an 8x8 grid, in OAM order, drawn with their palette and flips.
+    /// Cells are 8x16 when PPUCTRL selects tall sprites. Pattern value 0 uses the backdrop.
+    pub fn oam_image(&mut self, palette: &Palette) -> Image {
+        let height = self.ctrl.sprite_height();
+        let mut image = Image::new(8 * 8, 8 * height);
+        for sprite in self.sprites() {
+            let (cell_x, cell_y) = ((sprite.index % 8) * 8, (sprite.index / 8) * height);
+            for row in 0..height {
+                let addr = self.sprite_row_addr(&sprite, sprite.y as usize + 1 + row);
+                let lo = self.read_vram(addr);
+                let hi = self.read_vram(addr + 8);
+                for column in 0..8 {
+                    let column_in_tile = if sprite.flip_horizontal {
+                        7 - column
+                    } else {
+                        column
+                    };
+                    let value = pixel_value(lo, hi, column_in_tile);
+                    let color = self.debug_color(sprite.palette + 4, value, palette);
+                    image.set_pixel(cell_x + column, cell_y + row, color);
+                }
+            }
+        }
+        return image;
+    }
+
+    /// Decoded OAM, same as `sprites` but only those placed on screen (Y below $EF).
+    pub fn visible_sprites(&self) -> Vec<Sprite> {
+        return self
+            .sprites()
+            .into_iter()
+            .filter(|sprite| sprite.y < 0xEF)
+            .collect();
+    }
+
+    /// Palette RAM as 16x2 swatches: background palettes on the top row, sprites below.
+    pub fn palette_image(&self, palette: &Palette) -> Image {
+        let size = PALETTE_SWATCH_SIZE;
+        let mut image = Image::new(16 * size, 2 * size);
+        for (i, index) in self.palette_table.iter().enumerate() {
+            let color = palette.color(*index, 0);
+            let (swatch_x, swatch_y) = ((i % 16) * size, (i / 16) * size);
+            for y in 0..size {
+                for x in 0..size {
+                    image.set_pixel(swatch_x + x, swatch_y + y, color);
+                }
+            }
+        }
+        return image;
+    }
+
+    fn debug_color(&self, palette_index: u8, value: u8, palette: &Palette) -> Rgb {
+        let index = if value == 0 {
+            self.palette_table[0]
+        } else {
+            self.palette_table[(palette_index * 4 + value) as usize]
+        };
+        return palette.color(index, 0);
+    }
+}
+
+fn pixel_value(lo: u8, hi: u8, column: usize) -> u8 {
+    let bit = 7 - column;
+    return (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::{set_addr, test_ppu, write_bytes, write_solid_tile};
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::png;
+
+    fn test_palette() -> Palette {
+        return Palette::ntsc();
+    }
+
+    #[test]
+    fn test_pattern_table_uses_chosen_palette() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_solid_tile(&mut ppu, 0x1000, 0x11, 3);
+        write_bytes(&mut ppu, 0x3F00, &[0x0D, 0x01, 0x02, 0x03]);
+        write_bytes(&mut ppu, 0x3F1C, &[0x0D, 0x21, 0x22, 0x23]);
+        let palette = test_palette();
+
+        let image = ppu.pattern_table_image(1, 7, &palette);
+
+        assert_eq!((image.width, image.height), (128, 128));
+        // tile $11 sits in column 1, row 1
+        assert_eq!(image.get_pixel(8, 8), palette.color(0x23, 0));
+        assert_eq!(image.get_pixel(15, 15), palette.color(0x23, 0));
+        assert_eq!(image.get_pixel(7, 7), palette.color(0x0D, 0));
+        assert_eq!(image.get_pixel(16, 8), palette.color(0x0D, 0));
+    }
+
+    #[test]
+    fn test_nametable_image_shows_all_four_tables() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        write_solid_tile(&mut ppu, 0x0000, 1, 1);
+        write_bytes(&mut ppu, 0x3F00, &[0x0D, 0x16]);
+        // first tile of the $2400 nametable; with vertical mirroring $2C00 mirrors it
+        write_bytes(&mut ppu, 0x2400, &[1]);
+        let palette = test_palette();
+
+        let image = ppu.nametable_image(&palette);
+
+        assert_eq!((image.width, image.height), (512, 480));
+        let red = palette.color(0x16, 0);
+        let black = palette.color(0x0D, 0);
+        assert_eq!(image.get_pixel(256 + 4, 4), red);
+        assert_eq!(image.get_pixel(256 + 4, 240 + 4), red);
+        assert_eq!(image.get_pixel(4, 4), black);
+        assert_eq!(image.get_pixel(4, 240 + 4), black);
+    }
+
+    #[test]
+    fn test_nametable_image_outlines_scroll_rectangle() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        // scroll to (300, 20): nametable 1, x = 44
+        ppu.write_register(0x2000, 0x01);
+        ppu.write_register(0x2005, 44);
+        ppu.write_register(0x2005, 20);
+        let palette = test_palette();
+
+        let image = ppu.nametable_image(&palette);
+
+        assert_eq!(ppu.scroll_position(), (300, 20));
+        assert_eq!(image.get_pixel(300, 20), SCROLL_OVERLAY);
+        assert_eq!(image.get_pixel(300, 259), SCROLL_OVERLAY);
+        // the right edge wraps back into nametable 0
+        assert_eq!(image.get_pixel((300 + 255) % 512, 100), SCROLL_OVERLAY);
+        assert_eq!(image.get_pixel(10, 20), SCROLL_OVERLAY);
+        assert_ne!(image.get_pixel(100, 100), SCROLL_OVERLAY);
+    }
+
+    #[test]
+    fn test_oam_image_decodes_sprites() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        // left half of tile 2 is value 1, right half value 2
+        let mut data = vec![0xF0; 8];
+        data.extend(vec![0x0F; 8]);
+        write_bytes(&mut ppu, 0x0020, &data);
+        write_bytes(&mut ppu, 0x3F00, &[0x0D]);
+        write_bytes(&mut ppu, 0x3F14, &[0x0D, 0x11, 0x12]);
+        // sprite 9: tile 2, palette 5, flipped horizontally
+        ppu.write_register(0x2003, 9 * 4);
+        for byte in [0x40, 0x02, 0b0100_0001, 0x80] {
+            ppu.write_register(0x2004, byte);
+        }
+        let palette = test_palette();
+
+        let image = ppu.oam_image(&palette);
+
+        assert_eq!((image.width, image.height), (64, 64));
+        // sprite 9 is in column 1, row 1
+        assert_eq!(image.get_pixel(8, 8), palette.color(0x12, 0));
+        assert_eq!(image.get_pixel(15, 8), palette.color(0x11, 0));
+
+        let sprite = ppu.sprites()[9];
+        assert_eq!((sprite.tile, sprite.palette, sprite.x), (2, 1, 0x80));
+        assert!(sprite.flip_horizontal && !sprite.flip_vertical);
+    }
+
+    #[test]
+    fn test_oam_image_uses_tall_cells_for_8x16_sprites() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2000, 0x20);
+
+        let image = ppu.oam_image(&test_palette());
+
+        assert_eq!((image.width, image.height), (64, 128));
+    }
+
+    #[test]
+    fn test_visible_sprites_skips_hidden_entries() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2003, 0);
+        for _ in 0..256 {
+            ppu.write_register(0x2004, 0xFF);
+        }
+        ppu.write_register(0x2003, 4 * 3);
+        ppu.write_register(0x2004, 0x10);
+
+        let sprites = ppu.visible_sprites();
+
+        assert_eq!(sprites.len(), 1);
+        assert_eq!(sprites[0].index, 3);
+    }
+
+    #[test]
+    fn test_palette_ram_and_image() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x3F00, &[0x0F, 0x2A]);
+        write_bytes(&mut ppu, 0x3F11, &[0x16]);
+        let palette = test_palette();
+
+        let ram = ppu.palette_ram();
+        let image = ppu.palette_image(&palette);
+
+        assert_eq!(ram[1], 0x2A);
+        assert_eq!(ram[0x11], 0x16);
+        assert_eq!(image.get_pixel(16 + 3, 3), palette.color(0x2A, 0));
+        assert_eq!(image.get_pixel(16 + 3, 16 + 3), palette.color(0x16, 0));
+    }
+
+    #[test]
+    fn test_debug_images_export_as_png() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        set_addr(&mut ppu, 0x2000);
+
+        let png = ppu.nametable_image(&test_palette()).to_png();
+
+        assert!(png::verify_chunks(&png).is_ok());
+    }
+}
@@ -0,0 +1,436 @@
+//! The dot-accurate renderer: background tiles go through the PPU's fetch sequence and
+//! shift registers, sprites are fetched for the next line during dots 257-320.
+//!
+//!  dots 1-256 and 321-336, every 8 dots:
+//!  +-----+-----+-----+-----+-----+-----+-----+-----+
+//!  | NT        | AT        | pattern lo| pattern hi| -> loaded into the shifters
+//!  +-----+-----+-----+-----+-----+-----+-----+-----+    at the start of the next group
+
+use super::registers::{MaskRegister, StatusRegister};
+use super::scroll;
+use super::sprites::{Sprite, MAX_SPRITES_PER_LINE};
+use super::{Ppu, SCREEN_WIDTH};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+#[derive(Default)]
+pub(super) struct Pipeline {
+    next_tile: u8,
+    next_palette: u8,
+    next_pattern_lo: u8,
+    next_pattern_hi: u8,
+    pattern_lo: u16,
+    pattern_hi: u16,
+    attribute_lo: u16,
+    attribute_hi: u16,
+    sprite_row_addr: u16,
+    sprites: Vec<SpriteUnit>,
+}
+
+/// One of the 8 sprite output units, holding a fetched row of a sprite for the current line.
+#[derive(Clone, Copy, Default)]
+struct SpriteUnit {
+    x: u8,
+    /// Pattern planes with horizontal flipping already applied.
+    pattern_lo: u8,
+    pattern_hi: u8,
+    palette: u8,
+    behind_background: bool,
+    sprite_zero: bool,
+}
+
+impl Pipeline {
+    fn load_shifters(&mut self) {
+        let fill = |bit: u8| if bit != 0 { 0xFF } else { 0x00 };
+        self.pattern_lo = (self.pattern_lo & 0xFF00) | self.next_pattern_lo as u16;
+        self.pattern_hi = (self.pattern_hi & 0xFF00) | self.next_pattern_hi as u16;
+        self.attribute_lo = (self.attribute_lo & 0xFF00) | fill(self.next_palette & 0b01);
+        self.attribute_hi = (self.attribute_hi & 0xFF00) | fill(self.next_palette & 0b10);
+    }
+
+    fn shift(&mut self) {
+        self.pattern_lo <<= 1;
+        self.pattern_hi <<= 1;
+        self.attribute_lo <<= 1;
+        self.attribute_hi <<= 1;
+    }
+}
+
+impl SaveState for Pipeline {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.next_tile);
+        w.u8(self.next_palette);
+        w.u8(self.next_pattern_lo);
+        w.u8(self.next_pattern_hi);
+        w.u16(self.pattern_lo);
+        w.u16(self.pattern_hi);
+        w.u16(self.attribute_lo);
+        w.u16(self.attribute_hi);
+        w.u16(self.sprite_row_addr);
+        // every unit is written, so the state size does not depend on the line
+        w.u8(self.sprites.len() as u8);
+        for slot in 0..MAX_SPRITES_PER_LINE {
+            let unit = self.sprites.get(slot).copied().unwrap_or_default();
+            w.u8(unit.x);
+            w.u8(unit.pattern_lo);
+            w.u8(unit.pattern_hi);
+            w.u8(unit.palette);
+            w.bool(unit.behind_background);
+            w.bool(unit.sprite_zero);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.next_tile = r.u8()?;
+        self.next_palette = r.u8()?;
+        self.next_pattern_lo = r.u8()?;
+        self.next_pattern_hi = r.u8()?;
+        self.pattern_lo = r.u16()?;
+        self.pattern_hi = r.u16()?;
+        self.attribute_lo = r.u16()?;
+        self.attribute_hi = r.u16()?;
+        self.sprite_row_addr = r.u16()?;
+        let count = r.u8()? as usize;
+        if count > MAX_SPRITES_PER_LINE {
+            return Err(r.error(&format!("{} sprite units", count)));
+        }
+        self.sprites.clear();
+        for _ in 0..MAX_SPRITES_PER_LINE {
+            self.sprites.push(SpriteUnit {
+                x: r.u8()?,
+                pattern_lo: r.u8()?,
+                pattern_hi: r.u8()?,
+                palette: r.u8()?,
+                behind_background: r.bool()?,
+                sprite_zero: r.bool()?,
+            });
+        }
+        self.sprites.truncate(count);
+        return Ok(());
+    }
+}
+
+impl Ppu {
+    pub(super) fn step_dot_renderer(&mut self) {
+        let visible = self.scanline < 240;
+        if !visible && self.scanline != self.pre_render_scanline() {
+            return;
+        }
+
+        let dot = self.dot;
+        if dot == 257 {
+            self.pipeline.sprites.clear();
+        }
+        if self.mask.rendering_enabled() {
+            self.fetch_background(dot);
+            if (257..=320).contains(&dot) {
+                self.fetch_sprite((dot - 257) as usize);
+            }
+        }
+
+        if visible && (1..=SCREEN_WIDTH as u16).contains(&dot) {
+            self.output_pixel(dot as usize - 1);
+        }
+    }
+
+    fn fetch_background(&mut self, dot: u16) {
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.pipeline.shift();
+        }
+
+        if (1..=257).contains(&dot) || (321..=337).contains(&dot) {
+            let v = self.v;
+            let pattern_addr = self.ctrl.background_pattern_addr()
+                + self.pipeline.next_tile as u16 * 16
+                + scroll::fine_y(v);
+            match (dot - 1) % 8 {
+                0 => {
+                    self.pipeline.load_shifters();
+                    self.pipeline.next_tile = self.read_vram(scroll::tile_addr(v));
+                }
+                2 => {
+                    let attribute = self.read_vram(scroll::attribute_addr(v));
+                    self.pipeline.next_palette = (attribute >> scroll::attribute_shift(v)) & 0b11;
+                }
+                4 => self.pipeline.next_pattern_lo = self.read_vram(pattern_addr),
+                6 => self.pipeline.next_pattern_hi = self.read_vram(pattern_addr + 8),
+                _ => {}
+            }
+        }
+
+        // two more nametable fetches nobody uses, which some mappers count
+        if dot == 338 || dot == 340 {
+            self.read_vram(scroll::tile_addr(self.v));
+        }
+    }
+
+    /// Fetches for sprite slot `offset / 8` of the next line, empty slots fetch tile $FF.
+    fn fetch_sprite(&mut self, offset: usize) {
+        let slot = offset / 8;
+        let sprite = self.line_sprites.get(slot).copied();
+        match offset % 8 {
+            4 => {
+                self.pipeline.sprite_row_addr = match &sprite {
+                    Some(sprite) => self.sprite_row_addr(sprite, self.scanline as usize + 1),
+                    None if self.ctrl.sprite_height() == 16 => 0x1FE0,
+                    None => self.ctrl.sprite_pattern_addr() + 0xFF * 16,
+                };
+                self.read_vram(self.pipeline.sprite_row_addr);
+            }
+            6 => {
+                let addr = self.pipeline.sprite_row_addr;
+                let (lo, hi) = (self.read_vram(addr), self.read_vram(addr + 8));
+                if let Some(sprite) = sprite {
+                    self.load_sprite_unit(slot, &sprite, lo, hi);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn load_sprite_unit(&mut self, slot: usize, sprite: &Sprite, lo: u8, hi: u8) {
+        let (pattern_lo, pattern_hi) = if sprite.flip_horizontal {
+            (lo.reverse_bits(), hi.reverse_bits())
+        } else {
+            (lo, hi)
+        };
+        self.pipeline.sprites.push(SpriteUnit {
+            x: sprite.x,
+            pattern_lo,
+            pattern_hi,
+            palette: sprite.palette,
+            behind_background: sprite.behind_background,
+            sprite_zero: slot == 0 && self.sprite_zero_on_line,
+        });
+    }
+
+    fn output_pixel(&mut self, x: usize) {
+        let y = self.scanline as usize;
+
+        let mut background = (0, 0);
+        let show_background_left = self.mask.contains(MaskRegister::ShowBackgroundLeft);
+        if self.mask.contains(MaskRegister::ShowBackground) && (x >= 8 || show_background_left) {
+            let bit = 15 - self.fine_x as u16;
+            let pipeline = &self.pipeline;
+            let value =
+                (((pipeline.pattern_hi >> bit) & 1) << 1) | ((pipeline.pattern_lo >> bit) & 1);
+            let palette =
+                (((pipeline.attribute_hi >> bit) & 1) << 1) | ((pipeline.attribute_lo >> bit) & 1);
+            background = (value as u8, palette as u8);
+        }
+
+        let mut sprite = None;
+        let show_sprites_left = self.mask.contains(MaskRegister::ShowSpritesLeft);
+        if self.mask.contains(MaskRegister::ShowSprites) && (x >= 8 || show_sprites_left) {
+            for unit in self.pipeline.sprites.iter() {
+                let col = x.wrapping_sub(unit.x as usize);
+                if col >= 8 {
+                    continue;
+                }
+                let bit = 7 - col;
+                let value = (((unit.pattern_hi >> bit) & 1) << 1) | ((unit.pattern_lo >> bit) & 1);
+                if value == 0 {
+                    continue;
+                }
+
+                if unit.sprite_zero && background.0 != 0 && x != 255 {
+                    self.status.insert(StatusRegister::SpriteZeroHit);
+                }
+                // lower OAM index wins, even when it is behind the background
+                sprite = Some((value, *unit));
+                break;
+            }
+        }
+
+        let color = match (background, sprite) {
+            ((0, _), None) => self.palette_table[0],
+            ((value, palette), None) => self.palette_table[(palette * 4 + value) as usize],
+            ((value, palette), Some((_, unit))) if value != 0 && unit.behind_background => {
+                self.palette_table[(palette * 4 + value) as usize]
+            }
+            (_, Some((value, unit))) => {
+                self.palette_table[0x10 + (unit.palette * 4 + value) as usize]
+            }
+        };
+        self.put_pixel(x, y, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::*;
+    use super::super::{RenderMode, DOTS_PER_SCANLINE};
+    use super::*;
+    use crate::cartridge::Mirroring;
+
+    fn run_until(ppu: &mut Ppu, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.tick(1);
+        }
+    }
+
+    /// A scrolled background using every palette and attribute quadrant, with sprites that
+    /// flip, overlap, hide behind the background, clip at the left edge and hit sprite 0.
+    fn setup_scene(ppu: &mut Ppu) {
+        write_bytes(
+            ppu,
+            0x3F00,
+            &[
+                0x0F, 0x01, 0x02, 0x03, 0x0F, 0x11, 0x12, 0x13, 0x0F, 0x21, 0x22, 0x23, 0x0F, 0x31,
+                0x32, 0x33, 0x0F, 0x05, 0x06, 0x07, 0x0F, 0x15, 0x16, 0x17, 0x0F, 0x25, 0x26, 0x27,
+                0x0F, 0x35, 0x36, 0x37,
+            ],
+        );
+        // tiles with a gradient so flips and fine scrolling are visible
+        for tile in 0..16u16 {
+            let mut data = vec![];
+            for row in 0..8u16 {
+                data.push(((tile * 37 + row * 11) & 0xFF) as u8);
+            }
+            for row in 0..8u16 {
+                data.push(((tile * 53 + row * 7 + 0x0F) & 0xFF) as u8);
+            }
+            write_bytes(ppu, tile * 16, &data);
+        }
+        for i in 0..0x3C0u16 {
+            write_bytes(ppu, 0x2000 + i, &[(i % 16) as u8]);
+            write_bytes(ppu, 0x2400 + i, &[((i * 7) % 16) as u8]);
+        }
+        for i in 0..0x40u16 {
+            write_bytes(ppu, 0x23C0 + i, &[(i * 0x1B) as u8]);
+            write_bytes(ppu, 0x27C0 + i, &[(i * 0x6C) as u8]);
+        }
+
+        let sprites: [[u8; 4]; 6] = [
+            [40, 3, 0b0000_0000, 60],
+            [44, 5, 0b0100_0001, 64],
+            [50, 7, 0b1010_0010, 100],
+            [100, 9, 0b1100_0011, 2],
+            [150, 11, 0b0000_0001, 250],
+            [200, 13, 0b0010_0000, 120],
+        ];
+        ppu.write_register(0x2003, 0);
+        for i in 0..64 {
+            let entry = sprites.get(i).copied().unwrap_or([0xFF; 4]);
+            for byte in entry {
+                ppu.write_register(0x2004, byte);
+            }
+        }
+
+        ppu.write_register(0x2000, 0b0000_0001);
+        ppu.write_register(0x2005, 37);
+        ppu.write_register(0x2005, 21);
+    }
+
+    #[test]
+    fn test_dot_and_scanline_modes_agree() {
+        for mask in [0b0001_1110, 0b0001_1000, 0b0000_1010, 0b0001_0100] {
+            let mut scanline = test_ppu(Mirroring::Vertical);
+            let mut dot = test_ppu(Mirroring::Vertical);
+            dot.set_render_mode(RenderMode::Dot);
+            for ppu in [&mut scanline, &mut dot] {
+                setup_scene(ppu);
+                ppu.write_register(0x2001, mask);
+            }
+
+            for _ in 0..2 * 262 * DOTS_PER_SCANLINE as usize {
+                scanline.tick(1);
+                dot.tick(1);
+                assert!(
+                    scanline.status == dot.status,
+                    "status differs at line {} dot {} with mask {:#010b}",
+                    dot.scanline,
+                    dot.dot,
+                    mask
+                );
+            }
+            assert!(*scanline.frame() == *dot.frame(), "mask {:#010b}", mask);
+            let colors: std::collections::HashSet<_> = dot.frame().pixels.iter().collect();
+            assert!(colors.len() > 4, "mask {:#010b}", mask);
+        }
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_happens_at_the_pixel() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        ppu.set_render_mode(RenderMode::Dot);
+        write_bytes(&mut ppu, 0x3F00, &[0x0F, 0x01, 0x02, 0x03]);
+        write_solid_tile(&mut ppu, 0x0000, 1, 1);
+        for i in 0..0x3C0u16 {
+            write_bytes(&mut ppu, 0x2000 + i, &[1]);
+        }
+        ppu.write_register(0x2003, 0);
+        for byte in [49, 1, 0, 30] {
+            ppu.write_register(0x2004, byte);
+        }
+        set_addr(&mut ppu, 0);
+        ppu.write_register(0x2001, 0b0001_1110);
+
+        run_until(&mut ppu, 50, 31);
+        assert!(!ppu.status.contains(StatusRegister::SpriteZeroHit));
+        ppu.tick(1);
+        assert!(ppu.status.contains(StatusRegister::SpriteZeroHit));
+    }
+
+    #[test]
+    fn test_mid_line_mask_write_only_shows_in_dot_mode() {
+        let mut scanline = test_ppu(Mirroring::Vertical);
+        let mut dot = test_ppu(Mirroring::Vertical);
+        dot.set_render_mode(RenderMode::Dot);
+        for ppu in [&mut scanline, &mut dot] {
+            write_bytes(ppu, 0x3F00, &[0x0F, 0x01, 0x02, 0x03]);
+            write_solid_tile(ppu, 0x0000, 1, 1);
+            for i in 0..0x3C0u16 {
+                write_bytes(ppu, 0x2000 + i, &[1]);
+            }
+            set_addr(ppu, 0);
+            ppu.write_register(0x2001, 0b0001_1110);
+
+            // hide the background for the right half of line 10, sprites keep rendering on
+            run_until(ppu, 10, 129);
+            ppu.write_register(0x2001, 0b0001_0110);
+            run_until(ppu, 11, 0);
+            ppu.write_register(0x2001, 0b0001_1110);
+            run_frame(ppu);
+        }
+
+        assert!(scanline.frame().line(10).iter().all(|c| *c == 0x01));
+        let line = dot.frame().line(10);
+        assert!(line[..128].iter().all(|c| *c == 0x01));
+        assert!(line[128..].iter().all(|c| *c == 0x0F));
+        assert!(dot.frame().line(11) == scanline.frame().line(11));
+    }
+
+    #[test]
+    fn test_odd_frames_skip_a_dot_when_rendering() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        ppu.set_render_mode(RenderMode::Dot);
+        ppu.write_register(0x2001, 0b0000_1000);
+
+        let mut lengths = vec![];
+        run_frame(&mut ppu);
+        for _ in 0..4 {
+            let mut dots = 1;
+            while !ppu.tick(1) {
+                dots += 1;
+            }
+            lengths.push(dots);
+        }
+
+        let full = 262 * DOTS_PER_SCANLINE as usize;
+        assert_eq!(lengths, vec![full - 1, full, full - 1, full]);
+    }
+
+    #[test]
+    fn test_no_skipped_dot_with_rendering_disabled() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        run_frame(&mut ppu);
+
+        for _ in 0..2 {
+            let mut dots = 1;
+            while !ppu.tick(1) {
+                dots += 1;
+            }
+            assert_eq!(dots, 262 * DOTS_PER_SCANLINE as usize);
+        }
+    }
+}
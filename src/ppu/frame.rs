@@ -0,0 +1,45 @@
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+/// 256x240 framebuffer of 6-bit palette indices, as output by the PPU,
+/// along with the PPUMASK emphasis bits (5-7, shifted down) each pixel was drawn with.
+#[derive(PartialEq, Eq, Clone)]
+pub struct Frame {
+    pub pixels: Vec<u8>,
+    pub emphasis: Vec<u8>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Frame {
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            emphasis: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color_index: u8) {
+        self.pixels[y * SCREEN_WIDTH + x] = color_index;
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        return self.pixels[y * SCREEN_WIDTH + x];
+    }
+
+    pub fn set_emphasis(&mut self, x: usize, y: usize, emphasis: u8) {
+        self.emphasis[y * SCREEN_WIDTH + x] = emphasis;
+    }
+
+    pub fn get_emphasis(&self, x: usize, y: usize) -> u8 {
+        return self.emphasis[y * SCREEN_WIDTH + x];
+    }
+
+    pub fn line(&self, y: usize) -> &[u8] {
+        return &self.pixels[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH];
+    }
+}
@@ -0,0 +1,857 @@
+pub mod debug;
+pub mod dot;
+pub mod frame;
+pub mod registers;
+pub mod scroll;
+pub mod sprites;
+
+use crate::cartridge::{Mirroring, Region};
+use crate::mapper::Cartridge;
+use crate::palette;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+pub use frame::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+use registers::{ControlRegister, MaskRegister, StatusRegister};
+use sprites::{Sprite, MAX_SPRITES_PER_LINE, OAM_SIZE};
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+pub const SCANLINES_PER_FRAME: u16 = 262;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+const PAL_SCANLINES_PER_FRAME: u16 = 312;
+/// Dendy keeps NTSC-like timing for NMI handlers by starting vblank 50 lines later.
+const DENDY_VBLANK_SCANLINE: u16 = 291;
+
+/// How the PPU produces pixels.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RenderMode {
+    /// Draws each line in one go at dot 1, fast but blind to register writes mid-line.
+    Scanline,
+    /// Runs the fetches and shift registers of the real PPU dot by dot.
+    Dot,
+}
+
+const VRAM_SIZE: usize = 4 * 1024;
+const PALETTE_SIZE: usize = 32;
+
+pub struct Ppu {
+    cartridge: Cartridge,
+    render_mode: RenderMode,
+    pipeline: dot::Pipeline,
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+    vram: [u8; VRAM_SIZE],
+    palette_table: [u8; PALETTE_SIZE],
+    oam_data: [u8; OAM_SIZE],
+    oam_addr: u8,
+    line_sprites: Vec<Sprite>,
+    sprite_zero_on_line: bool,
+    sprite_zero_hit_dot: Option<u16>,
+
+    /// Current VRAM address, also the scroll position while rendering.
+    v: u16,
+    /// Temporary VRAM address, the scroll position at the top-left of the screen.
+    t: u16,
+    fine_x: u8,
+    /// First/second write toggle shared by $2005 and $2006.
+    w: bool,
+    read_buffer: u8,
+    open_bus: u8,
+
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame_count: u64,
+    scanlines_per_frame: u16,
+    vblank_scanline: u16,
+    skip_odd_frame_dot: bool,
+    nmi_interrupt: bool,
+    frame: Frame,
+}
+
+impl Ppu {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Ppu {
+            cartridge,
+            render_mode: RenderMode::Scanline,
+            pipeline: dot::Pipeline::default(),
+            ctrl: ControlRegister::empty(),
+            mask: MaskRegister::empty(),
+            status: StatusRegister::empty(),
+            vram: [0; VRAM_SIZE],
+            palette_table: [0; PALETTE_SIZE],
+            oam_data: [0; OAM_SIZE],
+            oam_addr: 0,
+            line_sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+            sprite_zero_on_line: false,
+            sprite_zero_hit_dot: None,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            read_buffer: 0,
+            open_bus: 0,
+            // start on the pre-render line so the first frame is fully set up from t
+            scanline: PRE_RENDER_SCANLINE,
+            dot: 0,
+            frame_count: 0,
+            scanlines_per_frame: SCANLINES_PER_FRAME,
+            vblank_scanline: VBLANK_SCANLINE,
+            skip_odd_frame_dot: true,
+            nmi_interrupt: false,
+            frame: Frame::new(),
+        }
+    }
+
+    /// Switches frame timing to the given region (NTSC timing is the default).
+    pub fn set_region(&mut self, region: Region) {
+        let starting = self.scanline == self.pre_render_scanline() && self.dot == 0;
+        (self.scanlines_per_frame, self.vblank_scanline) = match region {
+            Region::Ntsc | Region::Multi => (SCANLINES_PER_FRAME, VBLANK_SCANLINE),
+            Region::Pal => (PAL_SCANLINES_PER_FRAME, VBLANK_SCANLINE),
+            Region::Dendy => (PAL_SCANLINES_PER_FRAME, DENDY_VBLANK_SCANLINE),
+        };
+        // only the NTSC PPU shortens odd frames
+        self.skip_odd_frame_dot = matches!(region, Region::Ntsc | Region::Multi);
+        if starting {
+            self.scanline = self.pre_render_scanline();
+        }
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_mode = mode;
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        return self.render_mode;
+    }
+
+    pub fn scanlines_per_frame(&self) -> u16 {
+        return self.scanlines_per_frame;
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        return self.scanlines_per_frame - 1;
+    }
+
+    /// CPU read of $2000-$3FFF (mirrored every 8 bytes).
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let value = match addr & 0x0007 {
+            2 => self.read_status(),
+            4 => self.read_oam_data(),
+            7 => self.read_data(),
+            // write-only registers return whatever is left on the data bus
+            _ => self.open_bus,
+        };
+        self.open_bus = value;
+        return value;
+    }
+
+    /// CPU write of $2000-$3FFF (mirrored every 8 bytes).
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr & 0x0007 {
+            0 => self.write_ctrl(data),
+            1 => self.mask = MaskRegister::from_bits_retain(data),
+            3 => self.write_oam_addr(data),
+            4 => self.write_oam_data(data),
+            5 => self.write_scroll(data),
+            6 => self.write_addr(data),
+            7 => self.write_data(data),
+            _ => {}
+        }
+    }
+
+    /// Returns true once per NMI raised by the PPU.
+    pub fn poll_nmi(&mut self) -> bool {
+        return std::mem::take(&mut self.nmi_interrupt);
+    }
+
+    pub fn frame(&self) -> &Frame {
+        return &self.frame;
+    }
+
+    /// Advances the PPU by `dots` PPU cycles, returns true when a frame was completed.
+    pub fn tick(&mut self, dots: usize) -> bool {
+        let mut frame_completed = false;
+        for _ in 0..dots {
+            frame_completed |= self.step();
+        }
+        return frame_completed;
+    }
+
+    fn step(&mut self) -> bool {
+        let mut frame_completed = false;
+        let pre_render = self.pre_render_scanline();
+
+        match (self.scanline, self.dot) {
+            (0..=239, 1) if self.render_mode == RenderMode::Scanline => {
+                self.render_scanline(self.scanline as usize)
+            }
+            (0..=239, 257) if self.mask.rendering_enabled() => {
+                self.oam_addr = 0;
+                self.evaluate_sprites(self.scanline);
+            }
+            (line, 257) if line < 240 || line == pre_render => {
+                // no evaluation happens with rendering disabled or on the pre-render line,
+                // so the next line has no sprites (and line 0 never has any)
+                self.line_sprites.clear();
+                self.sprite_zero_on_line = false;
+                if self.mask.rendering_enabled() {
+                    self.oam_addr = 0;
+                }
+            }
+            (line, 1) if line == self.vblank_scanline => {
+                self.status.insert(StatusRegister::VerticalBlank);
+                if self.ctrl.contains(ControlRegister::GenerateNmi) {
+                    self.nmi_interrupt = true;
+                }
+                frame_completed = true;
+            }
+            (line, 1) if line == pre_render => {
+                self.status.remove(
+                    StatusRegister::VerticalBlank
+                        | StatusRegister::SpriteZeroHit
+                        | StatusRegister::SpriteOverflow,
+                );
+            }
+            _ => {}
+        }
+
+        if self.render_mode == RenderMode::Dot {
+            self.step_dot_renderer();
+        }
+        self.update_scroll_registers();
+
+        if self.sprite_zero_hit_dot == Some(self.dot) && self.scanline < 240 {
+            self.status.insert(StatusRegister::SpriteZeroHit);
+            self.sprite_zero_hit_dot = None;
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE - 1
+            && self.scanline == pre_render
+            && self.skip_odd_frame_dot
+            && self.frame_count % 2 == 1
+            && self.mask.rendering_enabled()
+        {
+            // odd frames jump from dot 339 of the pre-render line straight to (0, 0)
+            self.dot = DOTS_PER_SCANLINE;
+        }
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.scanlines_per_frame {
+                self.scanline = 0;
+                self.frame_count += 1;
+            }
+        }
+
+        return frame_completed;
+    }
+
+    /// True while the PPU is fetching, on the visible and pre-render lines with rendering enabled.
+    fn is_rendering(&self) -> bool {
+        return self.mask.rendering_enabled()
+            && (self.scanline < 240 || self.scanline == self.pre_render_scanline());
+    }
+
+    fn write_ctrl(&mut self, data: u8) {
+        let was_nmi_enabled = self.ctrl.contains(ControlRegister::GenerateNmi);
+        self.ctrl = ControlRegister::from_bits_retain(data);
+        self.write_ctrl_nametable(data);
+
+        // enabling NMI during vblank raises it immediately
+        if !was_nmi_enabled
+            && self.ctrl.contains(ControlRegister::GenerateNmi)
+            && self.status.contains(StatusRegister::VerticalBlank)
+        {
+            self.nmi_interrupt = true;
+        }
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let value = self.status.bits() | (self.open_bus & 0b0001_1111);
+        self.status.remove(StatusRegister::VerticalBlank);
+        self.w = false;
+        return value;
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let addr = self.v & 0x3FFF;
+        self.increment_vram_addr();
+
+        if addr >= 0x3F00 {
+            // palette reads are not buffered, the buffer is filled with the nametable "underneath"
+            self.read_buffer = self.read_vram(addr - 0x1000);
+            return (self.read_palette(addr) & 0x3F) | (self.open_bus & 0xC0);
+        }
+
+        let value = self.read_buffer;
+        self.read_buffer = self.read_vram(addr);
+        return value;
+    }
+
+    fn write_data(&mut self, data: u8) {
+        let addr = self.v & 0x3FFF;
+        self.increment_vram_addr();
+        self.write_vram(addr, data);
+    }
+
+    pub(crate) fn read_vram(&mut self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => self.cartridge.borrow_mut().ppu_read(addr),
+            0x2000..=0x3EFF => self.vram[self.mirror_vram_addr(addr)],
+            _ => self.read_palette(addr),
+        }
+    }
+
+    pub(crate) fn write_vram(&mut self, addr: u16, data: u8) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => self.cartridge.borrow_mut().ppu_write(addr, data),
+            0x2000..=0x3EFF => {
+                let index = self.mirror_vram_addr(addr);
+                self.vram[index] = data;
+            }
+            _ => {
+                let index = palette_index(addr);
+                self.palette_table[index] = data & 0x3F;
+            }
+        }
+    }
+
+    fn read_palette(&self, addr: u16) -> u8 {
+        return self.palette_table[palette_index(addr)];
+    }
+
+    /// Maps $2000-$3EFF to the console's 2 KiB of nametable RAM
+    /// (or the cartridge's extra 2 KiB for four-screen boards).
+    fn mirror_vram_addr(&self, addr: u16) -> usize {
+        let index = (addr as usize - 0x2000) % 0x1000;
+        let table = index / 0x400;
+        let offset = index % 0x400;
+
+        let physical_table = match self.cartridge.borrow().mirroring() {
+            Mirroring::Vertical => table & 1,
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        return physical_table * 0x400 + offset;
+    }
+
+    /// Writes a pixel the way the PPU outputs it: greyscale applied and emphasis attached.
+    fn put_pixel(&mut self, x: usize, y: usize, color: u8) {
+        let color = if self.mask.contains(MaskRegister::Greyscale) {
+            palette::greyscale_index(color)
+        } else {
+            color
+        };
+        self.frame.set_pixel(x, y, color);
+        self.frame.set_emphasis(x, y, self.mask.bits() >> 5);
+    }
+
+    fn render_scanline(&mut self, y: usize) {
+        let mut background = [0; SCREEN_WIDTH];
+        self.render_background_line(y, &mut background);
+        self.render_sprites_line(y, &background);
+    }
+
+    /// Draws the background of line `y` and fills `background` with its 2-bit pattern values.
+    fn render_background_line(&mut self, y: usize, background: &mut [u8; SCREEN_WIDTH]) {
+        let backdrop = self.palette_table[0];
+        let show_background = self.mask.contains(MaskRegister::ShowBackground);
+        let show_left = self.mask.contains(MaskRegister::ShowBackgroundLeft);
+
+        let pattern_table = self.ctrl.background_pattern_addr();
+
+        // v was already moved two tiles ahead by the prefetch at the end of the previous line
+        let mut v = scroll::decrement_coarse_x(scroll::decrement_coarse_x(self.v));
+        let fine_y = scroll::fine_y(v);
+        // 33 tiles cover the line when it starts part way into the first one
+        let mut tiles = [(0u8, 0u8, 0u8); 33];
+        if show_background {
+            for tile in tiles.iter_mut() {
+                let index = self.read_vram(scroll::tile_addr(v));
+                let attribute = self.read_vram(scroll::attribute_addr(v));
+                let palette = (attribute >> scroll::attribute_shift(v)) & 0b11;
+                let pattern_addr = pattern_table + index as u16 * 16 + fine_y;
+                let lo = self.read_vram(pattern_addr);
+                let hi = self.read_vram(pattern_addr + 8);
+                *tile = (lo, hi, palette);
+                v = scroll::increment_coarse_x(v);
+            }
+        }
+
+        for (x, pattern_value) in background.iter_mut().enumerate() {
+            if !show_background || (x < 8 && !show_left) {
+                self.put_pixel(x, y, backdrop);
+                continue;
+            }
+
+            let offset = x + self.fine_x as usize;
+            let (lo, hi, palette) = tiles[offset / 8];
+            let bit = 7 - (offset % 8);
+            let value = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+            *pattern_value = value;
+
+            let color = if value == 0 {
+                backdrop
+            } else {
+                self.palette_table[(palette * 4 + value) as usize]
+            };
+            self.put_pixel(x, y, color);
+        }
+    }
+}
+
+/// $3F10/$3F14/$3F18/$3F1C mirror $3F00/$3F04/$3F08/$3F0C.
+fn palette_index(addr: u16) -> usize {
+    let index = (addr as usize - 0x3F00) % PALETTE_SIZE;
+    if index >= 0x10 && index.is_multiple_of(4) {
+        return index - 0x10;
+    }
+    return index;
+}
+
+/// Everything but the render mode and the region timings, which come from the configuration.
+impl SaveState for Ppu {
+    fn save(&self, w: &mut StateWriter) {
+        self.pipeline.save(w);
+        w.u8(self.ctrl.bits());
+        w.u8(self.mask.bits());
+        w.u8(self.status.bits());
+        w.bytes(&self.vram);
+        w.bytes(&self.palette_table);
+        w.bytes(&self.oam_data);
+        w.u8(self.oam_addr);
+        // padded to 8 entries like the pipeline's units
+        w.u8(self.line_sprites.len() as u8);
+        for slot in 0..MAX_SPRITES_PER_LINE {
+            let sprite = self
+                .line_sprites
+                .get(slot)
+                .copied()
+                .unwrap_or(Sprite::from_oam(&[0; 4], 0));
+            w.u8(sprite.index as u8);
+            w.u8(sprite.y);
+            w.u8(sprite.tile);
+            w.u8(sprite.palette);
+            w.bool(sprite.behind_background);
+            w.bool(sprite.flip_horizontal);
+            w.bool(sprite.flip_vertical);
+            w.u8(sprite.x);
+        }
+        w.bool(self.sprite_zero_on_line);
+        // dots only go up to 340
+        w.u16(self.sprite_zero_hit_dot.unwrap_or(u16::MAX));
+        w.u16(self.v);
+        w.u16(self.t);
+        w.u8(self.fine_x);
+        w.bool(self.w);
+        w.u8(self.read_buffer);
+        w.u8(self.open_bus);
+        w.u16(self.scanline);
+        w.u16(self.dot);
+        w.u64(self.frame_count);
+        w.bool(self.nmi_interrupt);
+        w.bytes(&self.frame.pixels);
+        w.bytes(&self.frame.emphasis);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.pipeline.load(r)?;
+        self.ctrl = ControlRegister::from_bits_retain(r.u8()?);
+        self.mask = MaskRegister::from_bits_retain(r.u8()?);
+        self.status = StatusRegister::from_bits_retain(r.u8()?);
+        r.bytes(&mut self.vram)?;
+        r.bytes(&mut self.palette_table)?;
+        r.bytes(&mut self.oam_data)?;
+        self.oam_addr = r.u8()?;
+        let count = r.u8()? as usize;
+        if count > MAX_SPRITES_PER_LINE {
+            return Err(r.error(&format!("{} sprites on a line", count)));
+        }
+        self.line_sprites.clear();
+        for _ in 0..MAX_SPRITES_PER_LINE {
+            self.line_sprites.push(Sprite {
+                index: r.u8()? as usize,
+                y: r.u8()?,
+                tile: r.u8()?,
+                palette: r.u8()?,
+                behind_background: r.bool()?,
+                flip_horizontal: r.bool()?,
+                flip_vertical: r.bool()?,
+                x: r.u8()?,
+            });
+        }
+        self.line_sprites.truncate(count);
+        self.sprite_zero_on_line = r.bool()?;
+        self.sprite_zero_hit_dot = match r.u16()? {
+            u16::MAX => None,
+            dot => Some(dot),
+        };
+        self.v = r.u16()?;
+        self.t = r.u16()?;
+        self.fine_x = r.u8()?;
+        self.w = r.bool()?;
+        self.read_buffer = r.u8()?;
+        self.open_bus = r.u8()?;
+        self.scanline = r.u16()?;
+        self.dot = r.u16()?;
+        if self.scanline >= self.scanlines_per_frame || self.dot >= DOTS_PER_SCANLINE {
+            return Err(r.error("position is outside the frame, the state is for another region"));
+        }
+        self.frame_count = r.u64()?;
+        self.nmi_interrupt = r.bool()?;
+        r.bytes(&mut self.frame.pixels)?;
+        r.bytes(&mut self.frame.emphasis)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::test::ines_rom;
+    use crate::cartridge::Rom;
+    use crate::mapper::new_cartridge;
+
+    pub fn test_ppu(mirroring: Mirroring) -> Ppu {
+        let rom = Rom {
+            mirroring,
+            ..ines_rom(0, vec![0; 0x8000], vec![])
+        };
+        return Ppu::new(new_cartridge(rom).unwrap());
+    }
+
+    pub fn set_addr(ppu: &mut Ppu, addr: u16) {
+        ppu.write_register(0x2006, (addr >> 8) as u8);
+        ppu.write_register(0x2006, (addr & 0xFF) as u8);
+    }
+
+    pub fn write_bytes(ppu: &mut Ppu, addr: u16, data: &[u8]) {
+        set_addr(ppu, addr);
+        for byte in data {
+            ppu.write_register(0x2007, *byte);
+        }
+    }
+
+    /// Writes an 8x8 tile where every pixel has the given 2-bit value.
+    pub fn write_solid_tile(ppu: &mut Ppu, pattern_table: u16, tile: u8, value: u8) {
+        let lo = if value & 1 != 0 { 0xFF } else { 0x00 };
+        let hi = if value & 2 != 0 { 0xFF } else { 0x00 };
+        let mut data = vec![lo; 8];
+        data.extend(vec![hi; 8]);
+        write_bytes(ppu, pattern_table + tile as u16 * 16, &data);
+    }
+
+    pub fn run_frame(ppu: &mut Ppu) {
+        while !ppu.tick(1) {}
+    }
+
+    #[test]
+    fn test_vram_writes_and_buffered_reads() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x2305, &[0x66]);
+
+        set_addr(&mut ppu, 0x2305);
+        ppu.read_register(0x2007); // dummy read fills the buffer
+
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_vram_addr_increment_by_32() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2000, 0b100);
+        write_bytes(&mut ppu, 0x21FF, &[0x66, 0x77, 0x88]);
+
+        ppu.write_register(0x2000, 0);
+        set_addr(&mut ppu, 0x21FF);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+
+        set_addr(&mut ppu, 0x221F);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x77);
+
+        set_addr(&mut ppu, 0x223F);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x88);
+    }
+
+    #[test]
+    fn test_horizontal_mirroring() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x2405, &[0x66]);
+        write_bytes(&mut ppu, 0x2805, &[0x77]);
+
+        set_addr(&mut ppu, 0x2005);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+
+        set_addr(&mut ppu, 0x2C05);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x77);
+    }
+
+    #[test]
+    fn test_vertical_mirroring() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        write_bytes(&mut ppu, 0x2005, &[0x66]);
+        write_bytes(&mut ppu, 0x2C05, &[0x77]);
+
+        set_addr(&mut ppu, 0x2805);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+
+        set_addr(&mut ppu, 0x2405);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x77);
+    }
+
+    #[test]
+    fn test_vram_mirrors_above_0x3000() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x2005, &[0x66]);
+
+        set_addr(&mut ppu, 0x3005);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_status_read_resets_write_latch() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x2305, &[0x66]);
+
+        ppu.write_register(0x2006, 0x21);
+        ppu.write_register(0x2006, 0x23);
+        ppu.write_register(0x2006, 0x05);
+        ppu.read_register(0x2007);
+        assert_ne!(ppu.read_register(0x2007), 0x66);
+
+        ppu.read_register(0x2002);
+        set_addr(&mut ppu, 0x2305);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_status_read_clears_vblank() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.status.insert(StatusRegister::VerticalBlank);
+
+        let status = ppu.read_register(0x2002);
+
+        assert_eq!(status >> 7, 1);
+        assert!(!ppu.status.contains(StatusRegister::VerticalBlank));
+    }
+
+    #[test]
+    fn test_palette_reads_are_not_buffered() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x2F05, &[0x66]);
+        write_bytes(&mut ppu, 0x3F05, &[0x21]);
+
+        set_addr(&mut ppu, 0x3F05);
+        assert_eq!(ppu.read_register(0x2007), 0x21);
+        // the buffer now holds the nametable byte underneath the palette
+        set_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_bytes(&mut ppu, 0x3F10, &[0x11]);
+        write_bytes(&mut ppu, 0x3F24, &[0x12]);
+
+        set_addr(&mut ppu, 0x3F00);
+        assert_eq!(ppu.read_register(0x2007), 0x11);
+        set_addr(&mut ppu, 0x3F14);
+        assert_eq!(ppu.read_register(0x2007), 0x12);
+    }
+
+    #[test]
+    fn test_write_only_registers_read_open_bus() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2001, 0x5A);
+
+        assert_eq!(ppu.read_register(0x2000), 0x5A);
+        assert_eq!(ppu.read_register(0x2002) & 0x1F, 0x1A);
+    }
+
+    #[test]
+    fn test_registers_are_mirrored() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x3456, 0x23);
+        ppu.write_register(0x3456, 0x05);
+        ppu.write_register(0x2007, 0x66);
+
+        set_addr(&mut ppu, 0x2305);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x3FFF), 0x66);
+    }
+
+    #[test]
+    fn test_vblank_and_nmi() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2000, 0b1000_0000);
+
+        run_frame(&mut ppu);
+
+        assert_eq!((ppu.scanline, ppu.dot), (241, 2));
+        assert!(ppu.status.contains(StatusRegister::VerticalBlank));
+        assert!(ppu.poll_nmi());
+        assert!(!ppu.poll_nmi());
+
+        ppu.tick((PRE_RENDER_SCANLINE - 241) as usize * DOTS_PER_SCANLINE as usize);
+        assert!(!ppu.status.contains(StatusRegister::VerticalBlank));
+    }
+
+    #[test]
+    fn test_pal_frame_timing() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.set_region(Region::Pal);
+        assert_eq!(ppu.scanline, PAL_SCANLINES_PER_FRAME - 1);
+
+        run_frame(&mut ppu);
+        let start = ppu.frame_count;
+        run_frame(&mut ppu);
+
+        assert_eq!((ppu.scanline, ppu.dot), (241, 2));
+        assert_eq!(ppu.frame_count, start + 1);
+        ppu.tick((PAL_SCANLINES_PER_FRAME - 241 - 1) as usize * DOTS_PER_SCANLINE as usize);
+        assert!(!ppu.status.contains(StatusRegister::VerticalBlank));
+    }
+
+    #[test]
+    fn test_dendy_vblank_starts_later() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.set_region(Region::Dendy);
+
+        run_frame(&mut ppu);
+
+        assert_eq!((ppu.scanline, ppu.dot), (291, 2));
+    }
+
+    #[test]
+    fn test_enabling_nmi_during_vblank_raises_it() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        run_frame(&mut ppu);
+        assert!(!ppu.poll_nmi());
+
+        ppu.write_register(0x2000, 0b1000_0000);
+
+        assert!(ppu.poll_nmi());
+    }
+
+    fn expected_frame(color_at: impl Fn(usize, usize) -> u8) -> Frame {
+        let mut frame = Frame::new();
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                frame.set_pixel(x, y, color_at(x, y));
+            }
+        }
+        return frame;
+    }
+
+    fn setup_background(ppu: &mut Ppu) {
+        write_bytes(
+            ppu,
+            0x3F00,
+            &[0x0F, 0x01, 0x02, 0x03, 0x0F, 0x11, 0x12, 0x13],
+        );
+        write_solid_tile(ppu, 0x0000, 1, 1);
+        write_solid_tile(ppu, 0x0000, 2, 3);
+
+        // first nametable: tile 1 on the left half, tile 2 on the right half
+        for row in 0..30u16 {
+            let tiles: Vec<u8> = (0..32).map(|col| if col < 16 { 1 } else { 2 }).collect();
+            write_bytes(ppu, 0x2000 + row * 32, &tiles);
+        }
+        // top-left 32x32 pixels use palette 1
+        write_bytes(ppu, 0x23C0, &[0b01]);
+    }
+
+    #[test]
+    fn test_background_rendering() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_background(&mut ppu);
+        ppu.write_register(0x2001, 0b0000_1010);
+        set_addr(&mut ppu, 0);
+
+        run_frame(&mut ppu);
+
+        let expected = expected_frame(|x, y| match (x, y) {
+            (0..=15, 0..=15) => 0x11,
+            (0..=127, _) => 0x01,
+            _ => 0x03,
+        });
+        assert!(*ppu.frame() == expected);
+    }
+
+    #[test]
+    fn test_background_rendering_disabled_shows_backdrop() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_background(&mut ppu);
+        set_addr(&mut ppu, 0);
+
+        run_frame(&mut ppu);
+
+        assert!(*ppu.frame() == expected_frame(|_, _| 0x0F));
+    }
+
+    #[test]
+    fn test_background_left_column_clipping() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_background(&mut ppu);
+        ppu.write_register(0x2001, 0b0000_1000);
+        set_addr(&mut ppu, 0);
+
+        run_frame(&mut ppu);
+
+        assert_eq!(ppu.frame().get_pixel(7, 100), 0x0F);
+        assert_eq!(ppu.frame().get_pixel(8, 100), 0x01);
+    }
+
+    #[test]
+    fn test_greyscale_and_emphasis_are_applied_to_output() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_background(&mut ppu);
+        ppu.write_register(0x2001, 0b1010_1011);
+        set_addr(&mut ppu, 0);
+
+        run_frame(&mut ppu);
+
+        // 0x11 and 0x03 lose their hue
+        assert_eq!(ppu.frame().get_pixel(0, 0), 0x10);
+        assert_eq!(ppu.frame().get_pixel(200, 0), 0x00);
+        assert_eq!(ppu.frame().get_emphasis(200, 0), 0b101);
+    }
+
+    #[test]
+    fn test_background_horizontal_scroll() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_background(&mut ppu);
+        ppu.write_register(0x2001, 0b0000_1010);
+        set_addr(&mut ppu, 0);
+        ppu.write_register(0x2005, 64);
+        ppu.write_register(0x2005, 0);
+
+        run_frame(&mut ppu);
+
+        // the second nametable is empty, so its tile 0 shows the backdrop
+        let expected = expected_frame(|x, _| match x {
+            0..=63 => 0x01,
+            64..=191 => 0x03,
+            _ => 0x0F,
+        });
+        assert!(*ppu.frame() == expected);
+    }
+}
@@ -0,0 +1,106 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// PPUCTRL ($2000)
+    ///  7 6 5 4 3 2 1 0
+    ///  V P H B S I N N
+    ///  | | | | | | +-+--- Base nametable address (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
+    ///  | | | | | +------- VRAM address increment per CPU read/write of PPUDATA (0: add 1; 1: add 32)
+    ///  | | | | +--------- Sprite pattern table address for 8x8 sprites (0: $0000; 1: $1000)
+    ///  | | | +----------- Background pattern table address (0: $0000; 1: $1000)
+    ///  | | +------------- Sprite size (0: 8x8 pixels; 1: 8x16 pixels)
+    ///  | +--------------- PPU master/slave select
+    ///  +----------------- Generate an NMI at the start of the vertical blanking interval
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct ControlRegister: u8 {
+        const NametableLo = 0b0000_0001;
+        const NametableHi = 0b0000_0010;
+        const VramAddIncrement = 0b0000_0100;
+        const SpritePatternAddr = 0b0000_1000;
+        const BackgroundPatternAddr = 0b0001_0000;
+        const SpriteSize = 0b0010_0000;
+        const MasterSlaveSelect = 0b0100_0000;
+        const GenerateNmi = 0b1000_0000;
+    }
+}
+
+impl ControlRegister {
+    pub fn nametable_addr(&self) -> u16 {
+        return 0x2000 + (self.bits() & 0b11) as u16 * 0x400;
+    }
+
+    pub fn vram_addr_increment(&self) -> u16 {
+        if self.contains(ControlRegister::VramAddIncrement) {
+            return 32;
+        }
+        return 1;
+    }
+
+    pub fn sprite_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::SpritePatternAddr) {
+            return 0x1000;
+        }
+        return 0;
+    }
+
+    pub fn background_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::BackgroundPatternAddr) {
+            return 0x1000;
+        }
+        return 0;
+    }
+
+    pub fn sprite_height(&self) -> usize {
+        if self.contains(ControlRegister::SpriteSize) {
+            return 16;
+        }
+        return 8;
+    }
+}
+
+bitflags! {
+    /// PPUMASK ($2001)
+    ///  7 6 5 4 3 2 1 0
+    ///  B G R s b M m G
+    ///  | | | | | | | +--- Greyscale
+    ///  | | | | | | +----- Show background in leftmost 8 pixels of screen
+    ///  | | | | | +------- Show sprites in leftmost 8 pixels of screen
+    ///  | | | | +--------- Show background
+    ///  | | | +----------- Show sprites
+    ///  | | +------------- Emphasize red (green on PAL)
+    ///  | +--------------- Emphasize green (red on PAL)
+    ///  +----------------- Emphasize blue
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct MaskRegister: u8 {
+        const Greyscale = 0b0000_0001;
+        const ShowBackgroundLeft = 0b0000_0010;
+        const ShowSpritesLeft = 0b0000_0100;
+        const ShowBackground = 0b0000_1000;
+        const ShowSprites = 0b0001_0000;
+        const EmphasizeRed = 0b0010_0000;
+        const EmphasizeGreen = 0b0100_0000;
+        const EmphasizeBlue = 0b1000_0000;
+    }
+}
+
+impl MaskRegister {
+    pub fn rendering_enabled(&self) -> bool {
+        return self.intersects(MaskRegister::ShowBackground | MaskRegister::ShowSprites);
+    }
+}
+
+bitflags! {
+    /// PPUSTATUS ($2002)
+    ///  7 6 5 4 3 2 1 0
+    ///  V S O . . . . .
+    ///  | | | +-+-+-+-+--- open bus
+    ///  | | +------------- Sprite overflow
+    ///  | +--------------- Sprite 0 hit
+    ///  +----------------- Vertical blank has started
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    pub struct StatusRegister: u8 {
+        const SpriteOverflow = 0b0010_0000;
+        const SpriteZeroHit = 0b0100_0000;
+        const VerticalBlank = 0b1000_0000;
+    }
+}
@@ -0,0 +1,339 @@
+//! The PPU's internal "loopy" registers, shared by scrolling and $2006/$2007 addressing.
+//!
+//!  v and t are 15 bits wide:
+//!  yyy NN YYYYY XXXXX
+//!  ||| || ||||| +++++-- coarse X scroll
+//!  ||| || +++++-------- coarse Y scroll
+//!  ||| ++-------------- nametable select
+//!  +++----------------- fine Y scroll
+
+use super::Ppu;
+
+pub const COARSE_X: u16 = 0b000_0000_0001_1111;
+pub const COARSE_Y: u16 = 0b000_0011_1110_0000;
+pub const NAMETABLE_X: u16 = 0b000_0100_0000_0000;
+pub const NAMETABLE_Y: u16 = 0b000_1000_0000_0000;
+pub const FINE_Y: u16 = 0b111_0000_0000_0000;
+
+const HORIZONTAL_BITS: u16 = COARSE_X | NAMETABLE_X;
+const VERTICAL_BITS: u16 = COARSE_Y | NAMETABLE_Y | FINE_Y;
+
+pub fn increment_coarse_x(v: u16) -> u16 {
+    if v & COARSE_X == 31 {
+        return (v & !COARSE_X) ^ NAMETABLE_X;
+    }
+    return v + 1;
+}
+
+pub fn decrement_coarse_x(v: u16) -> u16 {
+    if v & COARSE_X == 0 {
+        return (v | 31) ^ NAMETABLE_X;
+    }
+    return v - 1;
+}
+
+pub fn increment_y(v: u16) -> u16 {
+    if v & FINE_Y != FINE_Y {
+        return v + 0x1000;
+    }
+
+    let v = v & !FINE_Y;
+    let coarse_y = (v & COARSE_Y) >> 5;
+    let (coarse_y, v) = match coarse_y {
+        // row 29 is the last row of tiles, the attribute table follows
+        29 => (0, v ^ NAMETABLE_Y),
+        // out of bounds rows (set through $2005/$2006) wrap without switching nametable
+        31 => (0, v),
+        _ => (coarse_y + 1, v),
+    };
+    return (v & !COARSE_Y) | (coarse_y << 5);
+}
+
+pub fn tile_addr(v: u16) -> u16 {
+    return 0x2000 | (v & 0x0FFF);
+}
+
+pub fn attribute_addr(v: u16) -> u16 {
+    return 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+}
+
+pub fn attribute_shift(v: u16) -> u16 {
+    return ((v >> 4) & 0b100) | (v & 0b10);
+}
+
+pub fn fine_y(v: u16) -> u16 {
+    return (v & FINE_Y) >> 12;
+}
+
+impl Ppu {
+    /// $2000: t: ...GH.. ........ <- d: ......GH
+    pub(super) fn write_ctrl_nametable(&mut self, data: u8) {
+        self.t = (self.t & !(NAMETABLE_X | NAMETABLE_Y)) | ((data as u16 & 0b11) << 10);
+    }
+
+    /// $2005, first write: t: ....... ...ABCDE <- d: ABCDE...; x: FGH <- d: .....FGH
+    /// second write:      t: FGH..AB CDE..... <- d: ABCDEFGH
+    pub(super) fn write_scroll(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & !COARSE_X) | (data as u16 >> 3);
+            self.fine_x = data & 0b111;
+        } else {
+            self.t = (self.t & !(COARSE_Y | FINE_Y))
+                | ((data as u16 & 0b111) << 12)
+                | ((data as u16 >> 3) << 5);
+        }
+        self.w = !self.w;
+    }
+
+    /// $2006, first write: t: .CDEFGH ........ <- d: ..CDEFGH (bit 14 is cleared)
+    /// second write:      t: ....... ABCDEFGH <- d: ABCDEFGH; v: <- t
+    pub(super) fn write_addr(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    /// Increments v after a $2007 access.
+    pub(super) fn increment_vram_addr(&mut self) {
+        if self.is_rendering() {
+            // the PPU is using v for its own fetches, so both increments happen at once
+            self.v = increment_y(increment_coarse_x(self.v));
+        } else {
+            self.v = self.v.wrapping_add(self.ctrl.vram_addr_increment()) & 0x7FFF;
+        }
+    }
+
+    /// The increments and copies the PPU does to v while it fetches tiles.
+    pub(super) fn update_scroll_registers(&mut self) {
+        if !self.is_rendering() {
+            return;
+        }
+
+        let dot = self.dot;
+        if ((8..=256).contains(&dot) || dot == 328 || dot == 336) && dot.is_multiple_of(8) {
+            self.v = increment_coarse_x(self.v);
+        }
+        if dot == 256 {
+            self.v = increment_y(self.v);
+        }
+        if dot == 257 {
+            self.v = (self.v & !HORIZONTAL_BITS) | (self.t & HORIZONTAL_BITS);
+        }
+        if self.scanline == self.pre_render_scanline() && (280..=304).contains(&dot) {
+            self.v = (self.v & !VERTICAL_BITS) | (self.t & VERTICAL_BITS);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::*;
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::ppu::{PRE_RENDER_SCANLINE, SCREEN_WIDTH};
+
+    fn run_until(ppu: &mut Ppu, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_register_writes_update_t_x_and_w() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+
+        ppu.write_register(0x2000, 0b11);
+        assert_eq!(ppu.t, 0b000_1100_0000_0000);
+
+        ppu.read_register(0x2002);
+        assert!(!ppu.w);
+
+        ppu.write_register(0x2005, 0b0111_1101);
+        assert_eq!(ppu.t, 0b000_1100_0000_1111);
+        assert_eq!(ppu.fine_x, 0b101);
+        assert!(ppu.w);
+
+        ppu.write_register(0x2005, 0b0101_1110);
+        assert_eq!(ppu.t, 0b110_1101_0110_1111);
+        assert!(!ppu.w);
+
+        ppu.write_register(0x2006, 0b0011_1101);
+        assert_eq!(ppu.t, 0b011_1101_0110_1111);
+        assert!(ppu.w);
+
+        ppu.write_register(0x2006, 0b1111_0000);
+        assert_eq!(ppu.t, 0b011_1101_1111_0000);
+        assert_eq!(ppu.v, ppu.t);
+        assert!(!ppu.w);
+    }
+
+    #[test]
+    fn test_increment_coarse_x_wraps_to_next_nametable() {
+        assert_eq!(
+            increment_coarse_x(0b000_0000_0000_0011),
+            0b000_0000_0000_0100
+        );
+        assert_eq!(
+            increment_coarse_x(0b000_0000_0001_1111),
+            0b000_0100_0000_0000
+        );
+        assert_eq!(
+            increment_coarse_x(0b000_0100_0001_1111),
+            0b000_0000_0000_0000
+        );
+        assert_eq!(
+            decrement_coarse_x(0b000_0100_0000_0000),
+            0b000_0000_0001_1111
+        );
+    }
+
+    #[test]
+    fn test_increment_y() {
+        // fine Y
+        assert_eq!(increment_y(0b010_0000_0000_0000), 0b011_0000_0000_0000);
+        // fine Y overflows into coarse Y
+        assert_eq!(increment_y(0b111_0000_0010_0000), 0b000_0000_0100_0000);
+        // row 29 switches vertical nametable
+        assert_eq!(increment_y(0b111_0011_1010_0000), 0b000_1000_0000_0000);
+        // row 31 wraps without switching nametable
+        assert_eq!(increment_y(0b111_0011_1110_0000), 0b000_0000_0000_0000);
+    }
+
+    #[test]
+    fn test_horizontal_bits_are_copied_at_dot_257() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        ppu.write_register(0x2001, 0b0000_1000);
+        run_until(&mut ppu, 10, 0);
+        ppu.write_register(0x2000, 0b01);
+        ppu.write_register(0x2005, 0b0010_1000);
+
+        run_until(&mut ppu, 10, 257);
+        assert_ne!(ppu.v & (COARSE_X | NAMETABLE_X), 0b000_0100_0000_0101);
+        ppu.tick(1);
+        assert_eq!(ppu.v & (COARSE_X | NAMETABLE_X), 0b000_0100_0000_0101);
+    }
+
+    #[test]
+    fn test_vertical_bits_are_copied_on_pre_render_line() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        ppu.write_register(0x2001, 0b0000_1000);
+        run_until(&mut ppu, 100, 0);
+        ppu.write_register(0x2000, 0b10);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2005, 0b0101_1110);
+
+        run_until(&mut ppu, PRE_RENDER_SCANLINE, 280);
+        assert_ne!(ppu.v & VERTICAL_BITS, ppu.t & VERTICAL_BITS);
+        run_until(&mut ppu, PRE_RENDER_SCANLINE, 305);
+        assert_eq!(ppu.v & VERTICAL_BITS, 0b110_1001_0110_0000);
+    }
+
+    #[test]
+    fn test_no_updates_with_rendering_disabled() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        set_addr(&mut ppu, 0x2345);
+
+        run_until(&mut ppu, 20, 300);
+
+        assert_eq!(ppu.v, 0x2345);
+    }
+
+    fn setup_nametables(ppu: &mut Ppu) {
+        write_bytes(ppu, 0x3F00, &[0x0F, 0x01, 0x02, 0x03]);
+        write_solid_tile(ppu, 0x0000, 1, 1);
+        write_solid_tile(ppu, 0x0000, 2, 2);
+        write_solid_tile(ppu, 0x0000, 3, 3);
+        // left nametable: rows 0-14 use tile 1, rows 15-29 use tile 2
+        for row in 0..30u16 {
+            let tile = if row < 15 { 1 } else { 2 };
+            write_bytes(ppu, 0x2000 + row * 32, &[tile; 32]);
+        }
+        // right nametable: tile 3 everywhere
+        for row in 0..30u16 {
+            write_bytes(ppu, 0x2400 + row * 32, &[3; 32]);
+        }
+        set_addr(ppu, 0);
+    }
+
+    fn line_color(ppu: &Ppu, y: usize) -> Option<u8> {
+        let line = ppu.frame().line(y);
+        if line.iter().all(|c| *c == line[0]) {
+            return Some(line[0]);
+        }
+        return None;
+    }
+
+    #[test]
+    fn test_fine_x_scroll() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_nametables(&mut ppu);
+        ppu.write_register(0x2001, 0b0000_1010);
+        ppu.write_register(0x2005, 3);
+        ppu.write_register(0x2005, 0);
+
+        run_frame(&mut ppu);
+
+        let line = ppu.frame().line(0);
+        assert_eq!(line[SCREEN_WIDTH - 4], 0x01);
+        assert_eq!(line[SCREEN_WIDTH - 3], 0x03);
+    }
+
+    #[test]
+    fn test_split_screen_with_mid_frame_ctrl_write() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_nametables(&mut ppu);
+        ppu.write_register(0x2001, 0b0000_1010);
+
+        // status bar style split: switch to the right nametable before dot 257 of line 99
+        run_until(&mut ppu, 99, 200);
+        ppu.write_register(0x2000, 0b01);
+        run_frame(&mut ppu);
+
+        assert_eq!(line_color(&ppu, 99), Some(0x01));
+        assert_eq!(line_color(&ppu, 100), Some(0x03));
+        assert_eq!(line_color(&ppu, 239), Some(0x03));
+    }
+
+    #[test]
+    fn test_mid_frame_addr_write_moves_rendering() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_nametables(&mut ppu);
+        ppu.write_register(0x2001, 0b0000_1010);
+
+        // during hblank of line 50, point v at row 15 of the left nametable
+        run_until(&mut ppu, 50, 300);
+        ppu.write_register(0x2006, 0x01);
+        ppu.write_register(0x2006, 0xE0);
+        run_frame(&mut ppu);
+
+        assert_eq!(line_color(&ppu, 50), Some(0x01));
+        assert_eq!(line_color(&ppu, 51), Some(0x02));
+        assert_eq!(line_color(&ppu, 170), Some(0x02));
+        // row 29 wraps to the top of the (mirrored) left nametable
+        assert_eq!(line_color(&ppu, 171), Some(0x01));
+    }
+
+    #[test]
+    fn test_scroll_resets_on_next_frame() {
+        let mut ppu = test_ppu(Mirroring::Vertical);
+        setup_nametables(&mut ppu);
+        ppu.write_register(0x2001, 0b0000_1010);
+        run_until(&mut ppu, 50, 300);
+        ppu.write_register(0x2006, 0x01);
+        ppu.write_register(0x2006, 0xE0);
+        run_frame(&mut ppu);
+        // t still holds the $2006 value, reset it like a game would during vblank
+        ppu.write_register(0x2000, 0);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2005, 0);
+
+        run_frame(&mut ppu);
+
+        assert_eq!(line_color(&ppu, 51), Some(0x01));
+        assert_eq!(line_color(&ppu, 120), Some(0x02));
+    }
+}
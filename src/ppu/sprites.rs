@@ -0,0 +1,493 @@
+use super::registers::{MaskRegister, StatusRegister};
+use super::{Ppu, SCREEN_WIDTH};
+
+pub const OAM_SIZE: usize = 256;
+pub const SPRITE_COUNT: usize = 64;
+pub const MAX_SPRITES_PER_LINE: usize = 8;
+
+/// A decoded OAM entry.
+///  byte 0: Y position of top of sprite, minus one
+///  byte 1: tile index (8x16: bit 0 is the pattern table)
+///  byte 2: attributes
+///    7 6 5 4 3 2 1 0
+///    V H P . . . p p
+///    | | |       +-+--- palette (4 to 7)
+///    | | +------------- priority (0: in front of background; 1: behind background)
+///    | +--------------- flip horizontally
+///    +----------------- flip vertically
+///  byte 3: X position of left side of sprite
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Sprite {
+    pub index: usize,
+    pub y: u8,
+    pub tile: u8,
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub x: u8,
+}
+
+impl Sprite {
+    pub fn from_oam(oam: &[u8], index: usize) -> Self {
+        let entry = &oam[index * 4..index * 4 + 4];
+        let attributes = entry[2];
+
+        Sprite {
+            index,
+            y: entry[0],
+            tile: entry[1],
+            palette: attributes & 0b11,
+            behind_background: attributes & 0b0010_0000 != 0,
+            flip_horizontal: attributes & 0b0100_0000 != 0,
+            flip_vertical: attributes & 0b1000_0000 != 0,
+            x: entry[3],
+        }
+    }
+}
+
+impl Ppu {
+    pub fn oam(&self) -> &[u8; OAM_SIZE] {
+        return &self.oam_data;
+    }
+
+    pub fn sprites(&self) -> Vec<Sprite> {
+        return (0..SPRITE_COUNT)
+            .map(|i| Sprite::from_oam(&self.oam_data, i))
+            .collect();
+    }
+
+    pub(super) fn write_oam_addr(&mut self, data: u8) {
+        self.oam_addr = data;
+    }
+
+    pub(super) fn write_oam_data(&mut self, data: u8) {
+        if self.is_rendering() {
+            // writes during rendering are ignored but still bump the high 6 bits of OAMADDR
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+            return;
+        }
+        self.oam_data[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    pub(super) fn read_oam_data(&self) -> u8 {
+        // secondary OAM is being cleared to $FF, which is what the CPU sees
+        if self.is_rendering() && self.scanline < 240 && (1..=64).contains(&self.dot) {
+            return 0xFF;
+        }
+
+        let value = self.oam_data[self.oam_addr as usize];
+        if self.oam_addr & 0b11 == 2 {
+            // bits 2-4 of the attribute byte do not exist
+            return value & 0b1110_0011;
+        }
+        return value;
+    }
+
+    /// Fills `line_sprites` for the next scanline, as the PPU does during dots 65-256.
+    ///
+    /// Once 8 sprites are found the hardware keeps scanning for the overflow flag, but
+    /// increments the byte offset within each entry along with the sprite index, so it ends up
+    /// comparing tile indices, attributes and X positions against the scanline instead of Y.
+    pub(super) fn evaluate_sprites(&mut self, scanline: u16) {
+        self.line_sprites.clear();
+        self.sprite_zero_on_line = false;
+
+        let height = self.ctrl.sprite_height() as u16;
+        let in_range = |y: u8| scanline.wrapping_sub(y as u16) < height;
+
+        let mut n = 0;
+        while n < SPRITE_COUNT && self.line_sprites.len() < MAX_SPRITES_PER_LINE {
+            if in_range(self.oam_data[n * 4]) {
+                if n == 0 {
+                    self.sprite_zero_on_line = true;
+                }
+                self.line_sprites.push(Sprite::from_oam(&self.oam_data, n));
+            }
+            n += 1;
+        }
+
+        let mut m = 0;
+        while n < SPRITE_COUNT {
+            if in_range(self.oam_data[n * 4 + m]) {
+                self.status.insert(StatusRegister::SpriteOverflow);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0b11;
+        }
+    }
+
+    /// Draws the sprites of the current line over `background` (2-bit pattern values)
+    /// and records the dot at which sprite 0 hits.
+    pub(super) fn render_sprites_line(&mut self, y: usize, background: &[u8; SCREEN_WIDTH]) {
+        self.sprite_zero_hit_dot = None;
+        if !self.mask.contains(MaskRegister::ShowSprites) {
+            return;
+        }
+
+        let show_left = self.mask.contains(MaskRegister::ShowSpritesLeft);
+        let background_enabled = self.mask.contains(MaskRegister::ShowBackground);
+        let show_background_left = self.mask.contains(MaskRegister::ShowBackgroundLeft);
+        let mut drawn = [false; SCREEN_WIDTH];
+
+        let sprites = self.line_sprites.clone();
+        for (slot, sprite) in sprites.iter().enumerate() {
+            let (lo, hi) = self.fetch_sprite_row(sprite, y);
+            let is_sprite_zero = slot == 0 && self.sprite_zero_on_line;
+
+            for col in 0..8 {
+                let x = sprite.x as usize + col;
+                if x >= SCREEN_WIDTH || (x < 8 && !show_left) {
+                    continue;
+                }
+
+                let bit = if sprite.flip_horizontal { col } else { 7 - col };
+                let value = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                if value == 0 {
+                    continue;
+                }
+
+                if is_sprite_zero
+                    && background[x] != 0
+                    && background_enabled
+                    && x != 255
+                    && (x >= 8 || (show_left && show_background_left))
+                    && self.sprite_zero_hit_dot.is_none()
+                {
+                    self.sprite_zero_hit_dot = Some(x as u16 + 1);
+                }
+
+                // lower OAM index wins, even when it is behind the background
+                if drawn[x] {
+                    continue;
+                }
+                drawn[x] = true;
+
+                if sprite.behind_background && background[x] != 0 {
+                    continue;
+                }
+                let color = self.palette_table[0x10 + (sprite.palette * 4 + value) as usize];
+                self.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    fn fetch_sprite_row(&mut self, sprite: &Sprite, y: usize) -> (u8, u8) {
+        let addr = self.sprite_row_addr(sprite, y);
+        return (self.read_vram(addr), self.read_vram(addr + 8));
+    }
+
+    /// Address of the low pattern plane of `sprite` for line `y`, the high plane follows 8 bytes later.
+    pub(super) fn sprite_row_addr(&self, sprite: &Sprite, y: usize) -> u16 {
+        let height = self.ctrl.sprite_height();
+        let mut row = y.wrapping_sub(sprite.y as usize + 1) % height;
+        if sprite.flip_vertical {
+            row = height - 1 - row;
+        }
+
+        let (table, tile) = if height == 16 {
+            let table = (sprite.tile as u16 & 1) * 0x1000;
+            let tile = (sprite.tile & 0xFE) as u16 + (row / 8) as u16;
+            (table, tile)
+        } else {
+            (self.ctrl.sprite_pattern_addr(), sprite.tile as u16)
+        };
+
+        return table + tile * 16 + (row % 8) as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::*;
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::ppu::{DOTS_PER_SCANLINE, SCANLINES_PER_FRAME};
+
+    fn write_oam(ppu: &mut Ppu, index: u8, entry: [u8; 4]) {
+        ppu.write_register(0x2003, index * 4);
+        for byte in entry {
+            ppu.write_register(0x2004, byte);
+        }
+    }
+
+    fn hide_all_sprites(ppu: &mut Ppu) {
+        ppu.write_register(0x2003, 0);
+        for _ in 0..OAM_SIZE {
+            ppu.write_register(0x2004, 0xFF);
+        }
+    }
+
+    fn setup(ppu: &mut Ppu) {
+        write_bytes(ppu, 0x3F00, &[0x0F, 0x01, 0x02, 0x03]);
+        write_bytes(
+            ppu,
+            0x3F10,
+            &[0x0F, 0x21, 0x22, 0x23, 0x0F, 0x31, 0x32, 0x33],
+        );
+        write_solid_tile(ppu, 0x0000, 1, 1);
+        write_solid_tile(ppu, 0x0000, 2, 2);
+        hide_all_sprites(ppu);
+    }
+
+    /// Runs the PPU until it is about to process `dot` of `scanline`.
+    fn run_until(ppu: &mut Ppu, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.tick(1);
+        }
+    }
+
+    fn run_to_next_frame(ppu: &mut Ppu) {
+        run_until(ppu, SCANLINES_PER_FRAME - 1, 0);
+        run_until(ppu, 240, 0);
+    }
+
+    #[test]
+    fn test_oam_addr_and_data() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2003, 0x10);
+        ppu.write_register(0x2004, 0x66);
+        ppu.write_register(0x2004, 0x77);
+
+        ppu.write_register(0x2003, 0x10);
+        assert_eq!(ppu.read_register(0x2004), 0x66);
+        // reads do not increment OAMADDR
+        assert_eq!(ppu.read_register(0x2004), 0x66);
+
+        ppu.write_register(0x2003, 0x11);
+        assert_eq!(ppu.read_register(0x2004), 0x77);
+    }
+
+    #[test]
+    fn test_oam_attribute_unimplemented_bits_read_as_zero() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_oam(&mut ppu, 1, [0, 0, 0xFF, 0]);
+
+        ppu.write_register(0x2003, 6);
+        assert_eq!(ppu.read_register(0x2004), 0xE3);
+    }
+
+    #[test]
+    fn test_oam_data_wraps_around() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        ppu.write_register(0x2003, 0xFF);
+        ppu.write_register(0x2004, 0x88);
+        ppu.write_register(0x2004, 0x77);
+
+        assert_eq!(ppu.oam()[0xFF], 0x88);
+        assert_eq!(ppu.oam()[0x00], 0x77);
+    }
+
+    #[test]
+    fn test_decoded_sprites() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        write_oam(&mut ppu, 3, [0x10, 0x20, 0b1110_0010, 0x30]);
+
+        assert_eq!(
+            ppu.sprites()[3],
+            Sprite {
+                index: 3,
+                y: 0x10,
+                tile: 0x20,
+                palette: 2,
+                behind_background: true,
+                flip_horizontal: true,
+                flip_vertical: true,
+                x: 0x30,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sprite_rendering() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup(&mut ppu);
+        write_oam(&mut ppu, 0, [49, 1, 0b01, 100]);
+        ppu.write_register(0x2001, 0b0001_0100);
+
+        run_to_next_frame(&mut ppu);
+
+        assert_eq!(ppu.frame().get_pixel(99, 50), 0x0F);
+        assert_eq!(ppu.frame().get_pixel(100, 49), 0x0F);
+        assert_eq!(ppu.frame().get_pixel(100, 50), 0x31);
+        assert_eq!(ppu.frame().get_pixel(107, 57), 0x31);
+        assert_eq!(ppu.frame().get_pixel(108, 57), 0x0F);
+        assert_eq!(ppu.frame().get_pixel(107, 58), 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_flipping() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup(&mut ppu);
+        // a tile with only its top-left pixel set
+        write_bytes(&mut ppu, 0x0030, &[0x80, 0, 0, 0, 0, 0, 0, 0]);
+        write_oam(&mut ppu, 0, [9, 3, 0, 10]);
+        write_oam(&mut ppu, 1, [9, 3, 0b0100_0000, 20]);
+        write_oam(&mut ppu, 2, [9, 3, 0b1000_0000, 30]);
+        write_oam(&mut ppu, 3, [9, 3, 0b1100_0000, 40]);
+        ppu.write_register(0x2001, 0b0001_0100);
+
+        run_to_next_frame(&mut ppu);
+
+        assert_eq!(ppu.frame().get_pixel(10, 10), 0x21);
+        assert_eq!(ppu.frame().get_pixel(27, 10), 0x21);
+        assert_eq!(ppu.frame().get_pixel(30, 17), 0x21);
+        assert_eq!(ppu.frame().get_pixel(47, 17), 0x21);
+        assert_eq!(ppu.frame().get_pixel(20, 10), 0x0F);
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup(&mut ppu);
+        write_solid_tile(&mut ppu, 0x1000, 4, 1);
+        write_solid_tile(&mut ppu, 0x1000, 5, 2);
+        ppu.write_register(0x2000, 0b0010_0000);
+        write_oam(&mut ppu, 0, [9, 0x05, 0, 10]);
+        write_oam(&mut ppu, 1, [9, 0x05, 0b1000_0000, 20]);
+        ppu.write_register(0x2001, 0b0001_0100);
+
+        run_to_next_frame(&mut ppu);
+
+        assert_eq!(ppu.frame().get_pixel(10, 10), 0x21);
+        assert_eq!(ppu.frame().get_pixel(10, 25), 0x22);
+        assert_eq!(ppu.frame().get_pixel(10, 26), 0x0F);
+        assert_eq!(ppu.frame().get_pixel(20, 10), 0x22);
+        assert_eq!(ppu.frame().get_pixel(20, 25), 0x21);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup(&mut ppu);
+        for row in 0..30u16 {
+            write_bytes(&mut ppu, 0x2000 + row * 32, &[1; 4]);
+        }
+        // behind the background, over a transparent and an opaque background tile
+        write_oam(&mut ppu, 0, [49, 2, 0b0010_0000, 28]);
+        // in front of sprite 2, but sprite 1 wins even when behind the background
+        write_oam(&mut ppu, 1, [49, 1, 0b0010_0000, 10]);
+        write_oam(&mut ppu, 2, [49, 2, 0b01, 10]);
+        ppu.write_register(0x2001, 0b0001_1110);
+        set_addr(&mut ppu, 0);
+
+        run_to_next_frame(&mut ppu);
+
+        assert_eq!(ppu.frame().get_pixel(30, 50), 0x01);
+        assert_eq!(ppu.frame().get_pixel(33, 50), 0x22);
+        assert_eq!(ppu.frame().get_pixel(12, 50), 0x01);
+    }
+
+    #[test]
+    fn test_sprites_per_line_limit_and_overflow() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup(&mut ppu);
+        for i in 0..9 {
+            write_oam(&mut ppu, i, [49, 1, 0, i * 10]);
+        }
+        ppu.write_register(0x2001, 0b0001_0100);
+
+        run_until(&mut ppu, 49, 0);
+        assert!(!ppu.status.contains(StatusRegister::SpriteOverflow));
+        run_to_next_frame(&mut ppu);
+
+        assert_eq!(ppu.frame().get_pixel(70, 50), 0x21);
+        assert_eq!(ppu.frame().get_pixel(80, 50), 0x0F);
+        assert!(ppu.status.contains(StatusRegister::SpriteOverflow));
+    }
+
+    #[test]
+    fn test_sprite_overflow_false_positive() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup(&mut ppu);
+        for i in 0..8 {
+            write_oam(&mut ppu, i, [49, 1, 0, i * 10]);
+        }
+        // sprite 9 is not on the line, but its tile index is read as a Y coordinate
+        write_oam(&mut ppu, 8, [200, 1, 0, 0]);
+        write_oam(&mut ppu, 9, [200, 49, 0, 0]);
+        ppu.write_register(0x2001, 0b0001_0100);
+
+        run_to_next_frame(&mut ppu);
+
+        assert!(ppu.status.contains(StatusRegister::SpriteOverflow));
+    }
+
+    #[test]
+    fn test_sprite_overflow_false_negative() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup(&mut ppu);
+        for i in 0..8 {
+            write_oam(&mut ppu, i, [49, 1, 0, i * 10]);
+        }
+        // sprite 9 is on the line, but its tile index is read instead of its Y coordinate
+        write_oam(&mut ppu, 8, [200, 1, 0, 0]);
+        write_oam(&mut ppu, 9, [49, 0xFF, 0, 0]);
+        ppu.write_register(0x2001, 0b0001_0100);
+
+        run_to_next_frame(&mut ppu);
+
+        assert!(!ppu.status.contains(StatusRegister::SpriteOverflow));
+    }
+
+    fn setup_sprite_zero_hit(ppu: &mut Ppu, sprite_x: u8) {
+        setup(ppu);
+        for row in 0..30u16 {
+            write_bytes(ppu, 0x2000 + row * 32, &[1; 32]);
+        }
+        write_oam(ppu, 0, [49, 1, 0, sprite_x]);
+        set_addr(ppu, 0);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_on_exact_dot() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup_sprite_zero_hit(&mut ppu, 100);
+        ppu.write_register(0x2001, 0b0001_1110);
+
+        run_until(&mut ppu, 50, 101);
+        assert!(!ppu.status.contains(StatusRegister::SpriteZeroHit));
+
+        ppu.tick(1);
+        assert!(ppu.status.contains(StatusRegister::SpriteZeroHit));
+
+        // cleared on the pre-render line
+        run_until(&mut ppu, SCANLINES_PER_FRAME - 1, 2);
+        assert!(!ppu.status.contains(StatusRegister::SpriteZeroHit));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_at_x_255() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup_sprite_zero_hit(&mut ppu, 255);
+        ppu.write_register(0x2001, 0b0001_1110);
+
+        run_until(&mut ppu, 240, 0);
+
+        assert!(!ppu.status.contains(StatusRegister::SpriteZeroHit));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_in_clipped_left_column() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup_sprite_zero_hit(&mut ppu, 0);
+        ppu.write_register(0x2001, 0b0001_1000);
+
+        run_until(&mut ppu, 50, 9);
+        ppu.tick(1);
+        assert!(!ppu.status.contains(StatusRegister::SpriteZeroHit));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_without_background() {
+        let mut ppu = test_ppu(Mirroring::Horizontal);
+        setup_sprite_zero_hit(&mut ppu, 100);
+        ppu.write_register(0x2001, 0b0001_0110);
+
+        ppu.tick(DOTS_PER_SCANLINE as usize * SCANLINES_PER_FRAME as usize);
+
+        assert!(!ppu.status.contains(StatusRegister::SpriteZeroHit));
+    }
+}
@@ -0,0 +1,344 @@
+//! Rewind: a save state every few frames, kept within a memory budget, plus the buttons of
+//! every frame since the oldest one, so any of those frames can be returned to exactly by
+//! loading the snapshot before it and replaying the input.
+//!
+//! Only the newest snapshot is kept in full. Each older one is stored as the XOR with the
+//! snapshot after it, run-length encoded: from one snapshot to the next most of RAM, VRAM and
+//! the cartridge's memory does not change, so that is mostly runs of zeros.
+//!
+//!  older: [delta 0] [delta 1] ... [delta n-1]   newest: [full state n]
+//!    snapshot k = snapshot k+1 XOR delta k
+
+use std::collections::VecDeque;
+
+use crate::controller::Buttons;
+use crate::nes::Nes;
+use crate::ppu::Frame;
+
+/// Buttons of the four players for one frame.
+pub type FrameInput = [Buttons; 4];
+
+/// How a snapshot is rebuilt from the one after it.
+enum Delta {
+    Xor(Vec<u8>),
+    /// States differ in size when a device was plugged in, those are kept whole.
+    Full(Vec<u8>),
+}
+
+impl Delta {
+    fn new(previous: Vec<u8>, next: &[u8]) -> Delta {
+        if previous.len() != next.len() {
+            return Delta::Full(previous);
+        }
+        return Delta::Xor(xor_encode(&previous, next));
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Delta::Xor(delta) => delta.len(),
+            Delta::Full(state) => state.len(),
+        }
+    }
+
+    /// Turns the snapshot after this one back into this one.
+    fn apply(self, next: &mut Vec<u8>) {
+        match self {
+            Delta::Xor(delta) => xor_apply(next, &delta),
+            Delta::Full(state) => *next = state,
+        }
+    }
+}
+
+pub struct Rewind {
+    interval: u64,
+    budget: usize,
+    /// Frames run since recording started (or up to which it was rewound).
+    frame: u64,
+    /// Frame number and state of the newest snapshot, taken before that frame ran.
+    newest: Option<(u64, Vec<u8>)>,
+    older: VecDeque<(u64, Delta)>,
+    older_bytes: usize,
+    /// The buttons of every frame from the oldest snapshot on.
+    inputs: VecDeque<FrameInput>,
+}
+
+impl Rewind {
+    /// Snapshots every `interval` frames (at least 1), dropping the oldest ones to stay under
+    /// `budget` bytes. The newest snapshot is always kept.
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1) as u64,
+            budget,
+            frame: 0,
+            newest: None,
+            older: VecDeque::new(),
+            older_bytes: 0,
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// Frames run through `run_frame`, less those rewound.
+    pub fn frame(&self) -> u64 {
+        return self.frame;
+    }
+
+    /// How many frames back `rewind` can go.
+    pub fn frames_available(&self) -> u64 {
+        return match self.oldest_frame() {
+            Some(oldest) => self.frame - oldest,
+            None => 0,
+        };
+    }
+
+    /// Bytes held by snapshots and the input log.
+    pub fn memory_used(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, |(_, state)| state.len());
+        return newest + self.older_bytes + self.inputs.len() * size_of::<FrameInput>();
+    }
+
+    /// Forgets all history, for when a game is loaded or reset outside of `run_frame`.
+    pub fn clear(&mut self) {
+        self.frame = 0;
+        self.newest = None;
+        self.older.clear();
+        self.older_bytes = 0;
+        self.inputs.clear();
+    }
+
+    /// Runs a frame with `input` held, taking a snapshot first if one is due, and returns the
+    /// audio it produced.
+    pub fn run_frame(&mut self, nes: &mut Nes, input: FrameInput) -> Result<Vec<f32>, String> {
+        let snapshot_due = self.frame.is_multiple_of(self.interval)
+            && self
+                .newest
+                .as_ref()
+                .is_none_or(|(frame, _)| *frame != self.frame);
+        if snapshot_due {
+            self.snapshot(nes);
+        }
+        self.inputs.push_back(input);
+        self.frame += 1;
+        let result = run_with(nes, input).map(|(_, samples)| samples);
+        self.evict();
+        return result;
+    }
+
+    /// Goes back `frames` frames, or as far as the history reaches, and returns how many
+    /// frames were rewound. Running on from there with `run_frame` records a new future.
+    /// Fails, forgetting the history, if `nes` is not running the game it was recorded on.
+    pub fn rewind(&mut self, nes: &mut Nes, frames: u64) -> Result<u64, String> {
+        let Some(oldest) = self.oldest_frame() else {
+            return Ok(0);
+        };
+        let target = self.frame.saturating_sub(frames).max(oldest);
+        if target == self.frame {
+            return Ok(0);
+        }
+
+        let (mut start, mut state) = self.newest.take().expect("there is a snapshot");
+        while start > target {
+            let (frame, delta) = self.older.pop_back().expect("the oldest is before target");
+            self.older_bytes -= delta.len();
+            delta.apply(&mut state);
+            start = frame;
+        }
+        if let Err(e) = nes.load_state(&state) {
+            // not the console the history was recorded on
+            self.clear();
+            return Err(e);
+        }
+        self.newest = Some((start, state));
+
+        let first = (start - oldest) as usize;
+        let last = (target - oldest) as usize;
+        for input in self.inputs.range(first..last) {
+            run_with(nes, *input)?;
+        }
+        // the replayed frames were heard already
+        nes.apu_mut().take_samples();
+
+        self.inputs.truncate(last);
+        let rewound = self.frame - target;
+        self.frame = target;
+        return Ok(rewound);
+    }
+
+    /// Goes back one frame, returns false when there is no history left.
+    pub fn rewind_frame(&mut self, nes: &mut Nes) -> Result<bool, String> {
+        return Ok(self.rewind(nes, 1)? == 1);
+    }
+
+    fn oldest_frame(&self) -> Option<u64> {
+        return match self.older.front() {
+            Some((frame, _)) => Some(*frame),
+            None => self.newest.as_ref().map(|(frame, _)| *frame),
+        };
+    }
+
+    fn snapshot(&mut self, nes: &Nes) {
+        let state = nes.save_state();
+        if let Some((frame, previous)) = self.newest.take() {
+            let delta = Delta::new(previous, &state);
+            self.older_bytes += delta.len();
+            self.older.push_back((frame, delta));
+        }
+        self.newest = Some((self.frame, state));
+    }
+
+    /// Drops the oldest snapshots and their input until the budget is met.
+    fn evict(&mut self) {
+        while self.memory_used() > self.budget {
+            let Some((frame, delta)) = self.older.pop_front() else {
+                break;
+            };
+            self.older_bytes -= delta.len();
+            let oldest = self.oldest_frame().expect("the newest snapshot is kept");
+            self.inputs.drain(..(oldest - frame) as usize);
+        }
+    }
+}
+
+fn run_with(nes: &mut Nes, input: FrameInput) -> Result<(&Frame, Vec<f32>), String> {
+    for (player, buttons) in input.iter().enumerate() {
+        nes.set_buttons(player, *buttons);
+    }
+    return nes.run_frame();
+}
+
+/// `a` XOR `b` (of the same length) as runs: a varint count of zero bytes to skip, a varint
+/// count of bytes that differ, then those bytes.
+fn xor_encode(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+    let mut i = 0;
+    while i < a.len() {
+        let zeros = a[i..]
+            .iter()
+            .zip(&b[i..])
+            .take_while(|(x, y)| x == y)
+            .count();
+        i += zeros;
+        let literals = a[i..]
+            .iter()
+            .zip(&b[i..])
+            .take_while(|(x, y)| x != y)
+            .count();
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, literals);
+        delta.extend(a[i..i + literals].iter().zip(&b[i..]).map(|(x, y)| x ^ y));
+        i += literals;
+    }
+    return delta;
+}
+
+fn xor_apply(data: &mut [u8], delta: &[u8]) {
+    let mut offset = 0;
+    let mut i = 0;
+    while i < delta.len() {
+        offset += read_varint(delta, &mut i);
+        let literals = read_varint(delta, &mut i);
+        for (byte, x) in data[offset..offset + literals].iter_mut().zip(&delta[i..]) {
+            *byte ^= x;
+        }
+        offset += literals;
+        i += literals;
+    }
+}
+
+/// LEB128: 7 bits per byte, low bits first, the top bit set on all but the last byte.
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*i];
+        *i += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Region;
+    use crate::nes::test::test_rom;
+
+    #[test]
+    fn test_xor_delta_round_trip() {
+        let older: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut newer = older.clone();
+        newer[3] ^= 0xFF;
+        newer[500..700].fill(0xAA);
+        newer[999] = 0;
+
+        let delta = xor_encode(&older, &newer);
+        assert!(delta.len() < 220, "{}", delta.len());
+
+        xor_apply(&mut newer, &delta);
+        assert_eq!(newer, older);
+    }
+
+    #[test]
+    fn test_identical_states_cost_almost_nothing() {
+        let state = vec![0x5A; 0x10000];
+
+        assert_eq!(xor_encode(&state, &state), vec![0x80, 0x80, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut data = vec![];
+        for value in [0, 0x7F, 0x80, 0x12345] {
+            write_varint(&mut data, value);
+        }
+
+        let mut i = 0;
+        let values: Vec<usize> = (0..4).map(|_| read_varint(&data, &mut i)).collect();
+        assert_eq!(values, vec![0, 0x7F, 0x80, 0x12345]);
+        assert_eq!(i, data.len());
+    }
+
+    // INC $00; JMP $8000
+    const COUNTER: [u8; 5] = [0xE6, 0x00, 0x4C, 0x00, 0x80];
+
+    #[test]
+    fn test_budget_drops_oldest_snapshots() {
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &COUNTER, &[])).unwrap();
+        // room for the full newest snapshot and a few deltas
+        let budget = nes.save_state().len() + 1024;
+        let mut rewind = Rewind::new(2, budget);
+
+        for _ in 0..200 {
+            rewind.run_frame(&mut nes, FrameInput::default()).unwrap();
+            assert!(rewind.memory_used() <= budget);
+        }
+
+        let available = rewind.frames_available();
+        assert!((2..200).contains(&available), "{}", available);
+        assert_eq!(rewind.rewind(&mut nes, 1000).unwrap(), available);
+        assert_eq!(rewind.frames_available(), 0);
+        assert!(!rewind.rewind_frame(&mut nes).unwrap());
+    }
+
+    #[test]
+    fn test_nothing_to_rewind() {
+        let mut nes = Nes::new(test_rom(Region::Ntsc, &COUNTER, &[])).unwrap();
+        let mut rewind = Rewind::new(4, 1 << 20);
+
+        assert!(!rewind.rewind_frame(&mut nes).unwrap());
+        rewind.run_frame(&mut nes, FrameInput::default()).unwrap();
+        rewind.clear();
+        assert_eq!(rewind.frames_available(), 0);
+        assert_eq!(rewind.memory_used(), 0);
+    }
+}
@@ -0,0 +1,598 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use crate::cartridge::{Mirroring, Region, Rom};
+use crate::hash::{to_hex, Crc32, Sha1};
+
+const BUNDLED_DATABASE: &str = include_str!("rom_db.txt");
+const FIELD_COUNT: usize = 12;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RomHashes {
+    pub crc32: u32,
+    pub sha1: String,
+}
+
+impl RomHashes {
+    /// Hashes PRG ROM followed by CHR ROM, so the (often broken) header does not matter.
+    pub fn new(rom: &Rom) -> Self {
+        let crc32 = Crc32::new()
+            .update(&rom.prg_rom)
+            .update(&rom.chr_rom)
+            .finish();
+
+        let mut sha1 = Sha1::new();
+        sha1.update(&rom.prg_rom);
+        sha1.update(&rom.chr_rom);
+
+        RomHashes {
+            crc32,
+            sha1: to_hex(&sha1.finish()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RomDbEntry {
+    pub crc32: u32,
+    pub sha1: String,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Option<Mirroring>,
+    pub region: Region,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub battery: bool,
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HeaderField {
+    Mapper,
+    Submapper,
+    Mirroring,
+    Region,
+    PrgRamSize,
+    PrgNvramSize,
+    ChrRamSize,
+    ChrNvramSize,
+    Battery,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HeaderCorrection {
+    pub field: HeaderField,
+    pub from: String,
+    pub to: String,
+}
+
+impl fmt::Display for HeaderCorrection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {} -> {}", self.field, self.from, self.to)
+    }
+}
+
+#[derive(Default)]
+pub struct RomDatabase {
+    entries: Vec<RomDbEntry>,
+    by_sha1: HashMap<String, usize>,
+    by_crc32: HashMap<u32, usize>,
+}
+
+impl RomDatabase {
+    /// The database shipped with the crate.
+    pub fn bundled() -> Self {
+        return RomDatabase::parse(BUNDLED_DATABASE)
+            .unwrap_or_else(|e| panic!("bundled rom database is invalid: {}", e));
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut db = RomDatabase::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_entry(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            db.insert(entry);
+        }
+
+        return Ok(db);
+    }
+
+    /// Imports the NES 2.0 XML database (`nes20db.xml`): one `<game>` element per dump, with
+    /// the hashes of PRG + CHR in `<rom>`, the board in `<pcb>`, RAM sizes in `<prgram>`,
+    /// `<prgnvram>`, `<chrram>` and `<chrnvram>`, the region in `<console>` and the file name in
+    /// the comment before it. Vs. System and PlayChoice dumps are left out.
+    pub fn from_nes20db(xml: &str) -> Result<Self, String> {
+        let mut db = RomDatabase::default();
+        let mut rest = xml;
+        let mut name = "";
+
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment.find("-->").ok_or("unterminated comment")?;
+                name = comment[..end].trim();
+                rest = &comment[end + 3..];
+                continue;
+            }
+            if !rest.starts_with("<game>") {
+                rest = &rest[1..];
+                continue;
+            }
+            let end = rest.find("</game>").ok_or("unterminated <game> element")?;
+            let game = &rest[..end];
+            rest = &rest[end..];
+
+            // the file name comment is inside the element in newer releases
+            let name = xml_comment(game).unwrap_or(name);
+            let file = name.rsplit(['\\', '/']).next().unwrap_or(name);
+            let name = file.strip_suffix(".nes").unwrap_or(file);
+            if let Some(entry) =
+                nes20db_entry(game, name).map_err(|e| format!("{}: {}", name, e))?
+            {
+                db.insert(entry);
+            }
+        }
+
+        return Ok(db);
+    }
+
+    /// The database in the format of `rom_db.txt`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            let mirroring = match entry.mirroring {
+                Some(Mirroring::Horizontal) => "H",
+                Some(Mirroring::Vertical) => "V",
+                Some(Mirroring::FourScreen) => "4",
+                _ => "-",
+            };
+            let region = match entry.region {
+                Region::Ntsc => "NTSC",
+                Region::Pal => "PAL",
+                Region::Multi => "MULTI",
+                Region::Dendy => "DENDY",
+            };
+            writeln!(
+                text,
+                "{:08X};{};{};{};{};{};{};{};{};{};{};{}",
+                entry.crc32,
+                entry.sha1,
+                entry.mapper,
+                entry.submapper,
+                mirroring,
+                region,
+                entry.prg_ram_size,
+                entry.prg_nvram_size,
+                entry.chr_ram_size,
+                entry.chr_nvram_size,
+                entry.battery as u8,
+                entry.name.replace(';', ",")
+            )
+            .unwrap();
+        }
+        return text;
+    }
+
+    pub fn insert(&mut self, entry: RomDbEntry) {
+        let index = self.entries.len();
+        self.by_sha1.insert(entry.sha1.clone(), index);
+        self.by_crc32.insert(entry.crc32, index);
+        self.entries.push(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    /// Looks up by SHA-1 first, falling back to CRC32.
+    pub fn lookup(&self, hashes: &RomHashes) -> Option<&RomDbEntry> {
+        return self
+            .by_sha1
+            .get(&hashes.sha1)
+            .or_else(|| self.by_crc32.get(&hashes.crc32))
+            .map(|i| &self.entries[*i]);
+    }
+
+    /// Overrides the header fields of `rom` with its database entry, if any,
+    /// and returns the fields that were changed.
+    pub fn correct_header(&self, rom: &mut Rom) -> Option<Vec<HeaderCorrection>> {
+        let entry = self.lookup(&RomHashes::new(rom))?;
+        return Some(entry.apply(rom));
+    }
+}
+
+impl RomDbEntry {
+    pub fn apply(&self, rom: &mut Rom) -> Vec<HeaderCorrection> {
+        let mut corrections = vec![];
+
+        fn correct<T: PartialEq + fmt::Debug>(
+            corrections: &mut Vec<HeaderCorrection>,
+            field: HeaderField,
+            current: &mut T,
+            value: T,
+        ) {
+            if *current != value {
+                corrections.push(HeaderCorrection {
+                    field,
+                    from: format!("{:?}", current),
+                    to: format!("{:?}", value),
+                });
+                *current = value;
+            }
+        }
+
+        use HeaderField::*;
+        correct(&mut corrections, Mapper, &mut rom.mapper, self.mapper);
+        correct(
+            &mut corrections,
+            Submapper,
+            &mut rom.submapper,
+            self.submapper,
+        );
+        if let Some(mirroring) = self.mirroring {
+            correct(&mut corrections, Mirroring, &mut rom.mirroring, mirroring);
+        }
+        correct(&mut corrections, Region, &mut rom.region, self.region);
+        correct(
+            &mut corrections,
+            PrgRamSize,
+            &mut rom.prg_ram_size,
+            self.prg_ram_size,
+        );
+        correct(
+            &mut corrections,
+            PrgNvramSize,
+            &mut rom.prg_nvram_size,
+            self.prg_nvram_size,
+        );
+        correct(
+            &mut corrections,
+            ChrRamSize,
+            &mut rom.chr_ram_size,
+            self.chr_ram_size,
+        );
+        correct(
+            &mut corrections,
+            ChrNvramSize,
+            &mut rom.chr_nvram_size,
+            self.chr_nvram_size,
+        );
+        correct(&mut corrections, Battery, &mut rom.battery, self.battery);
+
+        return corrections;
+    }
+}
+
+/// The database file `text` with its entries replaced by the ones imported from nes20db.xml,
+/// keeping the comments at its top that document the format.
+pub fn reimport(text: &str, xml: &str) -> Result<String, String> {
+    let imported = RomDatabase::from_nes20db(xml)?;
+    let mut result: String = text
+        .lines()
+        .take_while(|line| line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    result.push_str(&imported.to_text());
+    return Ok(result);
+}
+
+fn parse_entry(line: &str) -> Result<RomDbEntry, String> {
+    let fields: Vec<&str> = line.split(';').map(|f| f.trim()).collect();
+    if fields.len() != FIELD_COUNT {
+        return Err(format!(
+            "expected {} fields, found {}",
+            FIELD_COUNT,
+            fields.len()
+        ));
+    }
+
+    let number = |i: usize| -> Result<usize, String> {
+        return fields[i]
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number: {}", fields[i]));
+    };
+
+    let crc32 =
+        u32::from_str_radix(fields[0], 16).map_err(|_| format!("invalid crc32: {}", fields[0]))?;
+    let sha1 = fields[1].to_ascii_lowercase();
+    if sha1.len() != 40 || !sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid sha1: {}", fields[1]));
+    }
+
+    let mirroring = match fields[4] {
+        "H" => Some(Mirroring::Horizontal),
+        "V" => Some(Mirroring::Vertical),
+        "4" => Some(Mirroring::FourScreen),
+        "-" => None,
+        m => return Err(format!("invalid mirroring: {}", m)),
+    };
+    let region = match fields[5] {
+        "NTSC" => Region::Ntsc,
+        "PAL" => Region::Pal,
+        "MULTI" => Region::Multi,
+        "DENDY" => Region::Dendy,
+        r => return Err(format!("invalid region: {}", r)),
+    };
+
+    return Ok(RomDbEntry {
+        crc32,
+        sha1,
+        mapper: u16::try_from(number(2)?).map_err(|_| "mapper out of range")?,
+        submapper: u8::try_from(number(3)?).map_err(|_| "submapper out of range")?,
+        mirroring,
+        region,
+        prg_ram_size: number(6)?,
+        prg_nvram_size: number(7)?,
+        chr_ram_size: number(8)?,
+        chr_nvram_size: number(9)?,
+        battery: number(10)? != 0,
+        name: fields[11].to_string(),
+    });
+}
+
+/// One `<game>` of the NES 2.0 XML database, None for consoles other than the NES/Famicom.
+fn nes20db_entry(game: &str, name: &str) -> Result<Option<RomDbEntry>, String> {
+    let console_type = xml_attribute(game, "console", "type").unwrap_or("0");
+    if console_type != "0" {
+        return Ok(None);
+    }
+
+    let number = |element: &str, attribute: &str| -> Result<usize, String> {
+        return match xml_attribute(game, element, attribute) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("invalid {} {}: {}", element, attribute, value)),
+            None => Ok(0),
+        };
+    };
+    let hash = |attribute: &str| -> Result<&str, String> {
+        return xml_attribute(game, "rom", attribute)
+            .ok_or_else(|| format!("<rom> has no {}", attribute));
+    };
+
+    let mirroring = match xml_attribute(game, "pcb", "mirroring") {
+        Some("H") => Some(Mirroring::Horizontal),
+        Some("V") => Some(Mirroring::Vertical),
+        Some("4") => Some(Mirroring::FourScreen),
+        _ => None,
+    };
+    let region = match xml_attribute(game, "console", "region") {
+        Some("1") => Region::Pal,
+        Some("2") => Region::Multi,
+        Some("3") => Region::Dendy,
+        _ => Region::Ntsc,
+    };
+
+    let line = format!(
+        "{};{};{};{};-;NTSC;{};{};{};{};{};{}",
+        hash("crc32")?,
+        hash("sha1")?,
+        number("pcb", "mapper")?,
+        number("pcb", "submapper")?,
+        number("prgram", "size")?,
+        number("prgnvram", "size")?,
+        number("chrram", "size")?,
+        number("chrnvram", "size")?,
+        number("pcb", "battery")?,
+        name.replace(';', ",")
+    );
+    let entry = parse_entry(&line)?;
+    return Ok(Some(RomDbEntry {
+        mirroring,
+        region,
+        ..entry
+    }));
+}
+
+fn xml_comment(xml: &str) -> Option<&str> {
+    let start = xml.find("<!--")? + 4;
+    let end = start + xml[start..].find("-->")?;
+    return Some(xml[start..end].trim());
+}
+
+/// The value of `attribute` on the first `<element .../>` in `xml`.
+fn xml_attribute<'a>(xml: &'a str, element: &str, attribute: &str) -> Option<&'a str> {
+    let open = format!("<{} ", element);
+    let start = xml.find(&open)? + open.len();
+    let tag = &xml[start..start + xml[start..].find('>')?];
+    let key = format!("{}=\"", attribute);
+    let value = tag
+        .split_whitespace()
+        .find_map(|pair| pair.strip_prefix(key.as_str()))?;
+    return value.split('"').next();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test::{create_rom, TestRom};
+    use crate::cartridge::{CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+
+    fn bad_header_rom() -> Rom {
+        let mut header = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x40];
+        header.extend(b"DiskDude");
+
+        return Rom::new(&create_rom(TestRom {
+            header,
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        }))
+        .unwrap();
+    }
+
+    fn database_for(rom: &Rom) -> RomDatabase {
+        let hashes = RomHashes::new(rom);
+        let text = format!(
+            "# test database\n{:08X};{};4;1;V;PAL;0;8192;0;0;1;Test Game\n",
+            hashes.crc32, hashes.sha1
+        );
+        return RomDatabase::parse(&text).unwrap();
+    }
+
+    #[test]
+    fn test_rom_hashes_ignore_header() {
+        let rom = bad_header_rom();
+        let mut concatenated = rom.prg_rom.clone();
+        concatenated.extend(&rom.chr_rom);
+
+        let hashes = RomHashes::new(&rom);
+
+        assert_eq!(hashes.crc32, crate::hash::crc32(&concatenated));
+        assert_eq!(hashes.sha1, to_hex(&crate::hash::sha1(&concatenated)));
+    }
+
+    #[test]
+    fn test_bundled_database_parses() {
+        RomDatabase::bundled();
+    }
+
+    #[test]
+    fn test_correct_header() {
+        let mut rom = bad_header_rom();
+        let db = database_for(&rom);
+
+        let corrections = db.correct_header(&mut rom).unwrap();
+
+        assert_eq!(rom.mapper, 4);
+        assert_eq!(rom.submapper, 1);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert_eq!(rom.region, Region::Pal);
+        assert!(rom.battery);
+        assert_eq!(
+            corrections.iter().map(|c| c.field).collect::<Vec<_>>(),
+            vec![
+                HeaderField::Mapper,
+                HeaderField::Submapper,
+                HeaderField::Mirroring,
+                HeaderField::Region,
+                HeaderField::PrgRamSize,
+                HeaderField::PrgNvramSize,
+                HeaderField::Battery,
+            ]
+        );
+        assert_eq!(corrections[0].to_string(), "Mapper: 0 -> 4");
+    }
+
+    #[test]
+    fn test_correct_header_is_noop_when_header_matches() {
+        let mut rom = bad_header_rom();
+        let db = database_for(&rom);
+        db.correct_header(&mut rom);
+
+        let corrections = db.correct_header(&mut rom).unwrap();
+
+        assert!(corrections.is_empty());
+    }
+
+    #[test]
+    fn test_lookup_falls_back_to_crc32() {
+        let rom = bad_header_rom();
+        let db = database_for(&rom);
+        let mut hashes = RomHashes::new(&rom);
+        hashes.sha1 = "0".repeat(40);
+
+        assert_eq!(db.lookup(&hashes).unwrap().name, "Test Game");
+    }
+
+    #[test]
+    fn test_unknown_rom() {
+        let mut rom = bad_header_rom();
+        assert!(RomDatabase::bundled().correct_header(&mut rom).is_none());
+    }
+
+    const NES20DB: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+<game>
+	<!-- NES\Licensed\Test Game (USA).nes -->
+	<prgrom size="131072" crc32="11111111" sha1="1111111111111111111111111111111111111111" sum16="0000"/>
+	<rom size="131072" crc32="1A2B3C4D" sha1="A9993E364706816ABA3E25717850C26C9CD0D89D"/>
+	<prgnvram size="8192"/>
+	<chrram size="8192"/>
+	<pcb mapper="1" submapper="0" mirroring="H" battery="1"/>
+	<console type="0" region="1"/>
+	<expansion type="1"/>
+</game>
+<game>
+	<!-- NES\Vs\Arcade Game.nes -->
+	<rom size="40960" crc32="00000001" sha1="0000000000000000000000000000000000000001"/>
+	<pcb mapper="99" submapper="0" mirroring="4" battery="0"/>
+	<console type="1" region="0"/>
+</game>
+</nes20db>
+"#;
+
+    #[test]
+    fn test_nes20db_import() {
+        let db = RomDatabase::from_nes20db(NES20DB).unwrap();
+
+        assert_eq!(db.len(), 1);
+        let hashes = RomHashes {
+            crc32: 0x1A2B3C4D,
+            sha1: "a9993e364706816aba3e25717850c26c9cd0d89d".to_string(),
+        };
+        let entry = db.lookup(&hashes).unwrap();
+        assert_eq!(entry.name, "Test Game (USA)");
+        assert_eq!(entry.mapper, 1);
+        assert_eq!(entry.mirroring, Some(Mirroring::Horizontal));
+        assert_eq!(entry.region, Region::Pal);
+        assert_eq!(entry.prg_ram_size, 0);
+        assert_eq!(entry.prg_nvram_size, 8192);
+        assert_eq!(entry.chr_ram_size, 8192);
+        assert!(entry.battery);
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let db = RomDatabase::from_nes20db(NES20DB).unwrap();
+
+        let text = db.to_text();
+
+        assert_eq!(
+            text,
+            "1A2B3C4D;a9993e364706816aba3e25717850c26c9cd0d89d;1;0;H;PAL;0;8192;8192;0;1;\
+             Test Game (USA)\n"
+        );
+        assert_eq!(RomDatabase::parse(&text).unwrap().entries, db.entries);
+    }
+
+    #[test]
+    fn test_reimport_keeps_the_comments() {
+        let text = "# format\n#\n\
+                    00000000;0000000000000000000000000000000000000000;0;0;H;NTSC;0;0;0;0;0;Old\n";
+
+        let text = reimport(text, NES20DB).unwrap();
+
+        assert_eq!(
+            text,
+            "# format\n#\n1A2B3C4D;a9993e364706816aba3e25717850c26c9cd0d89d;1;0;H;PAL;0;8192;8192;0;1;\
+             Test Game (USA)\n"
+        );
+    }
+
+    #[test]
+    fn test_nes20db_errors_name_the_game() {
+        let xml = "<!-- Broken.nes --><game><pcb mapper=\"x\"/></game>";
+
+        assert_eq!(
+            RomDatabase::from_nes20db(xml).err(),
+            Some("Broken: <rom> has no crc32".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_database_line() {
+        let result = RomDatabase::parse("00000000;abc;0;0;H;NTSC;0;0;0;0;0;Bad");
+        assert_eq!(result.err(), Some("line 1: invalid sha1: abc".to_string()));
+    }
+}
@@ -0,0 +1,390 @@
+//! Save states: a snapshot of the whole machine in a versioned, self-describing format.
+//!
+//!  +--------+---------+-------+-------+-----
+//!  | "NESS" | version | chunk | chunk | ...
+//!  +--------+---------+-------+-------+-----
+//!    4        u16 LE
+//!
+//!  chunk: 4-byte tag, u32 LE payload length, payload
+//!
+//! Every component writes its fields in a fixed order into its own chunk. Fields are only ever
+//! appended, so a state written before a field existed still loads (the field keeps its current
+//! value) and a newer build's extra fields are skipped. Changes that cannot be expressed that
+//! way bump `VERSION`, and states outside `MIN_VERSION..=VERSION` are rejected.
+
+use crate::cartridge::Mirroring;
+
+pub const MAGIC: [u8; 4] = *b"NESS";
+pub const VERSION: u16 = 1;
+/// Oldest format version this build still reads.
+pub const MIN_VERSION: u16 = 1;
+const HEADER_SIZE: usize = 6;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+pub type Tag = [u8; 4];
+
+/// A component whose state goes into a save state.
+pub trait SaveState {
+    fn save(&self, w: &mut StateWriter);
+
+    /// Restores what `save` wrote. Fields missing at the end of the chunk, written by an older
+    /// build, keep their current values.
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String>;
+}
+
+/// Serializes fields little-endian, in the order they are written.
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    /// A byte block whose length the reader knows, like a fixed-size RAM.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+    }
+
+    /// A byte block prefixed with its length.
+    pub fn vec(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub fn mirroring(&mut self, mirroring: Mirroring) {
+        self.u8(match mirroring {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 1,
+            Mirroring::FourScreen => 2,
+            Mirroring::SingleScreenLower => 3,
+            Mirroring::SingleScreenUpper => 4,
+        });
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.data;
+    }
+}
+
+/// Reads back a chunk written by a `StateWriter`, failing with the chunk's name when it runs
+/// out of data or holds an impossible value.
+pub struct StateReader<'a> {
+    tag: Tag,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(tag: Tag, data: &'a [u8]) -> Self {
+        StateReader {
+            tag,
+            data,
+            position: 0,
+        }
+    }
+
+    /// True once every field was read: anything after is from a newer build.
+    pub fn is_empty(&self) -> bool {
+        return self.position >= self.data.len();
+    }
+
+    pub fn error(&self, message: &str) -> String {
+        return format!("{} chunk: {}", tag_name(self.tag), message);
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| self.error("truncated"))?;
+        self.position += len;
+        return Ok(bytes);
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        return Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()));
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        return Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()));
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        return Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()));
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        return match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(self.error(&format!("invalid boolean {}", value))),
+        };
+    }
+
+    /// Fills `out`, which must have the length that was written.
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), String> {
+        out.copy_from_slice(self.take(out.len())?);
+        return Ok(());
+    }
+
+    /// Reads a length-prefixed block into `out`, which must already have the written length:
+    /// RAM sizes come from the ROM, so a mismatch means the state is for another board.
+    pub fn vec_into(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let len = self.u32()? as usize;
+        if len != out.len() {
+            return Err(self.error(&format!(
+                "expected {} bytes of memory, found {}",
+                out.len(),
+                len
+            )));
+        }
+        return self.bytes(out);
+    }
+
+    pub fn mirroring(&mut self) -> Result<Mirroring, String> {
+        return match self.u8()? {
+            0 => Ok(Mirroring::Horizontal),
+            1 => Ok(Mirroring::Vertical),
+            2 => Ok(Mirroring::FourScreen),
+            3 => Ok(Mirroring::SingleScreenLower),
+            4 => Ok(Mirroring::SingleScreenUpper),
+            value => Err(self.error(&format!("invalid mirroring {}", value))),
+        };
+    }
+}
+
+pub fn tag_name(tag: Tag) -> String {
+    return String::from_utf8_lossy(&tag).trim_end().to_string();
+}
+
+/// Builds a save state from (tag, payload) chunks.
+pub fn encode(chunks: &[(Tag, Vec<u8>)]) -> Vec<u8> {
+    let mut state = Vec::with_capacity(
+        HEADER_SIZE
+            + chunks
+                .iter()
+                .map(|(_, payload)| CHUNK_HEADER_SIZE + payload.len())
+                .sum::<usize>(),
+    );
+    state.extend(MAGIC);
+    state.extend(VERSION.to_le_bytes());
+    for (tag, payload) in chunks {
+        state.extend(tag);
+        state.extend((payload.len() as u32).to_le_bytes());
+        state.extend(payload);
+    }
+    return state;
+}
+
+/// Splits a save state into its chunks, checking the header and the framing. Zero padding
+/// after the last chunk is ignored.
+pub fn decode(state: &[u8]) -> Result<Vec<(Tag, &[u8])>, String> {
+    if state.len() < HEADER_SIZE || state[0..4] != MAGIC {
+        return Err("not a save state".to_string());
+    }
+    let version = u16::from_le_bytes([state[4], state[5]]);
+    if version > VERSION {
+        return Err(format!(
+            "save state format version {} is newer than this build supports ({})",
+            version, VERSION
+        ));
+    }
+    if version < MIN_VERSION {
+        return Err(format!(
+            "save state format version {} is no longer supported (oldest is {})",
+            version, MIN_VERSION
+        ));
+    }
+
+    let mut chunks = vec![];
+    let mut offset = HEADER_SIZE;
+    while offset < state.len() {
+        // frontends hand fixed-size buffers back, zero-filled after the last chunk
+        if state[offset..].iter().all(|&byte| byte == 0) {
+            break;
+        }
+        let header = state
+            .get(offset..offset + CHUNK_HEADER_SIZE)
+            .ok_or("save state is truncated: incomplete chunk header".to_string())?;
+        let tag: Tag = header[0..4].try_into().unwrap();
+        let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let start = offset + CHUNK_HEADER_SIZE;
+        let payload = state
+            .get(start..start.saturating_add(len))
+            .ok_or_else(|| format!("save state is truncated in the {} chunk", tag_name(tag)))?;
+        chunks.push((tag, payload));
+        offset = start + len;
+    }
+    return Ok(chunks);
+}
+
+/// Looks a chunk up by tag.
+pub fn find_chunk<'a>(chunks: &[(Tag, &'a [u8])], tag: Tag) -> Result<StateReader<'a>, String> {
+    return chunks
+        .iter()
+        .find(|(chunk_tag, _)| *chunk_tag == tag)
+        .map(|(_, payload)| StateReader::new(tag, payload))
+        .ok_or_else(|| format!("save state has no {} chunk", tag_name(tag)));
+}
+
+/// Saves `component` as the payload of a chunk.
+pub fn save_chunk(tag: Tag, component: &dyn SaveState) -> (Tag, Vec<u8>) {
+    let mut w = StateWriter::new();
+    component.save(&mut w);
+    return (tag, w.into_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Counter {
+        count: u16,
+        /// A field added after the first version of the format.
+        running: bool,
+    }
+
+    impl SaveState for Counter {
+        fn save(&self, w: &mut StateWriter) {
+            w.u16(self.count);
+            w.bool(self.running);
+        }
+
+        fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+            self.count = r.u16()?;
+            if !r.is_empty() {
+                self.running = r.bool()?;
+            }
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let counter = Counter {
+            count: 0x1234,
+            running: true,
+        };
+        let state = encode(&[save_chunk(*b"CNT ", &counter)]);
+
+        assert_eq!(&state[0..6], b"NESS\x01\x00");
+        let chunks = decode(&state).unwrap();
+        let mut loaded = Counter::default();
+        loaded
+            .load(&mut find_chunk(&chunks, *b"CNT ").unwrap())
+            .unwrap();
+        assert_eq!(loaded, counter);
+    }
+
+    #[test]
+    fn test_fields_missing_from_old_states_keep_their_value() {
+        let state = encode(&[(*b"CNT ", vec![0x05, 0x00])]);
+
+        let chunks = decode(&state).unwrap();
+        let mut counter = Counter {
+            count: 0,
+            running: true,
+        };
+        counter
+            .load(&mut find_chunk(&chunks, *b"CNT ").unwrap())
+            .unwrap();
+
+        assert_eq!(counter.count, 5);
+        assert!(counter.running);
+    }
+
+    #[test]
+    fn test_unknown_chunks_and_trailing_fields_are_skipped() {
+        let state = encode(&[
+            (*b"NEW ", vec![1, 2, 3]),
+            (*b"CNT ", vec![0x05, 0x00, 0x01, 0xAA, 0xBB]),
+        ]);
+
+        let chunks = decode(&state).unwrap();
+        let mut counter = Counter::default();
+        counter
+            .load(&mut find_chunk(&chunks, *b"CNT ").unwrap())
+            .unwrap();
+
+        assert_eq!(counter.count, 5);
+        assert!(counter.running);
+    }
+
+    #[test]
+    fn test_zero_padding_is_ignored() {
+        let mut state = encode(&[(*b"CNT ", vec![0x05, 0x00])]);
+        state.extend([0; 13]);
+
+        let chunks = decode(&state).unwrap();
+
+        assert_eq!(chunks, vec![(*b"CNT ", &[0x05, 0x00][..])]);
+    }
+
+    #[test]
+    fn test_rejections() {
+        assert_eq!(decode(b"PNG").unwrap_err(), "not a save state");
+        assert_eq!(
+            decode(b"NESS\x09\x00").unwrap_err(),
+            "save state format version 9 is newer than this build supports (1)"
+        );
+        assert_eq!(
+            decode(b"NESS\x00\x00").unwrap_err(),
+            "save state format version 0 is no longer supported (oldest is 1)"
+        );
+
+        let mut state = encode(&[(*b"CPU ", vec![0; 10])]);
+        state.truncate(state.len() - 1);
+        assert_eq!(
+            decode(&state).unwrap_err(),
+            "save state is truncated in the CPU chunk"
+        );
+
+        let chunks = decode(b"NESS\x01\x00").unwrap();
+        assert_eq!(
+            find_chunk(&chunks, *b"PPU ").err().unwrap(),
+            "save state has no PPU chunk"
+        );
+    }
+
+    #[test]
+    fn test_reader_errors_name_the_chunk() {
+        let mut r = StateReader::new(*b"APU ", &[2]);
+        assert_eq!(r.bool().unwrap_err(), "APU chunk: invalid boolean 2");
+        assert_eq!(r.u8().unwrap_err(), "APU chunk: truncated");
+
+        let mut r = StateReader::new(*b"CART", &[4, 0, 0, 0, 1, 2, 3, 4]);
+        let mut ram = [0; 2];
+        assert_eq!(
+            r.vec_into(&mut ram).unwrap_err(),
+            "CART chunk: expected 2 bytes of memory, found 4"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! The terminal-independent parts of the `nes-term` frontend: drawing a frame with truecolor
+//! ANSI half-blocks, emulating held buttons from key presses, and frame pacing.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::controller::Buttons;
+use crate::png::Image;
+
+/// Upper half block: the foreground colour paints the top pixel, the background the bottom.
+const HALF_BLOCK: char = '\u{2580}';
+
+/// Largest picture that fits `columns` x `rows` cells keeping the source's aspect ratio, with
+/// one pixel per column and two per row. Never larger than the source.
+pub fn fit(
+    source_width: usize,
+    source_height: usize,
+    columns: usize,
+    rows: usize,
+) -> (usize, usize) {
+    let scale = (columns as f64 / source_width as f64)
+        .min(rows as f64 * 2.0 / source_height as f64)
+        .min(1.0);
+    let width = ((source_width as f64 * scale) as usize).max(1);
+    let height = ((source_height as f64 * scale) as usize).max(1);
+    return (width, height);
+}
+
+/// Box-filters `image` down to `width` x `height`: each pixel averages the source pixels it
+/// covers, so thin lines fade rather than vanish.
+pub fn downscale(image: &Image, width: usize, height: usize) -> Image {
+    let mut scaled = Image::new(width, height);
+    for y in 0..height {
+        let (top, bottom) = span(y, height, image.height);
+        for x in 0..width {
+            let (left, right) = span(x, width, image.width);
+            let mut sum = [0u32; 3];
+            for source_y in top..bottom {
+                for source_x in left..right {
+                    let i = (source_y * image.width + source_x) * 4;
+                    for (channel, total) in sum.iter_mut().enumerate() {
+                        *total += image.rgba[i + channel] as u32;
+                    }
+                }
+            }
+            let count = ((bottom - top) * (right - left)) as u32;
+            let i = (y * width + x) * 4;
+            for (channel, total) in sum.iter().enumerate() {
+                scaled.rgba[i + channel] = (total / count) as u8;
+            }
+            scaled.rgba[i + 3] = 0xFF;
+        }
+    }
+    return scaled;
+}
+
+/// Source pixels covered by pixel `index` of `scaled` pixels, at least one.
+fn span(index: usize, scaled: usize, source: usize) -> (usize, usize) {
+    let start = index * source / scaled;
+    let end = ((index + 1) * source / scaled).max(start + 1);
+    return (start, end);
+}
+
+/// ANSI escapes drawing `image` from the cursor's home position, two rows of pixels per line.
+/// Colour changes are only emitted when a cell differs from the previous one. The last line
+/// has no line break, so a picture as tall as the terminal does not scroll it.
+pub fn render(image: &Image) -> String {
+    let mut out = String::with_capacity(image.width * image.height * 12);
+    out.push_str("\x1b[H");
+    for y in (0..image.height).step_by(2) {
+        if y > 0 {
+            // raw mode does not turn \n into \r\n
+            out.push_str("\r\n");
+        }
+        let mut colors = None;
+        for x in 0..image.width {
+            let top = image.get_pixel(x, y);
+            let bottom = if y + 1 < image.height {
+                image.get_pixel(x, y + 1)
+            } else {
+                Default::default()
+            };
+            if colors != Some((top, bottom)) {
+                write!(
+                    out,
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                    top.r, top.g, top.b, bottom.r, bottom.g, bottom.b
+                )
+                .unwrap();
+                colors = Some((top, bottom));
+            }
+            out.push(HALF_BLOCK);
+        }
+        out.push_str("\x1b[0m\x1b[K");
+    }
+    // clear whatever a bigger picture left below
+    out.push_str("\x1b[J");
+    return out;
+}
+
+/// Terminals report key presses and auto-repeats but usually not releases, so a pressed button
+/// is held for `timeout` frames, renewed by each repeat. When the terminal does report
+/// releases, buttons are held until released.
+pub struct HeldButtons {
+    timeout: Option<u32>,
+    /// Frames left for each button, by bit.
+    frames_left: [u32; 8],
+}
+
+impl HeldButtons {
+    pub fn new(timeout: Option<u32>) -> Self {
+        HeldButtons {
+            timeout,
+            frames_left: [0; 8],
+        }
+    }
+
+    pub fn press(&mut self, buttons: Buttons) {
+        let frames = self.timeout.unwrap_or(u32::MAX);
+        for (bit, left) in self.frames_left.iter_mut().enumerate() {
+            if buttons.bits() & (1 << bit) != 0 {
+                *left = frames;
+            }
+        }
+    }
+
+    pub fn release(&mut self, buttons: Buttons) {
+        for (bit, left) in self.frames_left.iter_mut().enumerate() {
+            if buttons.bits() & (1 << bit) != 0 {
+                *left = 0;
+            }
+        }
+    }
+
+    /// The buttons held during the next frame.
+    pub fn next_frame(&mut self) -> Buttons {
+        let mut held = Buttons::empty();
+        for (bit, left) in self.frames_left.iter_mut().enumerate() {
+            if *left > 0 {
+                held |= Buttons::from_bits_retain(1 << bit);
+                if *left != u32::MAX {
+                    *left -= 1;
+                }
+            }
+        }
+        return held;
+    }
+}
+
+/// Paces frames to a fixed rate. Falling more than a frame behind (a slow terminal, a
+/// suspended process) restarts the schedule instead of running fast to catch up.
+pub struct FrameLimiter {
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl FrameLimiter {
+    pub fn new(frames_per_second: f64) -> Self {
+        FrameLimiter {
+            period: Duration::from_secs_f64(1.0 / frames_per_second),
+            next: None,
+        }
+    }
+
+    /// How long to wait at `now` before starting the next frame.
+    pub fn delay(&mut self, now: Instant) -> Duration {
+        let next = match self.next {
+            Some(next) if next + self.period >= now => next,
+            _ => now,
+        };
+        self.next = Some(next + self.period);
+        return next.saturating_duration_since(now);
+    }
+
+    pub fn wait(&mut self) {
+        std::thread::sleep(self.delay(Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Rgb;
+
+    #[test]
+    fn test_fit_keeps_aspect_and_never_upscales() {
+        assert_eq!(fit(256, 240, 300, 200), (256, 240));
+        assert_eq!(fit(256, 240, 128, 200), (128, 120));
+        assert_eq!(fit(256, 240, 200, 30), (64, 60));
+    }
+
+    #[test]
+    fn test_downscale_averages() {
+        let mut image = Image::new(4, 2);
+        image.set_pixel(0, 0, Rgb::new(200, 0, 0));
+        image.set_pixel(1, 1, Rgb::new(200, 100, 0));
+
+        let scaled = downscale(&image, 2, 1);
+
+        assert_eq!(scaled.get_pixel(0, 0), Rgb::new(100, 25, 0));
+        assert_eq!(scaled.get_pixel(1, 0), Rgb::new(0, 0, 0));
+    }
+
+    #[test]
+    fn test_render_uses_half_blocks() {
+        let mut image = Image::new(2, 3);
+        image.set_pixel(0, 0, Rgb::new(1, 2, 3));
+        image.set_pixel(1, 0, Rgb::new(1, 2, 3));
+        image.set_pixel(0, 1, Rgb::new(4, 5, 6));
+        image.set_pixel(1, 1, Rgb::new(4, 5, 6));
+
+        let out = render(&image);
+        let lines: Vec<&str> = out.split("\r\n").collect();
+
+        // two lines of cells, the odd last row padded with black, and no break after the last
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "\x1b[H\x1b[38;2;1;2;3m\x1b[48;2;4;5;6m\u{2580}\u{2580}\x1b[0m\x1b[K"
+        );
+        assert_eq!(lines[1].matches(HALF_BLOCK).count(), 2);
+        assert!(lines[1].ends_with("\x1b[0m\x1b[K\x1b[J"));
+    }
+
+    #[test]
+    fn test_held_buttons_time_out() {
+        let mut held = HeldButtons::new(Some(2));
+        held.press(Buttons::A | Buttons::Right);
+
+        assert_eq!(held.next_frame(), Buttons::A | Buttons::Right);
+        held.press(Buttons::A);
+        assert_eq!(held.next_frame(), Buttons::A | Buttons::Right);
+        assert_eq!(held.next_frame(), Buttons::A);
+        assert_eq!(held.next_frame(), Buttons::empty());
+    }
+
+    #[test]
+    fn test_held_buttons_until_released() {
+        let mut held = HeldButtons::new(None);
+        held.press(Buttons::Start);
+
+        for _ in 0..100 {
+            assert_eq!(held.next_frame(), Buttons::Start);
+        }
+        held.release(Buttons::Start);
+        assert_eq!(held.next_frame(), Buttons::empty());
+    }
+
+    #[test]
+    fn test_frame_limiter_schedule() {
+        let mut limiter = FrameLimiter::new(50.0);
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        assert_eq!(limiter.delay(start), ms(0));
+        // the frame took 5ms
+        assert_eq!(limiter.delay(start + ms(5)), ms(15));
+        assert_eq!(limiter.delay(start + ms(40)), ms(0));
+        // far behind: start over rather than rush
+        assert_eq!(limiter.delay(start + ms(200)), ms(0));
+        assert_eq!(limiter.delay(start + ms(201)), ms(19));
+    }
+}
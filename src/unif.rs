@@ -0,0 +1,334 @@
+use crate::cartridge::{Mirroring, Region, Rom, RomFormat, CHR_ROM_PAGE_SIZE};
+
+const UNIF_TAG: [u8; 4] = *b"UNIF";
+const HEADER_SIZE: usize = 32;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+/// Chunks that carry information we do not use but are part of the spec.
+const INFORMATIONAL_CHUNKS: [&str; 5] = ["NAME", "READ", "DINF", "WRTR", "CTRL"];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownChunk {
+    pub id: String,
+    pub offset: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnifRom {
+    pub rom: Rom,
+    pub board: String,
+    pub name: Option<String>,
+    pub unknown_chunks: Vec<UnknownChunk>,
+}
+
+pub fn is_unif(raw: &[u8]) -> bool {
+    return raw.len() >= 4 && raw[0..4] == UNIF_TAG;
+}
+
+/// Parses a UNIF image and resolves its `MAPR` board name to an iNES mapper number,
+/// so that it runs on the same mapper implementations as iNES dumps. Only the licensed NROM,
+/// UxROM, CNROM and AxROM boards are supported so far: unlicensed (`UNL-`, `BTL-`) and
+/// multicart (`BMC-`) boards are rejected with an error that says so.
+pub fn parse(raw: &[u8]) -> Result<UnifRom, String> {
+    if !is_unif(raw) || raw.len() < HEADER_SIZE {
+        return Err("file is not in UNIF file format".to_string());
+    }
+
+    let mut board = None;
+    let mut name = None;
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = None;
+    let mut battery = false;
+    let mut region = Region::Ntsc;
+    let mut unknown_chunks = vec![];
+
+    let mut offset = HEADER_SIZE;
+    while offset < raw.len() {
+        if raw.len() - offset < CHUNK_HEADER_SIZE {
+            return Err(format!("truncated chunk header at offset {:#X}", offset));
+        }
+
+        let id = String::from_utf8_lossy(&raw[offset..offset + 4]).into_owned();
+        let len = u32::from_le_bytes(raw[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let data_start = offset + CHUNK_HEADER_SIZE;
+        let data = raw
+            .get(data_start..data_start.saturating_add(len))
+            .ok_or_else(|| format!("chunk {} at offset {:#X} is truncated", id, offset))?;
+
+        if let Some((kind, index)) = rom_chunk(&id) {
+            match kind {
+                "PRG" => prg_chunks[index] = Some(data),
+                "CHR" => chr_chunks[index] = Some(data),
+                // PCKx / CCKx hold the CRC32 of the matching rom chunk
+                _ => {}
+            }
+        } else {
+            match id.as_str() {
+                "MAPR" => board = Some(read_string(data)),
+                "NAME" => name = Some(read_string(data)),
+                "MIRR" => mirroring = data.first().map(|m| parse_mirroring(*m)).transpose()?,
+                "BATR" => battery = data.first().is_none_or(|b| *b != 0),
+                "TVCI" => region = parse_region(data.first().copied().unwrap_or(0)),
+                _ if INFORMATIONAL_CHUNKS.contains(&id.as_str()) => {}
+                _ => unknown_chunks.push(UnknownChunk {
+                    id: id.clone(),
+                    offset,
+                    len,
+                }),
+            }
+        }
+
+        offset = data_start + len;
+    }
+
+    let board = board.ok_or_else(|| "UNIF file has no MAPR chunk".to_string())?;
+    let mapper = mapper_for_board(&board).ok_or_else(|| unsupported_board(&board))?;
+
+    let prg_rom: Vec<u8> = prg_chunks
+        .iter()
+        .flatten()
+        .flat_map(|c| c.iter())
+        .copied()
+        .collect();
+    let chr_rom: Vec<u8> = chr_chunks
+        .iter()
+        .flatten()
+        .flat_map(|c| c.iter())
+        .copied()
+        .collect();
+    if prg_rom.is_empty() {
+        return Err("UNIF file has no PRG chunk".to_string());
+    }
+    let chr_ram_size = if chr_rom.is_empty() {
+        CHR_ROM_PAGE_SIZE
+    } else {
+        0
+    };
+
+    return Ok(UnifRom {
+        rom: Rom {
+            format: RomFormat::Unif,
+            prg_rom,
+            chr_rom,
+            trainer: None,
+            mapper,
+            submapper: 0,
+            mirroring: mirroring.unwrap_or(Mirroring::Horizontal),
+            battery,
+            region,
+            prg_ram_size: if battery { 0 } else { 8 * 1024 },
+            prg_nvram_size: if battery { 8 * 1024 } else { 0 },
+            chr_ram_size,
+            chr_nvram_size: 0,
+        },
+        board,
+        name,
+        unknown_chunks,
+    });
+}
+
+/// Maps a UNIF board name (ie.: "NES-UNROM") to its iNES mapper number, for the licensed
+/// boards `mapper::new_mapper` can build. `UNL-`, `BTL-` and `BMC-` boards are unlicensed
+/// and multicart designs, none of which has a mapper yet.
+pub fn mapper_for_board(board: &str) -> Option<u16> {
+    let name = ["NES-", "HVC-"]
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))?;
+
+    let mapper = match name {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => 0,
+        "UNROM" | "UOROM" => 2,
+        "CNROM" => 3,
+        "AMROM" | "ANROM" | "AN1ROM" | "AOROM" => 7,
+        _ => return None,
+    };
+    return Some(mapper);
+}
+
+fn unsupported_board(board: &str) -> String {
+    if ["UNL-", "BTL-", "BMC-"]
+        .iter()
+        .any(|prefix| board.starts_with(prefix))
+    {
+        return format!(
+            "UNIF board {} is not supported: there are no mappers for unlicensed and multicart \
+             boards yet",
+            board
+        );
+    }
+    return format!("UNIF board {} is not supported", board);
+}
+
+/// Splits "PRG3" into ("PRG", 3), also recognises the PCKx / CCKx checksum chunks.
+fn rom_chunk(id: &str) -> Option<(&str, usize)> {
+    let kind = id.get(..3)?;
+    let index = id.get(3..)?;
+    if index.len() != 1 || !["PRG", "CHR", "PCK", "CCK"].contains(&kind) {
+        return None;
+    }
+    return usize::from_str_radix(index, 16).ok().map(|i| (kind, i));
+}
+
+fn parse_mirroring(value: u8) -> Result<Mirroring, String> {
+    match value {
+        0 => Ok(Mirroring::Horizontal),
+        1 => Ok(Mirroring::Vertical),
+        2 => Ok(Mirroring::SingleScreenLower),
+        3 => Ok(Mirroring::SingleScreenUpper),
+        4 => Ok(Mirroring::FourScreen),
+        // mirroring is controlled by the mapper, which resets to horizontal
+        5 => Ok(Mirroring::Horizontal),
+        _ => Err(format!("invalid UNIF mirroring value: {}", value)),
+    }
+}
+
+fn parse_region(value: u8) -> Region {
+    match value {
+        1 => Region::Pal,
+        2 => Region::Multi,
+        _ => Region::Ntsc,
+    }
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    return String::from_utf8_lossy(&data[..end]).into_owned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::new_cartridge;
+
+    fn chunk(id: &str, data: &[u8]) -> Vec<u8> {
+        let mut result = id.as_bytes().to_vec();
+        result.extend((data.len() as u32).to_le_bytes());
+        result.extend(data);
+        return result;
+    }
+
+    fn unif(chunks: Vec<Vec<u8>>) -> Vec<u8> {
+        let mut result = b"UNIF".to_vec();
+        result.extend(7u32.to_le_bytes());
+        result.extend([0; 24]);
+        for c in chunks {
+            result.extend(c);
+        }
+        return result;
+    }
+
+    #[test]
+    fn test_parse_unif() {
+        let raw = unif(vec![
+            chunk("MAPR", b"NES-UNROM\0"),
+            chunk("NAME", b"Test Game\0"),
+            chunk("PRG1", &[2; 0x4000]),
+            chunk("PRG0", &[1; 0x4000]),
+            chunk("MIRR", &[1]),
+            chunk("BATR", &[1]),
+        ]);
+
+        let unif = parse(&raw).unwrap();
+
+        assert_eq!(unif.board, "NES-UNROM");
+        assert_eq!(unif.name, Some("Test Game".to_string()));
+        assert_eq!(unif.rom.format, RomFormat::Unif);
+        assert_eq!(unif.rom.mapper, 2);
+        assert_eq!(unif.rom.prg_rom.len(), 0x8000);
+        assert_eq!(unif.rom.prg_rom[0], 1);
+        assert_eq!(unif.rom.prg_rom[0x4000], 2);
+        assert!(unif.rom.chr_rom.is_empty());
+        assert_eq!(unif.rom.chr_ram_size, CHR_ROM_PAGE_SIZE);
+        assert_eq!(unif.rom.mirroring, Mirroring::Vertical);
+        assert!(unif.rom.battery);
+        assert!(unif.unknown_chunks.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_chunks_are_reported() {
+        let raw = unif(vec![
+            chunk("MAPR", b"NES-NROM-256\0"),
+            chunk("PRG0", &[0; 0x8000]),
+            chunk("CHR0", &[0; 0x2000]),
+            chunk("ABCD", &[1, 2, 3]),
+        ]);
+
+        let unif = parse(&raw).unwrap();
+
+        assert_eq!(
+            unif.unknown_chunks,
+            vec![UnknownChunk {
+                id: "ABCD".to_string(),
+                offset: 32 + 21 + 8 + 0x8000 + 8 + 0x2000,
+                len: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_board() {
+        let raw = unif(vec![
+            chunk("MAPR", b"UNL-SOMETHING\0"),
+            chunk("PRG0", &[0; 16]),
+        ]);
+        assert_eq!(
+            parse(&raw).unwrap_err(),
+            "UNIF board UNL-SOMETHING is not supported: there are no mappers for unlicensed and \
+             multicart boards yet"
+        );
+
+        let raw = unif(vec![chunk("MAPR", b"NES-TLROM\0"), chunk("PRG0", &[0; 16])]);
+        assert_eq!(
+            parse(&raw).unwrap_err(),
+            "UNIF board NES-TLROM is not supported"
+        );
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let mut raw = unif(vec![chunk("MAPR", b"NES-NROM\0"), chunk("PRG0", &[0; 16])]);
+        raw.truncate(raw.len() - 1);
+
+        assert!(parse(&raw).is_err());
+    }
+
+    #[test]
+    fn test_board_names() {
+        assert_eq!(mapper_for_board("NES-NROM-128"), Some(0));
+        assert_eq!(mapper_for_board("HVC-CNROM"), Some(3));
+        assert_eq!(mapper_for_board("NES-AOROM"), Some(7));
+        assert_eq!(mapper_for_board("FOO"), None);
+    }
+
+    #[test]
+    fn test_boards_without_a_mapper_are_unsupported() {
+        for board in [
+            "NES-TLROM",
+            "NES-SNROM",
+            "NES-PNROM",
+            "NES-BNROM",
+            "BMC-NROM",
+        ] {
+            assert_eq!(mapper_for_board(board), None, "{}", board);
+        }
+    }
+
+    #[test]
+    fn test_supported_boards_build_a_cartridge() {
+        for board in ["NES-NROM-256", "NES-UNROM", "HVC-CNROM", "NES-AOROM"] {
+            let raw = unif(vec![
+                chunk("MAPR", format!("{}\0", board).as_bytes()),
+                chunk("PRG0", &[0x42; 0x8000]),
+                chunk("CHR0", &[0x24; 0x2000]),
+            ]);
+
+            let cartridge = new_cartridge(parse(&raw).unwrap().rom).unwrap();
+
+            let mut mapper = cartridge.borrow_mut();
+            assert_eq!(mapper.cpu_read(0xFFFC), 0x42, "{}", board);
+            assert_eq!(mapper.ppu_read(0x0000), 0x24, "{}", board);
+        }
+    }
+}
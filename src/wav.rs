@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::Path;
+
+const BITS_PER_SAMPLE: u16 = 16;
+const FORMAT_PCM: u16 = 1;
+
+/// Encodes mono samples in -1.0..1.0 as a 16-bit PCM WAV file. Values outside the range clip.
+pub fn encode(sample_rate: u32, samples: &[f32]) -> Vec<u8> {
+    let block_align = BITS_PER_SAMPLE / 8;
+    let data_len = samples.len() as u32 * block_align as u32;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_len).to_le_bytes());
+    wav.extend(b"WAVE");
+
+    wav.extend(b"fmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(FORMAT_PCM.to_le_bytes());
+    // channels
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(sample_rate.to_le_bytes());
+    wav.extend((sample_rate * block_align as u32).to_le_bytes());
+    wav.extend(block_align.to_le_bytes());
+    wav.extend(BITS_PER_SAMPLE.to_le_bytes());
+
+    wav.extend(b"data");
+    wav.extend(data_len.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        wav.extend(value.to_le_bytes());
+    }
+    return wav;
+}
+
+pub fn save(path: &Path, sample_rate: u32, samples: &[f32]) -> Result<(), String> {
+    return fs::write(path, encode(sample_rate, samples))
+        .map_err(|e| format!("unable to write {}: {}", path.display(), e));
+}
+
+/// Reads back what `encode` writes: returns the sample rate and samples.
+pub fn decode(wav: &[u8]) -> Result<(u32, Vec<f32>), String> {
+    if wav.len() < 44 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([wav[offset], wav[offset + 1]]);
+    let u32_at = |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+
+    if &wav[12..16] != b"fmt " || u16_at(20) != FORMAT_PCM || u16_at(22) != 1 {
+        return Err("only mono PCM is supported".to_string());
+    }
+    if u16_at(34) != BITS_PER_SAMPLE {
+        return Err(format!("unsupported sample size: {} bits", u16_at(34)));
+    }
+    if &wav[36..40] != b"data" {
+        return Err("missing data chunk".to_string());
+    }
+
+    let sample_rate = u32_at(24);
+    let data_len = u32_at(40) as usize;
+    let data = wav
+        .get(44..44 + data_len)
+        .ok_or("truncated data chunk".to_string())?;
+    let samples = data
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32)
+        .collect();
+    return Ok((sample_rate, samples));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let wav = encode(48_000, &[0.0, 1.0]);
+
+        assert_eq!(wav.len(), 48);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 40);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 48_000);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 96_000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[44..48], [0x00, 0x00, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn test_samples_are_clipped() {
+        let wav = encode(44_100, &[2.0, -2.0]);
+
+        assert_eq!(wav[44..48], [0xFF, 0x7F, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let samples = vec![0.0, 0.5, -0.25, 1.0];
+
+        let (rate, decoded) = decode(&encode(44_100, &samples)).unwrap();
+
+        assert_eq!(rate, 44_100);
+        for (a, b) in samples.iter().zip(decoded.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_other_files() {
+        assert!(decode(b"not a wav file at all, just some text padding it out").is_err());
+    }
+}
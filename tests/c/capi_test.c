@@ -0,0 +1,126 @@
+/* Drives the C ABI the way an embedding tool would. Built and run by tests/capi_tests.rs. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "nes_emulator.h"
+
+static int failures = 0;
+
+#define CHECK(condition)                                                      \
+    do {                                                                      \
+        if (!(condition)) {                                                   \
+            printf("%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition); \
+            failures++;                                                       \
+        }                                                                     \
+    } while (0)
+
+/* $8000: LDA #$42; STA $10; $8004: INC $11; JMP $8004 */
+static const uint8_t program[] = {0xA9, 0x42, 0x85, 0x10, 0xE6, 0x11, 0x4C, 0x04, 0x80};
+
+/* 32 KiB NROM image with CHR RAM, reset vector at $8000. */
+static uint8_t rom[16 + 0x8000];
+
+static void build_rom(void) {
+    static const uint8_t header[16] = {'N', 'E', 'S', 0x1A, 2, 0};
+    memcpy(rom, header, sizeof header);
+    memcpy(rom + 16, program, sizeof program);
+    rom[16 + 0x7FFC] = 0x00;
+    rom[16 + 0x7FFD] = 0x80;
+}
+
+int main(void) {
+    NesEmulator *nes = nes_create();
+    NesRegisters registers;
+    static const uint8_t garbage[] = "not a rom";
+
+    CHECK(nes != NULL);
+    CHECK(nes_step(nes) == NES_ERROR_NO_ROM);
+    CHECK(strcmp(nes_last_error(nes), "no rom loaded") == 0);
+    CHECK(nes_framebuffer(nes) == NULL);
+    CHECK(nes_load_rom(nes, garbage, sizeof garbage) == NES_ERROR_INVALID_ROM);
+    CHECK(strlen(nes_last_error(nes)) > 0);
+    CHECK(nes_load_rom(NULL, garbage, sizeof garbage) == NES_ERROR_NULL);
+
+    build_rom();
+    CHECK(nes_load_rom(nes, rom, sizeof rom) == NES_OK);
+
+    /* two instructions: LDA, STA */
+    CHECK(nes_step(nes) == 0);
+    CHECK(nes_step(nes) == 0);
+    CHECK(nes_registers(nes, &registers) == NES_OK);
+    CHECK(registers.pc == 0x8004);
+    CHECK(registers.a == 0x42);
+    CHECK(registers.cycles > 0);
+    CHECK(nes_peek(nes, 0x0010) == 0x42);
+    /* RAM is mirrored up to $1FFF */
+    CHECK(nes_peek(nes, 0x0810) == 0x42);
+    /* PRG ROM */
+    CHECK(nes_peek(nes, 0x8000) == 0xA9);
+
+    CHECK(nes_poke(nes, 0x0020, 7) == NES_OK);
+    CHECK(nes_peek(nes, 0x0020) == 7);
+
+    CHECK(nes_run_frame(nes) == NES_OK);
+    CHECK(nes_peek(nes, 0x0011) != 0);
+    const uint8_t *pixels = nes_framebuffer(nes);
+    CHECK(pixels != NULL);
+    /* opaque RGBA */
+    CHECK(pixels[3] == 0xFF);
+    CHECK(pixels[(NES_SCREEN_WIDTH * NES_SCREEN_HEIGHT - 1) * 4 + 3] == 0xFF);
+
+    /* the framebuffer keeps the last complete frame while the next one is drawn */
+    static uint8_t complete[NES_SCREEN_WIDTH * NES_SCREEN_HEIGHT * 4];
+    memcpy(complete, pixels, sizeof complete);
+    /* emphasise all colours */
+    CHECK(nes_poke(nes, 0x2001, 0xE0) == NES_OK);
+    for (int i = 0; i < 3000; i++) {
+        CHECK(nes_step(nes) == 0);
+    }
+    CHECK(memcmp(nes_framebuffer(nes), complete, sizeof complete) == 0);
+    CHECK(nes_run_frame(nes) == NES_OK);
+    CHECK(memcmp(nes_framebuffer(nes), complete, sizeof complete) != 0);
+    CHECK(nes_poke(nes, 0x2001, 0x00) == NES_OK);
+
+    CHECK(nes_set_buttons(nes, 0, NES_BUTTON_A | NES_BUTTON_START) == NES_OK);
+    CHECK(nes_set_buttons(nes, NES_PLAYERS, NES_BUTTON_A) == NES_ERROR_INVALID_ARGUMENT);
+
+    /* save, run on, load: the counter at $11 goes back */
+    size_t state_size = nes_state_size(nes);
+    CHECK(state_size > 0);
+    uint8_t *state = malloc(state_size);
+    CHECK(nes_save_state(nes, state, state_size - 1) == NES_ERROR_INVALID_ARGUMENT);
+    CHECK(nes_save_state(nes, state, state_size) == NES_OK);
+    uint8_t counter = nes_peek(nes, 0x0011);
+    CHECK(nes_run_frame(nes) == NES_OK);
+    CHECK(nes_peek(nes, 0x0011) != counter);
+    CHECK(nes_load_state(nes, garbage, sizeof garbage) == NES_ERROR_INVALID_STATE);
+    CHECK(strcmp(nes_last_error(nes), "not a save state") == 0);
+    CHECK(nes_load_state(nes, state, state_size) == NES_OK);
+    CHECK(nes_peek(nes, 0x0011) == counter);
+    free(state);
+
+    /* KIL */
+    rom[16] = 0x02;
+    CHECK(nes_load_rom(nes, rom, sizeof rom) == NES_OK);
+    CHECK(nes_step(nes) == NES_ERROR_UNSUPPORTED);
+    CHECK(strcmp(nes_last_error(nes), "unsupported opcode $02 at $8000") == 0);
+
+    /* the test hook's JAM panics */
+    rom[16] = 0xF2;
+    CHECK(nes_load_rom(nes, rom, sizeof rom) == NES_OK);
+    CHECK(nes_run_frame(nes) == NES_ERROR_PANIC);
+    CHECK(strncmp(nes_last_error(nes), "emulator panicked: ", 19) == 0);
+    CHECK(nes_step(nes) == NES_ERROR_NO_ROM);
+
+    nes_destroy(nes);
+    nes_destroy(NULL);
+
+    if (failures > 0) {
+        printf("%d checks failed\n", failures);
+        return 1;
+    }
+    printf("all passed\n");
+    return 0;
+}
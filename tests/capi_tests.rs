@@ -0,0 +1,87 @@
+//! Checks the generated C header and drives the C ABI from a C program built with the system
+//! compiler against the cdylib.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const HEADER: &str = "include/nes_emulator.h";
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn generate_header() -> String {
+    let bindings = cbindgen::Builder::new()
+        .with_src(manifest_dir().join("src/capi.rs"))
+        .with_language(cbindgen::Language::C)
+        .with_include_guard("NES_EMULATOR_H")
+        .with_autogen_warning(
+            "/* Generated by cbindgen from src/capi.rs, run the capi_tests with NES_UPDATE_HEADER=1 \
+             to regenerate. */",
+        )
+        .with_cpp_compat(true)
+        .generate()
+        .unwrap();
+    let mut header = vec![];
+    bindings.write(&mut header);
+    String::from_utf8(header).unwrap()
+}
+
+#[test]
+fn test_header_is_up_to_date() {
+    let generated = generate_header();
+    let path = manifest_dir().join(HEADER);
+    if std::env::var_os("NES_UPDATE_HEADER").is_some() {
+        fs::write(&path, &generated).unwrap();
+    }
+
+    let committed = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        committed == generated,
+        "{} is stale, run the capi_tests with NES_UPDATE_HEADER=1",
+        HEADER
+    );
+}
+
+#[test]
+fn test_c_program() {
+    let dir = std::env::temp_dir().join(format!("nes-capi-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("capi_test");
+    let library = common::cdylib_path();
+    let library_dir = library.parent().unwrap();
+
+    let status = Command::new("cc")
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-I"])
+        .arg(manifest_dir().join("include"))
+        .arg(manifest_dir().join("tests/c/capi_test.c"))
+        .arg("-o")
+        .arg(&program)
+        .arg("-L")
+        .arg(library_dir)
+        .arg("-lnes_emulator")
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .status()
+        .expect("a C compiler is installed");
+    assert!(status.success());
+
+    // cargo's LD_LIBRARY_PATH would find the copy of the cdylib that `cargo build` made,
+    // without the test hooks
+    let output = Command::new(&program)
+        .env("LD_LIBRARY_PATH", library_dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.ends_with("all passed\n"), "{}", stdout);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,4 +1,11 @@
+#![allow(clippy::needless_return)]
+
 use nes_emulator::cpu::{Flags, CPU};
+use nes_emulator::loader::parse_rom;
+use nes_emulator::mapper::new_cartridge;
+use nes_emulator::nes::Nes;
+use nes_emulator::ppu::Ppu;
+use std::path::PathBuf;
 use std::vec;
 
 #[allow(dead_code)]
@@ -30,9 +37,9 @@ pub fn assert_flags(cpu: &CPU, enabled_flags: Vec<Flags>) {
 
 #[allow(dead_code)]
 pub fn push_to_stack(cpu: &mut CPU, data: u8) {
-    let stack_addr = (0x0100 as u16) + (cpu.stack_pointer as u16);
+    let stack_addr = 0x0100_u16 + (cpu.stack_pointer as u16);
     cpu.memory.write(stack_addr, data);
-    cpu.stack_pointer -= 1;
+    cpu.stack_pointer = cpu.stack_pointer.wrapping_sub(1);
 }
 
 fn get_flag_name(f: Flags) -> &'static str {
@@ -57,3 +64,69 @@ pub fn print_memory_dump(cpu: &CPU) {
     }
     print!("\n\n");
 }
+
+/// A PPU on an NROM board with CHR RAM.
+#[allow(dead_code)]
+pub fn test_ppu() -> Ppu {
+    let rom = parse_rom(&nrom_image(&[])).unwrap().rom;
+    return Ppu::new(new_cartridge(rom).unwrap());
+}
+
+/// A console powered on with `nrom_image(program)`.
+#[allow(dead_code)]
+pub fn nes_with_program(program: &[u8]) -> Nes {
+    return Nes::new(parse_rom(&nrom_image(program)).unwrap().rom).unwrap();
+}
+
+/// A 32KB NROM iNES image with CHR RAM, `program` at $8000 (the reset vector) and the NMI and
+/// IRQ vectors pointing at an RTI at $FFF0.
+#[allow(dead_code)]
+pub fn nrom_image(program: &[u8]) -> Vec<u8> {
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 0, 0, 0];
+    image.resize(16, 0);
+    let mut prg_rom = vec![0; 0x8000];
+    prg_rom[..program.len()].copy_from_slice(program);
+    // RTI
+    prg_rom[0x7FF0] = 0x40;
+    prg_rom[0x7FFA..].copy_from_slice(&[0xF0, 0xFF, 0x00, 0x80, 0xF0, 0xFF]);
+    image.extend(prg_rom);
+    return image;
+}
+
+/// The crate's cdylib (the C API and the libretro core), which cargo builds next to the test
+/// binaries' directory.
+#[allow(dead_code)]
+pub fn cdylib_path() -> PathBuf {
+    let deps = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let name = format!(
+        "{}nes_emulator{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    );
+    return [deps.join(&name), deps.parent().unwrap().join(&name)]
+        .into_iter()
+        .find(|path| path.exists())
+        .expect("the cdylib is built along with the tests");
+}
+
+/// Turns on NMIs, background rendering and a pulse tone, then loops: polls controller 1 into a
+/// running sum at $11, counts at $10, writes the sum through $2007 and the count to the pulse
+/// period, so the picture, the sound and RAM all depend on the input history.
+#[allow(dead_code)]
+pub const INPUT_HISTORY_PROGRAM: [u8; 72] = [
+    /*LDA*/ 0xA9, 0x80, /*STA $2000*/ 0x8D, 0x00, 0x20, /*LDA*/ 0xA9, 0x01,
+    /*STA $4015*/ 0x8D, 0x15, 0x40, /*LDA*/ 0xA9, 0xBF, /*STA $4000*/ 0x8D, 0x00,
+    0x40, /*LDA*/ 0xA9, 0xFD, /*STA $4002*/ 0x8D, 0x02, 0x40, /*LDA*/ 0xA9, 0x00,
+    /*STA $4003*/ 0x8D, 0x03, 0x40, /*LDA*/ 0xA9, 0x0A, /*STA $2001*/ 0x8D, 0x01,
+    0x20, /*$801E LDA*/ 0xA9, 0x01, /*STA $4016*/ 0x8D, 0x16, 0x40, /*LDA*/ 0xA9,
+    0x00, /*STA $4016*/ 0x8D, 0x16, 0x40, /*LDA $4016*/ 0xAD, 0x16, 0x40,
+    /*ADC $11*/ 0x65, 0x11, /*STA $11*/ 0x85, 0x11, /*INC $10*/ 0xE6, 0x10,
+    /*LDA*/ 0xA9, 0x3F, /*STA $2006*/ 0x8D, 0x06, 0x20, /*LDA*/ 0xA9, 0x00,
+    /*STA $2006*/ 0x8D, 0x06, 0x20, /*LDA $11*/ 0xA5, 0x11, /*STA $2007*/ 0x8D,
+    0x07, 0x20, /*LDA $10*/ 0xA5, 0x10, /*STA $4002*/ 0x8D, 0x02, 0x40,
+    /*JMP $801E*/ 0x4C, 0x1E, 0x80,
+];
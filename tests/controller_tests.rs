@@ -0,0 +1,67 @@
+use nes_emulator::controller::Buttons;
+use nes_emulator::cpu::CPU;
+
+/// Strobes the controllers, then shifts the 8 buttons of the port at `$40xx` into $00,
+/// A ending up in bit 7.
+fn read_buttons_program(port_lo: u8) -> Vec<u8> {
+    vec![
+        /*LDA*/ 0xA9, 0x01, /*STA $4016*/ 0x8D, 0x16, 0x40, /*LDA*/ 0xA9, 0x00,
+        /*STA $4016*/ 0x8D, 0x16, 0x40, /*LDX*/ 0xA2, 0x08,
+        /*$800C LDA $40xx*/ 0xAD, port_lo, 0x40, /*AND*/ 0x29, 0x01, /*CMP*/ 0xC9,
+        0x01, /*ROL $00*/ 0x26, 0x00, /*DEX*/ 0xCA, /*BEQ+3*/ 0xF0, 0x03,
+        /*JMP $800C*/ 0x4C, 0x0C, 0x80, 0x00,
+    ]
+}
+
+fn run_with_buttons(port: usize, buttons: Buttons) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.memory.attach_controllers();
+    cpu.memory
+        .controller_mut(port)
+        .unwrap()
+        .set_buttons(buttons);
+
+    cpu.load_and_run_without_reset(read_buttons_program(0x16 + port as u8));
+
+    cpu
+}
+
+#[test]
+fn test_controller_1_buttons_are_read_in_order() {
+    let mut cpu = run_with_buttons(0, Buttons::A | Buttons::Start | Buttons::Right);
+
+    assert_eq!(cpu.memory.read(0x00), 0b1001_0001);
+}
+
+#[test]
+fn test_controller_2_is_read_from_4017() {
+    let mut cpu = run_with_buttons(1, Buttons::B | Buttons::Up);
+
+    assert_eq!(cpu.memory.read(0x00), 0b0100_1000);
+}
+
+#[test]
+fn test_no_buttons_pressed() {
+    let mut cpu = run_with_buttons(0, Buttons::empty());
+
+    assert_eq!(cpu.memory.read(0x00), 0x00);
+}
+
+#[test]
+fn test_reads_past_8_return_1() {
+    let mut cpu = run_with_buttons(0, Buttons::empty());
+
+    // the loop left the register empty, further reads see the 1s behind the buttons
+    assert_eq!(cpu.memory.read(0x4016) & 1, 1);
+}
+
+#[test]
+fn test_upper_bits_are_open_bus() {
+    let mut cpu = run_with_buttons(0, Buttons::all());
+
+    cpu.memory.write(0x4016, 1);
+    cpu.memory.read(0x8000);
+
+    // the last value on the bus was LDA's opcode, $A9
+    assert_eq!(cpu.memory.read(0x4016), 0b1010_0001);
+}
@@ -109,3 +109,32 @@ fn test_cpu_flag_negative() {
     assert_eq!(cpu.status.bits(), 0b1010_0100);
     assert_flag(&cpu, Flags::Negative);
 }
+
+#[test]
+fn test_0x38_sec_implied_sets_flag_correctly() {
+    let mut cpu = CPU::new();
+
+    cpu.load_and_run_without_reset(vec![0x38, 0x00]);
+
+    assert_flag(&cpu, Flags::Carry);
+}
+
+#[test]
+fn test_0x78_sei_implied_sets_flag_correctly() {
+    let mut cpu = CPU::new();
+    cpu.status.remove(Flags::InteruptDisable);
+
+    cpu.load_and_run_without_reset(vec![0x78, 0x00]);
+
+    assert_eq!(cpu.status.bits(), 0b0010_0100);
+    assert_no_flags(&cpu)
+}
+
+#[test]
+fn test_0xf8_sed_implied_sets_flag_correctly() {
+    let mut cpu = CPU::new();
+
+    cpu.load_and_run_without_reset(vec![0xF8, 0x00]);
+
+    assert_flag(&cpu, Flags::Decimal);
+}
@@ -0,0 +1,56 @@
+use nes_emulator::cpu::CPU;
+use std::vec;
+
+const RESET_CYCLES: u64 = 7;
+const BRK_CYCLES: u64 = 7;
+
+fn cycles(program: Vec<u8>) -> u64 {
+    let mut cpu = CPU::new();
+    cpu.memory.write_u16(0x10, 0x02FF);
+    cpu.load_and_run(program);
+    cpu.cycles - RESET_CYCLES - BRK_CYCLES
+}
+
+#[test]
+fn test_indexed_read_crossing_a_page_takes_a_cycle_more() {
+    // LDX #$00; LDA $02FF,X
+    assert_eq!(cycles(vec![0xA2, 0x00, 0xBD, 0xFF, 0x02, 0x00]), 2 + 4);
+    // LDX #$01; LDA $02FF,X
+    assert_eq!(cycles(vec![0xA2, 0x01, 0xBD, 0xFF, 0x02, 0x00]), 2 + 5);
+    // LDY #$01; SBC $02FF,Y
+    assert_eq!(cycles(vec![0xA0, 0x01, 0xF9, 0xFF, 0x02, 0x00]), 2 + 5);
+    // LDY #$01; CMP ($10),Y
+    assert_eq!(cycles(vec![0xA0, 0x01, 0xD1, 0x10, 0x00]), 2 + 6);
+    // LDY #$00; CMP ($10),Y
+    assert_eq!(cycles(vec![0xA0, 0x00, 0xD1, 0x10, 0x00]), 2 + 5);
+}
+
+#[test]
+fn test_indexed_writes_take_the_same_cycles_on_any_page() {
+    // LDX #$01; STA $02FF,X
+    assert_eq!(cycles(vec![0xA2, 0x01, 0x9D, 0xFF, 0x02, 0x00]), 2 + 5);
+    // LDX #$01; INC $02FF,X
+    assert_eq!(cycles(vec![0xA2, 0x01, 0xFE, 0xFF, 0x02, 0x00]), 2 + 7);
+}
+
+#[test]
+fn test_taken_branch_takes_a_cycle_more() {
+    // LDX #$00; BNE +2
+    assert_eq!(cycles(vec![0xA2, 0x00, 0xD0, 0x02, 0x00]), 2 + 2);
+    // LDX #$01; BNE +2; (skipped) LDX #$02
+    assert_eq!(
+        cycles(vec![0xA2, 0x01, 0xD0, 0x02, 0xA2, 0x02, 0x00]),
+        2 + 3
+    );
+}
+
+#[test]
+fn test_branch_to_another_page_takes_two_cycles_more() {
+    // JMP $80FA; $80FA: LDX #$01; BNE +4 from $80FE to $8102
+    let mut program = vec![0x4C, 0xFA, 0x80];
+    program.resize(0xFA, 0xEA);
+    program.extend([0xA2, 0x01, 0xD0, 0x04]);
+    program.resize(0x103, 0x00);
+
+    assert_eq!(cycles(program), 3 + 2 + 4);
+}
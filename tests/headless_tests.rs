@@ -0,0 +1,137 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use nes_emulator::png::verify_chunks;
+use nes_emulator::wav;
+
+/// Sets a blue backdrop and starts a pulse tone, then keeps polling controller 1: while A is
+/// held the picture gets red emphasis.
+const PROGRAM: [u8; 65] = [
+    /*LDA*/ 0xA9, 0x3F, /*STA $2006*/ 0x8D, 0x06, 0x20, /*LDA*/ 0xA9, 0x00,
+    /*STA $2006*/ 0x8D, 0x06, 0x20, /*LDA*/ 0xA9, 0x21, /*STA $2007*/ 0x8D, 0x07,
+    0x20, /*LDA*/ 0xA9, 0x01, /*STA $4015*/ 0x8D, 0x15, 0x40, /*LDA*/ 0xA9, 0xBF,
+    /*STA $4000*/ 0x8D, 0x00, 0x40, /*LDA*/ 0xA9, 0xFD, /*STA $4002*/ 0x8D, 0x02,
+    0x40, /*LDA*/ 0xA9, 0x00, /*STA $4003*/ 0x8D, 0x03, 0x40, /*$8023 LDA*/ 0xA9,
+    0x01, /*STA $4016*/ 0x8D, 0x16, 0x40, /*LDA*/ 0xA9, 0x00, /*STA $4016*/ 0x8D,
+    0x16, 0x40, /*LDA $4016*/ 0xAD, 0x16, 0x40, /*AND*/ 0x29, 0x01, /*BEQ+5*/ 0xF0,
+    0x05, /*LDA*/ 0xA9, 0x2A, /*JMP $803B*/ 0x4C, 0x3B, 0x80, /*$8039 LDA*/ 0xA9,
+    0x0A, /*$803B STA $2001*/ 0x8D, 0x01, 0x20, /*JMP $8023*/ 0x4C, 0x23, 0x80,
+];
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nes-headless-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn headless(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_nes-headless"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_writes_screenshot_hashes_and_audio() {
+    let dir = work_dir("outputs");
+    let rom = dir.join("game.nes");
+    fs::write(&rom, common::nrom_image(&PROGRAM)).unwrap();
+    let script = dir.join("script.txt");
+    fs::write(&script, "# hold A from frame 5\n5 A\n").unwrap();
+    let (png, wav_path, hashes) = (
+        dir.join("out.png"),
+        dir.join("out.wav"),
+        dir.join("hashes.txt"),
+    );
+
+    let output = headless(&[
+        rom.to_str().unwrap(),
+        "--frames",
+        "10",
+        "--screenshot",
+        png.to_str().unwrap(),
+        "--wav",
+        wav_path.to_str().unwrap(),
+        "--hashes",
+        hashes.to_str().unwrap(),
+        "--input",
+        script.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let chunks = verify_chunks(&fs::read(&png).unwrap()).unwrap();
+    assert_eq!(chunks.first(), Some(b"IHDR"));
+
+    let (rate, samples) = wav::decode(&fs::read(&wav_path).unwrap()).unwrap();
+    assert_eq!(rate, 44_100);
+    assert!(samples.iter().any(|s| s.abs() > 0.01));
+
+    let hashes = fs::read_to_string(&hashes).unwrap();
+    let lines: Vec<&str> = hashes.lines().collect();
+    assert_eq!(lines.len(), 10);
+    assert!(lines[0].starts_with("0 "));
+    // the picture is stable until A is pressed, then changes to a new stable picture
+    let hash = |frame: usize| lines[frame].split(' ').nth(1).unwrap();
+    assert_eq!(hash(3), hash(4));
+    assert_ne!(hash(4), hash(6));
+    assert_eq!(hash(7), hash(8));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(hash(9)), "{}", stdout);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_same_run_gives_same_hashes() {
+    let dir = work_dir("determinism");
+    let rom = dir.join("game.nes");
+    fs::write(&rom, common::nrom_image(&PROGRAM)).unwrap();
+
+    let first = headless(&[rom.to_str().unwrap(), "--frames", "5"]);
+    let second = headless(&[rom.to_str().unwrap(), "--frames", "5"]);
+
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_bad_arguments_fail() {
+    let output = headless(&["--frames", "5"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = headless(&["does-not-exist.nes"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("does-not-exist.nes"));
+}
+
+#[test]
+fn test_cpu_fault_fails_with_the_opcode_and_address() {
+    let dir = work_dir("fault");
+    let rom = dir.join("game.nes");
+    // SEI; CLD; LDX #$FF; TXS; KIL
+    fs::write(
+        &rom,
+        common::nrom_image(&[0x78, 0xD8, 0xA2, 0xFF, 0x9A, 0x02]),
+    )
+    .unwrap();
+
+    let output = headless(&[rom.to_str().unwrap(), "--frames", "5"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("frame 0: unsupported opcode $02 at $8005"),
+        "{}",
+        stderr
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,106 @@
+use nes_emulator::controller::{Buttons, Controller};
+use nes_emulator::cpu::CPU;
+use nes_emulator::input::four_score::FourScore;
+use nes_emulator::input::vaus::Vaus;
+use nes_emulator::input::zapper::Zapper;
+use nes_emulator::input::{Device, Input, Port};
+
+fn cpu_with(input: Input) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.memory.attach_input(input);
+    cpu
+}
+
+fn strobe(cpu: &mut CPU) {
+    cpu.memory.write(0x4016, 1);
+    cpu.memory.write(0x4016, 0);
+}
+
+/// `count` reads of `addr`, keeping the bits set in `mask`.
+fn read_bits(cpu: &mut CPU, addr: u16, mask: u8, count: usize) -> Vec<u8> {
+    (0..count).map(|_| cpu.memory.read(addr) & mask).collect()
+}
+
+fn byte_lsb_first(bits: &[u8]) -> u8 {
+    bits.iter()
+        .enumerate()
+        .fold(0, |byte, (i, bit)| byte | (bit << i))
+}
+
+fn byte_msb_first(bits: &[u8]) -> u8 {
+    bits.iter().fold(0, |byte, bit| (byte << 1) | bit)
+}
+
+#[test]
+fn test_four_score_reports_four_players_and_signature() {
+    let mut input = Input::new();
+    input
+        .connect(Port::One, Device::FourScore(FourScore::new()))
+        .unwrap();
+    let buttons = [Buttons::A, Buttons::B, Buttons::Start, Buttons::Right];
+    for (player, buttons) in buttons.iter().enumerate() {
+        input.controller_mut(player).unwrap().set_buttons(*buttons);
+    }
+    let mut cpu = cpu_with(input);
+    strobe(&mut cpu);
+
+    let port1 = read_bits(&mut cpu, 0x4016, 1, 24);
+    let port2 = read_bits(&mut cpu, 0x4017, 1, 24);
+
+    assert_eq!(byte_lsb_first(&port1[0..8]), Buttons::A.bits());
+    assert_eq!(byte_lsb_first(&port1[8..16]), Buttons::Start.bits());
+    assert_eq!(byte_msb_first(&port1[16..24]), 0x10);
+    assert_eq!(byte_lsb_first(&port2[0..8]), Buttons::B.bits());
+    assert_eq!(byte_lsb_first(&port2[8..16]), Buttons::Right.bits());
+    assert_eq!(byte_msb_first(&port2[16..24]), 0x20);
+}
+
+#[test]
+fn test_zapper_trigger_and_light_bits() {
+    let mut input = Input::new();
+    input
+        .connect(Port::Two, Device::Zapper(Zapper::new()))
+        .unwrap();
+    let mut cpu = cpu_with(input);
+
+    // no PPU to look at: no light sensed, trigger released
+    assert_eq!(cpu.memory.read(0x4017) & 0b1_1000, 0b0_1000);
+
+    let zapper = cpu.memory.input_mut().unwrap().zapper_mut().unwrap();
+    zapper.set_trigger(true);
+    assert_eq!(cpu.memory.read(0x4017) & 0b1_1000, 0b1_1000);
+}
+
+#[test]
+fn test_vaus_position_is_read_serially() {
+    let mut input = Input::new();
+    input.connect(Port::Two, Device::Vaus(Vaus::new())).unwrap();
+    input.vaus_mut().unwrap().set_position(0xA5);
+    let mut cpu = cpu_with(input);
+    strobe(&mut cpu);
+
+    let bits: Vec<u8> = read_bits(&mut cpu, 0x4017, 0b1000, 8)
+        .iter()
+        .map(|bit| bit >> 3)
+        .collect();
+
+    // the knob's value is reported inverted, most significant bit first
+    assert_eq!(byte_msb_first(&bits), !0xA5);
+}
+
+#[test]
+fn test_famicom_expansion_port_controller() {
+    let mut input = Input::new();
+    input
+        .connect(Port::Expansion, Device::Controller(Controller::new()))
+        .unwrap();
+    input.controller_mut(0).unwrap().set_buttons(Buttons::A);
+    input.controller_mut(2).unwrap().set_buttons(Buttons::B);
+    let mut cpu = cpu_with(input);
+    strobe(&mut cpu);
+
+    let bits = read_bits(&mut cpu, 0x4016, 0b11, 2);
+
+    // port 1's controller on D0, the expansion port's on D1
+    assert_eq!(bits, vec![0b01, 0b10]);
+}
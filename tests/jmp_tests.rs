@@ -29,3 +29,18 @@ fn test_0x6c_jmp_immediate_jumps_correctly() {
     assert_eq!(cpu.register_a, 0x02);
     assert_no_flags(&cpu);
 }
+
+#[test]
+fn test_0x6c_jmp_indirect_wraps_within_the_page() {
+    let mut cpu = CPU::new();
+    cpu.memory.write(0x02FF, 0x05);
+    cpu.memory.write(0x0200, 0x80);
+    cpu.memory.write(0x0300, 0x12);
+
+    cpu.load_and_run_without_reset(vec![
+        /*JMP*/ 0x6C, 0xFF, 0x02, /*LDX*/ 0xA2, 0x01, /*LDA*/ 0xA9, 0x02, 0x00,
+    ]);
+
+    assert_eq!(cpu.register_x, 0x00);
+    assert_eq!(cpu.register_a, 0x02);
+}
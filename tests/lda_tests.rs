@@ -214,6 +214,20 @@ fn test_0xa1_lda_indirect_x_load_data() {
     assert_no_flags(&cpu);
 }
 
+#[test]
+fn test_0xa1_lda_indirect_x_pointer_wraps_in_the_zero_page() {
+    let mut cpu = CPU::new();
+    cpu.register_x = 0x01;
+    cpu.memory.write(0xFF, 0x00);
+    cpu.memory.write(0x00, 0x10);
+    cpu.memory.write(0x100, 0x20);
+    cpu.memory.write(0x1000, 0x05);
+
+    cpu.load_and_run_without_reset(vec![0xA1, 0xFE, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x05);
+}
+
 #[test]
 fn test_0xa1_lda_indirect_x_zero_flag() {
     let mut cpu = CPU::new();
@@ -239,3 +253,17 @@ fn test_0xa1_lda_indirect_x_negative_flag() {
     assert_eq!(cpu.register_a, 0b1000_0000);
     assert_flag(&cpu, Flags::Negative);
 }
+
+#[test]
+fn test_0xb1_lda_indirect_y_pointer_wraps_in_the_zero_page() {
+    let mut cpu = CPU::new();
+    cpu.register_y = 0x01;
+    cpu.memory.write(0xFF, 0x00);
+    cpu.memory.write(0x00, 0x10);
+    cpu.memory.write(0x100, 0x20);
+    cpu.memory.write(0x1001, 0x05);
+
+    cpu.load_and_run_without_reset(vec![0xB1, 0xFF, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x05);
+}
@@ -0,0 +1,367 @@
+//! Loads the core's shared library the way a libretro frontend does and drives it through the
+//! exported C entry points.
+
+mod common;
+
+use std::cell::RefCell;
+use std::ffi::{c_void, CStr};
+use std::sync::{Mutex, MutexGuard};
+
+use libloading::{Library, Symbol};
+use nes_emulator::libretro::*;
+
+/// Backdrop $21, a pulse tone, rendering on, then controller 1's A button copied to $10 forever.
+const PROGRAM: [u8; 60] = [
+    /*LDA*/ 0xA9, 0x3F, /*STA $2006*/ 0x8D, 0x06, 0x20, /*LDA*/ 0xA9, 0x00,
+    /*STA $2006*/ 0x8D, 0x06, 0x20, /*LDA*/ 0xA9, 0x21, /*STA $2007*/ 0x8D, 0x07,
+    0x20, /*LDA*/ 0xA9, 0x01, /*STA $4015*/ 0x8D, 0x15, 0x40, /*LDA*/ 0xA9, 0xBF,
+    /*STA $4000*/ 0x8D, 0x00, 0x40, /*LDA*/ 0xA9, 0xFD, /*STA $4002*/ 0x8D, 0x02,
+    0x40, /*LDA*/ 0xA9, 0x00, /*STA $4003*/ 0x8D, 0x03, 0x40, /*LDA*/ 0xA9, 0x0A,
+    /*STA $2001*/ 0x8D, 0x01, 0x20, /*$8028 LDA*/ 0xA9, 0x01, /*STA $4016*/ 0x8D,
+    0x16, 0x40, /*LDA*/ 0xA9, 0x00, /*STA $4016*/ 0x8D, 0x16, 0x40,
+    /*LDA $4016*/ 0xAD, 0x16, 0x40, /*AND*/ 0x29, 0x01, /*STA $10*/ 0x85, 0x10,
+    /*JMP $8028*/ 0x4C, 0x28, 0x80,
+];
+
+/// The core keeps global state, so tests sharing the loaded library take turns.
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Default)]
+struct Frontend {
+    environment_calls: Vec<u32>,
+    memory_descriptors: Vec<(u64, usize, usize)>,
+    video_frames: Vec<(u32, u32, usize, u32)>,
+    audio_frames: usize,
+    loudest: i16,
+    a_pressed: bool,
+}
+
+thread_local! {
+    static FRONTEND: RefCell<Frontend> = RefCell::new(Frontend::default());
+}
+
+unsafe extern "C" fn environment(cmd: u32, data: *mut c_void) -> bool {
+    FRONTEND.with_borrow_mut(|frontend| {
+        frontend.environment_calls.push(cmd);
+        if cmd == RETRO_ENVIRONMENT_SET_MEMORY_MAPS {
+            let map = &*(data as *const RetroMemoryMap);
+            let descriptors =
+                std::slice::from_raw_parts(map.descriptors, map.num_descriptors as usize);
+            frontend.memory_descriptors = descriptors
+                .iter()
+                .map(|d| (d.flags, d.start, d.len))
+                .collect();
+        }
+    });
+    true
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: u32, height: u32, pitch: usize) {
+    // the pixel in the middle of the screen
+    let pixel = *(data as *const u32).add(120 * pitch / 4 + 128);
+    FRONTEND.with_borrow_mut(|frontend| frontend.video_frames.push((width, height, pitch, pixel)));
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = std::slice::from_raw_parts(data, frames * 2);
+    FRONTEND.with_borrow_mut(|frontend| {
+        frontend.audio_frames += frames;
+        frontend.loudest = samples
+            .iter()
+            .fold(frontend.loudest, |loudest, s| loudest.max(s.abs()));
+    });
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: u32, device: u32, _index: u32, id: u32) -> i16 {
+    let pressed = FRONTEND.with_borrow(|frontend| frontend.a_pressed);
+    (port == 0 && device == RETRO_DEVICE_JOYPAD && id == RETRO_DEVICE_ID_JOYPAD_A && pressed) as i16
+}
+
+struct Core {
+    library: Library,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Core {
+    fn load() -> Core {
+        let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        FRONTEND.with_borrow_mut(|frontend| *frontend = Frontend::default());
+        let core = Core {
+            library: unsafe { Library::new(common::cdylib_path()).unwrap() },
+            _lock: lock,
+        };
+        unsafe {
+            core.symbol::<unsafe extern "C" fn(RetroEnvironment)>(b"retro_set_environment")(
+                environment,
+            );
+            core.symbol::<unsafe extern "C" fn(RetroVideoRefresh)>(b"retro_set_video_refresh")(
+                video_refresh,
+            );
+            core.symbol::<unsafe extern "C" fn(RetroAudioSample)>(b"retro_set_audio_sample")(
+                audio_sample,
+            );
+            core.symbol::<unsafe extern "C" fn(RetroAudioSampleBatch)>(
+                b"retro_set_audio_sample_batch",
+            )(audio_sample_batch);
+            core.symbol::<unsafe extern "C" fn(RetroInputPoll)>(b"retro_set_input_poll")(
+                input_poll,
+            );
+            core.symbol::<unsafe extern "C" fn(RetroInputState)>(b"retro_set_input_state")(
+                input_state,
+            );
+            core.call(b"retro_init");
+        }
+        core
+    }
+
+    unsafe fn symbol<T>(&self, name: &[u8]) -> Symbol<'_, T> {
+        self.library.get(name).unwrap()
+    }
+
+    unsafe fn call(&self, name: &[u8]) {
+        self.symbol::<unsafe extern "C" fn()>(name)();
+    }
+
+    fn load_game(&self, rom: &[u8]) -> bool {
+        let game = RetroGameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        unsafe {
+            self.symbol::<unsafe extern "C" fn(*const RetroGameInfo) -> bool>(b"retro_load_game")(
+                &game,
+            )
+        }
+    }
+
+    fn system_ram(&self) -> &[u8] {
+        unsafe {
+            let data = self
+                .symbol::<unsafe extern "C" fn(u32) -> *mut c_void>(b"retro_get_memory_data")(
+                RETRO_MEMORY_SYSTEM_RAM,
+            );
+            let size = self.symbol::<unsafe extern "C" fn(u32) -> usize>(b"retro_get_memory_size")(
+                RETRO_MEMORY_SYSTEM_RAM,
+            );
+            std::slice::from_raw_parts(data as *const u8, size)
+        }
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        unsafe {
+            self.call(b"retro_unload_game");
+            self.call(b"retro_deinit");
+        }
+    }
+}
+
+#[test]
+fn test_system_info() {
+    let core = Core::load();
+    let mut info = RetroSystemInfo {
+        library_name: std::ptr::null(),
+        library_version: std::ptr::null(),
+        valid_extensions: std::ptr::null(),
+        need_fullpath: true,
+        block_extract: true,
+    };
+
+    unsafe {
+        assert_eq!(
+            core.symbol::<unsafe extern "C" fn() -> u32>(b"retro_api_version")(),
+            1
+        );
+        core.symbol::<unsafe extern "C" fn(*mut RetroSystemInfo)>(b"retro_get_system_info")(
+            &mut info,
+        );
+        assert_eq!(
+            CStr::from_ptr(info.library_name).to_str(),
+            Ok("nes-emulator")
+        );
+        assert_eq!(
+            CStr::from_ptr(info.valid_extensions).to_str(),
+            Ok("nes|unf|unif")
+        );
+    }
+    assert!(!info.need_fullpath);
+}
+
+#[test]
+fn test_run_delivers_video_audio_and_reads_input() {
+    let core = Core::load();
+    assert!(core.load_game(&common::nrom_image(&PROGRAM)));
+
+    let mut av_info: RetroSystemAvInfo = unsafe { std::mem::zeroed() };
+    unsafe {
+        core.symbol::<unsafe extern "C" fn(*mut RetroSystemAvInfo)>(b"retro_get_system_av_info")(
+            &mut av_info,
+        );
+    }
+    assert_eq!(av_info.geometry.base_width, 256);
+    assert!((av_info.timing.fps - 60.0988).abs() < 0.001);
+    let sample_rate = av_info.timing.sample_rate;
+
+    for _ in 0..10 {
+        unsafe { core.call(b"retro_run") };
+    }
+    assert_eq!(core.system_ram()[0x10], 0);
+    FRONTEND.with_borrow_mut(|frontend| frontend.a_pressed = true);
+    unsafe { core.call(b"retro_run") };
+    assert_eq!(core.system_ram()[0x10], 1);
+
+    FRONTEND.with_borrow(|frontend| {
+        assert!(frontend
+            .environment_calls
+            .contains(&RETRO_ENVIRONMENT_SET_PIXEL_FORMAT));
+        assert_eq!(frontend.video_frames.len(), 11);
+        let (width, height, pitch, pixel) = *frontend.video_frames.last().unwrap();
+        assert_eq!((width, height, pitch), (256, 240, 1024));
+        // palette entry $21: a light blue
+        let (r, b) = ((pixel >> 16) & 0xFF, pixel & 0xFF);
+        assert!(b > 0xC0 && r < b, "{:08x}", pixel);
+
+        // the first frame after power on is a partial one
+        let per_frame = sample_rate / av_info.timing.fps;
+        let expected = 11.0 * per_frame;
+        assert!((frontend.audio_frames as f64 - expected).abs() < per_frame);
+        assert!(frontend.loudest > 1000);
+    });
+}
+
+#[test]
+fn test_memory_maps_and_sizes() {
+    let core = Core::load();
+    assert!(core.load_game(&common::nrom_image(&PROGRAM)));
+
+    assert_eq!(core.system_ram().len(), 0x800);
+    FRONTEND.with_borrow(|frontend| {
+        assert_eq!(
+            frontend.memory_descriptors,
+            vec![
+                (RETRO_MEMDESC_SYSTEM_RAM, 0x0000, 0x800),
+                // no battery: mapped but not save RAM
+                (0, 0x6000, 0x2000),
+            ]
+        );
+    });
+    unsafe {
+        let size = core.symbol::<unsafe extern "C" fn(u32) -> usize>(b"retro_get_memory_size");
+        assert_eq!(size(RETRO_MEMORY_SAVE_RAM), 0);
+        assert_eq!(
+            core.symbol::<unsafe extern "C" fn() -> u32>(b"retro_get_region")(),
+            0
+        );
+    }
+}
+
+#[test]
+fn test_reset_keeps_ram() {
+    let core = Core::load();
+    assert!(core.load_game(&common::nrom_image(&PROGRAM)));
+    FRONTEND.with_borrow_mut(|frontend| frontend.a_pressed = true);
+    unsafe { core.call(b"retro_run") };
+
+    unsafe {
+        core.call(b"retro_reset");
+        core.call(b"retro_run");
+    }
+    // RAM survives the reset button
+    assert_eq!(core.system_ram()[0x10], 1);
+}
+
+#[test]
+fn test_serialize_and_unserialize() {
+    let core = Core::load();
+    assert!(core.load_game(&common::nrom_image(&PROGRAM)));
+    FRONTEND.with_borrow_mut(|frontend| frontend.a_pressed = true);
+    unsafe { core.call(b"retro_run") };
+    let serialize_size =
+        unsafe { core.symbol::<unsafe extern "C" fn() -> usize>(b"retro_serialize_size")() };
+    let serialize = unsafe {
+        core.symbol::<unsafe extern "C" fn(*mut c_void, usize) -> bool>(b"retro_serialize")
+    };
+    let unserialize = unsafe {
+        core.symbol::<unsafe extern "C" fn(*const c_void, usize) -> bool>(b"retro_unserialize")
+    };
+
+    assert!(serialize_size > 0x800);
+    let mut too_small = vec![0u8; serialize_size - 1];
+    assert!(!unsafe { serialize(too_small.as_mut_ptr() as *mut c_void, too_small.len()) });
+    // frontends may hand over more than asked for
+    let mut state = vec![0xFFu8; serialize_size + 13];
+    assert!(unsafe { serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+
+    FRONTEND.with_borrow_mut(|frontend| frontend.a_pressed = false);
+    unsafe { core.call(b"retro_run") };
+    assert_eq!(core.system_ram()[0x10], 0);
+    let garbage = [0x55u8; 64];
+    assert!(!unsafe { unserialize(garbage.as_ptr() as *const c_void, garbage.len()) });
+    assert_eq!(core.system_ram()[0x10], 0);
+
+    assert!(unsafe { unserialize(state.as_ptr() as *const c_void, state.len()) });
+    assert_eq!(core.system_ram()[0x10], 1);
+    // the size does not change as the game runs
+    unsafe { core.call(b"retro_run") };
+    assert_eq!(
+        unsafe { core.symbol::<unsafe extern "C" fn() -> usize>(b"retro_serialize_size")() },
+        serialize_size
+    );
+}
+
+#[test]
+fn test_rejects_bad_rom() {
+    let core = Core::load();
+
+    assert!(!core.load_game(b"not a rom"));
+    unsafe { core.call(b"retro_run") };
+    FRONTEND.with_borrow(|frontend| assert!(frontend.video_frames.is_empty()));
+}
+
+#[test]
+fn test_cpu_fault_stops_the_game() {
+    let core = Core::load();
+    // LDA #$01; STA $10; KIL
+    assert!(core.load_game(&common::nrom_image(&[0xA9, 0x01, 0x85, 0x10, 0x02])));
+
+    unsafe {
+        core.call(b"retro_run");
+        core.call(b"retro_run");
+    }
+
+    FRONTEND.with_borrow(|frontend| assert!(frontend.video_frames.is_empty()));
+    // what it left in memory can still be read, but there is nothing to save
+    assert_eq!(core.system_ram()[0x10], 1);
+    assert_eq!(
+        unsafe { core.symbol::<unsafe extern "C" fn() -> usize>(b"retro_serialize_size")() },
+        0
+    );
+}
+
+#[test]
+fn test_panic_does_not_reach_the_frontend() {
+    let core = Core::load();
+    // LDA #$01; STA $10; the test hook's JAM
+    assert!(core.load_game(&common::nrom_image(&[0xA9, 0x01, 0x85, 0x10, 0xF2])));
+
+    unsafe {
+        core.call(b"retro_run");
+        core.call(b"retro_run");
+        core.call(b"retro_reset");
+    }
+
+    FRONTEND.with_borrow(|frontend| assert!(frontend.video_frames.is_empty()));
+    assert_eq!(core.system_ram().len(), 0x800);
+    // another game runs normally
+    assert!(core.load_game(&common::nrom_image(&PROGRAM)));
+    unsafe { core.call(b"retro_run") };
+    FRONTEND.with_borrow(|frontend| assert_eq!(frontend.video_frames.len(), 1));
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{nes_with_program, INPUT_HISTORY_PROGRAM};
+use nes_emulator::controller::Buttons;
+use nes_emulator::movie::{ram_hash, Movie, Recorder};
+
+fn record(frames: usize) -> Movie {
+    let mut nes = nes_with_program(&INPUT_HISTORY_PROGRAM);
+    let mut recorder = Recorder::new(&nes);
+    for frame in 0..frames {
+        let buttons = if frame % 5 < 2 {
+            Buttons::A
+        } else {
+            Buttons::empty()
+        };
+        nes.set_buttons(0, buttons);
+        if frame == frames / 2 {
+            recorder.reset(&mut nes);
+        }
+        recorder.run_frame(&mut nes).unwrap();
+    }
+    recorder.finish(&nes)
+}
+
+#[test]
+fn test_recorded_movie_plays_back_to_the_same_ram() {
+    let movie = record(60);
+    assert_eq!(movie.frames.len(), 60);
+    assert!(movie.frames[30].reset);
+
+    let movie = Movie::parse(&movie.to_text()).unwrap();
+    let mut nes = nes_with_program(&INPUT_HISTORY_PROGRAM);
+    movie.verify(&mut nes).unwrap();
+    assert_eq!(Some(ram_hash(&nes)), movie.ram_crc32);
+}
+
+#[test]
+fn test_changed_input_desyncs() {
+    let mut movie = record(60);
+    movie.frames[10].input[0] ^= Buttons::A;
+
+    let error = movie
+        .verify(&mut nes_with_program(&INPUT_HISTORY_PROGRAM))
+        .unwrap_err();
+
+    assert!(error.starts_with("playback desynced"), "{}", error);
+}
+
+#[test]
+fn test_movie_for_another_game_is_rejected() {
+    let mut movie = record(2);
+    movie.rom_crc32 = Some(movie.rom_crc32.unwrap() ^ 1);
+
+    let error = movie
+        .play(&mut nes_with_program(&INPUT_HISTORY_PROGRAM))
+        .unwrap_err();
+
+    assert!(
+        error.starts_with("the movie is for another game"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_imported_movies_match_native_playback() {
+    let native = "nes-movie 1\nplayers 2\nframes\n. A .\n. A .\n. . .\nreset A .\n. . .\n";
+    let fm2 = "version 3\nport0 1\nport1 1\nfourscore 0\n\
+               |0|.......A|........||\n|0|.......A|........||\n|0|........|........||\n\
+               |1|.......A|........||\n|0|........|........||\n";
+    let bk2 = "[Input]\n\
+               LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|\n\
+               |..|.......A|\n|..|.......A|\n|..|........|\n|r.|.......A|\n|..|........|\n\
+               [/Input]\n";
+
+    let hashes: Vec<u32> = [native, fm2, bk2]
+        .iter()
+        .map(|text| {
+            let movie = Movie::parse_any(text).unwrap();
+            movie
+                .play(&mut nes_with_program(&INPUT_HISTORY_PROGRAM))
+                .unwrap()
+        })
+        .collect();
+
+    assert_eq!(hashes[0], hashes[1]);
+    assert_eq!(hashes[0], hashes[2]);
+    assert_ne!(
+        hashes[0],
+        Movie::default()
+            .play(&mut nes_with_program(&INPUT_HISTORY_PROGRAM))
+            .unwrap()
+    );
+}
@@ -0,0 +1,79 @@
+use nes_emulator::cpu::CPU;
+use std::vec;
+
+mod common;
+use common::test_ppu;
+
+const RESET_CYCLES: u64 = 7;
+const BRK_CYCLES: u64 = 7;
+
+#[test]
+fn test_oam_dma_copies_page_to_oam() {
+    let mut cpu = CPU::new();
+    cpu.memory.attach_ppu(test_ppu());
+    for i in 0..=0xFF_u16 {
+        cpu.memory.write(0x0200 + i, i as u8);
+    }
+
+    // LDA #$02; STA $4014
+    cpu.load_and_run(vec![0xA9, 0x02, 0x8D, 0x14, 0x40, 0x00]);
+
+    let oam = cpu.memory.ppu().unwrap().oam();
+    let expected: Vec<u8> = (0..=0xFF).collect();
+    assert_eq!(oam[..], expected[..]);
+}
+
+#[test]
+fn test_oam_dma_starts_at_oam_addr() {
+    let mut cpu = CPU::new();
+    cpu.memory.attach_ppu(test_ppu());
+    for i in 0..=0xFF_u16 {
+        cpu.memory.write(0x0300 + i, i as u8);
+    }
+
+    // LDA #$10; STA $2003; LDA #$03; STA $4014
+    cpu.load_and_run(vec![
+        0xA9, 0x10, 0x8D, 0x03, 0x20, 0xA9, 0x03, 0x8D, 0x14, 0x40, 0x00,
+    ]);
+
+    let oam = cpu.memory.ppu().unwrap().oam();
+    assert_eq!(oam[0x10], 0x00);
+    assert_eq!(oam[0xFF], 0xEF);
+    assert_eq!(oam[0x00], 0xF0);
+    assert_eq!(oam[0x0F], 0xFF);
+}
+
+#[test]
+fn test_oam_dma_stall_starting_on_odd_cycle() {
+    let mut cpu = CPU::new();
+
+    // LDA #$02 (2 cycles); STA $4014 (4 cycles)
+    cpu.load_and_run(vec![0xA9, 0x02, 0x8D, 0x14, 0x40, 0x00]);
+
+    let before_dma = RESET_CYCLES + 2 + 4;
+    assert_eq!(before_dma % 2, 1);
+    assert_eq!(cpu.cycles, before_dma + 514 + BRK_CYCLES);
+}
+
+#[test]
+fn test_oam_dma_stall_starting_on_even_cycle() {
+    let mut cpu = CPU::new();
+    cpu.memory.write(0x10, 0x02);
+
+    // LDA $10 (3 cycles); STA $4014 (4 cycles)
+    cpu.load_and_run(vec![0xA5, 0x10, 0x8D, 0x14, 0x40, 0x00]);
+
+    let before_dma = RESET_CYCLES + 3 + 4;
+    assert_eq!(before_dma % 2, 0);
+    assert_eq!(cpu.cycles, before_dma + 513 + BRK_CYCLES);
+}
+
+#[test]
+fn test_no_stall_without_oam_dma() {
+    let mut cpu = CPU::new();
+
+    // LDA #$02; STA $4013
+    cpu.load_and_run(vec![0xA9, 0x02, 0x8D, 0x13, 0x40, 0x00]);
+
+    assert_eq!(cpu.cycles, RESET_CYCLES + 2 + 4 + BRK_CYCLES);
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::nes_with_program;
+use nes_emulator::controller::Buttons;
+use nes_emulator::nes::Nes;
+use nes_emulator::rewind::{FrameInput, Rewind};
+
+/// Turns rendering on, then loops polling controller 1 into a running sum at $11 and counting
+/// at $10, so the machine state depends on the whole input history.
+const PROGRAM: [u8; 27] = [
+    /*LDA*/ 0xA9, 0x0A, /*STA $2001*/ 0x8D, 0x01, 0x20, /*$8005 LDA*/ 0xA9, 0x01,
+    /*STA $4016*/ 0x8D, 0x16, 0x40, /*LDA*/ 0xA9, 0x00, /*STA $4016*/ 0x8D, 0x16,
+    0x40, /*LDA $4016*/ 0xAD, 0x16, 0x40, /*ADC $11*/ 0x65, 0x11, /*STA $11*/ 0x85,
+    0x11, /*INC $10*/ 0xE6, 0x10, /*JMP $8005*/ 0x4C, 0x05, 0x80,
+];
+
+fn input(frame: u64) -> FrameInput {
+    let mut input = FrameInput::default();
+    if frame % 7 < 3 {
+        input[0] = Buttons::A;
+    }
+    input
+}
+
+/// Runs `frames` frames from where `rewind` is, returning the state after each.
+fn run(nes: &mut Nes, rewind: &mut Rewind, frames: u64) -> Vec<Vec<u8>> {
+    (0..frames)
+        .map(|_| {
+            let frame = rewind.frame();
+            rewind.run_frame(nes, input(frame)).unwrap();
+            nes.save_state()
+        })
+        .collect()
+}
+
+#[test]
+fn test_rewinds_frame_by_frame_and_resumes_identically() {
+    let mut nes = nes_with_program(&PROGRAM);
+    let mut rewind = Rewind::new(5, 16 << 20);
+    let mut states = vec![nes.save_state()];
+    states.extend(run(&mut nes, &mut rewind, 40));
+
+    for frame in (20..40).rev() {
+        assert!(rewind.rewind_frame(&mut nes).unwrap());
+        assert_eq!(rewind.frame(), frame);
+        assert!(
+            nes.save_state() == states[frame as usize],
+            "frame {}",
+            frame
+        );
+    }
+
+    let resumed = run(&mut nes, &mut rewind, 20);
+    assert!(resumed == states[21..41], "resumed run differs");
+}
+
+#[test]
+fn test_rewind_many_frames_at_once() {
+    let mut nes = nes_with_program(&PROGRAM);
+    let mut rewind = Rewind::new(4, 16 << 20);
+    let states = run(&mut nes, &mut rewind, 30);
+
+    assert_eq!(rewind.rewind(&mut nes, 13).unwrap(), 13);
+
+    assert_eq!(rewind.frame(), 17);
+    assert!(nes.save_state() == states[16]);
+    assert_eq!(rewind.frames_available(), 17);
+}
+
+#[test]
+fn test_snapshots_are_delta_compressed() {
+    let mut nes = nes_with_program(&PROGRAM);
+    let state_size = nes.save_state().len();
+    let mut rewind = Rewind::new(1, 16 << 20);
+
+    run(&mut nes, &mut rewind, 30);
+
+    // 30 snapshots in much less than 30 states' worth
+    assert_eq!(rewind.frames_available(), 30);
+    assert!(
+        rewind.memory_used() < state_size * 4,
+        "{} bytes for states of {}",
+        rewind.memory_used(),
+        state_size
+    );
+}
+
+#[test]
+fn test_history_of_another_game_is_rejected() {
+    let mut nes = nes_with_program(&PROGRAM);
+    let mut rewind = Rewind::new(1, 16 << 20);
+    run(&mut nes, &mut rewind, 3);
+    let mut other = nes_with_program(&[0x4C, 0x00, 0x80]);
+
+    assert!(rewind.rewind_frame(&mut other).is_err());
+    assert_eq!(rewind.frames_available(), 0);
+}
@@ -0,0 +1,20 @@
+use nes_emulator::cpu::CPU;
+
+mod common;
+use crate::common::assert_no_flags;
+
+#[test]
+fn test_0x60_rts_implied_returns_after_the_jsr() {
+    let mut cpu = CPU::new();
+
+    cpu.load_and_run_without_reset(vec![
+        /*JSR*/ 0x20, 0x06, 0x80, /*LDX*/ 0xA2, 0x01, /*BRK*/ 0x00,
+        /*LDA*/ 0xA9, 0x02, /*RTS*/ 0x60,
+    ]);
+
+    assert_eq!(cpu.register_a, 0x02);
+    assert_eq!(cpu.register_x, 0x01);
+    assert_eq!(cpu.stack_pointer, 0xFF);
+    assert_eq!(cpu.program_counter, 0x8006);
+    assert_no_flags(&cpu);
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::{nes_with_program, INPUT_HISTORY_PROGRAM};
+use nes_emulator::controller::Buttons;
+use nes_emulator::headless::frame_hash;
+use nes_emulator::nes::Nes;
+
+fn buttons(frame: usize) -> Buttons {
+    if frame.is_multiple_of(3) {
+        Buttons::A
+    } else {
+        Buttons::empty()
+    }
+}
+
+/// What the console shows, plays and keeps in RAM over `frames` frames.
+#[derive(Debug, PartialEq)]
+struct Trace {
+    frame_hashes: Vec<u32>,
+    samples: Vec<f32>,
+    ram: Vec<Vec<u8>>,
+    cycles: u64,
+}
+
+fn trace(nes: &mut Nes, frames: usize) -> Trace {
+    let mut trace = Trace {
+        frame_hashes: vec![],
+        samples: vec![],
+        ram: vec![],
+        cycles: 0,
+    };
+    for frame in 0..frames {
+        nes.set_buttons(0, buttons(frame));
+        let (picture, samples) = nes.run_frame().unwrap();
+        trace.frame_hashes.push(frame_hash(picture));
+        trace.samples.extend(samples);
+        trace.ram.push(nes.ram_mut().to_vec());
+    }
+    trace.cycles = nes.cpu.cycles;
+    trace
+}
+
+fn warmed_up() -> Nes {
+    let mut nes = nes_with_program(&INPUT_HISTORY_PROGRAM);
+    trace(&mut nes, 30);
+    // stop mid-frame, mid-instruction stream
+    for _ in 0..1234 {
+        nes.step().unwrap();
+    }
+    // audio already produced is output, not state
+    nes.apu_mut().take_samples();
+    nes
+}
+
+#[test]
+fn test_execution_continues_identically_after_load() {
+    let mut nes = warmed_up();
+    let state = nes.save_state();
+    let expected = trace(&mut nes, 20);
+    assert!(expected.ram[0][0x11] != expected.ram[19][0x11]);
+
+    nes.load_state(&state).unwrap();
+
+    assert_eq!(trace(&mut nes, 20), expected);
+}
+
+#[test]
+fn test_state_loads_into_a_fresh_console() {
+    let mut nes = warmed_up();
+    let state = nes.save_state();
+    let expected = trace(&mut nes, 20);
+
+    let mut fresh = nes_with_program(&INPUT_HISTORY_PROGRAM);
+    fresh.load_state(&state).unwrap();
+
+    assert_eq!(fresh.save_state(), state);
+    assert_eq!(trace(&mut fresh, 20), expected);
+}
+
+#[test]
+fn test_state_of_another_game_is_rejected() {
+    let state = warmed_up().save_state();
+    let mut other = nes_with_program(&[0x4C, 0x00, 0x80]);
+
+    let error = other.load_state(&state).unwrap_err();
+
+    assert!(
+        error.starts_with("save state is for another game"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_damaged_state_is_rejected_and_console_is_untouched() {
+    let mut nes = warmed_up();
+    let before = nes.save_state();
+    let mut state = before.clone();
+    state.truncate(state.len() - 10);
+
+    assert!(nes.load_state(&state).unwrap_err().contains("truncated"));
+    assert_eq!(nes.load_state(b"garbage").unwrap_err(), "not a save state");
+    assert_eq!(nes.save_state(), before);
+}
@@ -0,0 +1,106 @@
+use nes_emulator::cpu::{Flags, CPU};
+use std::vec;
+
+mod common;
+use common::{assert_flag, assert_flags};
+
+#[test]
+fn test_0xe9_sbc_immediate_subtracts_correctly() {
+    let mut cpu = CPU::new();
+    cpu.status.insert(Flags::Carry);
+    cpu.register_a = 0x05;
+
+    cpu.load_and_run_without_reset(vec![0xE9, 0x03, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x02);
+    assert_flag(&cpu, Flags::Carry);
+}
+
+#[test]
+fn test_0xe9_sbc_immediate_borrows_without_carry() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x05;
+
+    cpu.load_and_run_without_reset(vec![0xE9, 0x03, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x01);
+    assert_flag(&cpu, Flags::Carry);
+}
+
+#[test]
+fn test_0xe9_sbc_immediate_zero_flag() {
+    let mut cpu = CPU::new();
+    cpu.status.insert(Flags::Carry);
+    cpu.register_a = 0x42;
+
+    cpu.load_and_run_without_reset(vec![0xE9, 0x42, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x00);
+    assert_flags(&cpu, vec![Flags::Zero, Flags::Carry]);
+}
+
+#[test]
+fn test_0xe9_sbc_immediate_negative_result_clears_carry() {
+    let mut cpu = CPU::new();
+    cpu.status.insert(Flags::Carry);
+    cpu.register_a = 0x01;
+
+    cpu.load_and_run_without_reset(vec![0xE9, 0x02, 0x00]);
+
+    assert_eq!(cpu.register_a, 0xFF);
+    assert_flag(&cpu, Flags::Negative);
+}
+
+#[test]
+fn test_0xe9_sbc_immediate_overflow_flag() {
+    let mut cpu = CPU::new();
+    cpu.status.insert(Flags::Carry);
+    cpu.register_a = 0x80;
+
+    cpu.load_and_run_without_reset(vec![0xE9, 0x01, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x7F);
+    assert_flags(&cpu, vec![Flags::Overflow, Flags::Carry]);
+}
+
+#[test]
+fn test_0xe5_sbc_zero_page() {
+    let mut cpu = CPU::new();
+    cpu.status.insert(Flags::Carry);
+    cpu.register_a = 0x10;
+    cpu.memory.write(0x20, 0x01);
+
+    cpu.load_and_run_without_reset(vec![0xE5, 0x20, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x0F);
+    assert_flag(&cpu, Flags::Carry);
+}
+
+#[test]
+fn test_0xfd_sbc_absolute_x() {
+    let mut cpu = CPU::new();
+    cpu.status.insert(Flags::Carry);
+    cpu.register_a = 0x10;
+    cpu.register_x = 0x02;
+    cpu.memory.write(0x0302, 0x10);
+
+    cpu.load_and_run_without_reset(vec![0xFD, 0x00, 0x03, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x00);
+    assert_flags(&cpu, vec![Flags::Zero, Flags::Carry]);
+}
+
+#[test]
+fn test_0xf1_sbc_indirect_y() {
+    let mut cpu = CPU::new();
+    cpu.status.insert(Flags::Carry);
+    cpu.register_a = 0x00;
+    cpu.register_y = 0x01;
+    cpu.memory.write_u16(0x10, 0x0400);
+    cpu.memory.write(0x0401, 0x00);
+
+    cpu.load_and_run_without_reset(vec![0xF1, 0x10, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x00);
+    assert_flags(&cpu, vec![Flags::Zero, Flags::Carry]);
+}
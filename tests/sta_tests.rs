@@ -0,0 +1,83 @@
+use nes_emulator::cpu::CPU;
+use std::vec;
+
+mod common;
+use common::assert_no_flags;
+
+#[test]
+fn test_0x85_sta_zero_page_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x66;
+
+    cpu.load_and_run_without_reset(vec![0x85, 0x10, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x10), 0x66);
+    assert_no_flags(&cpu);
+}
+
+#[test]
+fn test_0x95_sta_zero_page_x_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x66;
+    cpu.register_x = 0x02;
+
+    cpu.load_and_run_without_reset(vec![0x95, 0xFF, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x01), 0x66);
+}
+
+#[test]
+fn test_0x8d_sta_absolute_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x66;
+
+    cpu.load_and_run_without_reset(vec![0x8D, 0x34, 0x12, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x1234), 0x66);
+}
+
+#[test]
+fn test_0x9d_sta_absolute_x_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x66;
+    cpu.register_x = 0x01;
+
+    cpu.load_and_run_without_reset(vec![0x9D, 0x34, 0x12, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x1235), 0x66);
+}
+
+#[test]
+fn test_0x99_sta_absolute_y_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x66;
+    cpu.register_y = 0x01;
+
+    cpu.load_and_run_without_reset(vec![0x99, 0x34, 0x12, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x1235), 0x66);
+}
+
+#[test]
+fn test_0x81_sta_indirect_x_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x66;
+    cpu.register_x = 0x01;
+    cpu.memory.write_u16(0x11, 0x1234);
+
+    cpu.load_and_run_without_reset(vec![0x81, 0x10, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x1234), 0x66);
+}
+
+#[test]
+fn test_0x91_sta_indirect_y_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x66;
+    cpu.register_y = 0x01;
+    cpu.memory.write_u16(0x10, 0x1234);
+
+    cpu.load_and_run_without_reset(vec![0x91, 0x10, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x1235), 0x66);
+}
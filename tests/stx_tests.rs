@@ -0,0 +1,37 @@
+use nes_emulator::cpu::CPU;
+use std::vec;
+
+mod common;
+use common::assert_no_flags;
+
+#[test]
+fn test_0x86_stx_zero_page_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_x = 0x66;
+
+    cpu.load_and_run_without_reset(vec![0x86, 0x10, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x10), 0x66);
+    assert_no_flags(&cpu);
+}
+
+#[test]
+fn test_0x96_stx_zero_page_y_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_x = 0x66;
+    cpu.register_y = 0x02;
+
+    cpu.load_and_run_without_reset(vec![0x96, 0xFF, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x01), 0x66);
+}
+
+#[test]
+fn test_0x8e_stx_absolute_store_data() {
+    let mut cpu = CPU::new();
+    cpu.register_x = 0x66;
+
+    cpu.load_and_run_without_reset(vec![0x8E, 0x34, 0x12, 0x00]);
+
+    assert_eq!(cpu.memory.read(0x1234), 0x66);
+}
@@ -0,0 +1,17 @@
+use std::vec;
+
+use nes_emulator::cpu::{Flags, CPU};
+
+mod common;
+use common::assert_flag;
+
+#[test]
+fn test_0xba_tsx_implied_copies_the_stack_pointer() {
+    let mut cpu = CPU::new();
+    cpu.stack_pointer = 0xF0;
+
+    cpu.load_and_run_without_reset(vec![0xBA, 0x00]);
+
+    assert_eq!(cpu.register_x, 0xF0);
+    assert_flag(&cpu, Flags::Negative);
+}
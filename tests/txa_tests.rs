@@ -0,0 +1,17 @@
+use std::vec;
+
+use nes_emulator::cpu::CPU;
+
+mod common;
+use common::assert_no_flags;
+
+#[test]
+fn test_0x8a_txa_implied_copy_data() {
+    let mut cpu = CPU::new();
+    cpu.register_x = 0x01;
+
+    cpu.load_and_run_without_reset(vec![0x8A, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x01);
+    assert_no_flags(&cpu);
+}
@@ -0,0 +1,17 @@
+use std::vec;
+
+use nes_emulator::cpu::{Flags, CPU};
+
+mod common;
+use common::assert_flag;
+
+#[test]
+fn test_0x9a_txs_implied_sets_no_flags() {
+    let mut cpu = CPU::new();
+    cpu.register_x = 0x00;
+
+    cpu.load_and_run_without_reset(vec![0xA2, 0xF0, 0x9A, 0x00]);
+
+    assert_eq!(cpu.stack_pointer, 0xF0);
+    assert_flag(&cpu, Flags::Negative);
+}
@@ -0,0 +1,18 @@
+use std::vec;
+
+use nes_emulator::cpu::{Flags, CPU};
+
+mod common;
+use common::assert_flag;
+
+#[test]
+fn test_0x98_tya_implied_zero_flag() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x05;
+    cpu.register_y = 0x00;
+
+    cpu.load_and_run_without_reset(vec![0x98, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x00);
+    assert_flag(&cpu, Flags::Zero);
+}